@@ -0,0 +1,81 @@
+use std::fmt;
+use std::time::Duration;
+
+/// How far (in logical pixels) the document scrolls between frames while benchmarking
+pub const BENCH_SCROLL_STEP: f32 = 40.;
+
+/// Times every frame while the document gets scrolled through from top to bottom
+#[derive(Debug, Default)]
+pub struct Bench {
+    frame_times: Vec<Duration>,
+}
+
+impl Bench {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, frame_time: Duration) {
+        self.frame_times.push(frame_time);
+    }
+
+    /// Summary of the frames recorded so far. `None` before the first frame
+    pub fn report(&self) -> Option<BenchReport> {
+        let mut frame_times = self.frame_times.clone();
+        frame_times.sort_unstable();
+        let max = *frame_times.last()?;
+        let percentile = |p: f32| {
+            let index = ((frame_times.len() - 1) as f32 * p).round() as usize;
+            frame_times[index]
+        };
+        Some(BenchReport {
+            frames: frame_times.len(),
+            total: frame_times.iter().sum(),
+            max,
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchReport {
+    pub frames: usize,
+    pub total: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.;
+        writeln!(f, "frames: {}", self.frames)?;
+        writeln!(f, "total: {:.2} ms", ms(self.total))?;
+        writeln!(f, "p50: {:.2} ms", ms(self.p50))?;
+        writeln!(f, "p99: {:.2} ms", ms(self.p99))?;
+        write!(f, "max: {:.2} ms", ms(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Bench;
+
+    #[test]
+    fn percentiles() {
+        let mut bench = Bench::new();
+        assert_eq!(bench.report(), None);
+        for ms in (1..=100).rev() {
+            bench.record(Duration::from_millis(ms));
+        }
+        let report = bench.report().unwrap();
+        assert_eq!(report.frames, 100);
+        assert_eq!(report.total, Duration::from_millis(5050));
+        assert_eq!(report.p50, Duration::from_millis(51));
+        assert_eq!(report.p99, Duration::from_millis(99));
+        assert_eq!(report.max, Duration::from_millis(100));
+    }
+}
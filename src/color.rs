@@ -1,3 +1,5 @@
+use crate::text::{AdmonitionKind, Change, DEFAULT_TEXT_SIZE};
+
 use serde::Deserialize;
 
 pub fn hex_to_linear_rgba(c: u32) -> [f32; 4] {
@@ -12,19 +14,254 @@ pub fn hex_to_linear_rgba(c: u32) -> [f32; 4] {
     [f(c >> 16), f(c >> 8), f(c), 1.0]
 }
 
+// The sizes that browsers give headings by default
+pub const DEFAULT_HEADING_SCALES: [f32; 6] = [2., 1.5, 1.17, 1., 0.83, 0.67];
+
+// CSS's basic color keywords along with orange
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("black", 0x000000),
+    ("silver", 0xc0c0c0),
+    ("gray", 0x808080),
+    ("grey", 0x808080),
+    ("white", 0xffffff),
+    ("maroon", 0x800000),
+    ("red", 0xff0000),
+    ("purple", 0x800080),
+    ("fuchsia", 0xff00ff),
+    ("magenta", 0xff00ff),
+    ("green", 0x008000),
+    ("lime", 0x00ff00),
+    ("olive", 0x808000),
+    ("yellow", 0xffff00),
+    ("navy", 0x000080),
+    ("blue", 0x0000ff),
+    ("teal", 0x008080),
+    ("aqua", 0x00ffff),
+    ("cyan", 0x00ffff),
+    ("orange", 0xffa500),
+];
+
+/// Parses hex colors like `#adbac7` or the shorthand `#abc` along with color names like `white`
+pub fn parse_color(color: &str) -> Option<u32> {
+    let color = color.trim();
+    match color.strip_prefix('#') {
+        Some(hex) if hex.chars().all(|c| c.is_ascii_hexdigit()) => match hex.len() {
+            6 => u32::from_str_radix(hex, 16).ok(),
+            // Shorthand doubles up each digit
+            3 => {
+                let long: String = hex.chars().flat_map(|c| [c, c]).collect();
+                u32::from_str_radix(&long, 16).ok()
+            }
+            _ => None,
+        },
+        Some(_) => None,
+        None => NAMED_COLORS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(color))
+            .map(|&(_, hex)| hex),
+    }
+}
+
+/// The inverse of [`hex_to_linear_rgba`]. Alpha is left out
+pub fn linear_rgba_to_hex(color: [f32; 4]) -> u32 {
+    let f = |x: f32| {
+        let x = if x > 0.0031308 {
+            1.055 * x.powf(1. / 2.4) - 0.055
+        } else {
+            x * 12.92
+        };
+        (x.clamp(0., 1.) * 255.).round() as u32
+    };
+    (f(color[0]) << 16) | (f(color[1]) << 8) | f(color[2])
+}
+
+// Relative luminance of a linear color as defined by WCAG
+fn luminance([r, g, b, _]: [f32; 4]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// WCAG contrast ratio between two linear colors. Ranges from 1 (no contrast) to 21
+pub fn contrast_ratio(a: [f32; 4], b: [f32; 4]) -> f32 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Moves `color` towards white or black (whichever is further from `background`) just enough for
+/// the two to have a contrast ratio of at least `min_ratio`
+pub fn ensure_contrast(color: [f32; 4], background: [f32; 4], min_ratio: f32) -> [f32; 4] {
+    if contrast_ratio(color, background) >= min_ratio {
+        return color;
+    }
+    let target = if luminance(background) < 0.18 { 1. } else { 0. };
+    let mix = |t: f32| {
+        let [r, g, b, a] = color;
+        let f = |x: f32| x + (target - x) * t;
+        [f(r), f(g), f(b), a]
+    };
+    // Searches for the smallest mix that's enough
+    let (mut low, mut high) = (0f32, 1f32);
+    for _ in 0..16 {
+        let mid = (low + high) / 2.;
+        if contrast_ratio(mix(mid), background) >= min_ratio {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    mix(high)
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Theme {
     pub text_color: [f32; 4],
     pub background_color: wgpu::Color,
+    /// Top and bottom colors of a gradient that's drawn over `background_color`. It stays put
+    /// while the document scrolls
+    pub background_gradient: Option<([f32; 4], [f32; 4])>,
     pub code_color: [f32; 4],
     pub code_block_color: [f32; 4],
     pub quote_block_color: [f32; 4],
+    /// Colors of the bars of nested quote blocks starting from the outermost one. Cycles for
+    /// quotes nested deeper than there are colors. Every bar uses `select_color` when empty
+    pub quote_bar_colors: Vec<[f32; 4]>,
+    /// Colors of headings from `h1` down. Headings past the end use `text_color`
+    pub heading_colors: Vec<[f32; 4]>,
+    /// Sizes of headings from `h1` to `h6` relative to the size of regular text
+    pub heading_scales: [f32; 6],
+    /// Extra space between the letters of headings from `h1` to `h6` in ems
+    pub heading_letter_spacing: [f32; 6],
     pub link_color: [f32; 4],
+    pub visited_link_color: [f32; 4],
+    /// Color of the underline or background of the link under the cursor
+    pub link_hover_color: [f32; 4],
+    pub link_hover_style: LinkHoverStyle,
+    /// Underlines links instead of only telling them apart by their color
+    pub underline_links: bool,
+    /// Horizontal rules use `text_color` unless they have their own color
+    pub rule_color: Option<[f32; 4]>,
+    /// Thickness of horizontal rules in logical pixels
+    pub rule_thickness: f32,
+    /// Fraction of the content width that horizontal rules span, centered
+    pub rule_width: f32,
+    pub rule_style: RuleStyle,
     pub select_color: [f32; 4],
+    /// Scrollbar thumb while it's left alone and while it's hovered or dragged
+    pub scrollbar_color: [f32; 4],
+    pub scrollbar_hover_color: [f32; 4],
     pub checkbox_color: [f32; 4],
+    pub note_color: [f32; 4],
+    pub tip_color: [f32; 4],
+    pub important_color: [f32; 4],
+    pub warning_color: [f32; 4],
+    pub caution_color: [f32; 4],
+    pub inserted_color: [f32; 4],
+    pub deleted_color: [f32; 4],
+    pub marked_color: [f32; 4],
+    /// Text of the lines that diffs add and remove
+    pub diff_added_color: [f32; 4],
+    pub diff_removed_color: [f32; 4],
     pub code_highlighter: SyntaxTheme,
 }
 
+impl Theme {
+    /// Background at `fraction` of the way down the screen
+    pub fn background_at(&self, fraction: f32) -> [f32; 4] {
+        match self.background_gradient {
+            Some((top, bottom)) => {
+                let t = fraction.clamp(0., 1.);
+                let mut color = top;
+                for (channel, bottom) in color.iter_mut().zip(bottom) {
+                    *channel += (bottom - *channel) * t;
+                }
+                color
+            }
+            None => {
+                let wgpu::Color { r, g, b, a } = self.background_color;
+                [r as f32, g as f32, b as f32, a as f32]
+            }
+        }
+    }
+
+    /// Color of headings of `level` from 1 to 6. `None` leaves them the color of regular text
+    pub fn heading_color(&self, level: u8) -> Option<[f32; 4]> {
+        self.heading_colors.get(usize::from(level) - 1).copied()
+    }
+
+    /// Text size of headings of `level` from 1 to 6
+    pub fn heading_size(&self, level: u8) -> f32 {
+        DEFAULT_TEXT_SIZE * self.heading_scales[usize::from(level) - 1]
+    }
+
+    /// Extra space between the letters of headings of `level` from 1 to 6 in ems
+    pub fn heading_letter_spacing(&self, level: u8) -> f32 {
+        self.heading_letter_spacing[usize::from(level) - 1]
+    }
+
+    /// Color of the bar of a quote block within `depth` other quote blocks
+    pub fn quote_bar_color(&self, depth: usize) -> [f32; 4] {
+        if self.quote_bar_colors.is_empty() {
+            self.select_color
+        } else {
+            self.quote_bar_colors[depth % self.quote_bar_colors.len()]
+        }
+    }
+
+    /// Background tint of inserted, deleted or marked text
+    pub fn change_color(&self, change: Change) -> [f32; 4] {
+        match change {
+            Change::Inserted => self.inserted_color,
+            Change::Deleted => self.deleted_color,
+            Change::Marked => self.marked_color,
+        }
+    }
+
+    /// Text color of a line that a diff adds or removes
+    pub fn diff_color(&self, change: Change) -> [f32; 4] {
+        match change {
+            Change::Inserted => self.diff_added_color,
+            Change::Deleted => self.diff_removed_color,
+            Change::Marked => self.marked_color,
+        }
+    }
+
+    /// Background of a line that a diff adds or removes. A faint tint of its color over the
+    /// code block's `background`
+    pub fn diff_background(&self, change: Change, background: [f32; 4]) -> [f32; 4] {
+        let color = self.diff_color(change);
+        let mix = |background: f32, color: f32| background * 0.85 + color * 0.15;
+        [
+            mix(background[0], color[0]),
+            mix(background[1], color[1]),
+            mix(background[2], color[2]),
+            background[3],
+        ]
+    }
+
+    /// Accent color of a callout's bar and title
+    pub fn admonition_color(&self, kind: AdmonitionKind) -> [f32; 4] {
+        match kind {
+            AdmonitionKind::Note => self.note_color,
+            AdmonitionKind::Tip => self.tip_color,
+            AdmonitionKind::Important => self.important_color,
+            AdmonitionKind::Warning => self.warning_color,
+            AdmonitionKind::Caution => self.caution_color,
+        }
+    }
+
+    /// The background of a callout. A faint tint of its accent color
+    pub fn admonition_background(&self, kind: AdmonitionKind) -> [f32; 4] {
+        let accent = self.admonition_color(kind);
+        let background = self.background_color;
+        let mix = |background: f64, accent: f32| background as f32 * 0.9 + accent * 0.1;
+        [
+            mix(background.r, accent[0]),
+            mix(background.g, accent[1]),
+            mix(background.b, accent[2]),
+            1.0,
+        ]
+    }
+}
+
 pub const DARK_DEFAULT: Theme = Theme {
     text_color: [0.5841, 0.6376, 0.6939, 1.0],
     background_color: wgpu::Color {
@@ -33,27 +270,130 @@ pub const DARK_DEFAULT: Theme = Theme {
         b: 0.0086,
         a: 1.0,
     },
+    background_gradient: None,
     code_color: [0.2542, 0.4508, 0.4621, 1.0],
     code_block_color: [0.0080 * 1.5, 0.0110 * 1.5, 0.0156 * 1.5, 1.0],
     quote_block_color: [0.0080, 0.0110, 0.0156, 1.0],
+    quote_bar_colors: Vec::new(),
+    heading_colors: Vec::new(),
+    heading_scales: DEFAULT_HEADING_SCALES,
+    heading_letter_spacing: [0.; 6],
     link_color: [0.0976, 0.3813, 1.0, 1.0],
+    visited_link_color: [0.4179, 0.2307, 0.9047, 1.0],
+    link_hover_color: [0.0976, 0.3813, 1.0, 1.0],
+    link_hover_style: LinkHoverStyle::Underline,
+    underline_links: false,
+    rule_color: None,
+    rule_thickness: 2.,
+    rule_width: 1.,
+    rule_style: RuleStyle::Solid,
     select_color: [0.17, 0.22, 0.3, 1.0],
+    scrollbar_color: [0.3, 0.3, 0.3, 1.0],
+    scrollbar_hover_color: [0.55, 0.55, 0.55, 1.0],
     checkbox_color: [0.1, 0.3, 0.1, 1.0],
+    note_color: [0.0578, 0.2918, 0.9387, 1.0],
+    tip_color: [0.0497, 0.4851, 0.0802, 1.0],
+    important_color: [0.4072, 0.2051, 0.9387, 1.0],
+    warning_color: [0.6445, 0.3185, 0.016, 1.0],
+    caution_color: [0.9387, 0.0823, 0.0666, 1.0],
+    inserted_color: [0.011, 0.0395, 0.0194, 1.0],
+    deleted_color: [0.0545, 0.0152, 0.0137, 1.0],
+    marked_color: [0.107, 0.0685, 0.0, 1.0],
+    diff_added_color: [0.0953, 0.4072, 0.1022, 1.0],
+    diff_removed_color: [0.7835, 0.0865, 0.0704, 1.0],
     code_highlighter: SyntaxTheme::Base16OceanDark,
 };
 
 pub const LIGHT_DEFAULT: Theme = Theme {
     text_color: [0., 0., 0., 1.0],
     background_color: wgpu::Color::WHITE,
+    background_gradient: None,
     code_color: [0.3864, 0.0123, 0.1095, 1.0],
     code_block_color: [0.92, 0.92, 0.92, 1.0],
     quote_block_color: [0.5841 * 1.5, 0.6376 * 1.5, 0.6939 * 1.5, 1.0],
+    quote_bar_colors: Vec::new(),
+    heading_colors: Vec::new(),
+    heading_scales: DEFAULT_HEADING_SCALES,
+    heading_letter_spacing: [0.; 6],
     link_color: [0.0975, 0.1813, 1.0, 1.0],
+    visited_link_color: [0.2582, 0.0595, 0.5647, 1.0],
+    link_hover_color: [0.0975, 0.1813, 1.0, 1.0],
+    link_hover_style: LinkHoverStyle::Underline,
+    underline_links: false,
+    rule_color: None,
+    rule_thickness: 2.,
+    rule_width: 1.,
+    rule_style: RuleStyle::Solid,
     select_color: [0.67, 0.85, 0.9, 1.0],
+    scrollbar_color: [0.3, 0.3, 0.3, 1.0],
+    scrollbar_hover_color: [0.12, 0.12, 0.12, 1.0],
     checkbox_color: [0.37, 0.85, 0.5, 1.0],
+    note_color: [0.0027, 0.1413, 0.7011, 1.0],
+    tip_color: [0.0103, 0.2122, 0.0382, 1.0],
+    important_color: [0.2232, 0.0802, 0.7379, 1.0],
+    warning_color: [0.3231, 0.1356, 0.0, 1.0],
+    caution_color: [0.6376, 0.0176, 0.0284, 1.0],
+    inserted_color: [0.7011, 0.9647, 0.7529, 1.0],
+    deleted_color: [1.0, 0.8308, 0.8148, 1.0],
+    marked_color: [1.0, 0.8963, 0.3662, 1.0],
+    diff_added_color: [0.0103, 0.2122, 0.0382, 1.0],
+    diff_removed_color: [0.624, 0.016, 0.0273, 1.0],
     code_highlighter: SyntaxTheme::Base16OceanLight,
 };
 
+/// Colors of code blocks. Kept apart from the document's [`Theme`] so that code can have a look
+/// of its own like dark code blocks in a light document
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CodeTheme {
+    /// Fill behind code blocks. When unset highlighted blocks use the highlighter's background
+    /// and others use the document theme's code block color
+    pub background: Option<[f32; 4]>,
+    /// Color of code that the highlighter doesn't color itself
+    pub text_color: [f32; 4],
+    pub highlighter: SyntaxTheme,
+}
+
+impl CodeTheme {
+    /// Highlighted tokens get nudged to at least this contrast ratio against the background
+    pub const MIN_CONTRAST: f32 = 3.;
+}
+
+impl From<&Theme> for CodeTheme {
+    fn from(theme: &Theme) -> Self {
+        Self {
+            background: None,
+            text_color: theme.code_color,
+            highlighter: theme.code_highlighter,
+        }
+    }
+}
+
+/// How the link under the cursor stands out
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkHoverStyle {
+    /// Nothing besides the cursor changing
+    None,
+    /// A line drawn under the link
+    #[default]
+    Underline,
+    /// A faint box drawn behind the link
+    Background,
+}
+
+/// How horizontal rules are drawn
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleStyle {
+    /// One unbroken bar
+    #[default]
+    Solid,
+    /// Dashes a few times as long as the rule is thick
+    Dashed,
+    /// Square dots as big as the rule is thick
+    Dotted,
+}
+
 #[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum SyntaxTheme {
@@ -79,3 +419,81 @@ impl SyntaxTheme {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        contrast_ratio, ensure_contrast, hex_to_linear_rgba, parse_color, Theme, DARK_DEFAULT,
+    };
+
+    #[test]
+    fn background_follows_the_gradient() {
+        let [r, g, b, a] = [0.1, 0.2, 0.3, 1.];
+        assert_eq!(DARK_DEFAULT.background_at(0.5), {
+            let wgpu::Color { r, g, b, a } = DARK_DEFAULT.background_color;
+            [r as f32, g as f32, b as f32, a as f32]
+        });
+
+        let theme = Theme {
+            background_gradient: Some(([0., 0., 0., 1.], [r, g, b, a])),
+            ..DARK_DEFAULT
+        };
+        assert_eq!(theme.background_at(0.), [0., 0., 0., 1.]);
+        assert_eq!(theme.background_at(0.5), [0.05, 0.1, 0.15, 1.]);
+        // Off screen stays at the ends
+        assert_eq!(theme.background_at(2.), [r, g, b, a]);
+    }
+
+    #[test]
+    fn headings_by_level() {
+        // Defaults match the sizes that headings always had
+        let sizes: Vec<_> = (1..=6)
+            .map(|level| DARK_DEFAULT.heading_size(level))
+            .collect();
+        assert_eq!(sizes, [32., 24., 18.72, 16., 13.28, 10.72]);
+        assert_eq!(DARK_DEFAULT.heading_color(1), None);
+
+        let red = hex_to_linear_rgba(0xff0000);
+        let theme = Theme {
+            heading_colors: vec![red],
+            heading_scales: [3., 2., 1., 1., 1., 1.],
+            ..DARK_DEFAULT
+        };
+        assert_eq!(theme.heading_color(1), Some(red));
+        assert_eq!(theme.heading_color(2), None);
+        assert_eq!(theme.heading_size(1), 48.);
+    }
+
+    #[test]
+    fn colors_parse_from_hex_and_names() {
+        assert_eq!(parse_color("#adbac7"), Some(0xadbac7));
+        assert_eq!(parse_color("#FA0"), Some(0xffaa00));
+        assert_eq!(parse_color(" White "), Some(0xffffff));
+        assert_eq!(parse_color("#adbac"), None);
+        assert_eq!(parse_color("#+12345"), None);
+        assert_eq!(parse_color("blurple"), None);
+    }
+
+    #[test]
+    fn contrast_gets_raised_to_the_minimum() {
+        let black = [0., 0., 0., 1.];
+        let white = [1., 1., 1., 1.];
+        assert!((contrast_ratio(black, white) - 21.).abs() < 0.01);
+
+        // Already legible colors are left alone
+        let light_gray = [0.6, 0.6, 0.6, 1.];
+        assert_eq!(ensure_contrast(light_gray, black, 3.), light_gray);
+
+        // Dark text on a dark background gets lightened
+        let dark_gray = [0.02, 0.02, 0.02, 0.5];
+        let raised = ensure_contrast(dark_gray, black, 3.);
+        assert!(contrast_ratio(raised, black) >= 3.);
+        assert!(raised[0] > dark_gray[0]);
+        assert_eq!(raised[3], 0.5);
+
+        // Light text on a light background gets darkened
+        let darkened = ensure_contrast(light_gray, white, 4.5);
+        assert!(contrast_ratio(darkened, white) >= 4.5);
+        assert!(darkened[0] < light_gray[0]);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{
+    image::Image,
+    positioner::{Positioned, Spacer},
+    table::Table,
+    text::{Text, TextBox},
+    Element,
+};
+
+/// Builds a document out of elements directly instead of parsing markdown. The elements can be
+/// displayed with [`Renderer::set_elements`](crate::renderer::Renderer::set_elements)
+///
+/// Spacing between elements matches what the markdown interpreter produces
+pub struct DocumentBuilder {
+    elements: Vec<Positioned<Element>>,
+    hidpi_scale: f32,
+    text_color: [f32; 4],
+}
+
+impl DocumentBuilder {
+    pub fn new(hidpi_scale: f32, text_color: [f32; 4]) -> Self {
+        Self {
+            elements: Vec::new(),
+            hidpi_scale,
+            text_color,
+        }
+    }
+
+    /// Text using the document's scale and color. Combine it into text boxes and table cells
+    pub fn text(&self, text: &str) -> Text {
+        Text::new(text.to_owned(), self.hidpi_scale, self.text_color)
+    }
+
+    /// A text box using the document's scale. Also useful for table cells
+    pub fn text_box(&self, texts: Vec<Text>) -> TextBox {
+        TextBox::new(texts, self.hidpi_scale)
+    }
+
+    /// A block of text followed by a spacer
+    pub fn paragraph(self, texts: Vec<Text>) -> Self {
+        let text_box = self.text_box(texts);
+        self.element(text_box).spacer()
+    }
+
+    pub fn image(self, image: Image) -> Self {
+        self.element(image).spacer()
+    }
+
+    pub fn table(self, table: Table) -> Self {
+        self.spacer().element(table).spacer()
+    }
+
+    /// Empty space between elements
+    pub fn spacer(self) -> Self {
+        self.element(Spacer::new(5., false))
+    }
+
+    /// A horizontal line across the document
+    pub fn rule(self) -> Self {
+        self.element(Spacer::new(5., true))
+    }
+
+    /// Adds any element as is without extra spacing
+    pub fn element(mut self, element: impl Into<Element>) -> Self {
+        self.elements.push(Positioned::new(element.into()));
+        self
+    }
+
+    pub fn build(self) -> Vec<Positioned<Element>> {
+        self.elements
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spacing() {
+        let builder = DocumentBuilder::new(1., [0., 0., 0., 1.]);
+        let heading = builder.text("Heading").with_size(32.).make_bold(true);
+        let cell = builder.text_box(vec![builder.text("Cell")]);
+        let elements = builder
+            .paragraph(vec![heading])
+            .table(Table::new().with_header(cell))
+            .rule()
+            .build();
+
+        let kinds: Vec<_> = elements
+            .iter()
+            .map(|element| match element.inner {
+                Element::TextBox(_) => "text",
+                Element::Spacer(Spacer {
+                    visibile: false, ..
+                }) => "space",
+                Element::Spacer(Spacer { visibile: true, .. }) => "rule",
+                Element::Table(_) => "table",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, ["text", "space", "space", "table", "space", "rule"]);
+    }
+}
@@ -13,6 +13,7 @@ use font_kit::properties::{Properties, Style, Weight};
 use font_kit::source::SystemSource;
 use serde::{Deserialize, Serialize};
 use wgpu_glyph::ab_glyph::{FontArc, FontRef, FontVec};
+use wgpu_glyph::FontId;
 
 use crate::opts::FontOptions;
 
@@ -34,6 +35,18 @@ enum FontType {
 }
 
 impl FontType {
+    /// In the order that the variants of a family get loaded in
+    const ALL: [Self; 4] = [Self::Regular, Self::Italic, Self::Bold, Self::BoldItalic];
+
+    fn properties(&self) -> Properties {
+        match self {
+            Self::Regular => *Properties::new().style(Style::Normal),
+            Self::Italic => *Properties::new().style(Style::Italic),
+            Self::Bold => *Properties::new().weight(Weight::BOLD),
+            Self::BoldItalic => *Properties::new().weight(Weight::BOLD).style(Style::Italic),
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         match &self {
             Self::Regular => "Regular",
@@ -145,109 +158,238 @@ impl TryFrom<HandleCache> for Handle {
     }
 }
 
-pub fn get_fonts(font_opts: &FontOptions) -> anyhow::Result<Vec<FontArc>> {
-    let regular_name = &font_opts.regular_font;
-    let monospace_name = &font_opts.monospace_font;
+/// Index (in groups of 4 font variants) of the regular text font
+pub const TEXT_FONT: usize = 0;
+/// Index (in groups of 4 font variants) of the monospace font
+pub const MONOSPACE_FONT: usize = 1;
+/// Index (in groups of 4 font variants) of the emoji fallback font
+pub const EMOJI_FONT: usize = 2;
+
+/// The regular face of the monospace font
+pub const MONOSPACE_REGULAR: FontId = font_id(MONOSPACE_FONT, false, false);
+/// The bold face of the regular text font
+pub const TEXT_BOLD: FontId = font_id(TEXT_FONT, true, false);
+
+/// The face of the font at index `font` (like [`MONOSPACE_FONT`]) in the order that the variants
+/// of a family get loaded in by [`get_fonts()`]
+pub const fn font_id(font: usize, bold: bool, italic: bool) -> FontId {
+    FontId(font * FontType::ALL.len() + 2 * bold as usize + italic as usize)
+}
 
-    if regular_name.is_none() && monospace_name.is_none() {
-        load_best_fonts()
+// `wgpu_glyph` can only draw glyph outlines, so fonts that have outlines for their emoji are
+// preferred over bitmap-only color emoji fonts
+const EMOJI_FAMILIES: &[&str] = &[
+    "Noto Emoji",
+    "Segoe UI Emoji",
+    "Twemoji Mozilla",
+    "Noto Color Emoji",
+    "Apple Color Emoji",
+];
+
+/// Loads the regular and monospace fonts followed by the emoji fonts. Fonts that fail to load get
+/// skipped and replaced by the system's default fonts (or whichever fonts did load), so this only
+/// fails when there isn't a single usable font
+pub fn get_fonts(font_opts: &FontOptions) -> anyhow::Result<Vec<FontArc>> {
+    let inlyne_cache = if font_opts.regular_font.is_some() || font_opts.monospace_font.is_some() {
+        dirs::cache_dir().map(|cache_dir| cache_dir.join("inlyne"))
     } else {
-        match dirs::cache_dir() {
-            Some(cache_dir) => {
-                let inlyne_cache = cache_dir.join("inlyne");
-                if !inlyne_cache.exists() {
-                    let _ = fs::create_dir_all(&inlyne_cache);
-                }
+        None
+    };
+    if let Some(inlyne_cache) = &inlyne_cache {
+        if !inlyne_cache.exists() {
+            let _ = fs::create_dir_all(inlyne_cache);
+        }
+    }
 
-                let reg_cache_path = inlyne_cache.join("font_regular.toml");
-                let mut handles = load_maybe_cached_fonts_by_name(
-                    regular_name.as_deref(),
-                    FamilyName::SansSerif,
-                    &reg_cache_path,
-                )?;
-
-                let mono_cache_path = inlyne_cache.join("font_mono.toml");
-                let mono_handles = load_maybe_cached_fonts_by_name(
-                    monospace_name.as_deref(),
-                    FamilyName::Monospace,
-                    &mono_cache_path,
-                )?;
-
-                handles.extend(mono_handles.into_iter());
-                Ok(handles)
-            }
-            None => load_best_fonts(),
+    let mut skipped = Vec::new();
+    let mut text_fonts = load_family(
+        font_opts.regular_font.as_deref(),
+        FamilyName::SansSerif,
+        inlyne_cache
+            .as_ref()
+            .map(|cache| cache.join("font_regular.toml"))
+            .as_deref(),
+        &mut skipped,
+    );
+    text_fonts.extend(load_family(
+        font_opts.monospace_font.as_deref(),
+        FamilyName::Monospace,
+        inlyne_cache
+            .as_ref()
+            .map(|cache| cache.join("font_mono.toml"))
+            .as_deref(),
+        &mut skipped,
+    ));
+    if !skipped.is_empty() {
+        log::warn!(
+            "Skipped fonts that failed to load in favor of the default ones: {}",
+            skipped.join(", ")
+        );
+    }
+
+    let mut fonts = fill_missing(text_fonts).context("No usable fonts could be loaded")?;
+    let emoji_fonts = load_emoji_fonts(&fonts);
+    fonts.extend(emoji_fonts.into_iter());
+    Ok(fonts)
+}
+
+// Replaces fonts that failed to load with another variant of the same family (in groups of 4)
+// falling back to any font that did load. `None` when nothing loaded at all
+fn fill_missing<T: Clone>(fonts: Vec<Option<T>>) -> Option<Vec<T>> {
+    let any = fonts.iter().flatten().next()?.clone();
+    let filled = fonts
+        .chunks(4)
+        .flat_map(|family| {
+            let sibling = family.iter().flatten().next().unwrap_or(&any).clone();
+            family
+                .iter()
+                .map(move |font| font.clone().unwrap_or_else(|| sibling.clone()))
+        })
+        .collect();
+    Some(filled)
+}
+
+// Emoji fonts only come in a single variant so it gets used for regular, italic, bold and
+// bold-italic alike. Falls back to the regular text fonts when no emoji font is installed
+fn load_emoji_fonts(fallback: &[FontArc]) -> Vec<FontArc> {
+    let source = SystemSource::new();
+    let families: Vec<_> = EMOJI_FAMILIES
+        .iter()
+        .map(|&name| FamilyName::Title(name.to_owned()))
+        .collect();
+    match select_best_font(&source, &families, &Properties::new()).and_then(load_font) {
+        Ok(font) => vec![font; 4],
+        Err(err) => {
+            log::warn!(
+                "Failed loading an emoji font. Emoji may not render. Error: {}",
+                err
+            );
+            fallback[..4].to_vec()
         }
     }
 }
 
-fn load_maybe_cached_fonts_by_name(
+// Loads the 4 variants of the font named `name` (cached at `cache_path`). Variants that can't be
+// loaded get swapped for the same variant of `fallback_family`. Every font that fails gets added
+// to `skipped` and left as `None` when even the fallback fails
+fn load_family(
     name: Option<&str>,
     fallback_family: FamilyName,
-    path: &Path,
-) -> anyhow::Result<Vec<FontArc>> {
-    let fonts = match name {
-        Some(name) => match load_cached_fonts_by_name(name, path) {
-            Some(fonts) => fonts,
-            None => {
-                let handles = load_best_handles_by_name(FamilyName::Title(name.to_owned()))?;
-                if let Ok(font_cache) = FontCache::new(name, &handles) {
-                    fs::write(path, toml::to_string(&font_cache)?)?;
-                }
-                handles
-                    .into_iter()
-                    .map(|info| load_font(info.handle))
-                    .collect::<anyhow::Result<Vec<_>>>()?
-            }
-        },
-        None => load_best_fonts_by_name(fallback_family)?,
+    cache_path: Option<&Path>,
+    skipped: &mut Vec<String>,
+) -> Vec<Option<FontArc>> {
+    if let (Some(name), Some(path)) = (name, cache_path) {
+        if let Some(fonts) = load_cached_fonts_by_name(name, path) {
+            return fonts.into_iter().map(Some).collect();
+        }
+    }
+
+    let source = SystemSource::new();
+    // A family that isn't installed at all gets a single warning instead of one skipped font for
+    // every variant
+    let name = name.filter(|name| {
+        let exists = family_exists(&source, name);
+        if !exists {
+            log::warn!(
+                "Couldn't find the {} font. Falling back to the {} font",
+                name,
+                FontInfo::family_name_to_str(&fallback_family)
+            );
+        }
+        exists
+    });
+    let mut load = |family_name: FamilyName, font_type: FontType| match load_variant(
+        &source,
+        family_name.clone(),
+        font_type,
+    ) {
+        Ok(loaded) => Some(loaded),
+        Err(err) => {
+            skipped.push(format!(
+                "{}-{} ({})",
+                FontInfo::family_name_to_str(&family_name),
+                font_type.as_str(),
+                err
+            ));
+            None
+        }
     };
-    Ok(fonts)
+    let mut fonts = Vec::with_capacity(FontType::ALL.len());
+    let mut infos = Vec::with_capacity(FontType::ALL.len());
+    for font_type in FontType::ALL {
+        let named = name.and_then(|name| load(FamilyName::Title(name.to_owned()), font_type));
+        let font = match named {
+            Some((font, info)) => {
+                infos.push(info);
+                Some(font)
+            }
+            None => load(fallback_family.clone(), font_type).map(|(font, _)| font),
+        };
+        fonts.push(font);
+    }
+
+    // Only complete families get cached
+    if let (Some(name), Some(path)) = (name, cache_path) {
+        if infos.len() == FontType::ALL.len() {
+            if let Err(err) = cache_fonts(name, &infos, path) {
+                log::warn!("Failed caching the {} font: {}", name, err);
+            }
+        }
+    }
+    fonts
 }
 
-fn load_best_fonts() -> anyhow::Result<Vec<FontArc>> {
-    let mut fonts = load_best_fonts_by_name(FamilyName::SansSerif)?;
-    fonts.extend(load_best_fonts_by_name(FamilyName::Monospace)?.into_iter());
-    Ok(fonts)
+fn family_exists(source: &SystemSource, name: &str) -> bool {
+    source
+        .select_family_by_name(name)
+        .map_or(false, |family| !family.fonts().is_empty())
 }
 
-fn load_best_handles_by_name(family_name: FamilyName) -> anyhow::Result<Vec<FontInfo>> {
-    let source = SystemSource::new();
-    let name = &[family_name.clone()];
-    let base = FontInfo {
-        handle: select_best_font(&source, name, Properties::new().style(Style::Normal))?,
-        family_name: family_name.clone(),
-        font_type: FontType::Regular,
-    };
-    let italic = FontInfo {
-        handle: select_best_font(&source, name, Properties::new().style(Style::Italic))?,
-        family_name: family_name.clone(),
-        font_type: FontType::Italic,
-    };
-    let bold = FontInfo {
-        handle: select_best_font(&source, name, Properties::new().weight(Weight::BOLD))?,
-        family_name: family_name.clone(),
-        font_type: FontType::Bold,
-    };
-    let bold_italic = FontInfo {
-        handle: select_best_font(
-            &source,
-            name,
-            Properties::new().weight(Weight::BOLD).style(Style::Italic),
-        )?,
+fn cache_fonts(name: &str, font_infos: &[FontInfo], path: &Path) -> anyhow::Result<()> {
+    let font_cache = FontCache::new(name, font_infos)?;
+    fs::write(path, toml::to_string(&font_cache)?)?;
+    Ok(())
+}
+
+fn load_variant(
+    source: &SystemSource,
+    family_name: FamilyName,
+    font_type: FontType,
+) -> anyhow::Result<(FontArc, FontInfo)> {
+    let props = font_type.properties();
+    let handle = select_best_font(source, &[family_name.clone()], &props)?;
+    // The closest match of a family without a bold or italic face is its regular one. Those get
+    // swapped for the fallback family's face instead of drawing bold or italic text upright at the
+    // regular weight. Faces can't be synthesized since glyphs get drawn as is from the glyph cache
+    if let FamilyName::Title(name) = &family_name {
+        let found = handle.load()?.properties();
+        anyhow::ensure!(
+            is_bold_enough(props.weight, found.weight),
+            "{} has no bold face",
+            name
+        );
+        anyhow::ensure!(
+            is_slanted_enough(props.style, found.style),
+            "{} has no italic face",
+            name
+        );
+    }
+    let font = load_font(handle.clone())?;
+    let info = FontInfo {
+        handle,
         family_name,
-        font_type: FontType::BoldItalic,
+        font_type,
     };
+    Ok((font, info))
+}
 
-    Ok(vec![base, italic, bold, bold_italic])
+fn is_bold_enough(requested: Weight, found: Weight) -> bool {
+    requested < Weight::SEMIBOLD || found >= Weight::SEMIBOLD
 }
 
-fn load_best_fonts_by_name(family_name: FamilyName) -> anyhow::Result<Vec<FontArc>> {
-    let handles = load_best_handles_by_name(family_name)?;
-    handles
-        .into_iter()
-        .map(|info| load_font(info.handle))
-        .collect::<anyhow::Result<Vec<_>>>()
+// Oblique faces pass for italic ones
+fn is_slanted_enough(requested: Style, found: Style) -> bool {
+    requested == Style::Normal || found != Style::Normal
 }
 
 fn load_cached_fonts_by_name(desired_name: &str, path: &Path) -> Option<Vec<FontArc>> {
@@ -291,7 +433,7 @@ fn load_font(handle: Handle) -> anyhow::Result<FontArc> {
         Handle::Path { path, font_index } => {
             let file = fs::File::open(path)?;
             // Font files can be big. Memmap and leak the font file to avoid keeping it in memory.
-            // Because we load 8 font files this will leak exactly 8 * 16 = 128 bytes
+            // Because we load at most 9 font files this will leak at most 9 * 16 = 144 bytes
             // SAFETY: This is safe as long as nothing (either in or outside of this program)
             // modifies the file while it's memmapped. Unfortunately there is nothing we can do to
             // guarantee this won't happen, but memmapping font files is a common practice
@@ -307,3 +449,34 @@ fn load_font(handle: Handle) -> anyhow::Result<FontArc> {
         )?)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use font_kit::properties::{Style, Weight};
+
+    use super::{fill_missing, is_bold_enough, is_slanted_enough};
+
+    #[test]
+    fn missing_fonts_get_filled_in() {
+        // The bold monospace font is missing and so is the whole regular family
+        let fonts = vec![None, None, None, None, Some(5), Some(6), None, Some(8)];
+        assert_eq!(fill_missing(fonts), Some(vec![5, 5, 5, 5, 5, 6, 5, 8]));
+        assert_eq!(fill_missing::<u8>(vec![None; 8]), None);
+    }
+
+    #[test]
+    fn regular_faces_dont_pass_for_bold_ones() {
+        assert!(is_bold_enough(Weight::NORMAL, Weight::NORMAL));
+        assert!(is_bold_enough(Weight::NORMAL, Weight::BOLD));
+        assert!(is_bold_enough(Weight::BOLD, Weight::SEMIBOLD));
+        assert!(!is_bold_enough(Weight::BOLD, Weight::NORMAL));
+        assert!(!is_bold_enough(Weight::BOLD, Weight::MEDIUM));
+    }
+
+    #[test]
+    fn upright_faces_dont_pass_for_italic_ones() {
+        assert!(is_slanted_enough(Style::Normal, Style::Normal));
+        assert!(is_slanted_enough(Style::Italic, Style::Oblique));
+        assert!(!is_slanted_enough(Style::Italic, Style::Normal));
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashSet;
+
+use wgpu_glyph::SectionGlyph;
+
+/// Default for the largest (in texels a side) that the glyph cache texture can get before it gets
+/// dropped and started over
+pub const DEFAULT_GLYPH_CACHE_MAX_SIZE: u32 = 4096;
+
+/// How far (in pixels) a glyph's position can be from a cached one's for the cached rasterization
+/// to be reused
+pub const GLYPH_CACHE_POSITION_TOLERANCE: f32 = 0.5;
+
+// Room that the draw cache leaves around every glyph along with what rounding out to whole texels
+// can add
+const GLYPH_PADDING: f32 = 4.;
+
+/// Keeps the glyph cache texture from growing without bound
+///
+/// The draw cache evicts the glyphs that were used least recently once it fills up, but when the
+/// glyphs of a single frame don't fit `wgpu_glyph` doubles the texture and never shrinks it again.
+/// Zooming through many sizes can leave it huge. `wgpu_glyph` doesn't say how big the texture got,
+/// so it's estimated from the glyphs that get queued for each frame. Once the estimate passes the
+/// maximum size, the glyph brush gets built again which drops every cached glyph along with the
+/// texture. A single frame that needs more than the maximum on its own is left to grow the texture
+/// instead since starting over would have to rasterize all of its glyphs again every frame
+#[derive(Debug)]
+pub struct GlyphCacheBound {
+    initial_size: u32,
+    max_size: u32,
+    scale_tolerance: f32,
+    // Estimated size of the texture
+    size: u32,
+    // Glyphs queued for the frame being drawn along with the texels they take up
+    frame_glyphs: HashSet<GlyphKey>,
+    frame_area: f32,
+    largest_glyph: f32,
+    // Whether a frame that doesn't fit within the maximum on its own was logged already
+    logged_oversized_frame: bool,
+}
+
+// A glyph as far as the draw cache can tell glyphs apart. This mirrors the private rounding of
+// `glyph_brush_draw_cache` 0.1.5 (through `wgpu_glyph` 0.17), so it has to be checked again
+// whenever that gets bumped. Drifting from it only makes the size estimate less accurate
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: usize,
+    glyph: u16,
+    scale: (u32, u32),
+    offset: (u16, u16),
+}
+
+impl GlyphKey {
+    // Rounds the scale and subpixel offset the same way that the draw cache does
+    fn new(glyph: &SectionGlyph, scale_tolerance: f32) -> Self {
+        let scale = glyph.glyph.scale;
+        let offset = |position: f32| {
+            let mut offset = position.fract();
+            if offset > 0.5 {
+                offset -= 1.;
+            } else if offset < -0.5 {
+                offset += 1.;
+            }
+            ((offset + 0.5) / GLYPH_CACHE_POSITION_TOLERANCE + 0.5) as u16
+        };
+        Self {
+            font: glyph.font_id.0,
+            glyph: glyph.glyph.id.0,
+            scale: (
+                (scale.x / scale_tolerance + 0.5) as u32,
+                (scale.y / scale_tolerance + 0.5) as u32,
+            ),
+            offset: (
+                offset(glyph.glyph.position.x),
+                offset(glyph.glyph.position.y),
+            ),
+        }
+    }
+}
+
+impl GlyphCacheBound {
+    pub fn new(initial_size: u32, max_size: u32, scale_tolerance: f32) -> Self {
+        let initial_size = initial_size.max(1);
+        Self {
+            initial_size,
+            max_size: max_size.max(initial_size),
+            scale_tolerance,
+            size: initial_size,
+            frame_glyphs: HashSet::new(),
+            frame_area: 0.,
+            largest_glyph: 0.,
+            logged_oversized_frame: false,
+        }
+    }
+
+    /// Size that the texture starts out at
+    pub fn initial_size(&self) -> u32 {
+        self.initial_size
+    }
+
+    pub fn max_size(&self) -> u32 {
+        self.max_size
+    }
+
+    /// Can't be any smaller than the initial size
+    pub fn set_max_size(&mut self, max_size: u32) {
+        self.max_size = max_size.max(self.initial_size);
+    }
+
+    pub fn scale_tolerance(&self) -> f32 {
+        self.scale_tolerance
+    }
+
+    /// How big the texture is estimated to have gotten
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Counts the glyphs of a section queued for the frame
+    pub fn queue<'a>(&mut self, glyphs: impl IntoIterator<Item = &'a SectionGlyph>) {
+        for glyph in glyphs {
+            let scale = glyph.glyph.scale;
+            if self
+                .frame_glyphs
+                .insert(GlyphKey::new(glyph, self.scale_tolerance))
+            {
+                // Glyphs fit within a square as wide as they are tall. Overestimating makes up for
+                // the room that's lost packing them into rows
+                let side = scale.x.max(scale.y) + GLYPH_PADDING;
+                self.frame_area += side * side;
+                self.largest_glyph = self.largest_glyph.max(side);
+            }
+        }
+    }
+
+    /// Ends the frame that the queued glyphs got drawn in. Returns whether the texture outgrew the
+    /// maximum size through earlier frames, in which case the glyph brush has to be built again
+    pub fn finish_frame(&mut self) -> bool {
+        // Doubles until the frame fits like `wgpu_glyph` does. Rows of glyphs can leave a glyph's
+        // worth of room unused along the right and bottom edges
+        let mut frame_size = self.initial_size;
+        while self.largest_glyph >= frame_size as f32
+            || self.frame_area > (frame_size as f32 - self.largest_glyph).powi(2)
+        {
+            frame_size *= 2;
+        }
+        self.size = self.size.max(frame_size);
+        self.frame_glyphs.clear();
+        self.frame_area = 0.;
+        self.largest_glyph = 0.;
+        if frame_size > self.max_size {
+            if !self.logged_oversized_frame {
+                log::warn!(
+                    "A single frame needs a glyph cache of about {0}x{0} which is past the \
+                     maximum of {1}x{1}. Letting it grow instead of starting over every frame",
+                    frame_size,
+                    self.max_size
+                );
+                self.logged_oversized_frame = true;
+            }
+            false
+        } else if self.size > self.max_size {
+            self.size = self.initial_size;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glyph_brush::{BrushError, GlyphBrush, GlyphBrushBuilder, GlyphCruncher, Section, Text};
+
+    use super::{GlyphCacheBound, GLYPH_CACHE_POSITION_TOLERANCE};
+    use crate::renderer::DEFAULT_GLYPH_CACHE_SCALE_TOLERANCE;
+    use crate::{fonts, opts::FontOptions};
+
+    const INITIAL_SIZE: u32 = 256;
+    const MAX_SIZE: u32 = 1024;
+
+    fn new_glyph_brush(fonts: &[glyph_brush::ab_glyph::FontArc]) -> GlyphBrush<()> {
+        GlyphBrushBuilder::using_fonts(fonts.to_vec())
+            .initial_cache_size((INITIAL_SIZE, INITIAL_SIZE))
+            .draw_cache_position_tolerance(GLYPH_CACHE_POSITION_TOLERANCE)
+            .draw_cache_scale_tolerance(DEFAULT_GLYPH_CACHE_SCALE_TOLERANCE)
+            .build()
+    }
+
+    // Queues a frame of `text` at `scale` and grows the cache texture when the frame doesn't fit
+    // the same way that `wgpu_glyph` does
+    fn draw_frame(
+        glyph_brush: &mut GlyphBrush<()>,
+        bound: Option<&mut GlyphCacheBound>,
+        text: &str,
+        scale: f32,
+    ) {
+        let section = Section::default()
+            .with_bounds((1_000., f32::INFINITY))
+            .add_text(Text::new(text).with_scale(scale));
+        if let Some(bound) = bound {
+            bound.queue(glyph_brush.glyphs(&section));
+        }
+        glyph_brush.queue(section);
+        loop {
+            match glyph_brush.process_queued(|_, _| {}, |_| ()) {
+                Ok(_) => break,
+                Err(BrushError::TextureTooSmall { suggested }) => {
+                    glyph_brush.resize_texture(suggested.0, suggested.1)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn zooming_keeps_the_glyph_cache_bounded() {
+        // Rasterizing glyphs needs a system font
+        let fonts = match fonts::get_fonts(&FontOptions::default()) {
+            Ok(fonts) => fonts,
+            Err(_) => return,
+        };
+        let text = "The quick brown fox jumps over the lazy dog 0123456789 \
+            THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG";
+        // Zooms all the way in and back out again
+        let zooms = || {
+            (0..=150)
+                .chain((0..=150).rev())
+                .map(|step| 1. + step as f32 / 10.)
+        };
+
+        // Left alone, the texture grows past the maximum and stays that big
+        let mut unbounded = new_glyph_brush(&fonts);
+        for zoom in zooms() {
+            draw_frame(&mut unbounded, None, text, 16. * zoom);
+        }
+        let size = unbounded.texture_dimensions();
+        assert!(size.0 > MAX_SIZE || size.1 > MAX_SIZE, "{size:?}");
+
+        let mut bound =
+            GlyphCacheBound::new(INITIAL_SIZE, MAX_SIZE, DEFAULT_GLYPH_CACHE_SCALE_TOLERANCE);
+        let mut glyph_brush = new_glyph_brush(&fonts);
+        let mut rebuilds = 0;
+        for zoom in zooms() {
+            draw_frame(&mut glyph_brush, Some(&mut bound), text, 16. * zoom);
+            let size = glyph_brush.texture_dimensions();
+            let outgrown = bound.finish_frame();
+            let estimated = bound.size();
+            // The estimate never falls behind the texture that it's bounding
+            assert!(
+                outgrown || (size.0 <= estimated && size.1 <= estimated),
+                "Texture grew to {size:?} while the estimate was {estimated}",
+            );
+            if outgrown {
+                glyph_brush = new_glyph_brush(&fonts);
+                rebuilds += 1;
+            }
+        }
+        // Frames too big for the maximum get to grow the texture, but only until zooming back out
+        assert!(rebuilds > 0);
+        let size = glyph_brush.texture_dimensions();
+        assert!(size.0 <= MAX_SIZE && size.1 <= MAX_SIZE, "{size:?}");
+    }
+
+    #[test]
+    fn outgrowing_the_maximum_starts_over() {
+        use glyph_brush::ab_glyph::{point, Glyph, GlyphId};
+        use glyph_brush::{FontId, SectionGlyph};
+
+        let glyphs = |count: u16, scale: f32| -> Vec<SectionGlyph> {
+            (0..count)
+                .map(|id| SectionGlyph {
+                    section_index: 0,
+                    byte_index: 0,
+                    glyph: Glyph {
+                        id: GlyphId(id),
+                        scale: scale.into(),
+                        position: point(0., 0.),
+                    },
+                    font_id: FontId(0),
+                })
+                .collect()
+        };
+        let mut bound = GlyphCacheBound::new(256, 1024, 0.5);
+
+        // 49 glyphs of 28 texels with padding fill the texture but for the edges
+        bound.queue(&glyphs(49, 28.));
+        assert!(!bound.finish_frame());
+        assert_eq!(bound.size(), 256);
+        // The same glyphs twice in a frame only take up room once
+        bound.queue(&glyphs(50, 28.));
+        bound.queue(&glyphs(50, 28.));
+        assert!(!bound.finish_frame());
+        assert_eq!(bound.size(), 512);
+        // Nor does a glyph that's too big for the texture
+        bound.queue(&glyphs(1, 400.));
+        assert!(!bound.finish_frame());
+        assert_eq!(bound.size(), 1024);
+        // Starting over wouldn't help a frame that needs more than the maximum by itself
+        bound.queue(&glyphs(1, 1_200.));
+        assert!(!bound.finish_frame());
+        assert_eq!(bound.size(), 4096);
+        bound.queue(&glyphs(1, 1_200.));
+        assert!(!bound.finish_frame());
+        // The texture it left behind gets dropped once a frame fits again
+        bound.queue(&glyphs(1, 28.));
+        assert!(bound.finish_frame());
+        assert_eq!(bound.size(), 256);
+    }
+}
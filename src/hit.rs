@@ -0,0 +1,282 @@
+use wgpu_glyph::GlyphCruncher;
+
+use crate::positioner::{Positioned, DEFAULT_MARGIN};
+use crate::table::{self, Table};
+use crate::utils::{Point, Rect, Size};
+use crate::Element;
+
+/// One step down the element tree on the way to the element that got hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HitStep {
+    /// Index into the elements of the document, a row or a section
+    Index(usize),
+    /// A cell of a table. Rows count from the header row at 0
+    Cell { row: usize, column: usize },
+    /// The summary of a section
+    Summary,
+}
+
+/// The element under a point
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// Steps leading from the document down to the element
+    pub path: Vec<HitStep>,
+    /// Index of the character under the point (counted across all of its texts) when the element
+    /// is a text box
+    pub char_offset: Option<usize>,
+    /// Whether the point is on the checkbox of a task rather than on its text
+    pub checkbox: bool,
+    /// Bounds of the element
+    pub bounds: Rect,
+}
+
+impl Hit {
+    fn new(step: HitStep, bounds: Rect) -> Self {
+        Self {
+            path: vec![step],
+            char_offset: None,
+            checkbox: false,
+            bounds,
+        }
+    }
+
+    // Narrows the hit down to an element within the one that was hit
+    fn descend(&mut self, inner: Option<Hit>) {
+        if let Some(inner) = inner {
+            self.path.extend(inner.path);
+            self.char_offset = inner.char_offset;
+            self.checkbox = inner.checkbox;
+            self.bounds = inner.bounds;
+        }
+    }
+}
+
+// Checkboxes are drawn to the left of their text box's bounds, so the checkbox of a task in a row
+// or section can be outside of the row's or section's bounds too
+fn on_checkbox(element: &Positioned<Element>, loc: Point, zoom: f32) -> bool {
+    match (&element.inner, &element.bounds) {
+        (Element::TextBox(text_box), Some(bounds)) => text_box
+            .checkbox_bounds(bounds, zoom)
+            .map_or(false, |checkbox| checkbox.contains(loc)),
+        (Element::Row(row), _) => row
+            .elements
+            .iter()
+            .any(|element| on_checkbox(element, loc, zoom)),
+        (Element::Section(section), _) => {
+            !*section.hidden.borrow()
+                && section
+                    .elements
+                    .iter()
+                    .any(|element| on_checkbox(element, loc, zoom))
+        }
+        _ => false,
+    }
+}
+
+/// Finds the innermost element at `loc` along with where the point is within it. Everything is
+/// in document coordinates. `text_zoom` is the `zoom` that text gets laid out with, so including
+/// the text scale
+pub fn hit_test<T: GlyphCruncher>(
+    elements: &[Positioned<Element>],
+    glyph_brush: &mut T,
+    loc: Point,
+    screen_size: Size,
+    zoom: f32,
+    text_zoom: f32,
+) -> Option<Hit> {
+    let (index, element, bounds) = elements.iter().enumerate().find_map(|(index, element)| {
+        let bounds = element.bounds.as_ref()?;
+        let hit = (bounds.contains(loc) || on_checkbox(element, loc, zoom))
+            && !matches!(element.inner, Element::Spacer(_));
+        hit.then_some((index, element, bounds))
+    })?;
+    let mut hit = Hit::new(HitStep::Index(index), bounds.clone());
+    let available = (screen_size.0 - bounds.pos.0 - DEFAULT_MARGIN, screen_size.1);
+    match &element.inner {
+        Element::TextBox(_) if on_checkbox(element, loc, zoom) => hit.checkbox = true,
+        Element::TextBox(text_box) => {
+            let text_bounds = (text_box.wrap_width(available.0, zoom), available.1);
+            hit.char_offset =
+                text_box.char_offset_at(glyph_brush, loc, bounds.pos, text_bounds, text_zoom);
+        }
+        Element::Table(table) => {
+            hit.descend(table_hit(
+                table,
+                glyph_brush,
+                loc,
+                bounds.pos,
+                available,
+                text_zoom,
+            ));
+        }
+        Element::Row(row) => {
+            hit.descend(hit_test(
+                &row.elements,
+                glyph_brush,
+                loc,
+                screen_size,
+                zoom,
+                text_zoom,
+            ));
+        }
+        Element::Section(section) => {
+            let summary = section
+                .summary
+                .as_ref()
+                .as_ref()
+                .and_then(|summary| summary.bounds.as_ref())
+                .filter(|bounds| bounds.contains(loc));
+            if let Some(summary) = summary {
+                hit.descend(Some(Hit::new(HitStep::Summary, summary.clone())));
+            } else if !*section.hidden.borrow() {
+                hit.descend(hit_test(
+                    &section.elements,
+                    glyph_brush,
+                    loc,
+                    screen_size,
+                    zoom,
+                    text_zoom,
+                ));
+            }
+        }
+        Element::Image(_) | Element::Spacer(_) => {}
+    }
+    Some(hit)
+}
+
+/// The element that a hit's path leads to
+pub fn resolve<'a>(elements: &'a [Positioned<Element>], path: &[HitStep]) -> Option<&'a Element> {
+    match path.split_first()? {
+        (HitStep::Index(index), rest) => resolve_within(&elements.get(*index)?.inner, rest),
+        _ => None,
+    }
+}
+
+fn resolve_within<'a>(element: &'a Element, path: &[HitStep]) -> Option<&'a Element> {
+    let (step, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Some(element),
+    };
+    match (element, step) {
+        (Element::Row(row), _) => resolve(&row.elements, path),
+        (Element::Section(section), HitStep::Summary) => {
+            let summary = section.summary.as_ref().as_ref()?;
+            resolve_within(&summary.inner, rest)
+        }
+        (Element::Section(section), _) => resolve(&section.elements, path),
+        (Element::Table(table), HitStep::Cell { row, column }) => {
+            let cells = match row.checked_sub(1) {
+                Some(row) => table.rows.get(row)?,
+                None => &table.headers,
+            };
+            resolve_within(&cells.get(*column)?.inner, rest)
+        }
+        _ => None,
+    }
+}
+
+fn table_hit<T: GlyphCruncher>(
+    table: &Table,
+    glyph_brush: &mut T,
+    loc: Point,
+    pos: Point,
+    bounds: Size,
+    zoom: f32,
+) -> Option<Hit> {
+    let (row, column, cell) = table.cell_at(loc)?;
+    let cell_bounds = cell.bounds.as_ref()?;
+    let mut hit = Hit::new(HitStep::Cell { row, column }, cell_bounds.clone());
+    match &cell.inner {
+        Element::TextBox(text_box) => {
+            let text_bounds =
+                table.cell_text_bounds(glyph_brush, text_box, cell_bounds, pos, bounds, zoom);
+            hit.char_offset =
+                text_box.char_offset_at(glyph_brush, loc, cell_bounds.pos, text_bounds, zoom);
+        }
+        Element::Table(inner) => {
+            let inner_bounds = table::cell_inner_bounds(cell_bounds, pos, bounds);
+            hit.descend(table_hit(
+                inner,
+                glyph_brush,
+                loc,
+                cell_bounds.pos,
+                inner_bounds,
+                zoom,
+            ));
+        }
+        _ => {}
+    }
+    Some(hit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hit_test, resolve, HitStep};
+    use crate::positioner::{Positioned, Row, Section, Spacer};
+    use crate::table::Table;
+    use crate::text::{tests::NoGlyphs, Checkbox, TextBox};
+    use crate::utils::Rect;
+    use crate::Element;
+
+    fn placed(element: impl Into<Element>, bounds: Rect) -> Positioned<Element> {
+        let mut positioned = Positioned::new(element.into());
+        positioned.bounds = Some(bounds);
+        positioned
+    }
+
+    #[test]
+    fn paths_lead_into_rows_and_tables() {
+        let text = || TextBox::new(Vec::new(), 1.);
+        let mut table = Table::new().with_header(text()).with_row([text(), text()]);
+        table.rows[0][1].bounds = Some(Rect::new((100., 140.), (50., 20.)));
+        let row = Row::new(vec![placed(table, Rect::new((0., 100.), (200., 80.)))], 1.);
+        let elements = vec![
+            placed(text(), Rect::new((0., 0.), (200., 50.))),
+            placed(Spacer::new(50., false), Rect::new((0., 50.), (200., 50.))),
+            placed(row, Rect::new((0., 100.), (200., 80.))),
+        ];
+        let hit_at = |loc| hit_test(&elements, &mut NoGlyphs, loc, (800., 600.), 1., 1.);
+
+        let hit = hit_at((10., 10.)).unwrap();
+        assert_eq!(hit.path, [HitStep::Index(0)]);
+        // Spacers don't count as being hit
+        assert_eq!(hit_at((10., 60.)), None);
+        // The table's cells are positioned separately from the table itself
+        let hit = hit_at((120., 150.)).unwrap();
+        assert_eq!(
+            hit.path,
+            [
+                HitStep::Index(2),
+                HitStep::Index(0),
+                HitStep::Cell { row: 1, column: 1 }
+            ]
+        );
+        assert_eq!(hit.bounds, Rect::new((100., 140.), (50., 20.)));
+        assert_eq!(hit.char_offset, None);
+        assert!(matches!(
+            resolve(&elements, &hit.path),
+            Some(Element::TextBox(_))
+        ));
+        assert!(resolve(&elements, &[HitStep::Index(5)]).is_none());
+    }
+
+    #[test]
+    fn checkboxes_hang_out_past_their_section() {
+        let mut task = TextBox::new(Vec::new(), 1.);
+        task.set_checkbox(Some(Checkbox::Unchecked));
+        let section = Section::new(
+            None,
+            vec![placed(task, Rect::new((60., 0.), (140., 40.)))],
+            1.,
+        );
+        let elements = vec![placed(section, Rect::new((60., 0.), (140., 40.)))];
+        let hit_at = |loc| hit_test(&elements, &mut NoGlyphs, loc, (800., 600.), 1., 1.);
+
+        // The checkbox sits in the margin to the left of the task's text
+        let hit = hit_at((40., 20.)).unwrap();
+        assert_eq!(hit.path, [HitStep::Index(0), HitStep::Index(0)]);
+        assert!(hit.checkbox);
+        assert!(!hit_at((100., 20.)).unwrap().checkbox);
+        assert_eq!(hit_at((20., 20.)), None);
+    }
+}
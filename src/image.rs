@@ -1,39 +1,361 @@
-use crate::positioner::DEFAULT_MARGIN;
+use crate::positioner::{Float, DEFAULT_MARGIN};
+use crate::text::DEFAULT_TEXT_SIZE;
 use crate::utils::{Align, Point, Size};
 use crate::InlyneEvent;
+use anyhow::Context;
 use bytemuck::{Pod, Zeroable};
-use image::{ImageBuffer, RgbaImage};
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageBuffer, ImageEncoder, RgbaImage};
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
 use wgpu::util::DeviceExt;
 use wgpu::{Device, TextureFormat};
 use winit::event_loop::EventLoopProxy;
 
 use std::borrow::Cow;
 
-#[derive(Debug)]
-pub enum ImageSize {
-    PxWidth(u32),
-    PxHeight(u32),
+/// A length from an HTML `width` or `height` attribute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    /// Logical pixels. Bare numbers are pixels too
+    Px(f32),
+    /// Percent of the width available to the document
+    Percent(f32),
+    /// Multiples of the default text size
+    Em(f32),
+}
+
+impl FromStr for Length {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, unit): (&str, fn(f32) -> Self) = if let Some(n) = s.strip_suffix('%') {
+            (n, Self::Percent)
+        } else if let Some(n) = s.strip_suffix("rem").or_else(|| s.strip_suffix("em")) {
+            (n, Self::Em)
+        } else {
+            (s.strip_suffix("px").unwrap_or(s), Self::Px)
+        };
+        match number.trim().parse::<f32>() {
+            Ok(number) if number.is_finite() && number >= 0. => Ok(unit(number)),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Length {
+    /// Resolves to physical pixels. Percentages need something to be a percent of, so they only
+    /// resolve when `percent_of` is set
+    pub fn resolve(self, percent_of: Option<f32>, em: f32, scale: f32) -> Option<f32> {
+        match self {
+            Self::Px(px) => Some(px * scale),
+            Self::Percent(percent) => percent_of.map(|whole| whole * percent / 100.),
+            Self::Em(ems) => Some(ems * em * scale),
+        }
+    }
+}
+
+/// The `width` and `height` an image asked for. Leaving one out keeps the image's aspect ratio
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImageSize {
+    pub width: Option<Length>,
+    pub height: Option<Length>,
+}
+
+/// Encoding of the bytes that an image was loaded from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Raster(image::ImageFormat),
+    Svg,
+}
+
+impl ImageFormat {
+    /// Extension that files of the format get saved with
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Raster(format) => format.extensions_str().first().copied().unwrap_or("img"),
+            Self::Svg => "svg",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ImageData {
     rgba_image: RgbaImage,
     scale: bool,
+    // Encoding of the bytes the image was decoded from. `None` when it wasn't recognized
+    format: Option<ImageFormat>,
+}
+
+impl ImageData {
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png).write_image(
+            self.rgba_image.as_raw(),
+            self.rgba_image.width(),
+            self.rgba_image.height(),
+            ColorType::Rgba8,
+        )?;
+        Ok(png)
+    }
+}
+
+/// An image that can be saved as the bytes it was loaded from. The bytes aren't kept around after
+/// decoding, so they get read or fetched again from where the image came from
+#[derive(Debug, Clone)]
+pub struct SavableImage {
+    // The image's `src` along with the document that it's relative to
+    source: Option<(String, PathBuf)>,
+    image: Arc<Mutex<Option<ImageData>>>,
 }
 
-#[derive(Debug, Default)]
+impl SavableImage {
+    // Encoding of the bytes that the image can be loaded from again
+    fn original_format(&self) -> Option<ImageFormat> {
+        self.source.as_ref()?;
+        self.image.lock().unwrap().as_ref()?.format
+    }
+
+    /// Format that the image gets saved in unless its bytes fail to load again
+    pub fn format(&self) -> ImageFormat {
+        self.original_format()
+            .unwrap_or(ImageFormat::Raster(image::ImageFormat::Png))
+    }
+
+    /// The bytes the image was loaded from along with their format. Falls back to encoding the
+    /// decoded image as a PNG when the original encoding wasn't recognized or the bytes can't be
+    /// loaded anymore. Fetching can take a while, so this is meant to be called off the event loop
+    pub fn encoded(&self) -> anyhow::Result<(Vec<u8>, ImageFormat)> {
+        if let (Some(format), Some((src, file_path))) = (self.original_format(), &self.source) {
+            match load_bytes(src, file_path) {
+                Some(bytes) => return Ok((bytes, format)),
+                None => log::warn!("Failed loading {} again. Saving it as a PNG instead", src),
+            }
+        }
+        let png = self
+            .image
+            .lock()
+            .unwrap()
+            .as_ref()
+            .context("Image hasn't loaded yet")?
+            .to_png()?;
+        Ok((png, ImageFormat::Raster(image::ImageFormat::Png)))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct Image {
     pub image: Arc<Mutex<Option<ImageData>>>,
+    // The `src` that the image gets loaded from along with the document that it's relative to
+    source: Option<(String, PathBuf)>,
     pub is_aligned: Option<Align>,
+    /// Set for images that text wraps around instead of going above and below them
+    pub float: Option<Float>,
     callback: Arc<Mutex<Option<EventLoopProxy<InlyneEvent>>>>,
     pub size: Option<ImageSize>,
     pub bind_group: Option<Arc<wgpu::BindGroup>>,
     pub is_link: Option<String>,
     pub hidpi_scale: f32,
+    decode_priority: Arc<Mutex<f32>>,
+}
+
+// Number of images that get loaded at the same time
+const DECODE_WORKERS: usize = 4;
+
+// An image waiting on the decoder along with everything needed to load it
+struct DecodeJob {
+    src: String,
+    file_path: PathBuf,
+    hidpi_scale: f32,
+    image: Arc<Mutex<Option<ImageData>>>,
+    callback: Arc<Mutex<Option<EventLoopProxy<InlyneEvent>>>>,
+    // Distance from the viewport. Lower gets decoded sooner
+    priority: Arc<Mutex<f32>>,
+}
+
+impl DecodeJob {
+    fn priority(&self) -> f32 {
+        *self.priority.lock().unwrap()
+    }
+
+    fn run(self) {
+        let Self {
+            src,
+            file_path,
+            hidpi_scale,
+            image: slot,
+            callback,
+            ..
+        } = self;
+        let image_data = match load_bytes(&src, &file_path) {
+            Some(image_data) => image_data,
+            None => return,
+        };
+
+        if let Ok(image) = image::load_from_memory(&image_data) {
+            *(slot.lock().unwrap()) = Some(ImageData {
+                rgba_image: image.into_rgba8(),
+                scale: true,
+                format: image::guess_format(&image_data)
+                    .ok()
+                    .map(ImageFormat::Raster),
+            });
+        } else {
+            let mut opt = usvg::Options::default();
+            opt.fontdb.load_system_fonts();
+            if let Ok(rtree) = usvg::Tree::from_data(&image_data, &opt.to_ref()) {
+                let pixmap_size = rtree.svg_node().size.to_screen_size();
+                let mut pixmap = tiny_skia::Pixmap::new(
+                    (pixmap_size.width() as f32 * hidpi_scale) as u32,
+                    (pixmap_size.height() as f32 * hidpi_scale) as u32,
+                )
+                .unwrap();
+                resvg::render(
+                    &rtree,
+                    usvg::FitTo::Zoom(hidpi_scale),
+                    tiny_skia::Transform::default(),
+                    pixmap.as_mut(),
+                )
+                .unwrap();
+                *(slot.lock().unwrap()) = Some(ImageData {
+                    rgba_image: ImageBuffer::from_raw(
+                        pixmap.width(),
+                        pixmap.height(),
+                        pixmap.data().into(),
+                    )
+                    .unwrap(),
+                    scale: false,
+                    format: Some(ImageFormat::Svg),
+                });
+            }
+        }
+        if let Ok(Some(callback)) = callback.try_lock().as_deref() {
+            callback
+                .send_event(InlyneEvent::LoadedImage(src, slot.clone()))
+                .unwrap();
+        }
+    }
+}
+
+// Reads the image at `src` relative to the document at `file_path` or fetches it when it isn't a
+// local file
+fn load_bytes(src: &str, file_path: &Path) -> Option<Vec<u8>> {
+    let mut src_path = PathBuf::from(src);
+    if src_path.is_relative() {
+        if let Some(parent_dir) = file_path.parent() {
+            src_path = parent_dir.join(src_path.strip_prefix("./").unwrap_or(&src_path));
+        }
+    }
+
+    if let Ok(mut img_file) = File::open(&src_path) {
+        let img_file_size = src_path.metadata().ok()?.len();
+        let mut img_buf = Vec::with_capacity(img_file_size as usize);
+        img_file.read_to_end(&mut img_buf).ok()?;
+        Some(img_buf)
+    } else {
+        ureq::get(src)
+            .call()
+            .and_then(|resp| {
+                // Limit the length to 20 MiB to avoid malicious servers causing OOM
+                const MAX_SIZE: usize = 20 * 1_024 * 1_024;
+
+                let initial_capacity = resp
+                    .header("Content-Length")
+                    .and_then(|len| len.parse().ok())
+                    .unwrap_or(1_024);
+                let mut bytes = Vec::with_capacity(std::cmp::min(initial_capacity, MAX_SIZE));
+                resp.into_reader()
+                    .take(MAX_SIZE as u64)
+                    .read_to_end(&mut bytes)?;
+                Ok(bytes)
+            })
+            .ok()
+    }
+}
+
+// Images waiting on the workers. Gets closed once the last handle to the decoder is dropped
+#[derive(Default)]
+struct DecodeQueue {
+    jobs: Vec<DecodeJob>,
+    closed: bool,
+}
+
+// Closes the queue when dropped, which stops the workers once they're done with their current
+// image. Images that were still waiting never get loaded
+struct DecodeWorkers(Arc<(Mutex<DecodeQueue>, Condvar)>);
+
+impl Drop for DecodeWorkers {
+    fn drop(&mut self) {
+        let (queue, pending) = &*self.0;
+        queue.lock().unwrap().closed = true;
+        pending.notify_all();
+    }
+}
+
+/// Loads images on a small pool of worker threads. Each time a worker finishes an image it picks
+/// up the pending image closest to the viewport, so scrolling somewhere reprioritizes what gets
+/// loaded next. Clones share the same workers, which stop once every clone is dropped
+#[derive(Clone)]
+pub struct ImageDecoder {
+    workers: Arc<DecodeWorkers>,
+}
+
+impl ImageDecoder {
+    pub fn new() -> Self {
+        let queue = Arc::new((Mutex::new(DecodeQueue::default()), Condvar::new()));
+        for _ in 0..DECODE_WORKERS {
+            let queue = queue.clone();
+            std::thread::spawn(move || loop {
+                let job = {
+                    let (queue, pending) = &*queue;
+                    let mut queue = queue.lock().unwrap();
+                    while queue.jobs.is_empty() && !queue.closed {
+                        queue = pending.wait(queue).unwrap();
+                    }
+                    if queue.closed {
+                        return;
+                    }
+                    // Ties go to the image that was queued first
+                    let jobs = &mut queue.jobs;
+                    let next = (0..jobs.len())
+                        .min_by(|&a, &b| jobs[a].priority().total_cmp(&jobs[b].priority()))
+                        .unwrap();
+                    jobs.remove(next)
+                };
+                job.run();
+            });
+        }
+        Self {
+            workers: Arc::new(DecodeWorkers(queue)),
+        }
+    }
+
+    fn push(&self, job: DecodeJob) {
+        let (queue, pending) = &*self.workers.0;
+        queue.lock().unwrap().jobs.push(job);
+        pending.notify_one();
+    }
+}
+
+impl Default for ImageDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Dimensions that fit within `max_dimension` on both sides while keeping the aspect ratio. `None`
+/// when the image already fits
+fn fit_texture((width, height): (u32, u32), max_dimension: u32) -> Option<(u32, u32)> {
+    if width <= max_dimension && height <= max_dimension {
+        return None;
+    }
+    let scale = max_dimension as f64 / width.max(height) as f64;
+    let fit = |side: u32| ((side as f64 * scale).round() as u32).clamp(1, max_dimension);
+    Some((fit(width), fit(height)))
 }
 
 impl Image {
@@ -44,8 +366,29 @@ impl Image {
         sampler: &wgpu::Sampler,
         bindgroup_layout: &wgpu::BindGroupLayout,
     ) {
-        let dimensions = self.buffer_dimensions();
         if let Some(image_data) = self.image.lock().unwrap().as_ref() {
+            // The device was created with the adapter's own texture size limit. Anything bigger
+            // fails validation, so huge images get shrunk to fit first
+            let max_dimension = device.limits().max_texture_dimension_2d;
+            let original = image_data.rgba_image.dimensions();
+            let downscaled = fit_texture(original, max_dimension).map(|(width, height)| {
+                log::info!(
+                    "Downscaling {}x{} image to {}x{} to fit the GPU's max texture size of {}",
+                    original.0,
+                    original.1,
+                    width,
+                    height,
+                    max_dimension,
+                );
+                image::imageops::resize(
+                    &image_data.rgba_image,
+                    width,
+                    height,
+                    image::imageops::FilterType::Triangle,
+                )
+            });
+            let rgba_image = downscaled.as_ref().unwrap_or(&image_data.rgba_image);
+            let dimensions = rgba_image.dimensions();
             let texture_size = wgpu::Extent3d {
                 width: dimensions.0,
                 height: dimensions.1,
@@ -69,7 +412,7 @@ impl Image {
                     aspect: wgpu::TextureAspect::All,
                 },
                 // The actual pixel data
-                &image_data.rgba_image,
+                rgba_image,
                 // The layout of the texture
                 wgpu::ImageDataLayout {
                     offset: 0,
@@ -98,89 +441,29 @@ impl Image {
         }
     }
 
-    pub fn from_src(src: String, file_path: PathBuf, hidpi_scale: f32) -> Image {
-        let image = Arc::new(Mutex::new(None));
-        let callback = Arc::new(Mutex::new(None::<EventLoopProxy<InlyneEvent>>));
-        let image_clone = image.clone();
-        let callback_clone = callback.clone();
-        std::thread::spawn(move || {
-            let mut src_path = PathBuf::from(src.clone());
-            if src_path.is_relative() {
-                if let Some(parent_dir) = file_path.parent() {
-                    src_path = parent_dir.join(src_path.strip_prefix("./").unwrap_or(&src_path));
-                }
-            }
-
-            let image_data = if let Ok(mut img_file) = File::open(&src_path) {
-                let img_file_size = src_path.metadata().unwrap().len();
-                let mut img_buf = Vec::with_capacity(img_file_size as usize);
-                img_file.read_to_end(&mut img_buf).unwrap();
-                img_buf
-            } else if let Ok(data) = ureq::get(&src).call().and_then(|resp| {
-                // Limit the length to 20 MiB to avoid malicious servers causing OOM
-                const MAX_SIZE: usize = 20 * 1_024 * 1_024;
-
-                let initial_capacity = resp
-                    .header("Content-Length")
-                    .and_then(|len| len.parse().ok())
-                    .unwrap_or(1_024);
-                let mut bytes = Vec::with_capacity(std::cmp::min(initial_capacity, MAX_SIZE));
-                resp.into_reader()
-                    .take(MAX_SIZE as u64)
-                    .read_to_end(&mut bytes)?;
-                Ok(bytes)
-            }) {
-                data
-            } else {
-                return;
-            };
-
-            if let Ok(image) = image::load_from_memory(&image_data) {
-                *(image_clone.lock().unwrap()) = Some(ImageData {
-                    rgba_image: image.into_rgba8(),
-                    scale: true,
-                });
-            } else {
-                let mut opt = usvg::Options::default();
-                opt.fontdb.load_system_fonts();
-                if let Ok(rtree) = usvg::Tree::from_data(&image_data, &opt.to_ref()) {
-                    let pixmap_size = rtree.svg_node().size.to_screen_size();
-                    let mut pixmap = tiny_skia::Pixmap::new(
-                        (pixmap_size.width() as f32 * hidpi_scale) as u32,
-                        (pixmap_size.height() as f32 * hidpi_scale) as u32,
-                    )
-                    .unwrap();
-                    resvg::render(
-                        &rtree,
-                        usvg::FitTo::Zoom(hidpi_scale),
-                        tiny_skia::Transform::default(),
-                        pixmap.as_mut(),
-                    )
-                    .unwrap();
-                    *(image_clone.lock().unwrap()) = Some(ImageData {
-                        rgba_image: ImageBuffer::from_raw(
-                            pixmap.width(),
-                            pixmap.height(),
-                            pixmap.data().into(),
-                        )
-                        .unwrap(),
-                        scale: false,
-                    });
-                }
-            }
-            if let Ok(Some(callback)) = callback_clone.try_lock().as_deref() {
-                callback
-                    .send_event(InlyneEvent::LoadedImage(src, image_clone.clone()))
-                    .unwrap();
-            }
-        });
-
-        Image {
-            image,
-            callback,
+    /// Loads the image at `src` (a path relative to `file_path` or a URL) once `decoder` gets to
+    /// it
+    pub fn from_src(
+        src: String,
+        file_path: PathBuf,
+        hidpi_scale: f32,
+        decoder: &ImageDecoder,
+    ) -> Image {
+        let image = Image {
             hidpi_scale,
+            source: Some((src.clone(), file_path.clone())),
+            decode_priority: Arc::new(Mutex::new(f32::INFINITY)),
             ..Default::default()
-        }
+        };
+        decoder.push(DecodeJob {
+            src,
+            file_path,
+            hidpi_scale,
+            image: image.image.clone(),
+            callback: image.callback.clone(),
+            priority: image.decode_priority.clone(),
+        });
+        image
     }
 
     pub fn from_image_data(image_data: Arc<Mutex<Option<ImageData>>>, hidpi_scale: f32) -> Image {
@@ -191,6 +474,22 @@ impl Image {
         }
     }
 
+    /// Handle for saving the image that can be sent off the event loop. `None` until the image
+    /// has loaded
+    pub fn savable(&self) -> Option<SavableImage> {
+        self.image.lock().unwrap().as_ref()?;
+        Some(SavableImage {
+            source: self.source.clone(),
+            image: self.image.clone(),
+        })
+    }
+
+    /// How far (in physical pixels) the image is from the viewport. Pending images closest to the
+    /// viewport get decoded first
+    pub fn set_viewport_distance(&self, distance: f32) {
+        *self.decode_priority.lock().unwrap() = distance;
+    }
+
     pub fn set_link(&mut self, link: String) {
         self.is_link = Some(link);
     }
@@ -204,23 +503,44 @@ impl Image {
         self
     }
 
+    pub fn with_float(mut self, float: Float) -> Self {
+        self.float = Some(float);
+        self
+    }
+
     pub fn with_size(mut self, size: ImageSize) -> Self {
         self.size = Some(size);
         self
     }
 
-    pub fn dimensions_from_image_size(&self, size: &ImageSize) -> (u32, u32) {
-        let image_dimensions = self.buffer_dimensions();
-        match size {
-            ImageSize::PxWidth(px_width) => (
-                *px_width,
-                ((*px_width as f32 / image_dimensions.0 as f32) * image_dimensions.1 as f32) as u32,
-            ),
-            ImageSize::PxHeight(px_height) => (
-                ((*px_height as f32 / image_dimensions.1 as f32) * image_dimensions.0 as f32)
-                    as u32,
-                *px_height,
-            ),
+    /// The size in physical pixels that `size` asks for. Percentages of the width are of
+    /// `content_width` while percentages of the height are ignored since the document doesn't
+    /// have a height to take them from. `None` when neither resolves
+    pub fn dimensions_from_image_size(
+        &self,
+        size: &ImageSize,
+        content_width: f32,
+        zoom: f32,
+    ) -> Option<Size> {
+        let (image_width, image_height) = self.buffer_dimensions();
+        let (image_width, image_height) = (image_width as f32, image_height as f32);
+        let scale = self.hidpi_scale * zoom;
+        let width = size
+            .width
+            .and_then(|width| width.resolve(Some(content_width), DEFAULT_TEXT_SIZE, scale));
+        let height = size
+            .height
+            .and_then(|height| height.resolve(None, DEFAULT_TEXT_SIZE, scale));
+        // There's no aspect ratio to keep until the image has loaded
+        if image_width == 0. || image_height == 0. {
+            let resolved = width.is_some() || height.is_some();
+            return resolved.then(|| (width.unwrap_or(0.), height.unwrap_or(0.)));
+        }
+        match (width, height) {
+            (Some(width), Some(height)) => Some((width, height)),
+            (Some(width), None) => Some((width, width / image_width * image_height)),
+            (None, Some(height)) => Some((height / image_height * image_width, height)),
+            (None, None) => None,
         }
     }
 
@@ -244,19 +564,15 @@ impl Image {
         if let Some(dimensions) = self
             .size
             .as_ref()
-            .map(|image_size| self.dimensions_from_image_size(image_size))
+            .and_then(|image_size| self.dimensions_from_image_size(image_size, max_width, zoom))
         {
-            let target_dimensions = (
-                (dimensions.0 as f32 * self.hidpi_scale * zoom) as u32,
-                (dimensions.1 as f32 * self.hidpi_scale * zoom) as u32,
-            );
-            if target_dimensions.0 > max_width as u32 {
+            if dimensions.0 > max_width {
                 (
                     max_width as u32,
-                    ((max_width / buffer_size.0 as f32) * buffer_size.1 as f32) as u32,
+                    ((max_width / dimensions.0) * dimensions.1) as u32,
                 )
             } else {
-                target_dimensions
+                (dimensions.0 as u32, dimensions.1 as u32)
             }
         } else if buffer_size.0 > max_width {
             (
@@ -279,6 +595,10 @@ impl Image {
 pub struct ImageVertex {
     pub pos: [f32; 3],
     pub tex_coords: [f32; 2],
+    /// Size of the whole image in pixels
+    pub size: [f32; 2],
+    /// Radius of the image's rounded corners in pixels
+    pub corner_radius: f32,
 }
 pub struct ImageRenderer {
     pub render_pipeline: wgpu::RenderPipeline,
@@ -287,6 +607,18 @@ pub struct ImageRenderer {
     pub sampler: wgpu::Sampler,
 }
 
+/// Fits an image with `dimensions` inside of the box at `pos` with `size` while keeping its aspect
+/// ratio. The image gets centered along the axis that has space left over
+pub fn contain(pos: Point, size: Size, dimensions: (u32, u32)) -> (Point, Size) {
+    if dimensions.0 == 0 || dimensions.1 == 0 {
+        return (pos, size);
+    }
+    let scale = (size.0 / dimensions.0 as f32).min(size.1 / dimensions.1 as f32);
+    let fitted = (dimensions.0 as f32 * scale, dimensions.1 as f32 * scale);
+    let inset = ((size.0 - fitted.0) / 2., (size.1 - fitted.1) / 2.);
+    ((pos.0 + inset.0, pos.1 + inset.1), fitted)
+}
+
 pub fn point(x: f32, y: f32, position: Point, size: Size, screen: Size) -> [f32; 3] {
     let scale_x = size.0 / screen.0;
     let scale_y = size.1 / screen.1;
@@ -298,7 +630,11 @@ pub fn point(x: f32, y: f32, position: Point, size: Size, screen: Size) -> [f32;
 }
 
 impl ImageRenderer {
-    pub fn new(device: &Device, format: &TextureFormat) -> Self {
+    pub fn new(
+        device: &Device,
+        format: &TextureFormat,
+        output_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -324,14 +660,20 @@ impl ImageRenderer {
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&texture_bind_group_layout],
+            // Images get encoded for the swapchain the same way as the rest of the geometry
+            bind_group_layouts: &[&texture_bind_group_layout, output_layout],
             push_constant_ranges: &[],
         });
 
         let vertex_buffers = [wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<ImageVertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2],
+            attributes: &wgpu::vertex_attr_array![
+                0 => Float32x3,
+                1 => Float32x2,
+                2 => Float32x2,
+                3 => Float32,
+            ],
         }];
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -390,28 +732,28 @@ impl ImageRenderer {
         }
     }
 
-    pub fn vertex_buf(device: &Device, pos: Point, size: Size, screen_size: Size) -> wgpu::Buffer {
+    pub fn vertex_buf(
+        device: &Device,
+        pos: Point,
+        size: Size,
+        corner_radius: f32,
+        screen_size: Size,
+    ) -> wgpu::Buffer {
+        let vertex = |x, y, tex_coords| ImageVertex {
+            pos: point(x, y, pos, size, screen_size),
+            tex_coords,
+            size: [size.0, size.1],
+            corner_radius,
+        };
         let vertices: &[ImageVertex] = &[
             // TOP LEFT
-            ImageVertex {
-                pos: point(-1.0, 1.0, pos, size, screen_size),
-                tex_coords: [0.0, 0.0],
-            },
+            vertex(-1.0, 1.0, [0.0, 0.0]),
             // BOTTOM LEFT
-            ImageVertex {
-                pos: point(-1.0, -1.0, pos, size, screen_size),
-                tex_coords: [0.0, 1.0],
-            },
+            vertex(-1.0, -1.0, [0.0, 1.0]),
             // BOTTOM RIGHT
-            ImageVertex {
-                pos: point(1.0, -1.0, pos, size, screen_size),
-                tex_coords: [1.0, 1.0],
-            },
+            vertex(1.0, -1.0, [1.0, 1.0]),
             // TOP RIGHT
-            ImageVertex {
-                pos: point(1.0, 1.0, pos, size, screen_size),
-                tex_coords: [1.0, 0.0],
-            },
+            vertex(1.0, 1.0, [1.0, 0.0]),
         ];
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
@@ -420,3 +762,150 @@ impl ImageRenderer {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{
+        contain, fit_texture, Image, ImageData, ImageDecoder, ImageFormat, ImageSize, Length,
+        SavableImage,
+    };
+    use crate::text::DEFAULT_TEXT_SIZE;
+    use image::RgbaImage;
+
+    #[test]
+    fn oversized_textures_keep_their_aspect_ratio() {
+        assert_eq!(fit_texture((8_000, 8_000), 8_192), None);
+        assert_eq!(fit_texture((8_000, 8_000), 2_048), Some((2_048, 2_048)));
+        assert_eq!(fit_texture((16_000, 4_000), 8_000), Some((8_000, 2_000)));
+        assert_eq!(fit_texture((100, 100_000), 1_000), Some((1, 1_000)));
+    }
+
+    #[test]
+    fn decode_workers_stop_with_the_last_decoder() {
+        let decoder = ImageDecoder::new();
+        let queue = decoder.workers.0.clone();
+        let clone = decoder.clone();
+        drop(decoder);
+        // Every worker holds onto the queue until it stops
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(Arc::strong_count(&queue) > 1);
+
+        drop(clone);
+        let started = Instant::now();
+        while Arc::strong_count(&queue) > 1 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn lengths_parse_with_units() {
+        assert_eq!("120".parse(), Ok(Length::Px(120.)));
+        assert_eq!("120px".parse(), Ok(Length::Px(120.)));
+        assert_eq!(" 50% ".parse(), Ok(Length::Percent(50.)));
+        assert_eq!("2em".parse(), Ok(Length::Em(2.)));
+        assert_eq!("1.5rem".parse(), Ok(Length::Em(1.5)));
+        assert_eq!("-3".parse::<Length>(), Err(()));
+        assert_eq!("auto".parse::<Length>(), Err(()));
+    }
+
+    #[test]
+    fn relative_sizes_resolve() {
+        let image_data = ImageData {
+            rgba_image: RgbaImage::new(200, 100),
+            scale: true,
+            format: None,
+        };
+        let image = Image::from_image_data(Arc::new(Mutex::new(Some(image_data))), 2.);
+        let size = |width, height| ImageSize { width, height };
+
+        // Percentages are of the content width and the height follows the aspect ratio
+        let half = size(Some(Length::Percent(50.)), None);
+        assert_eq!(
+            image.dimensions_from_image_size(&half, 800., 1.),
+            Some((400., 200.))
+        );
+        // Ems scale along with the text
+        let ems = size(None, Some(Length::Em(2.)));
+        let em_height = 2. * DEFAULT_TEXT_SIZE * 2.;
+        assert_eq!(
+            image.dimensions_from_image_size(&ems, 800., 1.),
+            Some((em_height * 2., em_height))
+        );
+        // Percent heights have nothing to be a percent of, so the image keeps its own size
+        let tall = size(None, Some(Length::Percent(50.)));
+        assert_eq!(image.dimensions_from_image_size(&tall, 800., 1.), None);
+        // Both being set stretches the image
+        let both = size(Some(Length::Px(10.)), Some(Length::Px(10.)));
+        assert_eq!(
+            image.dimensions_from_image_size(&both, 800., 1.),
+            Some((20., 20.))
+        );
+    }
+
+    #[test]
+    fn contain_keeps_aspect_ratio() {
+        // Wide image in a square box gets centered vertically
+        assert_eq!(
+            contain((10., 10.), (100., 100.), (200, 100)),
+            ((10., 35.), (100., 50.))
+        );
+        // Tall image in a square box gets centered horizontally
+        assert_eq!(
+            contain((0., 0.), (100., 100.), (50, 100)),
+            ((25., 0.), (50., 100.))
+        );
+        // Matching aspect ratio fills the whole box
+        assert_eq!(
+            contain((0., 0.), (100., 50.), (400, 200)),
+            ((0., 0.), (100., 50.))
+        );
+        // Images that haven't loaded yet are left alone
+        assert_eq!(
+            contain((0., 0.), (10., 10.), (0, 0)),
+            ((0., 0.), (10., 10.))
+        );
+    }
+
+    #[test]
+    fn saving_loads_the_original_bytes_again() {
+        let png = ImageFormat::Raster(image::ImageFormat::Png);
+        let image_data = |format| ImageData {
+            rgba_image: RgbaImage::new(2, 2),
+            scale: true,
+            format,
+        };
+        // Unrecognized encodings get saved as a PNG
+        let image = Image::from_image_data(Arc::new(Mutex::new(Some(image_data(None)))), 1.);
+        let (bytes, format) = image.savable().unwrap().encoded().unwrap();
+        assert_eq!(format, png);
+        assert_eq!(
+            image::guess_format(&bytes).unwrap(),
+            image::ImageFormat::Png
+        );
+
+        let dir = std::env::temp_dir().join(format!("inlyne-save-image-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("image.svg"), b"<svg/>").unwrap();
+        let savable = |src: &str| SavableImage {
+            source: Some((src.to_owned(), dir.join("doc.md"))),
+            image: Arc::new(Mutex::new(Some(image_data(Some(ImageFormat::Svg))))),
+        };
+        assert_eq!(
+            savable("./image.svg").encoded().unwrap(),
+            (b"<svg/>".to_vec(), ImageFormat::Svg)
+        );
+        // Sources that went away fall back to the decoded image
+        assert_eq!(savable("gone.svg").encoded().unwrap().1, png);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Nothing to save until the image loads
+        let pending = Image::from_image_data(Arc::new(Mutex::new(None)), 1.);
+        assert!(pending.savable().is_none());
+        assert_eq!(ImageFormat::Svg.extension(), "svg");
+        assert_eq!(png.extension(), "png");
+    }
+}
@@ -1,20 +1,32 @@
-use crate::color::hex_to_linear_rgba;
+use crate::color::{ensure_contrast, hex_to_linear_rgba, parse_color, CodeTheme};
+use crate::fonts;
 use crate::image::Image;
+use crate::image::ImageDecoder;
 use crate::image::ImageSize;
+use crate::image::Length;
+use crate::opts::{BlockPadding, Opts};
+use crate::positioner::BlankLines;
+use crate::positioner::Float;
 use crate::positioner::Positioned;
 use crate::positioner::Row;
+use crate::positioner::RowWidth;
 use crate::positioner::Section;
 use crate::positioner::Spacer;
 use crate::positioner::DEFAULT_MARGIN;
-use crate::table::Table;
-use crate::ImageCache;
+use crate::table::{ColumnLimit, Table, TableLayout};
+use crate::utils::ImageCache;
 
 use crate::color::Theme;
-use crate::text::{Text, TextBox};
+use crate::text::{
+    AdmonitionKind, Bullet, Change, DefinitionPart, FontWeights, LineNumber, LinkColors,
+    ListMarker, Text, TextBox, VerticalAlign, BOLD_WEIGHT, DEFAULT_TEXT_SIZE, REGULAR_WEIGHT,
+};
 use crate::utils::Align;
 use crate::Element;
 
-use comrak::{markdown_to_html_with_plugins, ComrakOptions};
+use comrak::arena_tree::Node;
+use comrak::nodes::{Ast, AstNode, NodeCode, NodeCodeBlock, NodeHtmlBlock, NodeValue};
+use comrak::{format_html_with_plugins, parse_document, Arena, ComrakOptions};
 use html5ever::local_name;
 use html5ever::tendril::*;
 use html5ever::tokenizer::BufferQueue;
@@ -23,9 +35,11 @@ use html5ever::tokenizer::TagToken;
 use html5ever::tokenizer::{Token, TokenSink, TokenSinkResult};
 use html5ever::tokenizer::{Tokenizer, TokenizerOpts};
 use html5ever::Attribute;
+use serde::Deserialize;
 use winit::window::Window;
 use Token::{CharacterTokens, EOFToken};
 
+use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -34,8 +48,437 @@ use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 
+const CODE_TEXT_SIZE: f32 = 18.;
+
+/// What happens to HTML that's written in the markdown
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RawHtml {
+    /// Every tag that inlyne knows how to display gets rendered
+    Render,
+    /// Only tags that style text get rendered. Other tags are dropped, but the text within them is
+    /// kept
+    RenderSubset,
+    /// Shown as code. The safe choice for documents that can't be trusted
+    #[default]
+    Escape,
+    /// Left out entirely
+    Strip,
+}
+
+// Tags that `RawHtml::RenderSubset` keeps. Their attributes get dropped, so `<span>` and `<font>`
+// would have nothing left to style with and are dropped (keeping their text) along with the rest
+const TEXT_STYLE_TAGS: &[&str] = &["b", "strong", "i", "em", "br", "sub", "sup", "kbd", "mark"];
+// Tags whose contents aren't meant to be read
+const HIDDEN_CONTENT_TAGS: &[&str] = &["script", "style"];
+
+// Applies the `RawHtml` policy to the HTML nodes of the document. This needs to run before any of
+// the markers below get added since those are HTML nodes too
+fn apply_raw_html<'a>(root: &'a AstNode<'a>, raw_html: RawHtml) {
+    if raw_html == RawHtml::Render {
+        return;
+    }
+    let nodes: Vec<_> = root.descendants().collect();
+    for node in nodes {
+        let replacement = match (&node.data.borrow().value, raw_html) {
+            (NodeValue::HtmlBlock(block), RawHtml::Escape) => {
+                Some(NodeValue::CodeBlock(NodeCodeBlock {
+                    fenced: false,
+                    fence_char: b'`',
+                    fence_length: 0,
+                    fence_offset: 0,
+                    info: b"html".to_vec(),
+                    literal: block.literal.clone(),
+                }))
+            }
+            (NodeValue::HtmlBlock(block), RawHtml::RenderSubset) => {
+                Some(NodeValue::HtmlBlock(NodeHtmlBlock {
+                    block_type: block.block_type,
+                    literal: text_style_subset(&block.literal),
+                }))
+            }
+            (NodeValue::HtmlInline(html), RawHtml::Escape) => Some(NodeValue::Code(NodeCode {
+                num_backticks: 1,
+                literal: html.clone(),
+            })),
+            (NodeValue::HtmlInline(html), RawHtml::RenderSubset) => {
+                Some(NodeValue::HtmlInline(text_style_subset(html)))
+            }
+            (NodeValue::HtmlBlock(_) | NodeValue::HtmlInline(_), _) => None,
+            _ => continue,
+        };
+        match replacement {
+            Some(value) => node.data.borrow_mut().value = value,
+            None => node.detach(),
+        }
+    }
+}
+
+// Drops every tag that isn't in `TEXT_STYLE_TAGS` along with the attributes of the ones that are.
+// The text between tags is kept unless it's within a `HIDDEN_CONTENT_TAGS` tag
+fn text_style_subset(html: &[u8]) -> Vec<u8> {
+    let html = String::from_utf8_lossy(html);
+    let mut subset = String::new();
+    let mut rest = &*html;
+    let mut hidden_until: Option<String> = None;
+    while let Some(start) = rest.find('<') {
+        if hidden_until.is_none() {
+            subset.push_str(&rest[..start]);
+        }
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let tag = rest[1..].strip_prefix('/');
+        let is_closing = tag.is_some();
+        let name: String = tag
+            .unwrap_or(&rest[1..])
+            .chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let end = match tag_end(rest) {
+            Some(end) if !name.is_empty() => end,
+            // Not a tag, just a `<` in the text
+            _ => {
+                if hidden_until.is_none() {
+                    subset.push_str("&lt;");
+                }
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        rest = &rest[end + 1..];
+        if let Some(hidden) = &hidden_until {
+            if is_closing && name == *hidden {
+                hidden_until = None;
+            }
+        } else if TEXT_STYLE_TAGS.contains(&name.as_str()) {
+            subset.push('<');
+            if is_closing {
+                subset.push('/');
+            }
+            subset.push_str(&name);
+            subset.push('>');
+        } else if !is_closing && HIDDEN_CONTENT_TAGS.contains(&name.as_str()) {
+            hidden_until = Some(name);
+        }
+    }
+    if hidden_until.is_none() {
+        subset.push_str(rest);
+    }
+    subset.into_bytes()
+}
+
+// Where the tag at the start of `html` ends. A `>` within a quoted attribute value doesn't end it
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut after_equals = false;
+    for (i, c) in html.char_indices() {
+        if let Some(open) = quote {
+            if c == open {
+                quote = None;
+            }
+        } else if after_equals && (c == '"' || c == '\'') {
+            quote = Some(c);
+        } else if c == '>' {
+            return Some(i);
+        }
+        if !c.is_whitespace() {
+            after_equals = c == '=' && quote.is_none();
+        }
+    }
+    None
+}
+
+// Whether a line of a diff adds or removes something. The `+++` and `---` headers naming the files
+// do neither
+fn diff_change(line: &str) -> Option<Change> {
+    if line.starts_with("+++") || line.starts_with("---") {
+        None
+    } else if line.starts_with('+') {
+        Some(Change::Inserted)
+    } else if line.starts_with('-') {
+        Some(Change::Deleted)
+    } else {
+        None
+    }
+}
+
+// What a `style` attribute asks for. Only properties that color or style the text are understood
+// so that styles can't break the layout. Everything else is ignored
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct InlineStyle {
+    color: Option<[f32; 4]>,
+    background: Option<[f32; 4]>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strike_through: bool,
+    // In ems
+    letter_spacing: Option<f32>,
+}
+
+impl InlineStyle {
+    fn parse(style: &str) -> Self {
+        let mut inline_style = Self::default();
+        // Later declarations win like they do in CSS
+        for declaration in style.split(';') {
+            let (property, value) = match declaration.split_once(':') {
+                Some((property, value)) => (property.trim().to_ascii_lowercase(), value),
+                None => continue,
+            };
+            let value = value.trim().to_ascii_lowercase();
+            match property.as_str() {
+                "color" => inline_style.color = css_color(&value).or(inline_style.color),
+                "background" | "background-color" => {
+                    inline_style.background = css_color(&value).or(inline_style.background)
+                }
+                "font-weight" => {
+                    inline_style.bold = matches!(value.as_str(), "bold" | "bolder")
+                        || value
+                            .parse()
+                            .map_or(false, |weight: u16| weight >= BOLD_WEIGHT)
+                }
+                "font-style" => {
+                    inline_style.italic = matches!(value.as_str(), "italic" | "oblique")
+                }
+                "text-decoration" | "text-decoration-line" => {
+                    let mut lines = value.split_whitespace();
+                    inline_style.underline = lines.clone().any(|line| line == "underline");
+                    inline_style.strike_through = lines.any(|line| line == "line-through");
+                }
+                "letter-spacing" => {
+                    inline_style.letter_spacing =
+                        css_letter_spacing(&value).or(inline_style.letter_spacing)
+                }
+                _ => {}
+            }
+        }
+        inline_style
+    }
+
+    // Inner styles win where they pick a color and add to the outer ones otherwise
+    fn within(self, outer: Self) -> Self {
+        Self {
+            color: self.color.or(outer.color),
+            background: self.background.or(outer.background),
+            bold: self.bold || outer.bold,
+            italic: self.italic || outer.italic,
+            underline: self.underline || outer.underline,
+            strike_through: self.strike_through || outer.strike_through,
+            letter_spacing: self.letter_spacing.or(outer.letter_spacing),
+        }
+    }
+}
+
+// Letter spacing in ems. Pixels are taken relative to the default text size
+fn css_letter_spacing(value: &str) -> Option<f32> {
+    if value == "normal" {
+        return Some(0.);
+    }
+    match value.parse().ok()? {
+        Length::Em(ems) => Some(ems),
+        Length::Px(px) => Some(px / DEFAULT_TEXT_SIZE),
+        Length::Percent(_) => None,
+    }
+}
+
+// Hex colors like `#fa0` or `#ffaa00` and basic color names
+fn css_color(value: &str) -> Option<[f32; 4]> {
+    parse_color(value).map(hex_to_linear_rgba)
+}
+
+// comrak doesn't keep track of where things came from in the HTML it renders, so this adds empty
+// spans with the line that each block starts on. They use the `data-sourcepos` attribute that
+// newer versions of comrak add to every block
+fn mark_source_lines<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) {
+    let nodes: Vec<_> = root.descendants().collect();
+    for node in nodes {
+        let (line, is_inline) = {
+            let ast = node.data.borrow();
+            match &ast.value {
+                NodeValue::Paragraph | NodeValue::Heading(_) | NodeValue::TableCell => {
+                    (ast.start_line, true)
+                }
+                // The code starts on the line after the opening fence
+                NodeValue::CodeBlock(code_block) if code_block.fenced => {
+                    (ast.start_line + 1, false)
+                }
+                NodeValue::CodeBlock(_) | NodeValue::Table(_) => (ast.start_line, false),
+                _ => continue,
+            }
+        };
+        // Not every node gets a line while parsing
+        if line == 0 {
+            continue;
+        }
+        let marker = format!("<span data-sourcepos=\"{0}:1-{0}:1\"></span>", line).into_bytes();
+        let value = if is_inline {
+            NodeValue::HtmlInline(marker)
+        } else {
+            NodeValue::HtmlBlock(NodeHtmlBlock {
+                block_type: 0,
+                literal: marker,
+            })
+        };
+        let marker_node = arena.alloc(Node::new(RefCell::new(Ast::new(value))));
+        if is_inline {
+            node.prepend(marker_node);
+        } else {
+            node.insert_before(marker_node);
+        }
+    }
+}
+
+// `==text==` marks its text the same way that `<mark>` does. comrak doesn't know about it, so this
+// splits the text nodes at each `==` and turns pairs of them into `<mark>` tags. Like emphasis,
+// marked text can't start or end with whitespace and pairs never reach outside of their parent
+fn mark_highlights<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) {
+    let text_node = |text: &[u8]| {
+        let value = NodeValue::Text(text.to_vec());
+        arena.alloc(Node::new(RefCell::new(Ast::new(value))))
+    };
+    let parents: Vec<_> = root.descendants().collect();
+    for parent in parents {
+        let children: Vec<_> = parent.children().collect();
+        let mut markers = Vec::new();
+        for (i, child) in children.iter().enumerate() {
+            let text = match &child.data.borrow().value {
+                NodeValue::Text(text) => text.clone(),
+                _ => continue,
+            };
+            let mut start = 0;
+            for at in highlight_markers(&text) {
+                let can_close = match text[..at].last() {
+                    Some(before) => !before.is_ascii_whitespace(),
+                    None => i > 0,
+                };
+                let can_open = match text.get(at + 2) {
+                    Some(after) => !after.is_ascii_whitespace(),
+                    None => i + 1 < children.len(),
+                };
+                if at > start {
+                    child.insert_before(text_node(&text[start..at]));
+                }
+                let marker = text_node(b"==");
+                child.insert_before(marker);
+                markers.push((marker, can_open, can_close));
+                start = at + 2;
+            }
+            if start == text.len() {
+                child.detach();
+            } else if start > 0 {
+                child.data.borrow_mut().value = NodeValue::Text(text[start..].to_vec());
+            }
+        }
+
+        // Markers that don't find a partner stay as plain `==`
+        let mut opener = None;
+        for (marker, can_open, can_close) in markers {
+            match opener {
+                Some(open) if can_close => {
+                    open.data.borrow_mut().value = NodeValue::HtmlInline(b"<mark>".to_vec());
+                    marker.data.borrow_mut().value = NodeValue::HtmlInline(b"</mark>".to_vec());
+                    opener = None;
+                }
+                None if can_open => opener = Some(marker),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Where each `==` in `text` starts. Longer runs of `=` are left alone
+fn highlight_markers(text: &[u8]) -> Vec<usize> {
+    let mut markers = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let run = text[i..].iter().take_while(|&&b| b == b'=').count();
+        if run == 2 {
+            markers.push(i);
+        }
+        i += run.max(1);
+    }
+    markers
+}
+
+// Blank lines only ever separate blocks in the rendered HTML, so this adds an empty span with a
+// `data-blank-lines` attribute in front of top-level blocks that had more than one blank line
+// above them in `md`
+fn mark_blank_lines<'a>(arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>, md: &str) {
+    let lines: Vec<_> = md.lines().collect();
+    let blocks: Vec<_> = root.children().skip(1).collect();
+    for node in blocks {
+        let start_line = node.data.borrow().start_line as usize;
+        // Lines are counted from 1, so the line above the block is at `start_line - 2`
+        let blank = lines[..start_line.saturating_sub(1).min(lines.len())]
+            .iter()
+            .rev()
+            .take_while(|line| line.trim().is_empty())
+            .count();
+        if blank < 2 {
+            continue;
+        }
+        let marker = format!("<span data-blank-lines=\"{}\"></span>", blank - 1).into_bytes();
+        let value = NodeValue::HtmlBlock(NodeHtmlBlock {
+            block_type: 0,
+            literal: marker,
+        });
+        node.insert_before(arena.alloc(Node::new(RefCell::new(Ast::new(value)))));
+    }
+}
+
+// How many extra blank lines a `data-blank-lines` attribute asks for
+fn blank_lines(attrs: &[Attribute]) -> Option<usize> {
+    let blank_lines = attrs
+        .iter()
+        .find(|attr| &attr.name.local == "data-blank-lines")?;
+    blank_lines.value.parse().ok()
+}
+
+// The width that a table cell's `width` attribute asks its column to take. Percentages share the
+// table's width with the other flexible columns
+fn column_width(attrs: &[Attribute]) -> Option<RowWidth> {
+    let width = attrs
+        .iter()
+        .find(|attr| attr.name.local == local_name!("width"))?;
+    let width = match width.value.parse().ok()? {
+        Length::Px(px) => RowWidth::Fixed(px),
+        Length::Percent(percent) => RowWidth::Flex(percent),
+        Length::Em(ems) => RowWidth::Fixed(ems * DEFAULT_TEXT_SIZE),
+    };
+    Some(width)
+}
+
+// The line that a `data-sourcepos` attribute (`start_line:start_column-end_line:end_column`)
+// starts on
+fn source_line(attrs: &[Attribute]) -> Option<usize> {
+    let sourcepos = attrs
+        .iter()
+        .find(|attr| &attr.name.local == "data-sourcepos")?;
+    sourcepos.value.split(':').next()?.parse().ok()
+}
+
+/// The part of the window that the interpreter talks to. Lets the interpreter run without a real
+/// window
+pub trait WindowInteractor: Send + Sync {
+    fn request_redraw(&self);
+}
+
+impl WindowInteractor for Window {
+    fn request_redraw(&self) {
+        Window::request_redraw(self)
+    }
+}
+
 mod html {
-    use crate::{positioner::Section, table::Table, text::TextBox, utils::Align};
+    use crate::{
+        positioner::{Positioned, Section},
+        table::Table,
+        utils::Align,
+    };
 
     pub enum HeaderType {
         H1,
@@ -47,14 +490,14 @@ mod html {
     }
 
     impl HeaderType {
-        pub fn text_size(&self) -> f32 {
+        pub fn level(&self) -> u8 {
             match &self {
-                Self::H1 => 32.,
-                Self::H2 => 24.,
-                Self::H3 => 18.72,
-                Self::H4 => 16.,
-                Self::H5 => 13.28,
-                Self::H6 => 10.72,
+                Self::H1 => 1,
+                Self::H2 => 2,
+                Self::H3 => 3,
+                Self::H4 => 4,
+                Self::H5 => 5,
+                Self::H6 => 6,
             }
         }
     }
@@ -86,6 +529,8 @@ mod html {
         pub pre_formatted: usize,
         pub block_quote: usize,
         pub link: Vec<String>,
+        pub change: Vec<Change>,
+        pub vertical_align: Vec<VerticalAlign>,
     }
 
     pub enum Element {
@@ -93,7 +538,7 @@ mod html {
         ListItem,
         Input,
         Table(Table),
-        TableRow(Vec<TextBox>),
+        TableRow(Vec<Positioned<crate::Element>>),
         Header(Header),
         Paragraph(Option<Align>),
         Div(Option<Align>),
@@ -108,8 +553,75 @@ struct State {
     element_stack: Vec<html::Element>,
     text_options: html::TextOptions,
     span_color: [f32; 4],
+    // Fill behind the code block being parsed. Highlighted tokens are kept legible against it
+    code_background: [f32; 4],
     // Stores the row and a counter of newlines after each image
     inline_images: Option<(Row, usize)>,
+    opened_list_item: bool,
+    // Set from the start of a list item until its first text gets its bullet or number
+    awaiting_list_marker: bool,
+    // Parts of the definition lists that are open. Definition lists can be nested within
+    // definitions
+    definition_parts: Vec<DefinitionPart>,
+    // The last paragraph of a quote when it looks like it attributes the quote. Held back until
+    // it's known whether the quote ends with it. Spacers that come after it are held along with it
+    // so that they don't give it away before the quote ends
+    attribution: Option<(TextBox, Vec<Spacer>)>,
+    // Content of the table cell being interpreted for each of the open tables. Text in a cell is
+    // only moved here when a nested table comes after it or the cell ends
+    cell_elements: Vec<Vec<Positioned<Element>>>,
+    // Lines of the code block being parsed. They're held until the block ends so that every line
+    // knows how wide the block's line numbers get
+    code_lines: Vec<TextBox>,
+    // Source line of the last block that started. Goes to the next text box that gets pushed
+    source_line: Option<usize>,
+    // The callout type of each open blockquote. `None` for plain quotes
+    admonitions: Vec<Option<AdmonitionKind>>,
+    // Set when a blockquote just opened and its first text may be a `[!KIND]` marker
+    awaiting_admonition: bool,
+    // Background picked by the `style` of each open `div` and `p`
+    block_backgrounds: Vec<Option<[f32; 4]>>,
+    // Style of each open `span` and `font`
+    span_styles: Vec<InlineStyle>,
+    // Set while in a code block of the `diff` language
+    diff_block: bool,
+    // Set once an image floats. The end of the document gets cleared so it makes room for it
+    floats: bool,
+    // Anchors of elements with an `id` waiting for the next text box that gets pushed. Footnotes
+    // and their references link to each other through them
+    ids: Vec<String>,
+}
+
+/// How documents get interpreted. Built once from [`Opts`] and handed to each interpreter
+#[derive(Clone)]
+pub struct InterpreterOptions {
+    pub theme: Theme,
+    pub code_theme: CodeTheme,
+    pub block_padding: BlockPadding,
+    /// Lays out every code block on a grid instead of just the ones marked as `grid`
+    pub monospace_grid: bool,
+    /// Layout of tables that don't pick one with a `data-layout` attribute
+    pub table_layout: TableLayout,
+    pub column_limit: ColumnLimit,
+    pub blank_lines: BlankLines,
+    pub raw_html: RawHtml,
+    pub font_weights: FontWeights,
+}
+
+impl From<&Opts> for InterpreterOptions {
+    fn from(opts: &Opts) -> Self {
+        Self {
+            theme: opts.theme.clone(),
+            code_theme: opts.code_theme,
+            block_padding: opts.block_padding,
+            monospace_grid: opts.monospace_grid,
+            table_layout: opts.table_layout,
+            column_limit: opts.column_limit,
+            blank_lines: opts.blank_lines,
+            raw_html: opts.raw_html,
+            font_weights: opts.font_opts.weights(),
+        }
+    }
 }
 
 pub struct HtmlInterpreter {
@@ -117,7 +629,17 @@ pub struct HtmlInterpreter {
     current_textbox: TextBox,
     hidpi_scale: f32,
     theme: Theme,
-    window: Arc<Window>,
+    code_theme: CodeTheme,
+    block_padding: BlockPadding,
+    // Lays out every code block on a grid instead of just the ones marked as `grid`
+    monospace_grid: bool,
+    // Layout of tables that don't pick one with a `data-layout` attribute
+    table_layout: TableLayout,
+    column_limit: ColumnLimit,
+    blank_lines: BlankLines,
+    raw_html: RawHtml,
+    font_weights: FontWeights,
+    window: Arc<dyn WindowInteractor>,
     state: State,
     file_path: PathBuf,
     // Whether the interpreters is allowed to queue elements
@@ -126,17 +648,30 @@ pub struct HtmlInterpreter {
     stopped: bool,
     first_pass: bool,
     image_cache: ImageCache,
+    image_decoder: ImageDecoder,
 }
 
 impl HtmlInterpreter {
     pub fn new(
-        window: Arc<Window>,
+        window: Arc<dyn WindowInteractor>,
         element_queue: Arc<Mutex<VecDeque<Element>>>,
-        theme: Theme,
+        options: InterpreterOptions,
         hidpi_scale: f32,
         file_path: PathBuf,
         image_cache: ImageCache,
+        image_decoder: ImageDecoder,
     ) -> Self {
+        let InterpreterOptions {
+            theme,
+            code_theme,
+            block_padding,
+            monospace_grid,
+            table_layout,
+            column_limit,
+            blank_lines,
+            raw_html,
+            font_weights,
+        } = options;
         Self {
             window,
             element_queue,
@@ -147,11 +682,20 @@ impl HtmlInterpreter {
                 ..Default::default()
             },
             theme,
+            code_theme,
+            block_padding,
+            monospace_grid,
+            table_layout,
+            column_limit,
+            blank_lines,
+            raw_html,
+            font_weights,
             file_path,
             should_queue: Arc::new(AtomicBool::new(true)),
             stopped: false,
             first_pass: true,
             image_cache,
+            image_decoder,
         }
     }
 
@@ -161,12 +705,16 @@ impl HtmlInterpreter {
         options.extension.table = true;
         options.extension.strikethrough = true;
         options.extension.tasklist = true;
+        options.extension.footnotes = true;
+        options.extension.description_lists = true;
+        // Bare `http(s)://` and `www.` URLs become links. Trailing punctuation is left out
+        options.extension.autolink = true;
         options.parse.smart = true;
         options.render.unsafe_ = true;
 
         let mut plugins = comrak::ComrakPlugins::default();
         let adapter = comrak::plugins::syntect::SyntectAdapter::new(
-            self.theme.code_highlighter.as_syntect_name(),
+            self.code_theme.highlighter.as_syntect_name(),
         );
         plugins.render.codefence_syntax_highlighter = Some(&adapter);
         let mut tok = Tokenizer::new(self, TokenizerOpts::default());
@@ -180,7 +728,17 @@ impl HtmlInterpreter {
                 tok.sink.state = State::default();
                 tok.sink.current_textbox = TextBox::new(Vec::new(), tok.sink.hidpi_scale);
                 tok.sink.stopped = false;
-                let htmlified = markdown_to_html_with_plugins(&md_string, &options, &plugins);
+                let arena = Arena::new();
+                let root = parse_document(&arena, &md_string, &options);
+                apply_raw_html(root, tok.sink.raw_html);
+                mark_highlights(&arena, root);
+                if tok.sink.blank_lines == BlankLines::Preserve {
+                    mark_blank_lines(&arena, root, &md_string);
+                }
+                mark_source_lines(&arena, root);
+                let mut htmlified = Vec::new();
+                format_html_with_plugins(root, &options, &mut htmlified, &plugins).unwrap();
+                let htmlified = String::from_utf8(htmlified).unwrap();
 
                 input.push_back(
                     Tendril::from_str(&htmlified)
@@ -212,6 +770,54 @@ impl HtmlInterpreter {
         None
     }
 
+    // Images within a line of text flow along with it at the size of the text before them
+    fn push_inline_image(&mut self, image: Image) {
+        let size = self
+            .current_textbox
+            .texts
+            .last()
+            .map_or(DEFAULT_TEXT_SIZE, |text| text.size);
+        let mut text = Text::new(String::new(), self.hidpi_scale, self.theme.text_color)
+            .with_size(size)
+            .with_inline_image(image);
+        if let Some(link) = self.state.text_options.link.last() {
+            text = text.with_link((*link).clone());
+        }
+        self.current_textbox.texts.push(text);
+    }
+
+    // Numbers the lines of the code block that just ended and pushes them
+    fn push_code_lines(&mut self) {
+        let lines = std::mem::take(&mut self.state.code_lines);
+        let digits = lines.len().to_string().len();
+        let first_source_line = self.state.source_line.take();
+        for (i, mut line) in lines.into_iter().enumerate() {
+            line.set_line_number(Some(LineNumber {
+                number: i + 1,
+                digits,
+            }));
+            line.source_line = first_source_line.map(|source_line| source_line + i);
+            if self.state.diff_block {
+                let text: String = line.texts.iter().map(|text| text.text.as_str()).collect();
+                line.diff = diff_change(&text);
+            }
+            if let Some(change) = line.diff {
+                let background = self
+                    .theme
+                    .diff_background(change, self.state.code_background);
+                let color = ensure_contrast(
+                    self.theme.diff_color(change),
+                    background,
+                    CodeTheme::MIN_CONTRAST,
+                );
+                for text in &mut line.texts {
+                    text.color = Some(color);
+                }
+            }
+            self.push_element(line.into());
+        }
+    }
+
     fn push_current_textbox(&mut self) {
         // Push any inline images
         if let Some((row, count)) = self.state.inline_images.take() {
@@ -223,8 +829,9 @@ impl HtmlInterpreter {
             }
         }
 
-        if !self.current_textbox.texts.is_empty() {
-            let mut empty = true;
+        let trailing_breaks = self.take_trailing_breaks();
+        if !self.current_textbox.texts.is_empty() || self.current_textbox.list_marker.is_some() {
+            let mut empty = self.current_textbox.list_marker.is_none();
             for text in &self.current_textbox.texts {
                 if !text.text.trim().is_empty() {
                     empty = false;
@@ -232,30 +839,320 @@ impl HtmlInterpreter {
                 }
             }
             if !empty {
+                if self.current_textbox.source_line.is_none() {
+                    self.current_textbox.source_line = self.state.source_line.take();
+                }
                 self.current_textbox.indent = self.state.global_indent;
-                let section = self.state.element_stack.iter_mut().rev().find_map(|e| {
-                    if let html::Element::Details(section) = e {
-                        Some(section)
-                    } else {
-                        None
-                    }
-                });
-                if let Some(section) = section {
-                    section
-                        .elements
-                        .push(Positioned::new(self.current_textbox.clone().into()));
+                self.current_textbox.list_depth = self.list_depth();
+                self.current_textbox.set_admonition(self.admonition());
+                self.current_textbox.ids.append(&mut self.state.ids);
+                self.current_textbox.definition_part = self.state.definition_parts.last().copied();
+                // Code blocks keep the background of their highlighting
+                if !self.current_textbox.is_code_block
+                    && self.current_textbox.background_color.is_none()
+                {
+                    let background = self.block_background();
+                    self.current_textbox.set_background_color(background);
+                }
+                if self.is_attribution(&self.current_textbox) {
+                    self.push_attribution(false);
+                    self.state.attribution = Some((self.current_textbox.clone(), Vec::new()));
                 } else {
-                    self.push_element(self.current_textbox.clone().into());
+                    self.push_block(self.current_textbox.clone().into());
                 }
             }
         }
+        // Breaks at the end of a paragraph have no line left to push down, so they become space
+        // below it instead
+        if let Some(size) = trailing_breaks {
+            self.push_block(Spacer::new(size, false).into());
+        }
         self.current_textbox = TextBox::new(Vec::new(), self.hidpi_scale);
         self.current_textbox.indent = self.state.global_indent;
+        self.current_textbox.list_depth = self.list_depth();
+    }
+
+    // Paragraphs of quotes that start with a dash like `— Author` might be attributing the quote
+    fn is_attribution(&self, text_box: &TextBox) -> bool {
+        let text: String = text_box
+            .texts
+            .iter()
+            .map(|text| text.text.as_str())
+            .collect();
+        self.state.text_options.block_quote >= 1
+            && !text_box.is_code_block
+            && text.trim_start().starts_with(&['\u{2014}', '\u{2013}'][..])
+    }
+
+    // Pushes the quote paragraph that was held back in case it attributes the quote. It only gets
+    // set apart from the rest of the quote when nothing else came after it in the quote
+    fn push_attribution(&mut self, ends_quote: bool) {
+        if let Some((mut attribution, spacers)) = self.state.attribution.take() {
+            if ends_quote {
+                attribution.set_align(Align::Right);
+                for text in &mut attribution.texts {
+                    text.is_italic = true;
+                }
+            }
+            self.push_block(attribution.into());
+            for spacer in spacers {
+                self.push_block(spacer.into());
+            }
+        }
+    }
+
+    // Holds spacers back along with a held attribution instead of letting them push it
+    fn hold_with_attribution(&mut self, element: Element) -> Option<Element> {
+        match (element, &mut self.state.attribution) {
+            (Element::Spacer(spacer), Some((_, spacers))) => {
+                spacers.push(spacer);
+                None
+            }
+            (element, _) => Some(element),
+        }
+    }
+
+    // Pushes into the innermost open `<details>` when there is one
+    fn push_block(&mut self, element: Element) {
+        let element = match self.hold_with_attribution(element) {
+            Some(element) => element,
+            None => return,
+        };
+        self.push_attribution(false);
+        let section = self.state.element_stack.iter_mut().rev().find_map(|e| {
+            if let html::Element::Details(section) = e {
+                Some(section)
+            } else {
+                None
+            }
+        });
+        if let Some(section) = section {
+            section.elements.push(Positioned::new(element));
+        } else {
+            self.push_element(element);
+        }
+    }
+
+    // Hard breaks stay within the text box so that the lines around them still flow as a single
+    // paragraph
+    fn push_line_break(&mut self) {
+        let size = self
+            .current_textbox
+            .texts
+            .last()
+            .map_or(DEFAULT_TEXT_SIZE, |text| text.size);
+        let text = Text::new("\n".to_string(), self.hidpi_scale, self.theme.text_color);
+        self.current_textbox.texts.push(text.with_size(size));
+    }
+
+    // Removes the whitespace ending the current text box and returns the height of the lines that
+    // any hard breaks in it would have added
+    fn take_trailing_breaks(&mut self) -> Option<f32> {
+        if self.current_textbox.is_code_block {
+            return None;
+        }
+        let mut height = 0.;
+        while let Some(text) = self.current_textbox.texts.last() {
+            if !text.text.trim().is_empty() || text.inline_image.is_some() {
+                break;
+            }
+            height += text.text.matches('\n').count() as f32 * text.size;
+            self.current_textbox.texts.pop();
+        }
+        (height > 0.).then_some(height)
+    }
+
+    fn list_depth(&self) -> usize {
+        self.state
+            .element_stack
+            .iter()
+            .filter(|element| matches!(element, html::Element::List(_)))
+            .count()
+    }
+
+    // The background of the innermost `div` or `p` that has one
+    fn block_background(&self) -> Option<[f32; 4]> {
+        self.state
+            .block_backgrounds
+            .iter()
+            .rev()
+            .flatten()
+            .next()
+            .copied()
+    }
+
+    // The styles of the open spans combined
+    fn span_style(&self) -> InlineStyle {
+        self.state
+            .span_styles
+            .iter()
+            .fold(InlineStyle::default(), |outer, style| style.within(outer))
+    }
+
+    // The type of the innermost callout that's open
+    fn admonition(&self) -> Option<AdmonitionKind> {
+        self.state
+            .admonitions
+            .iter()
+            .rev()
+            .flatten()
+            .next()
+            .copied()
     }
+
+    // Callouts start with a line holding only their `[!KIND]` marker. When `text` starts with one
+    // it's swapped for a title line and the text after the marker is returned
+    fn take_admonition_marker(&mut self, text: &str) -> Option<String> {
+        let text = text.trim_start();
+        let (marker, rest) = text.split_once('\n').unwrap_or((text, ""));
+        let kind = AdmonitionKind::from_marker(marker.trim_end())?;
+        *self.state.admonitions.last_mut()? = Some(kind);
+
+        let title = format!("{} {}", kind.icon(), kind.title());
+        let title =
+            Text::new(title, self.hidpi_scale, self.theme.admonition_color(kind)).make_bold(true);
+        self.current_textbox.texts.push(title);
+        self.current_textbox
+            .set_quote_block(Some(self.state.text_options.block_quote));
+        self.current_textbox
+            .set_block_padding(self.block_padding.quote);
+        self.push_current_textbox();
+        Some(rest.trim_start().to_owned())
+    }
+
+    fn in_table(&self) -> bool {
+        self.state
+            .element_stack
+            .iter()
+            .any(|element| matches!(element, html::Element::Table(_)))
+    }
+
+    // Searches the currently nested elements for the innermost table
+    fn current_table(&mut self) -> Option<&mut Table> {
+        self.state
+            .element_stack
+            .iter_mut()
+            .rev()
+            .find_map(|element| match element {
+                html::Element::Table(table) => Some(table),
+                _ => None,
+            })
+    }
+
+    // Moves the text of the table cell being interpreted into the cell's content. Blank text is
+    // only kept for cells that would be empty without it
+    fn flush_cell_text(&mut self, cell_ends: bool) {
+        let blank = self
+            .current_textbox
+            .texts
+            .iter()
+            .all(|text| text.text.trim().is_empty());
+        if let Some(elements) = self.state.cell_elements.last_mut() {
+            if !blank || (cell_ends && elements.is_empty()) {
+                let mut text_box = self.current_textbox.clone();
+                text_box.source_line = self.state.source_line.take();
+                elements.push(Positioned::new(text_box.into()));
+            }
+        }
+        self.current_textbox.texts.clear();
+    }
+
+    // Takes the content of the table cell that just ended aligning text to its column. Text
+    // around nested tables gets stacked along with them
+    fn take_table_cell(&mut self, align: Align) -> Positioned<Element> {
+        self.flush_cell_text(true);
+        let mut elements = self
+            .state
+            .cell_elements
+            .last_mut()
+            .map(std::mem::take)
+            .unwrap_or_default();
+        for element in &mut elements {
+            if let Element::TextBox(text_box) = &mut element.inner {
+                text_box.set_align(align);
+            }
+        }
+        if elements.len() == 1 {
+            return elements.remove(0);
+        }
+        let layout = self
+            .current_table()
+            .map(|table| table.layout)
+            .unwrap_or(self.table_layout);
+        let stack = Table::new()
+            .with_layout(layout)
+            .with_column_limit(self.column_limit)
+            .with_hidpi_scale(self.hidpi_scale)
+            .stacked(elements);
+        Positioned::new(stack.into())
+    }
+
+    // Starts the current text box with the bullet or number of the list item being interpreted
+    fn push_list_marker(&mut self) {
+        self.state.awaiting_list_marker = false;
+        // Bullets take their shape from the unordered lists around the innermost one
+        let outer_unordered = self
+            .state
+            .element_stack
+            .iter()
+            .filter(|element| {
+                matches!(
+                    element,
+                    html::Element::List(html::List {
+                        list_type: html::ListType::Unordered,
+                    })
+                )
+            })
+            .count()
+            .saturating_sub(1);
+        let list = self
+            .state
+            .element_stack
+            .iter_mut()
+            .rev()
+            .find_map(|element| match element {
+                html::Element::List(list) => Some(list),
+                _ => None,
+            })
+            .expect("List ended unexpectedly");
+
+        let marker = match &mut list.list_type {
+            html::ListType::Ordered(index) => {
+                *index += 1;
+                ListMarker::Number(*index - 1)
+            }
+            html::ListType::Unordered => ListMarker::Bullet(Bullet::at_depth(outer_unordered)),
+        };
+        self.current_textbox.list_marker = Some(marker);
+    }
+
     fn push_spacer(&mut self) {
         self.push_element(Spacer::new(5., false).into());
     }
+
+    // Color of code that the highlighter didn't color. Code blocks follow the code theme while
+    // inline code sits on the document and follows its theme
+    fn code_text_color(&self) -> [f32; 4] {
+        if self.state.text_options.pre_formatted >= 1 {
+            self.legible_code_color(self.code_theme.text_color)
+        } else {
+            self.theme.code_color
+        }
+    }
+
+    fn legible_code_color(&self, color: [f32; 4]) -> [f32; 4] {
+        if self.state.text_options.pre_formatted >= 1 {
+            ensure_contrast(color, self.state.code_background, CodeTheme::MIN_CONTRAST)
+        } else {
+            color
+        }
+    }
     fn push_element(&mut self, element: Element) {
+        let element = match self.hold_with_attribution(element) {
+            Some(element) => element,
+            None => return,
+        };
+        self.push_attribution(false);
         self.element_queue.lock().unwrap().push_back(element);
         if self.first_pass {
             self.window.request_redraw()
@@ -273,9 +1170,30 @@ impl TokenSink for HtmlInterpreter {
         if self.stopped {
             return TokenSinkResult::Continue;
         }
+        // Whether this token directly follows the start of a list item
+        let opened_list_item = std::mem::take(&mut self.state.opened_list_item);
         match token {
             TagToken(tag) => {
                 let tag_name = tag.name.to_string();
+                if tag.kind == TagKind::StartTag {
+                    if let Some(line) = source_line(&tag.attrs) {
+                        self.state.source_line = Some(line);
+                    }
+                    if let Some(lines) = blank_lines(&tag.attrs) {
+                        self.push_current_textbox();
+                        self.push_block(
+                            Spacer::new(lines as f32 * DEFAULT_TEXT_SIZE, false).into(),
+                        );
+                    }
+                }
+                let id = (tag.kind == TagKind::StartTag)
+                    .then(|| {
+                        tag.attrs
+                            .iter()
+                            .find(|attr| attr.name.local == local_name!("id"))
+                    })
+                    .flatten()
+                    .map(|attr| format!("#{}", attr.value));
                 match tag.kind {
                     TagKind::StartTag => match tag_name.as_str() {
                         "blockquote" => {
@@ -283,16 +1201,63 @@ impl TokenSink for HtmlInterpreter {
                             self.push_current_textbox();
                             self.state.text_options.block_quote += 1;
                             self.state.global_indent += DEFAULT_MARGIN / 2.;
+                            self.state.admonitions.push(None);
+                            self.state.awaiting_admonition = true;
                             self.current_textbox
                                 .set_quote_block(Some(self.state.text_options.block_quote));
+                            self.current_textbox
+                                .set_block_padding(self.block_padding.quote);
+                        }
+                        "th" => {
+                            self.state.text_options.bold += 1;
+                            let mut align = Align::Left;
+                            for attr in &tag.attrs {
+                                if attr.name.local == local_name!("align") {
+                                    match attr.value.to_string().as_str() {
+                                        "center" => align = Align::Center,
+                                        "right" => align = Align::Right,
+                                        _ => {}
+                                    }
+                                }
+                            }
+                            let width = column_width(&tag.attrs);
+                            if let Some(table) = self.current_table() {
+                                if let Some(width) = width {
+                                    table.set_column_width(table.alignments.len(), width);
+                                }
+                                table.alignments.push(align);
+                            }
+                        }
+                        "td" => {
+                            let column = match self.state.element_stack.last() {
+                                Some(html::Element::TableRow(row)) => row.len(),
+                                _ => 0,
+                            };
+                            if let Some(width) = column_width(&tag.attrs) {
+                                if let Some(table) = self.current_table() {
+                                    table.set_column_width(column, width);
+                                }
+                            }
                         }
-                        "th" => self.state.text_options.bold += 1,
-                        "td" => {}
                         "table" => {
-                            self.push_spacer();
-                            self.state
-                                .element_stack
-                                .push(html::Element::Table(Table::new()));
+                            if self.in_table() {
+                                // Text before the nested table stays in the cell above it
+                                self.flush_cell_text(false);
+                            } else {
+                                self.push_spacer();
+                            }
+                            self.state.cell_elements.push(Vec::new());
+                            let layout = tag
+                                .attrs
+                                .iter()
+                                .find(|attr| &attr.name.local == "data-layout")
+                                .and_then(|attr| TableLayout::from_attr(&attr.value))
+                                .unwrap_or(self.table_layout);
+                            let table = Table::new()
+                                .with_layout(layout)
+                                .with_column_limit(self.column_limit)
+                                .with_hidpi_scale(self.hidpi_scale);
+                            self.state.element_stack.push(html::Element::Table(table));
                         }
                         "a" => {
                             let attrs = tag.attrs;
@@ -304,29 +1269,53 @@ impl TokenSink for HtmlInterpreter {
                             }
                         }
                         "small" => self.state.text_options.small += 1,
-                        "br" => self.push_current_textbox(),
-                        "ins" | "u" => self.state.text_options.underline += 1,
-                        "del" | "s" => self.state.text_options.strike_through += 1,
+                        "sup" => self
+                            .state
+                            .text_options
+                            .vertical_align
+                            .push(VerticalAlign::Super),
+                        "sub" => self
+                            .state
+                            .text_options
+                            .vertical_align
+                            .push(VerticalAlign::Sub),
+                        "br" => {
+                            let clear = tag.attrs.iter().any(|attr| {
+                                attr.name.local == local_name!("clear") && &*attr.value != "none"
+                            });
+                            if clear {
+                                self.push_current_textbox();
+                                self.push_block(Spacer::clearing().into());
+                            } else {
+                                self.push_line_break();
+                            }
+                        }
+                        "ins" => {
+                            self.state.text_options.underline += 1;
+                            self.state.text_options.change.push(Change::Inserted);
+                        }
+                        "del" => {
+                            self.state.text_options.strike_through += 1;
+                            self.state.text_options.change.push(Change::Deleted);
+                        }
+                        "mark" => self.state.text_options.change.push(Change::Marked),
+                        "kbd" => self.state.text_options.code += 1,
+                        "u" => self.state.text_options.underline += 1,
+                        "s" => self.state.text_options.strike_through += 1,
                         "img" => {
                             let mut align = None;
-                            let mut size = None;
+                            let mut float = None;
+                            let mut size = ImageSize::default();
                             for attr in &tag.attrs {
                                 match attr.name.local {
                                     local_name!("align") => match attr.value.to_string().as_str() {
                                         "center" => align = Some(Align::Center),
-                                        "left" => align = Some(Align::Left),
+                                        "left" => float = Some(Float::Left),
+                                        "right" => float = Some(Float::Right),
                                         _ => {}
                                     },
-                                    local_name!("width") => {
-                                        if let Ok(px_width) = attr.value.parse::<u32>() {
-                                            size = Some(ImageSize::PxWidth(px_width));
-                                        }
-                                    }
-                                    local_name!("height") => {
-                                        if let Ok(px_height) = attr.value.parse::<u32>() {
-                                            size = Some(ImageSize::PxHeight(px_height));
-                                        }
-                                    }
+                                    local_name!("width") => size.width = attr.value.parse().ok(),
+                                    local_name!("height") => size.height = attr.value.parse().ok(),
                                     _ => {}
                                 }
                             }
@@ -348,17 +1337,32 @@ impl TokenSink for HtmlInterpreter {
                                             src,
                                             self.file_path.clone(),
                                             self.hidpi_scale,
+                                            &self.image_decoder,
                                         )
                                         .with_align(*align),
                                     };
                                     if let Some(link) = self.state.text_options.link.last() {
                                         image.set_link((*link).clone())
                                     }
-                                    if let Some(size) = size {
+                                    if size != ImageSize::default() {
                                         image = image.with_size(size);
                                     }
+                                    // Floats go in ahead of the text box being built so that it
+                                    // wraps around them
+                                    if let Some(float) = float {
+                                        self.push_element(image.with_float(float).into());
+                                        self.state.floats = true;
+                                        break;
+                                    }
 
-                                    if align == &Align::Left {
+                                    let mid_sentence = self
+                                        .current_textbox
+                                        .texts
+                                        .iter()
+                                        .any(|text| !text.text.trim().is_empty());
+                                    if align == &Align::Left && mid_sentence {
+                                        self.push_inline_image(image);
+                                    } else if align == &Align::Left {
                                         if let Some((row, count)) = &mut self.state.inline_images {
                                             row.elements.push(Positioned::new(image.into()));
                                             // Restart newline count
@@ -383,6 +1387,7 @@ impl TokenSink for HtmlInterpreter {
                         "div" | "p" => {
                             self.push_current_textbox();
                             let mut align = None;
+                            let mut background = None;
                             for attr in tag.attrs {
                                 if attr.name.local == local_name!("align")
                                     || attr.name.local == *"text-align"
@@ -393,8 +1398,11 @@ impl TokenSink for HtmlInterpreter {
                                         "right" => align = Some(Align::Right),
                                         _ => {}
                                     }
+                                } else if attr.name.local == local_name!("style") {
+                                    background = InlineStyle::parse(&attr.value).background;
                                 }
                             }
+                            self.state.block_backgrounds.push(background);
                             if let Some(align) = align.or_else(|| self.find_current_align()) {
                                 self.current_textbox.set_align(align);
                             }
@@ -405,10 +1413,41 @@ impl TokenSink for HtmlInterpreter {
                             });
                         }
                         "em" | "i" => self.state.text_options.italic += 1,
-                        "bold" | "strong" => self.state.text_options.bold += 1,
-                        "code" => self.state.text_options.code += 1,
+                        "b" | "bold" | "strong" => self.state.text_options.bold += 1,
+                        "code" => {
+                            self.state.text_options.code += 1;
+                            // Fences with the `grid` language opt into the grid layout
+                            let is_grid = tag.attrs.iter().any(|attr| {
+                                attr.name.local == local_name!("class")
+                                    && attr.value.split_whitespace().any(|c| c == "language-grid")
+                            });
+                            if self.state.text_options.pre_formatted >= 1 && is_grid {
+                                self.current_textbox.set_monospace_grid(true);
+                            }
+                            self.state.diff_block = self.state.text_options.pre_formatted >= 1
+                                && tag.attrs.iter().any(|attr| {
+                                    attr.name.local == local_name!("class")
+                                        && attr
+                                            .value
+                                            .split_whitespace()
+                                            .any(|c| c == "language-diff")
+                                });
+                        }
+                        "dl" => self.push_current_textbox(),
+                        "dt" => {
+                            self.push_current_textbox();
+                            self.state.text_options.bold += 1;
+                            self.state.definition_parts.push(DefinitionPart::Term);
+                        }
+                        "dd" => {
+                            self.push_current_textbox();
+                            self.state.global_indent += DEFAULT_MARGIN / 2.;
+                            self.state.definition_parts.push(DefinitionPart::Definition);
+                        }
                         "li" => {
                             self.state.element_stack.push(html::Element::ListItem);
+                            self.state.opened_list_item = true;
+                            self.state.awaiting_list_marker = true;
                         }
                         "ul" => {
                             self.push_current_textbox();
@@ -470,6 +1509,7 @@ impl TokenSink for HtmlInterpreter {
                         }
                         "pre" => {
                             self.push_current_textbox();
+                            let mut background = None;
                             for Attribute { name, value } in &tag.attrs {
                                 if &name.local == "style" {
                                     let style = value.to_string();
@@ -478,37 +1518,42 @@ impl TokenSink for HtmlInterpreter {
                                         .find_map(|style| style.strip_prefix("background-color:#"))
                                     {
                                         if let Ok(hex) = u32::from_str_radix(hex_str, 16) {
-                                            let bg_color = hex_to_linear_rgba(hex);
-                                            self.current_textbox
-                                                .set_background_color(Some(bg_color));
+                                            background = Some(hex_to_linear_rgba(hex));
                                         }
                                     }
                                 }
                             }
+                            // A background picked for the code theme wins over the highlighter's
+                            let background = self.code_theme.background.or(background);
+                            self.current_textbox.set_background_color(background);
+                            self.state.code_background =
+                                background.unwrap_or(self.theme.code_block_color);
                             self.state.text_options.pre_formatted += 1;
+                            self.state.span_color = self.code_text_color();
                             self.current_textbox.set_code_block(true);
+                            self.current_textbox.set_monospace_grid(self.monospace_grid);
+                            self.current_textbox
+                                .set_block_padding(self.block_padding.code);
                         }
                         "tr" => {
                             self.state
                                 .element_stack
                                 .push(html::Element::TableRow(Vec::new()));
                         }
-                        // HACK: spans are only supported enough to get syntax highlighting in code
-                        // blocks working
-                        "span" => {
+                        "span" | "font" => {
+                            let mut style = InlineStyle::default();
                             for Attribute { name, value } in &tag.attrs {
                                 if &name.local == "style" {
-                                    let styles = value.to_string();
-                                    if let Some(hex_str) = styles
-                                        .split(';')
-                                        .find_map(|style| style.strip_prefix("color:#"))
-                                    {
-                                        if let Ok(hex) = u32::from_str_radix(hex_str, 16) {
-                                            self.state.span_color = hex_to_linear_rgba(hex);
-                                        }
-                                    }
+                                    style = InlineStyle::parse(value);
+                                } else if &name.local == "color" && tag_name == "font" {
+                                    style.color = css_color(&value.trim().to_ascii_lowercase());
                                 }
                             }
+                            // Highlighted code is kept legible against the code block
+                            if let Some(color) = style.color {
+                                self.state.span_color = self.legible_code_color(color);
+                            }
+                            self.state.span_styles.push(style);
                         }
                         "input" => {
                             for Attribute { name, value } in &tag.attrs {
@@ -516,11 +1561,13 @@ impl TokenSink for HtmlInterpreter {
                                     let value = value.to_string();
                                     if value == "checkbox" {
                                         self.push_current_textbox();
-                                        self.current_textbox.set_checkbox(Some(
-                                            tag.attrs
-                                                .iter()
-                                                .any(|attr| &attr.name.local == "checked"),
-                                        ));
+                                        let is_checked = tag
+                                            .attrs
+                                            .iter()
+                                            .any(|attr| &attr.name.local == "checked");
+                                        self.current_textbox.set_checkbox(Some(is_checked.into()));
+                                        // The checkbox stands in for the item's bullet
+                                        self.state.awaiting_list_marker = false;
                                         self.state.element_stack.push(html::Element::Input);
                                     }
                                 }
@@ -530,7 +1577,9 @@ impl TokenSink for HtmlInterpreter {
                             self.push_current_textbox();
                             self.push_spacer();
                             let section = Section::new(None, vec![], self.hidpi_scale);
-                            *section.hidden.borrow_mut() = true;
+                            // Sections start collapsed unless they're marked as open
+                            let open = tag.attrs.iter().any(|attr| attr.name.local == *"open");
+                            *section.hidden.borrow_mut() = !open;
                             self.state
                                 .element_stack
                                 .push(html::Element::Details(section));
@@ -545,27 +1594,49 @@ impl TokenSink for HtmlInterpreter {
                         _ => {}
                     },
                     TagKind::EndTag => match tag_name.as_str() {
-                        "ins" | "u" => self.state.text_options.underline -= 1,
-                        "del" | "s" => self.state.text_options.strike_through -= 1,
+                        "ins" => {
+                            self.state.text_options.underline -= 1;
+                            self.state.text_options.change.pop();
+                        }
+                        "del" => {
+                            self.state.text_options.strike_through -= 1;
+                            self.state.text_options.change.pop();
+                        }
+                        "mark" => {
+                            self.state.text_options.change.pop();
+                        }
+                        "kbd" => self.state.text_options.code -= 1,
+                        "u" => self.state.text_options.underline -= 1,
+                        "s" => self.state.text_options.strike_through -= 1,
                         "small" => self.state.text_options.small -= 1,
+                        "sub" | "sup" => {
+                            self.state.text_options.vertical_align.pop();
+                        }
                         "th" => {
-                            let mut table = None;
-                            for element in self.state.element_stack.iter_mut().rev() {
-                                if let html::Element::Table(ref mut html_table) = element {
-                                    table = Some(html_table);
-                                    break;
-                                }
+                            let align = self
+                                .current_table()
+                                .map(|table| table.column_align(table.headers.len()))
+                                .unwrap_or_default();
+                            let cell = self.take_table_cell(align);
+                            if let Some(table) = self.current_table() {
+                                table.push_header(cell);
                             }
-                            table.unwrap().push_header(self.current_textbox.clone());
-                            self.current_textbox.texts.clear();
                             self.state.text_options.bold -= 1;
                         }
                         "td" => {
+                            let column = match self.state.element_stack.last() {
+                                Some(html::Element::TableRow(row)) => row.len(),
+                                _ => 0,
+                            };
+                            let align = self
+                                .current_table()
+                                .map(|table| table.column_align(column))
+                                .unwrap_or_default();
+                            let cell = self.take_table_cell(align);
                             let table_row = self.state.element_stack.last_mut();
                             if let Some(html::Element::TableRow(ref mut row)) = table_row {
-                                row.push(self.current_textbox.clone());
+                                row.push(cell);
                             }
-                            self.current_textbox.texts.clear();
                         }
                         "tr" => {
                             let table_row = self.state.element_stack.pop();
@@ -585,8 +1656,15 @@ impl TokenSink for HtmlInterpreter {
                             if let Some(html::Element::Table(table)) =
                                 self.state.element_stack.pop()
                             {
-                                self.push_element(table.into());
-                                self.push_spacer();
+                                self.state.cell_elements.pop();
+                                if self.in_table() {
+                                    if let Some(elements) = self.state.cell_elements.last_mut() {
+                                        elements.push(Positioned::new(table.into()));
+                                    }
+                                } else {
+                                    self.push_element(table.into());
+                                    self.push_spacer();
+                                }
                             }
                         }
                         "a" => {
@@ -599,9 +1677,10 @@ impl TokenSink for HtmlInterpreter {
                                 self.push_spacer();
                             }
                             self.state.element_stack.pop();
+                            self.state.block_backgrounds.pop();
                         }
                         "em" | "i" => self.state.text_options.italic -= 1,
-                        "bold" | "strong" => self.state.text_options.bold -= 1,
+                        "b" | "bold" | "strong" => self.state.text_options.bold -= 1,
                         "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
                             if tag_name.as_str() == "h1" {
                                 self.state.text_options.underline -= 1;
@@ -617,11 +1696,22 @@ impl TokenSink for HtmlInterpreter {
                                 }
                             }
                             self.current_textbox.set_anchor(Some(anchor_name));
+                            if let Some(html::Element::Header(header)) =
+                                self.state.element_stack.last()
+                            {
+                                self.current_textbox.heading_level =
+                                    Some(header.header_type.level());
+                            }
                             self.push_current_textbox();
                             self.push_spacer();
                             self.state.element_stack.pop();
                         }
                         "li" => {
+                            // Empty items keep their marker so that they still take up a line
+                            if opened_list_item && self.current_textbox.texts.is_empty() {
+                                self.push_list_marker();
+                            }
+                            self.state.awaiting_list_marker = false;
                             self.push_current_textbox();
                             self.state.element_stack.pop();
                         }
@@ -629,6 +1719,22 @@ impl TokenSink for HtmlInterpreter {
                             self.push_current_textbox();
                             self.state.element_stack.pop();
                         }
+                        "dl" => {
+                            self.push_current_textbox();
+                            if self.state.global_indent == 0. {
+                                self.push_spacer();
+                            }
+                        }
+                        "dt" => {
+                            self.push_current_textbox();
+                            self.state.text_options.bold -= 1;
+                            self.state.definition_parts.pop();
+                        }
+                        "dd" => {
+                            self.push_current_textbox();
+                            self.state.global_indent -= DEFAULT_MARGIN / 2.;
+                            self.state.definition_parts.pop();
+                        }
                         "ul" | "ol" => {
                             self.push_current_textbox();
                             self.state.global_indent -= DEFAULT_MARGIN / 2.;
@@ -638,21 +1744,40 @@ impl TokenSink for HtmlInterpreter {
                             }
                         }
                         "pre" => {
+                            if self
+                                .current_textbox
+                                .texts
+                                .iter()
+                                .any(|text| !text.text.trim().is_empty())
+                            {
+                                self.state.code_lines.push(self.current_textbox.clone());
+                                self.current_textbox.texts.clear();
+                            }
+                            self.push_code_lines();
+                            self.state.diff_block = false;
                             self.push_current_textbox();
                             self.push_spacer();
                             self.state.text_options.pre_formatted -= 1;
+                            self.state.span_color = self.code_text_color();
                             self.current_textbox.set_code_block(false);
+                            self.current_textbox.set_monospace_grid(false);
                         }
                         "blockquote" => {
                             self.push_current_textbox();
+                            self.push_attribution(true);
                             self.state.text_options.block_quote -= 1;
                             self.state.global_indent -= DEFAULT_MARGIN / 2.;
+                            self.state.admonitions.pop();
+                            self.state.awaiting_admonition = false;
                             self.current_textbox.set_quote_block(None);
                             if self.state.global_indent == 0. {
                                 self.push_spacer();
                             }
                         }
-                        "span" => self.state.span_color = self.theme.code_color,
+                        "span" | "font" => {
+                            self.state.span_color = self.code_text_color();
+                            self.state.span_styles.pop();
+                        }
                         "details" => {
                             self.push_current_textbox();
                             if let Some(html::Element::Details(section)) =
@@ -676,16 +1801,29 @@ impl TokenSink for HtmlInterpreter {
                         _ => {}
                     },
                 }
+                // Goes on after the tag so that block tags don't hand it to the text box before them
+                self.state.ids.extend(id);
             }
             CharacterTokens(str) => {
                 let mut str = str.to_string();
+                if self.state.awaiting_admonition && !str.trim().is_empty() {
+                    self.state.awaiting_admonition = false;
+                    if let Some(rest) = self.take_admonition_marker(&str) {
+                        if rest.trim().is_empty() {
+                            return TokenSinkResult::Continue;
+                        }
+                        str = rest;
+                    }
+                }
                 if str == "\n" {
                     if self.state.text_options.pre_formatted >= 1 {
                         if !self.current_textbox.texts.is_empty() {
-                            self.push_element(self.current_textbox.clone().into());
+                            self.state.code_lines.push(self.current_textbox.clone());
                             self.current_textbox.texts.clear();
                         } else {
-                            self.push_element(self.current_textbox.clone().with_padding(12.).into())
+                            self.state
+                                .code_lines
+                                .push(self.current_textbox.clone().with_font_size(CODE_TEXT_SIZE))
                         }
                     }
                     if let Some(last_text) = self.current_textbox.texts.last() {
@@ -727,67 +1865,47 @@ impl TokenSink for HtmlInterpreter {
                     }
 
                     let mut text = Text::new(str, self.hidpi_scale, self.theme.text_color);
-                    if let Some(html::Element::ListItem) = self.state.element_stack.last() {
-                        let mut list = None;
-                        for element in self.state.element_stack.iter_mut().rev() {
-                            if let html::Element::List(html_list) = element {
-                                list = Some(html_list);
-                            }
-                        }
-                        let list = list.expect("List ended unexpectedly");
-
-                        if self.current_textbox.texts.is_empty() {
-                            if let html::List {
-                                list_type: html::ListType::Ordered(index),
-                                ..
-                            } = list
-                            {
-                                self.current_textbox.texts.push(
-                                    Text::new(
-                                        format!("{}. ", index),
-                                        self.hidpi_scale,
-                                        self.theme.text_color,
-                                    )
-                                    .make_bold(true),
-                                );
-                                *index += 1;
-                            } else if let html::List {
-                                list_type: html::ListType::Unordered,
-                                ..
-                            } = list
-                            {
-                                self.current_textbox.texts.push(
-                                    Text::new(
-                                        "· ".to_string(),
-                                        self.hidpi_scale,
-                                        self.theme.text_color,
-                                    )
-                                    .make_bold(true),
-                                )
-                            }
-                        }
+                    // Only the first line of an item gets its marker. Paragraphs after it and
+                    // after nested lists line up with the text instead
+                    if self.state.awaiting_list_marker && self.current_textbox.texts.is_empty() {
+                        self.push_list_marker();
                     }
                     if self.state.text_options.block_quote >= 1 {
                         self.current_textbox
                             .set_quote_block(Some(self.state.text_options.block_quote));
+                        self.current_textbox
+                            .set_block_padding(self.block_padding.quote);
                     }
                     if self.state.text_options.code >= 1 {
                         text = text
                             .with_color(self.state.span_color)
-                            .with_font(1)
-                            .with_size(18.)
+                            .with_font(fonts::MONOSPACE_FONT)
+                            .with_size(CODE_TEXT_SIZE)
+                    }
+                    if self.font_weights.body != REGULAR_WEIGHT {
+                        text = text.with_weight(self.font_weights.body);
                     }
                     for elem in self.state.element_stack.iter().rev() {
                         if let html::Element::Header(header) = elem {
+                            let weight = self.font_weights.heading;
+                            let level = header.header_type.level();
                             text = text
-                                .with_size(header.header_type.text_size())
-                                .make_bold(true);
+                                .with_size(self.theme.heading_size(level))
+                                .with_weight(weight)
+                                .with_letter_spacing(self.theme.heading_letter_spacing(level))
+                                .make_bold(weight >= BOLD_WEIGHT);
+                            if let Some(color) = self.theme.heading_color(level) {
+                                text = text.with_color(color);
+                            }
                             break;
                         }
                     }
                     if let Some(link) = self.state.text_options.link.last() {
                         text = text.with_link((*link).clone());
-                        text = text.with_color(self.theme.link_color);
+                        text = text.with_link_colors(LinkColors::from(&self.theme));
+                        if self.theme.underline_links {
+                            text = text.make_underlined(true);
+                        }
                     }
                     if self.state.text_options.bold >= 1 {
                         text = text.make_bold(true);
@@ -804,11 +1922,42 @@ impl TokenSink for HtmlInterpreter {
                     if self.state.text_options.small >= 1 {
                         text = text.with_size(12.);
                     }
-                    self.current_textbox.texts.push(text);
+                    let span_style = self.span_style();
+                    // Code already took the span's color
+                    if let Some(color) = span_style.color {
+                        if self.state.text_options.code == 0 {
+                            text = text.with_color(color);
+                        }
+                    }
+                    if span_style.bold {
+                        text = text.make_bold(true);
+                    }
+                    if span_style.italic {
+                        text = text.make_italic(true);
+                    }
+                    if span_style.underline {
+                        text = text.make_underlined(true);
+                    }
+                    if span_style.strike_through {
+                        text = text.make_striked(true);
+                    }
+                    if let Some(letter_spacing) = span_style.letter_spacing {
+                        text = text.with_letter_spacing(letter_spacing);
+                    }
+                    text = text.with_background(span_style.background);
+                    text = text.with_change(self.state.text_options.change.last().copied());
+                    if let Some(vertical_align) = self.state.text_options.vertical_align.last() {
+                        text = text.with_vertical_align(*vertical_align);
+                    }
+                    self.current_textbox.texts.extend(text.split_emoji());
                 }
             }
             EOFToken => {
                 self.push_current_textbox();
+                self.push_attribution(false);
+                if self.state.floats {
+                    self.push_element(Spacer::clearing().into());
+                }
                 self.should_queue
                     .store(false, std::sync::atomic::Ordering::Relaxed);
                 self.first_pass = false;
@@ -819,3 +1968,766 @@ impl TokenSink for HtmlInterpreter {
         TokenSinkResult::Continue
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+    use std::path::PathBuf;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use super::{
+        text_style_subset, HtmlInterpreter, InlineStyle, InterpreterOptions, RawHtml,
+        WindowInteractor,
+    };
+    use crate::color::{hex_to_linear_rgba, CodeTheme, Theme, DARK_DEFAULT};
+    use crate::image::{ImageDecoder, ImageSize, Length};
+    use crate::opts::BlockPadding;
+    use crate::positioner::{BlankLines, Positioned, RowWidth, DEFAULT_MARGIN};
+    use crate::table::{ColumnLimit, TableLayout};
+    use crate::text::{
+        document_title, AdmonitionKind, Bullet, Change, DefinitionPart, ListMarker, TextBox,
+        VerticalAlign, DEFAULT_TEXT_SIZE,
+    };
+    use crate::utils::Align;
+    use crate::Element;
+
+    struct DummyWindow;
+
+    impl WindowInteractor for DummyWindow {
+        fn request_redraw(&self) {}
+    }
+
+    fn interpret_md(md: &str) -> Vec<Element> {
+        interpret_md_with(md, BlankLines::Collapse, RawHtml::Render)
+    }
+
+    fn interpret_md_with(md: &str, blank_lines: BlankLines, raw_html: RawHtml) -> Vec<Element> {
+        interpret_md_themed(md, blank_lines, raw_html, DARK_DEFAULT)
+    }
+
+    fn interpret_md_themed(
+        md: &str,
+        blank_lines: BlankLines,
+        raw_html: RawHtml,
+        theme: Theme,
+    ) -> Vec<Element> {
+        let element_queue = Arc::new(Mutex::new(VecDeque::new()));
+        let options = InterpreterOptions {
+            code_theme: CodeTheme::from(&theme),
+            theme,
+            block_padding: BlockPadding::default(),
+            monospace_grid: false,
+            table_layout: TableLayout::default(),
+            column_limit: ColumnLimit::default(),
+            blank_lines,
+            raw_html,
+            font_weights: FontWeights::default(),
+        };
+        let interpreter = HtmlInterpreter::new(
+            Arc::new(DummyWindow),
+            element_queue.clone(),
+            options,
+            1.,
+            PathBuf::from("test.md"),
+            Arc::new(Mutex::new(HashMap::new())),
+            ImageDecoder::new(),
+        );
+        let (sender, reciever) = mpsc::channel();
+        sender.send(md.to_owned()).unwrap();
+        // Dropping the sender lets the interpreter finish after this document
+        drop(sender);
+        interpreter.intepret_md(reciever);
+        let elements = element_queue.lock().unwrap().drain(..).collect();
+        elements
+    }
+
+    fn section_hidden(md: &str) -> Vec<bool> {
+        interpret_md(md)
+            .iter()
+            .filter_map(|element| match element {
+                Element::Section(section) => Some(*section.hidden.borrow()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn text_boxes_know_their_source_line() {
+        let md = "# Heading\n\nParagraph\n\n```\nfirst\nsecond\n```";
+        let source_lines: Vec<_> = interpret_md(md)
+            .iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.source_line),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(source_lines, [Some(1), Some(3), Some(6), Some(7)]);
+    }
+
+    #[test]
+    fn callouts_get_a_title_line() {
+        let md = "> [!WARNING]\n> Mind the gap\n\n> [!nope]\n> Plain quote";
+        let text_boxes: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box),
+                _ => None,
+            })
+            .collect();
+        let admonitions: Vec<_> = text_boxes.iter().map(|tb| tb.admonition).collect();
+        assert_eq!(
+            admonitions,
+            [
+                Some(AdmonitionKind::Warning),
+                Some(AdmonitionKind::Warning),
+                None
+            ]
+        );
+        assert_eq!(text_boxes[0].texts[0].text, "\u{26a0} Warning");
+        assert_eq!(text_boxes[1].texts[0].text, "Mind the gap");
+        assert!(text_boxes[2].texts[0].text.starts_with("[!nope]"));
+    }
+
+    #[test]
+    fn bare_urls_become_links() {
+        let md = "See https://example.com/docs. Or (www.example.com)";
+        let links: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.texts),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|text| text.link.map(|link| (text.text, link)))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                (
+                    "https://example.com/docs".to_owned(),
+                    "https://example.com/docs".to_owned()
+                ),
+                (
+                    "www.example.com".to_owned(),
+                    "http://www.example.com".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_get_underlined() {
+        let underlined = |underline_links| -> Vec<_> {
+            let theme = Theme {
+                underline_links,
+                ..DARK_DEFAULT
+            };
+            let md = "Plain [link](https://example.com) text";
+            interpret_md_themed(md, BlankLines::Collapse, RawHtml::Render, theme)
+                .into_iter()
+                .filter_map(|element| match element {
+                    Element::TextBox(text_box) => Some(text_box.texts),
+                    _ => None,
+                })
+                .flatten()
+                .map(|text| (text.text.trim().to_owned(), text.is_underlined))
+                .filter(|(text, _)| !text.is_empty())
+                .collect()
+        };
+        let text = |text: &str, is_underlined| (text.to_owned(), is_underlined);
+        assert_eq!(
+            underlined(true),
+            [
+                text("Plain", false),
+                text("link", true),
+                text("text", false)
+            ]
+        );
+        // Links are only told apart by their color by default
+        assert!(underlined(false)
+            .iter()
+            .all(|(_, is_underlined)| !is_underlined));
+        assert!(!DARK_DEFAULT.underline_links);
+    }
+
+    #[test]
+    fn ins_and_del_mark_their_change() {
+        let md = "Was <del>old</del> now <ins>new</ins> <s>plain</s>";
+        let changes: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.texts),
+                _ => None,
+            })
+            .flatten()
+            .filter(|text| !text.text.trim().is_empty())
+            .map(|text| (text.text.trim().to_owned(), text.change))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("Was".to_owned(), None),
+                ("old".to_owned(), Some(Change::Deleted)),
+                ("now".to_owned(), None),
+                ("new".to_owned(), Some(Change::Inserted)),
+                ("plain".to_owned(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn mark_highlights_its_text() {
+        let md = "Plain <mark>marked</mark>";
+        let changes: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.texts),
+                _ => None,
+            })
+            .flatten()
+            .map(|text| text.change)
+            .collect();
+        assert_eq!(changes, [None, Some(Change::Marked)]);
+    }
+
+    #[test]
+    fn double_equals_highlight_their_text() {
+        let md = "Plain ==marked **bold**== and a == b ===";
+        let texts: Vec<_> = interpret_md_with(md, BlankLines::Collapse, RawHtml::Escape)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.texts),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        let marked: Vec<_> = texts
+            .iter()
+            .filter(|text| text.change == Some(Change::Marked))
+            .map(|text| text.text.trim())
+            .collect();
+        assert_eq!(marked, ["marked", "bold"]);
+        let plain: String = texts.iter().map(|text| text.text.as_str()).collect();
+        assert!(plain.ends_with(" and a == b ==="), "{}", plain);
+    }
+
+    #[test]
+    fn sup_and_sub_leave_the_baseline() {
+        let md = "x<sup>2</sup> H<sub>2</sub>O";
+        let aligns: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.texts),
+                _ => None,
+            })
+            .flatten()
+            .filter(|text| !text.text.trim().is_empty())
+            .map(|text| (text.text.trim().to_owned(), text.vertical_align))
+            .collect();
+        assert_eq!(
+            aligns,
+            [
+                ("x".to_owned(), VerticalAlign::Baseline),
+                ("2".to_owned(), VerticalAlign::Super),
+                ("H".to_owned(), VerticalAlign::Baseline),
+                ("2".to_owned(), VerticalAlign::Sub),
+                ("O".to_owned(), VerticalAlign::Baseline),
+            ]
+        );
+    }
+
+    #[test]
+    fn footnotes_link_to_each_other() {
+        let md = "First[^note] and again[^note]\n\n[^note]: The note\n\n[^unused]: Never shown";
+        let links = |text_box: &TextBox| -> Vec<String> {
+            text_box
+                .texts
+                .iter()
+                .filter_map(|text| text.link.clone())
+                .collect()
+        };
+        let text_boxes: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) if !links(&text_box).is_empty() => Some(text_box),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(text_boxes.len(), 2);
+        let (reference, definition) = (&text_boxes[0], &text_boxes[1]);
+        assert_eq!(links(reference), ["#fn1", "#fn1"]);
+        assert_eq!(reference.ids, ["#fnref1", "#fnref1"]);
+        let marker = reference
+            .texts
+            .iter()
+            .find(|text| text.link.is_some())
+            .unwrap();
+        assert_eq!(marker.vertical_align, VerticalAlign::Super);
+
+        assert_eq!(links(definition), ["#fnref1"]);
+        assert_eq!(definition.ids, ["#fn1"]);
+        assert!(!definition
+            .texts
+            .iter()
+            .any(|text| text.text.contains("Never shown")));
+    }
+
+    #[test]
+    fn list_items_get_markers() {
+        let md = "\
+3. three
+4. four
+
+   more of four
+   - bullet
+     - nested
+       - deeper
+   - [ ] task
+";
+        let markers: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => {
+                    let text: String = text_box
+                        .texts
+                        .iter()
+                        .map(|text| text.text.as_str())
+                        .collect();
+                    Some((text.trim().to_owned(), text_box.list_marker))
+                }
+                _ => None,
+            })
+            .collect();
+        let marker = |text: &str, marker| (text.to_owned(), marker);
+        assert_eq!(
+            markers,
+            [
+                marker("three", Some(ListMarker::Number(3))),
+                marker("four", Some(ListMarker::Number(4))),
+                marker("more of four", None),
+                marker("bullet", Some(ListMarker::Bullet(Bullet::Disc))),
+                marker("nested", Some(ListMarker::Bullet(Bullet::Circle))),
+                marker("deeper", Some(ListMarker::Bullet(Bullet::Square))),
+                marker("task", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn definition_lists_indent_their_definitions() {
+        let md = "Apple\n\n: A fruit\n\nCarrot\n\n: A vegetable\n\nAfter";
+        let parts: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => {
+                    let text: String = text_box
+                        .texts
+                        .iter()
+                        .map(|text| text.text.as_str())
+                        .collect();
+                    let is_bold = text_box.texts.iter().all(|text| text.is_bold);
+                    Some((
+                        text.trim().to_owned(),
+                        text_box.definition_part,
+                        is_bold,
+                        text_box.indent,
+                    ))
+                }
+                _ => None,
+            })
+            .collect();
+        let indent = DEFAULT_MARGIN / 2.;
+        let part = |text: &str, part, is_bold, indent| (text.to_owned(), part, is_bold, indent);
+        assert_eq!(
+            parts,
+            [
+                part("Apple", Some(DefinitionPart::Term), true, 0.),
+                part("A fruit", Some(DefinitionPart::Definition), false, indent),
+                part("Carrot", Some(DefinitionPart::Term), true, 0.),
+                part(
+                    "A vegetable",
+                    Some(DefinitionPart::Definition),
+                    false,
+                    indent
+                ),
+                part("After", None, false, 0.),
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_ending_in_a_dash_get_attributed() {
+        let md = "> Quote\n>\n> --- Author\n\n> --- Not last\n>\n> Quote";
+        let styles: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => {
+                    let text: String = text_box
+                        .texts
+                        .iter()
+                        .map(|text| text.text.as_str())
+                        .collect();
+                    let is_italic = text_box.texts.iter().all(|text| text.is_italic);
+                    Some((text.trim().to_owned(), is_italic, text_box.align))
+                }
+                _ => None,
+            })
+            .collect();
+        let style = |text: &str, is_italic, align| (text.to_owned(), is_italic, align);
+        assert_eq!(
+            styles,
+            [
+                style("Quote", false, Align::Left),
+                style("\u{2014} Author", true, Align::Right),
+                style("\u{2014} Not last", false, Align::Left),
+                style("Quote", false, Align::Left),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_lines_know_their_change() {
+        let md = "```diff\n--- a/file\n+++ b/file\n context\n-old\n+new\n```";
+        let changes: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) if text_box.is_code_block => Some(text_box.diff),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                None,
+                None,
+                None,
+                Some(Change::Deleted),
+                Some(Change::Inserted)
+            ]
+        );
+    }
+
+    #[test]
+    fn text_style_subset_drops_other_tags() {
+        let html = br#"<div align="center"><B class="x">bold</B><!-- note --><script>alert(1)</script> 1 < 2<br/></div>"#;
+        assert_eq!(
+            String::from_utf8(text_style_subset(html)).unwrap(),
+            "<b>bold</b> 1 &lt; 2<br>"
+        );
+        // A `>` in a quoted attribute doesn't end the tag
+        let html = br#"<b title="a > b">bold</b><span title='x>y'>kept</span> don't"#;
+        assert_eq!(
+            String::from_utf8(text_style_subset(html)).unwrap(),
+            "<b>bold</b>kept don't"
+        );
+    }
+
+    #[test]
+    fn first_top_level_heading_titles_the_document() {
+        let title = |md: &str| {
+            let elements: Vec<_> = interpret_md(md).into_iter().map(Positioned::new).collect();
+            document_title(&elements)
+        };
+        assert_eq!(
+            title("Intro\n\n## Sub\n\n# Main *title*\n\n# Second").as_deref(),
+            Some("Main title")
+        );
+        assert_eq!(title("## Sub"), None);
+    }
+
+    #[test]
+    fn styled_blocks_get_a_background() {
+        let red = Some(hex_to_linear_rgba(0xff0000));
+        let background = |style| InlineStyle::parse(style).background;
+        assert_eq!(background("color: blue; background: #ff0000"), red);
+        assert_eq!(background("background-color:#f00"), red);
+        assert_eq!(background("background: red"), red);
+        assert_eq!(background("background: url(red.png)"), None);
+
+        let md = "<div style=\"background: #ff0000\">\n\nInside\n\n</div>\n\nOutside";
+        let backgrounds: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.background_color),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(backgrounds, [red, None]);
+    }
+
+    #[test]
+    fn spans_space_out_their_letters() {
+        let spacing = |style| InlineStyle::parse(style).letter_spacing;
+        assert_eq!(spacing("letter-spacing: 0.2em"), Some(0.2));
+        assert_eq!(spacing("letter-spacing: 4px"), Some(4. / DEFAULT_TEXT_SIZE));
+        assert_eq!(spacing("letter-spacing: normal"), Some(0.));
+        assert_eq!(spacing("letter-spacing: 10%"), None);
+
+        let md = "<span style=\"letter-spacing: 0.5em\">spaced</span> plain";
+        let spacings: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.texts),
+                _ => None,
+            })
+            .flatten()
+            .filter(|text| !text.text.trim().is_empty())
+            .map(|text| (text.text.trim().to_owned(), text.letter_spacing))
+            .collect();
+        assert_eq!(
+            spacings,
+            [("spaced".to_owned(), 0.5), ("plain".to_owned(), 0.)]
+        );
+    }
+
+    #[test]
+    fn spans_style_their_text() {
+        let md =
+            "<span style=\"color: red; background: #00f; font-weight: bold; position: fixed\">\
+                  styled <font color=\"lime\">nested</font></span> plain";
+        let texts: Vec<_> = interpret_md(md)
+            .into_iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.texts),
+                _ => None,
+            })
+            .flatten()
+            .filter(|text| !text.text.trim().is_empty())
+            .map(|text| {
+                (
+                    text.text.trim().to_owned(),
+                    text.color,
+                    text.background,
+                    text.is_bold,
+                )
+            })
+            .collect();
+        let red = Some(hex_to_linear_rgba(0xff0000));
+        let lime = Some(hex_to_linear_rgba(0x00ff00));
+        let blue = Some(hex_to_linear_rgba(0x0000ff));
+        assert_eq!(
+            texts,
+            [
+                ("styled".to_owned(), red, blue, true),
+                ("nested".to_owned(), lime, blue, true),
+                ("plain".to_owned(), None, None, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn html_features_need_render() {
+        let md = "<details open>\n<summary>Summary</summary>\n\nShown\n</details>\n\n\
+            <div style=\"background: #ff0000\">\n\nInside\n\n</div>\n\n\
+            <img src=\"missing.png\" align=\"left\" width=\"50%\">\n\n\
+            <del>old</del> <ins>new</ins> <span style=\"color: red\">red</span> \
+            <font color=\"lime\">lime</font>";
+        let red = Some(hex_to_linear_rgba(0xff0000));
+        let lime = Some(hex_to_linear_rgba(0x00ff00));
+        // Escaped by default and `<span>` and `<font>` are left out of the subset too
+        for raw_html in [RawHtml::default(), RawHtml::RenderSubset] {
+            let elements = interpret_md_with(md, BlankLines::Collapse, raw_html);
+            let mut shown = String::new();
+            for element in elements {
+                let text_box = match element {
+                    Element::TextBox(text_box) => text_box,
+                    Element::Section(_) | Element::Image(_) | Element::Row(_) => {
+                        panic!("{raw_html:?} rendered {element:?}")
+                    }
+                    _ => continue,
+                };
+                assert_ne!(text_box.background_color, red, "{raw_html:?}");
+                for text in text_box.texts {
+                    assert_eq!(text.change, None, "{raw_html:?}");
+                    assert!(text.color != red && text.color != lime, "{raw_html:?}");
+                    shown.push_str(&text.text);
+                }
+            }
+            assert!(shown.contains("red") && shown.contains("lime"), "{shown}");
+        }
+    }
+
+    #[test]
+    fn raw_html_policies() {
+        let md = "<b>bold</b> <u>under</u>\n\n<div>block</div>";
+        let texts = |raw_html| -> Vec<_> {
+            interpret_md_with(md, BlankLines::Collapse, raw_html)
+                .into_iter()
+                .filter_map(|element| match element {
+                    Element::TextBox(text_box) => Some(text_box.texts),
+                    _ => None,
+                })
+                .flatten()
+                .filter(|text| !text.text.trim().is_empty())
+                .map(|text| {
+                    (
+                        text.text.trim().to_owned(),
+                        text.is_bold,
+                        text.is_underlined,
+                    )
+                })
+                .collect()
+        };
+        let text = |text: &str, is_bold, is_underlined| (text.to_owned(), is_bold, is_underlined);
+
+        assert_eq!(
+            texts(RawHtml::Render),
+            [
+                text("bold", true, false),
+                text("under", false, true),
+                text("block", false, false),
+            ]
+        );
+        assert_eq!(
+            texts(RawHtml::RenderSubset),
+            [
+                text("bold", true, false),
+                text("under", false, false),
+                text("block", false, false),
+            ]
+        );
+        assert_eq!(texts(RawHtml::Strip), [text("bold under", false, false)]);
+        let escaped: String = texts(RawHtml::Escape)
+            .into_iter()
+            .map(|(text, is_bold, _)| {
+                assert!(!is_bold);
+                text
+            })
+            .collect();
+        assert!(escaped.contains("<b>bold</b>"));
+        assert!(escaped.contains("<div>block</div>"));
+    }
+
+    #[test]
+    fn details_start_collapsed() {
+        let md = "<details>\n<summary>Summary</summary>\n\nHidden text\n</details>";
+        assert_eq!(section_hidden(md), [true]);
+    }
+
+    #[test]
+    fn open_details_start_expanded() {
+        let md = "<details open>\n<summary>Summary</summary>\n\nShown text\n</details>";
+        assert_eq!(section_hidden(md), [false]);
+    }
+
+    fn spacers(elements: &[Element]) -> Vec<f32> {
+        elements
+            .iter()
+            .filter_map(|element| match element {
+                Element::Spacer(spacer) => Some(spacer.space),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hard_breaks_stay_within_the_paragraph() {
+        let elements = interpret_md("one  \ntwo\\\nthree");
+        let text_boxes: Vec<_> = elements
+            .iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text_boxes.len(), 1);
+        let text: String = text_boxes[0]
+            .texts
+            .iter()
+            .map(|t| t.text.as_str())
+            .collect();
+        assert_eq!(text, "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn trailing_hard_breaks_become_space() {
+        let elements = interpret_md("Paragraph<br><br>\n\nNext");
+        let texts: Vec<_> = elements
+            .iter()
+            .filter_map(|element| match element {
+                Element::TextBox(text_box) => Some(text_box.texts.len()),
+                _ => None,
+            })
+            .collect();
+        // The breaks aren't left dangling at the end of the paragraph
+        assert_eq!(texts, [1, 1]);
+        assert!(spacers(&elements).contains(&(2. * DEFAULT_TEXT_SIZE)));
+    }
+
+    #[test]
+    fn blank_lines() {
+        let md = "First\n\n\n\nSecond\n\nThird";
+        let extra = 2. * DEFAULT_TEXT_SIZE;
+        assert!(!spacers(&interpret_md(md)).contains(&extra));
+        let preserved = spacers(&interpret_md_with(
+            md,
+            BlankLines::Preserve,
+            RawHtml::Render,
+        ));
+        assert_eq!(preserved.iter().filter(|&&space| space == extra).count(), 1);
+    }
+
+    #[test]
+    fn image_sizes_keep_their_units() {
+        let elements =
+            interpret_md(r#"<img src="missing.png" align="center" width="50%" height="2em">"#);
+        let size = elements.iter().find_map(|element| match element {
+            Element::Image(image) => image.size,
+            _ => None,
+        });
+        assert_eq!(
+            size,
+            Some(ImageSize {
+                width: Some(Length::Percent(50.)),
+                height: Some(Length::Em(2.)),
+            })
+        );
+    }
+
+    #[test]
+    fn table_cells_set_their_column_widths() {
+        let html = "<table><tr><th width=\"120\">A</th><th>B</th><th>C</th></tr>\
+            <tr><td width=\"10\">1</td><td width=\"30%\">2</td><td>3</td></tr></table>";
+        let widths = interpret_md(html)
+            .into_iter()
+            .find_map(|element| match element {
+                Element::Table(table) => Some(table.widths),
+                _ => None,
+            });
+        assert_eq!(
+            widths,
+            Some(vec![Some(RowWidth::Fixed(120.)), Some(RowWidth::Flex(30.))])
+        );
+    }
+
+    #[test]
+    fn text_around_nested_tables_stays_in_the_cell() {
+        let html = "<table><tr><td>before<table><tr><td>inner</td></tr></table>after</td>\
+            <td>plain</td></tr></table>";
+        let table = interpret_md(html)
+            .into_iter()
+            .find_map(|element| match element {
+                Element::Table(table) => Some(table),
+                _ => None,
+            })
+            .unwrap();
+        let text = |element: &Element| match element {
+            Element::TextBox(text_box) => text_box
+                .texts
+                .iter()
+                .map(|text| text.text.as_str())
+                .collect::<String>(),
+            _ => panic!("Expected text, got {:?}", element),
+        };
+        let stack = match &table.rows[0][0].inner {
+            Element::Table(stack) if stack.stacked => stack,
+            cell => panic!("Expected a stacked cell, got {:?}", cell),
+        };
+        assert_eq!(text(&stack.headers[0].inner), "before");
+        assert!(matches!(&stack.rows[0][0].inner, Element::Table(inner) if !inner.stacked));
+        assert_eq!(text(&stack.rows[1][0].inner), "after");
+        // Cells without a nested table keep holding just their text
+        assert_eq!(text(&table.rows[0][1].inner), "plain");
+    }
+}
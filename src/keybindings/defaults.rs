@@ -20,6 +20,14 @@ pub fn defaults() -> Keybindings {
                 ctrl_or_command,
             )]),
         ),
+        // Render the clipboard as the document: Ctrl+V / Command+V
+        (
+            Action::PasteDocument,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::V),
+                ctrl_or_command,
+            )]),
+        ),
         // Zoom in: Ctrl++ / Command++
         (
             Action::ZoomIn,
@@ -51,6 +59,44 @@ pub fn defaults() -> Keybindings {
                 ctrl_or_command,
             )]),
         ),
+        // Scale up text: +
+        (
+            Action::TextScaleUp,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::Equals),
+                ModifiersState::SHIFT,
+            )]),
+        ),
+        (
+            Action::TextScaleUp,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::Plus),
+                ModifiersState::SHIFT,
+            )]),
+        ),
+        // Scale down text: -
+        (
+            Action::TextScaleDown,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::Minus)]),
+        ),
+        // Focus the next link, checkbox or section: Tab
+        (
+            Action::FocusNext,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::Tab)]),
+        ),
+        // Focus the previous link, checkbox or section: Shift+Tab
+        (
+            Action::FocusPrevious,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::Tab),
+                ModifiersState::SHIFT,
+            )]),
+        ),
+        // Follow or toggle the focused element: Enter
+        (
+            Action::Activate,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::Return)]),
+        ),
         // Scroll up: Up-arrow
         (
             Action::ScrollUp,
@@ -61,6 +107,16 @@ pub fn defaults() -> Keybindings {
             Action::ScrollDown,
             KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::Down)]),
         ),
+        // Scroll left in documents wider than the screen: Left-arrow
+        (
+            Action::ScrollLeft,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::Left)]),
+        ),
+        // Scroll right in documents wider than the screen: Right-arrow
+        (
+            Action::ScrollRight,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::Right)]),
+        ),
         // Go to top of doc: Home
         (
             Action::ToTop,
@@ -71,5 +127,65 @@ pub fn defaults() -> Keybindings {
             Action::ToBottom,
             KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::End)]),
         ),
+        // Toggle render stats overlay: F3
+        (
+            Action::ToggleStats,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::F3)]),
+        ),
+        // Toggle the document minimap: F4
+        (
+            Action::ToggleMinimap,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::F4)]),
+        ),
+        // Step through the document's slides: F5
+        (
+            Action::TogglePresentation,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::F5)]),
+        ),
+        // Switch to the next theme: F6
+        (
+            Action::NextTheme,
+            KeyCombo(vec![ModifiedKey::from(VirtualKeyCode::F6)]),
+        ),
+        // Collapse or expand every section: Ctrl+E / Command+E
+        (
+            Action::ToggleSections,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::E),
+                ctrl_or_command,
+            )]),
+        ),
+        // Switch to the next tab: Ctrl+Tab
+        (
+            Action::NextTab,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::Tab),
+                ModifiersState::CTRL,
+            )]),
+        ),
+        // Switch to the previous tab: Ctrl+Shift+Tab
+        (
+            Action::PreviousTab,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::Tab),
+                ModifiersState::CTRL | ModifiersState::SHIFT,
+            )]),
+        ),
+        // Close the current tab: Ctrl+W / Command+W
+        (
+            Action::CloseTab,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::W),
+                ctrl_or_command,
+            )]),
+        ),
+        // Save the image under the cursor: Ctrl+S / Command+S
+        (
+            Action::SaveImage,
+            KeyCombo(vec![ModifiedKey(
+                Key::from(VirtualKeyCode::S),
+                ctrl_or_command,
+            )]),
+        ),
     ]
 }
@@ -135,10 +135,27 @@ pub enum Action {
     ToBottom,
     ScrollUp,
     ScrollDown,
+    ScrollLeft,
+    ScrollRight,
     ZoomIn,
     ZoomOut,
     ZoomReset,
+    TextScaleUp,
+    TextScaleDown,
+    FocusNext,
+    FocusPrevious,
+    Activate,
     Copy,
+    PasteDocument,
+    ToggleStats,
+    ToggleMinimap,
+    TogglePresentation,
+    ToggleSections,
+    NextTheme,
+    NextTab,
+    PreviousTab,
+    CloseTab,
+    SaveImage,
     Quit,
 }
 
@@ -0,0 +1,122 @@
+pub mod bench;
+pub mod color;
+pub mod document;
+pub mod fonts;
+pub mod glyph_cache;
+pub mod hit;
+pub mod image;
+pub mod interpreter;
+pub mod keybindings;
+pub mod minimap;
+pub mod opts;
+pub mod pdf;
+pub mod png;
+pub mod positioner;
+pub mod renderer;
+pub mod shapes;
+pub mod svg;
+pub mod table;
+pub mod text;
+pub mod utils;
+
+use std::path::PathBuf;
+
+use crate::image::Image;
+use crate::table::Table;
+
+use positioner::Row;
+use positioner::Section;
+use positioner::Spacer;
+use text::TextBox;
+use utils::MaybeImageData;
+
+#[derive(Debug)]
+pub enum InlyneEvent {
+    LoadedImage(String, MaybeImageData),
+    FileReload(PathBuf),
+    /// Everything that's been piped into stdin so far
+    PipedInput(String),
+    Reposition,
+    /// The OS switched between light and dark mode
+    OsThemeChanged(bool),
+}
+
+#[derive(Debug)]
+pub enum Element {
+    TextBox(TextBox),
+    Spacer(Spacer),
+    Image(Image),
+    Table(Table),
+    Row(Row),
+    Section(Section),
+}
+
+impl Element {
+    /// Updates the scale factor that the element (and anything within it) gets sized by
+    pub fn set_hidpi_scale(&mut self, hidpi_scale: f32) {
+        let set_all = |elements: &mut [positioner::Positioned<Element>]| {
+            for element in elements {
+                element.inner.set_hidpi_scale(hidpi_scale);
+            }
+        };
+        match self {
+            Element::TextBox(text_box) => text_box.set_hidpi_scale(hidpi_scale),
+            Element::Spacer(_) => {}
+            Element::Image(image) => image.hidpi_scale = hidpi_scale,
+            Element::Table(table) => {
+                table.hidpi_scale = hidpi_scale;
+                set_all(&mut table.headers);
+                for row in &mut table.rows {
+                    set_all(row);
+                }
+            }
+            Element::Row(row) => {
+                row.hidpi_scale = hidpi_scale;
+                set_all(&mut row.elements);
+            }
+            Element::Section(section) => {
+                section.hidpi_scale = hidpi_scale;
+                if let Some(summary) = &mut *section.summary {
+                    summary.inner.set_hidpi_scale(hidpi_scale);
+                }
+                set_all(&mut section.elements);
+            }
+        }
+    }
+}
+
+impl From<Section> for Element {
+    fn from(section: Section) -> Self {
+        Element::Section(section)
+    }
+}
+
+impl From<Row> for Element {
+    fn from(row: Row) -> Self {
+        Element::Row(row)
+    }
+}
+
+impl From<Image> for Element {
+    fn from(image: Image) -> Self {
+        Element::Image(image)
+    }
+}
+
+impl From<Spacer> for Element {
+    fn from(spacer: Spacer) -> Self {
+        Element::Spacer(spacer)
+    }
+}
+
+impl From<TextBox> for Element {
+    fn from(text_box: TextBox) -> Self {
+        Element::TextBox(text_box)
+    }
+}
+
+impl From<Table> for Element {
+    fn from(table: Table) -> Self {
+        Element::Table(table)
+    }
+}
@@ -1,51 +1,38 @@
-pub mod color;
-pub mod fonts;
-pub mod image;
-pub mod interpreter;
-mod keybindings;
-pub mod opts;
-pub mod positioner;
-pub mod renderer;
-pub mod table;
-pub mod text;
-pub mod utils;
-
-use crate::image::Image;
-use crate::interpreter::HtmlInterpreter;
-use crate::opts::Opts;
-use crate::table::Table;
-use crate::text::Text;
-
-use keybindings::{Action, Key, KeyCombos, ModifiedKey};
-use opts::Args;
-use opts::Config;
-use positioner::Positioned;
-use positioner::Row;
-use positioner::Section;
-use positioner::Spacer;
-use positioner::DEFAULT_MARGIN;
-use positioner::DEFAULT_PADDING;
-use renderer::Renderer;
-use text::TextBox;
-use utils::{ImageCache, MaybeImageData, Point, Rect, Size};
+use inlyne::bench::{Bench, BENCH_SCROLL_STEP};
+use inlyne::color::{CodeTheme, Theme};
+use inlyne::hit::{self, HitStep};
+use inlyne::image::{Image, ImageDecoder, SavableImage};
+use inlyne::interpreter::{HtmlInterpreter, InterpreterOptions};
+use inlyne::keybindings::{Action, Key, KeyCombos, ModifiedKey};
+use inlyne::opts::{
+    Args, AutoTheme, Config, Opts, ScrollTo, State, TextScale, ThemeChoice, ThemeFile,
+};
+use inlyne::positioner::{self, Positioned, Section};
+use inlyne::renderer::{
+    self, AdapterFallback, DocumentState, FocusAction, Focusable, Renderer, ScrollbarState,
+};
+use inlyne::text::{self, Checkbox, Text, TextBox};
+use inlyne::utils::{self, ImageCache, LinkOpener, Point, Size};
+use inlyne::{pdf, png, svg, Element, InlyneEvent};
 
 use anyhow::Context;
 use copypasta::{ClipboardContext, ClipboardProvider};
 use notify::op::Op;
-use notify::{raw_watcher, RecursiveMode, Watcher};
+use notify::{raw_watcher, RecommendedWatcher, RecursiveMode, Watcher};
 
 use winit::event::ModifiersState;
 use winit::event::{ElementState, MouseButton};
 use winit::{
+    dpi::LogicalSize,
     event::{Event, KeyboardInput, MouseScrollDelta, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::{CursorIcon, Window},
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy},
+    window::{CursorIcon, Window, WindowBuilder},
 };
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
@@ -53,79 +40,120 @@ use std::sync::mpsc;
 use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
-pub enum InlyneEvent {
-    LoadedImage(String, MaybeImageData),
-    FileReload,
-    Reposition,
-}
+// How often the OS's dark mode setting gets checked while the theme follows it. The checks get
+// spaced further apart up to the maximum for as long as the setting stays the same
+const THEME_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const THEME_POLL_MAX_INTERVAL: Duration = Duration::from_secs(30);
+// Shortest time between renders of markdown that's still being piped in
+const STDIN_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
 
 pub enum Hoverable<'a> {
     Image(&'a Image),
     Text(&'a Text),
+    Checkbox(&'a TextBox),
     Summary(&'a Section),
 }
 
-#[derive(Debug)]
-pub enum Element {
-    TextBox(TextBox),
-    Spacer(Spacer),
-    Image(Image),
-    Table(Table),
-    Row(Row),
-    Section(Section),
-}
-
-impl From<Section> for Element {
-    fn from(section: Section) -> Self {
-        Element::Section(section)
-    }
-}
-
-impl From<Row> for Element {
-    fn from(row: Row) -> Self {
-        Element::Row(row)
+impl Hoverable<'_> {
+    fn cursor_icon(&self) -> CursorIcon {
+        match self {
+            Self::Image(Image { is_link: None, .. }) => CursorIcon::Default,
+            Self::Text(Text { link: None, .. }) => CursorIcon::Text,
+            Self::Image(_) | Self::Text(_) | Self::Checkbox(_) | Self::Summary(_) => {
+                CursorIcon::Hand
+            }
+        }
     }
 }
 
-impl From<Image> for Element {
-    fn from(image: Image) -> Self {
-        Element::Image(image)
-    }
+// Where the markdown of a tab comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    File,
+    Stdin,
+    Clipboard,
 }
 
-impl From<Spacer> for Element {
-    fn from(spacer: Spacer) -> Self {
-        Element::Spacer(spacer)
-    }
+// An open document. The renderer holds the state of the active tab's document while the other
+// tabs keep theirs in `document`
+struct Tab {
+    // Relative links and images are still resolved from here when the markdown isn't from a file
+    file_path: PathBuf,
+    source: Source,
+    // The markdown of documents that don't come from a file so that they can be parsed again
+    markdown: Option<String>,
+    document: DocumentState,
+    element_queue: Arc<Mutex<VecDeque<Element>>>,
+    interpreter_sender: mpsc::Sender<String>,
+    interpreter_should_queue: Arc<AtomicBool>,
+    // Where to scroll to once the document finishes parsing
+    scroll_to: Option<ScrollTo>,
+    // Text of the document's first top level heading once it's been parsed
+    heading: Option<String>,
+    // Watches the tab's file for changes until the tab gets closed
+    _watcher: Option<RecommendedWatcher>,
 }
 
-impl From<TextBox> for Element {
-    fn from(text_box: TextBox) -> Self {
-        Element::TextBox(text_box)
+impl Tab {
+    fn title(&self) -> String {
+        match self.source {
+            Source::File => self
+                .file_path
+                .file_name()
+                .unwrap_or(self.file_path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            Source::Stdin => "stdin".to_owned(),
+            Source::Clipboard => "clipboard".to_owned(),
+        }
     }
-}
 
-impl From<Table> for Element {
-    fn from(table: Table) -> Self {
-        Element::Table(table)
+    // Documents go by their first heading and otherwise by their path
+    fn window_title(&self) -> String {
+        let path =
+            root_filepath_to_vcs_dir(&self.file_path).filter(|_| self.source == Source::File);
+        match (&self.heading, path) {
+            (Some(heading), _) => format!("Inlyne - {}", heading),
+            (None, Some(path)) => format!("Inlyne - {}", path.to_string_lossy()),
+            (None, None) => "Inlyne".to_owned(),
+        }
     }
 }
 
 pub struct Inlyne {
     window: Arc<Window>,
-    event_loop: EventLoop<InlyneEvent>,
+    event_proxy: EventLoopProxy<InlyneEvent>,
     renderer: Renderer,
-    element_queue: Arc<Mutex<VecDeque<Element>>>,
     clipboard: ClipboardContext,
-    elements: Vec<Positioned<Element>>,
     lines_to_scroll: f32,
-    args: Args,
+    scroll_multiplier: f32,
+    invert_scroll: bool,
     image_cache: ImageCache,
-    interpreter_sender: mpsc::Sender<String>,
-    interpreter_should_queue: Arc<AtomicBool>,
+    // Shared by every tab's interpreter so that parsing again doesn't start more workers
+    image_decoder: ImageDecoder,
+    tabs: Vec<Tab>,
+    active_tab: usize,
+    // The theme in here is left behind by theme changes. Interpreters get the renderer's
+    interpreter_options: InterpreterOptions,
+    link_opener: LinkOpener,
+    checkbox_write_back: bool,
+    // Set when the theme follows the OS's dark mode setting
+    auto_theme: Option<AutoTheme>,
+    themes: Vec<ThemeChoice>,
+    // Name of the theme from `themes` that's being shown. Themes can be identical, so they're
+    // told apart by name instead of by their colors
+    theme_name: Option<String>,
+    theme_file: Option<ThemeFile>,
     keycombos: KeyCombos,
+    text_scale: TextScale,
+    state: State,
+    // Whether the scale tracks the monitor the window is on. Unset when the config or CLI picks
+    // the scale
+    follows_monitor_scale: bool,
+    // Set when benchmarking with `--bench`
+    bench: Option<Bench>,
 }
 
 /// Gets a relative path extending from the repo root falling back to the full path
@@ -162,144 +190,534 @@ fn root_filepath_to_vcs_dir(path: &Path) -> Option<PathBuf> {
 }
 
 impl Inlyne {
-    pub fn spawn_watcher(&self) {
+    /// Reloads `file_path` whenever it gets written to. The thread sending the reloads stops once
+    /// the returned watcher gets dropped
+    pub fn spawn_watcher(&self, file_path: PathBuf) -> anyhow::Result<RecommendedWatcher> {
         // Create a channel to receive the events.
         let (watch_tx, watch_rx) = channel();
 
         // Create a watcher object, delivering raw events.
         // The notification back-end is selected based on the platform.
-        let mut watcher = raw_watcher(watch_tx).unwrap();
+        let mut watcher = raw_watcher(watch_tx)?;
 
         // Add the file path to be watched.
-        let event_proxy = self.event_loop.create_proxy();
-        let file_path = self.args.file_path.clone();
+        watcher.watch(&file_path, RecursiveMode::NonRecursive)?;
+
+        let event_proxy = self.event_proxy.clone();
         std::thread::spawn(move || {
-            watcher
-                .watch(file_path, RecursiveMode::NonRecursive)
-                .unwrap();
+            // Events stop coming in once the watcher is dropped along with its sender
+            for event in watch_rx {
+                if event.op.map_or(false, |op| op.intersects(Op::WRITE)) {
+                    // Always reload the primary configuration file.
+                    let _ = event_proxy.send_event(InlyneEvent::FileReload(file_path.clone()));
+                }
+            }
+        });
+        Ok(watcher)
+    }
 
-            loop {
-                let event = match watch_rx.recv() {
-                    Ok(event) => event,
-                    Err(err) => {
-                        log::warn!("Config watcher channel dropped unexpectedly: {}", err);
+    // Watches the directory that the theme file is in instead of the file itself, so that the file
+    // gets picked up when it's created later and when editors save it by replacing it
+    fn spawn_theme_file_watcher(&self, file_path: PathBuf) {
+        let dir = match file_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        let (watch_tx, watch_rx) = channel();
+        let mut watcher = match raw_watcher(watch_tx) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                log::warn!("Failed watching the theme file: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+            log::warn!("Failed watching {:?} for the theme file: {}", dir, err);
+            return;
+        }
+
+        let event_proxy = self.event_proxy.clone();
+        std::thread::spawn(move || {
+            // The watcher stops once it's dropped
+            let _watcher = watcher;
+            for event in watch_rx {
+                let is_theme_file =
+                    event.path.as_deref().and_then(Path::file_name) == file_path.file_name();
+                let changed = event.op.map_or(false, |op| {
+                    op.intersects(Op::CREATE | Op::WRITE | Op::RENAME | Op::CLOSE_WRITE)
+                });
+                if is_theme_file && changed {
+                    let reload = InlyneEvent::FileReload(file_path.clone());
+                    if event_proxy.send_event(reload).is_err() {
+                        // The event loop is gone
                         break;
                     }
-                };
+                }
+            }
+        });
+    }
 
-                if event.op.unwrap().intersects(Op::WRITE) {
-                    // Always reload the primary configuration file.
-                    let _ = event_proxy.send_event(InlyneEvent::FileReload);
+    // Follows the OS's dark mode setting. Windows reports changes to the window and GNOME reports
+    // them through `gsettings`. Anywhere else the setting gets checked every so often
+    fn spawn_theme_watcher(&self) {
+        if cfg!(windows) {
+            return;
+        }
+        let event_proxy = self.event_proxy.clone();
+        std::thread::spawn(move || {
+            let mut prefers_dark = utils::os_prefers_dark();
+            // Whether the setting changed or `None` once the event loop is gone
+            let mut update = |now_prefers_dark: bool| {
+                if now_prefers_dark == prefers_dark {
+                    return Some(false);
                 }
+                prefers_dark = now_prefers_dark;
+                let changed = InlyneEvent::OsThemeChanged(prefers_dark);
+                event_proxy.send_event(changed).ok().map(|_| true)
+            };
+            let monitored = cfg!(all(unix, not(target_os = "macos")))
+                && utils::monitor_os_theme(|prefers_dark| update(prefers_dark).is_some());
+            if monitored {
+                return;
+            }
+            let mut interval = THEME_POLL_INTERVAL;
+            loop {
+                std::thread::sleep(interval);
+                interval = match update(utils::os_prefers_dark()) {
+                    Some(true) => THEME_POLL_INTERVAL,
+                    Some(false) => (interval * 2).min(THEME_POLL_MAX_INTERVAL),
+                    None => break,
+                };
             }
         });
     }
 
-    pub async fn new(opts: &Opts, args: Args) -> anyhow::Result<Self> {
+    // Reads stdin on another thread, rendering everything that's been piped in so far every so
+    // often
+    fn spawn_stdin_reader(&self) {
+        let event_proxy = self.event_proxy.clone();
+        std::thread::spawn(move || {
+            let stdin = std::io::stdin();
+            let streamed = utils::stream_markdown(stdin, STDIN_UPDATE_INTERVAL, |md_string| {
+                let _ = event_proxy.send_event(InlyneEvent::PipedInput(md_string));
+            });
+            if let Err(err) = streamed {
+                log::warn!("Failed reading stdin: {}", err);
+            }
+        });
+    }
+
+    pub async fn new(
+        opts: &Opts,
+        args: Args,
+        event_loop: &EventLoop<InlyneEvent>,
+    ) -> anyhow::Result<Self> {
         let keycombos = KeyCombos::new(opts.keybindings.clone())?;
 
-        let event_loop = EventLoop::<InlyneEvent>::with_user_event();
-        let window = Arc::new(Window::new(&event_loop).unwrap());
-        match root_filepath_to_vcs_dir(&args.file_path) {
-            Some(path) => window.set_title(&format!("Inlyne - {}", path.to_string_lossy())),
-            None => window.set_title("Inlyne"),
+        let state = State::load().unwrap_or_else(|err| {
+            log::warn!("Failed loading state: {}", err);
+            State::default()
+        });
+        // Documents reopen at the size they were last closed at
+        let window_size = state.window_size(&args.file_path).or_else(|| {
+            match (opts.window_width, opts.window_height) {
+                (None, None) => None,
+                // The side that isn't set matches winit's default size
+                (width, height) => Some([
+                    width.map_or(800., f64::from),
+                    height.map_or(600., f64::from),
+                ]),
+            }
+        });
+        let mut window_builder = WindowBuilder::new();
+        if let Some([width, height]) = window_size {
+            window_builder = window_builder.with_inner_size(LogicalSize::new(width, height));
         }
-        let renderer = Renderer::new(
+        let window = Arc::new(window_builder.build(event_loop).unwrap());
+        // The theme switched to last time wins over the config's unless the CLI picks one, the
+        // theme follows the OS or there's a theme file
+        let remembered_theme = state
+            .theme
+            .as_ref()
+            .filter(|_| {
+                args.theme.is_none() && opts.auto_theme.is_none() && opts.theme_file.is_none()
+            })
+            .and_then(|name| opts.themes.iter().find(|choice| &choice.name == name));
+        let (theme, code_theme) = match remembered_theme {
+            Some(choice) => (choice.theme.clone(), choice.code_theme),
+            None => (opts.theme.clone(), opts.code_theme),
+        };
+        let mut renderer = Renderer::new(
             &window,
-            opts.theme.clone(),
+            theme,
             opts.scale.unwrap_or(window.scale_factor() as f32),
             opts.font_opts.clone(),
+            opts.staging_belt_size,
+            opts.glyph_cache_size,
+            opts.glyph_cache_scale_tolerance,
+            opts.reduce_motion
+                .unwrap_or_else(utils::os_prefers_reduced_motion),
+            AdapterFallback::from_env().unwrap_or(opts.gpu_fallback),
+            opts.gpu_backend,
+            opts.gpu_power,
+            opts.gpu.as_ref(),
         )
         .await?;
+        renderer.positioner.max_image_width = opts.max_image_width;
+        renderer.positioner.max_image_height = opts.max_image_height;
+        renderer.set_dither(opts.dither);
+        if let Some(max_size) = opts.glyph_cache_max_size {
+            renderer.set_glyph_cache_max_size(max_size);
+        }
+        renderer.pixel_snap = opts.pixel_snap;
+        renderer.positioner.line_numbers = opts.line_numbers;
+        renderer.positioner.spacing = opts.spacing;
+        renderer.indent_guides = opts.indent_guides;
+        renderer.show_minimap = opts.minimap;
+        renderer.presentation = opts.presentation;
+        renderer.doc_stats_options = opts.doc_stats;
+        renderer.selection_options = opts.selection;
+        renderer.image_style = opts.image_style;
+        renderer.code_theme = code_theme;
+        // The default text scale has to fit within the configured range too
+        renderer.positioner.text_scale = opts.text_scale.clamp(state.text_scale.unwrap_or(1.));
         let clipboard = ClipboardContext::new().unwrap();
 
-        let element_queue = Arc::new(Mutex::new(VecDeque::new()));
         let image_cache = Arc::new(Mutex::new(HashMap::new()));
-        let interpreter = HtmlInterpreter::new(
-            window.clone(),
-            element_queue.clone(),
-            renderer.theme.clone(),
-            renderer.hidpi_scale,
-            args.file_path.clone(),
-            image_cache.clone(),
-        );
-
-        let (interpreter_sender, interpreter_reciever) = channel();
-        let interpreter_should_queue = interpreter.should_queue.clone();
-        std::thread::spawn(move || interpreter.intepret_md(interpreter_reciever));
-        let md_string = std::fs::read_to_string(&opts.file_path)
-            .with_context(|| format!("Could not read file at {:?}", opts.file_path))?;
-        interpreter_sender.send(md_string)?;
 
-        Ok(Self {
+        let mut inlyne = Self {
             window,
-            event_loop,
+            event_proxy: event_loop.create_proxy(),
             renderer,
-            element_queue,
             clipboard,
-            elements: Vec::new(),
             lines_to_scroll: opts.lines_to_scroll,
-            args,
-            interpreter_sender,
-            interpreter_should_queue,
+            scroll_multiplier: opts.scroll_multiplier,
+            invert_scroll: opts.invert_scroll,
             image_cache,
+            image_decoder: ImageDecoder::new(),
+            tabs: Vec::new(),
+            active_tab: 0,
+            interpreter_options: InterpreterOptions::from(opts),
+            link_opener: opts.link_opener.clone(),
+            checkbox_write_back: opts.checkbox_write_back,
+            auto_theme: opts.auto_theme.clone(),
+            themes: opts.themes.clone(),
+            theme_name: remembered_theme.map(|choice| choice.name.clone()),
+            theme_file: opts.theme_file.clone(),
             keycombos,
-        })
+            text_scale: opts.text_scale,
+            state,
+            follows_monitor_scale: opts.scale.is_none(),
+            bench: opts.bench.then(Bench::new),
+        };
+        if inlyne.auto_theme.is_some() {
+            inlyne.spawn_theme_watcher();
+        }
+        if let Some(theme_file) = &inlyne.theme_file {
+            inlyne.spawn_theme_file_watcher(theme_file.path.clone());
+        }
+        inlyne.open_tab(args.file_path, opts.scroll_to)?;
+        for file_path in args.extra_files {
+            inlyne.open_tab(file_path, None)?;
+        }
+        inlyne.switch_tab(0)?;
+        Ok(inlyne)
+    }
+
+    // Starts parsing the file at `file_path` in a new tab and switches to it
+    fn open_tab(&mut self, file_path: PathBuf, scroll_to: Option<ScrollTo>) -> anyhow::Result<()> {
+        let element_queue = Arc::new(Mutex::new(VecDeque::new()));
+        let (interpreter_sender, interpreter_should_queue) =
+            self.spawn_interpreter(file_path.clone(), element_queue.clone());
+        let (source, watcher) = if utils::is_stdin(&file_path) {
+            // Piped input gets rendered as it comes in, so there's nothing to parse just yet
+            self.spawn_stdin_reader();
+            (Source::Stdin, None)
+        } else {
+            interpreter_sender.send(utils::read_markdown(&file_path)?)?;
+            let watcher = match self.spawn_watcher(file_path.clone()) {
+                Ok(watcher) => Some(watcher),
+                Err(err) => {
+                    log::warn!("Failed watching {:?} for changes: {}", file_path, err);
+                    None
+                }
+            };
+            (Source::File, watcher)
+        };
+
+        self.tabs.push(Tab {
+            file_path,
+            source,
+            markdown: None,
+            document: DocumentState::default(),
+            element_queue,
+            interpreter_sender,
+            interpreter_should_queue,
+            scroll_to,
+            heading: None,
+            _watcher: watcher,
+        });
+        self.switch_tab(self.tabs.len() - 1)
+    }
+
+    // Starts a thread that parses the markdown sent to it into `element_queue` with the current
+    // theme. The thread stops once the returned sender gets dropped
+    fn spawn_interpreter(
+        &self,
+        file_path: PathBuf,
+        element_queue: Arc<Mutex<VecDeque<Element>>>,
+    ) -> (mpsc::Sender<String>, Arc<AtomicBool>) {
+        let interpreter = HtmlInterpreter::new(
+            self.window.clone(),
+            element_queue,
+            InterpreterOptions {
+                theme: self.renderer.theme.clone(),
+                code_theme: self.renderer.code_theme,
+                ..self.interpreter_options.clone()
+            },
+            self.renderer.hidpi_scale,
+            file_path,
+            self.image_cache.clone(),
+            self.image_decoder.clone(),
+        );
+        let (sender, reciever) = channel();
+        let should_queue = interpreter.should_queue.clone();
+        std::thread::spawn(move || interpreter.intepret_md(reciever));
+        (sender, should_queue)
+    }
+
+    // Switches between the light and dark theme when following the OS's dark mode setting
+    fn follow_os_theme(&mut self, prefers_dark: bool) {
+        if let Some(auto_theme) = &self.auto_theme {
+            let (theme, code_theme) = auto_theme.pick(prefers_dark);
+            self.theme_name = None;
+            self.set_theme(theme, code_theme);
+        }
+    }
+
+    // Picks up changes to the theme file. A broken file keeps the current theme around
+    fn reload_theme_file(&mut self) {
+        let choice = match self.theme_file.as_ref().map(ThemeFile::load) {
+            Some(Ok(choice)) => choice,
+            Some(Err(err)) => {
+                log::warn!("{:#}", err);
+                return;
+            }
+            None => return,
+        };
+        match self.themes.iter_mut().find(|old| old.name == choice.name) {
+            Some(old) => *old = choice.clone(),
+            None => self.themes.push(choice.clone()),
+        }
+        self.auto_theme = None;
+        self.theme_name = Some(choice.name);
+        self.set_theme(choice.theme, choice.code_theme);
+    }
+
+    // Cycles to the theme after the current one and remembers it for next time. Picking a theme
+    // stops following the OS's dark mode setting
+    fn next_theme(&mut self) {
+        let current = match &self.theme_name {
+            Some(name) => self.themes.iter().position(|choice| &choice.name == name),
+            // Themes from the CLI or the OS's dark mode setting can still match one by its colors
+            None => self
+                .themes
+                .iter()
+                .position(|choice| choice.theme == self.renderer.theme),
+        };
+        let next = current.map_or(0, |index| (index + 1) % self.themes.len());
+        let choice = self.themes[next].clone();
+        self.auto_theme = None;
+        self.theme_name = Some(choice.name.clone());
+        self.state.theme = Some(choice.name);
+        Self::save_state(&self.state);
+        self.set_theme(choice.theme, choice.code_theme);
     }
 
-    pub fn run(mut self) {
+    // The theme's colors get baked into the text while parsing, so every tab gets its own
+    // interpreter with the new theme and gets parsed again. Tabs that fail to load again are
+    // logged and left for the rest to get the theme
+    fn set_theme(&mut self, theme: Theme, code_theme: CodeTheme) {
+        if theme == self.renderer.theme && code_theme == self.renderer.code_theme {
+            return;
+        }
+        self.renderer.set_theme(theme, code_theme);
+        for index in 0..self.tabs.len() {
+            let tab = &self.tabs[index];
+            tab.interpreter_should_queue.store(false, Ordering::Relaxed);
+            let (sender, should_queue) =
+                self.spawn_interpreter(tab.file_path.clone(), tab.element_queue.clone());
+            let tab = &mut self.tabs[index];
+            tab.interpreter_sender = sender;
+            tab.interpreter_should_queue = should_queue;
+            let loaded = match (tab.source, tab.markdown.clone()) {
+                (Source::File, _) => self.reload_tab(index),
+                (_, Some(md_string)) => self.load_markdown(index, md_string),
+                // Nothing's been piped in yet
+                (_, None) => Ok(()),
+            };
+            if let Err(err) = loaded {
+                log::warn!(
+                    "Failed loading {} with the new theme: {:#}",
+                    self.tabs[index].title(),
+                    err
+                );
+            }
+        }
+        self.window.request_redraw();
+    }
+
+    fn switch_tab(&mut self, index: usize) -> anyhow::Result<()> {
+        if index != self.active_tab {
+            // The new tab's document gets swapped into the renderer for the active one, which gets
+            // stored in its tab. The active tab holds onto an empty document in the meantime
+            let mut document = std::mem::take(&mut self.tabs[index].document);
+            let swapped = self.renderer.swap_document(&mut document);
+            self.tabs[self.active_tab].document = document;
+            self.active_tab = index;
+            swapped?;
+        }
+
+        self.window.set_title(&self.tabs[index].window_title());
+        let titles = self.tabs.iter().map(Tab::title).collect();
+        self.renderer.set_tabs(titles, index)?;
+        self.window.request_redraw();
+        Ok(())
+    }
+
+    // Closes the active tab. Returns false when it's the last one
+    fn close_tab(&mut self) -> anyhow::Result<bool> {
+        if self.tabs.len() == 1 {
+            return Ok(false);
+        }
+        let closing = self.active_tab;
+        let next = if closing + 1 < self.tabs.len() {
+            closing + 1
+        } else {
+            closing - 1
+        };
+        self.switch_tab(next)?;
+        self.tabs.remove(closing);
+        if next > closing {
+            self.active_tab -= 1;
+        }
+        self.switch_tab(self.active_tab)?;
+        Ok(true)
+    }
+
+    // Parses the file of the tab at `index` again from the top
+    fn reload_tab(&mut self, index: usize) -> anyhow::Result<()> {
+        let md_string = utils::read_markdown(&self.tabs[index].file_path)?;
+        self.load_markdown(index, md_string)
+    }
+
+    // Replaces the document of the tab at `index` with `md_string`
+    fn load_markdown(&mut self, index: usize, md_string: String) -> anyhow::Result<()> {
+        let tab = &mut self.tabs[index];
+        if tab.source != Source::File {
+            tab.markdown = Some(md_string.clone());
+        }
+        tab.interpreter_should_queue.store(false, Ordering::Relaxed);
+        tab.element_queue.lock().unwrap().clear();
+        if index == self.active_tab {
+            self.renderer.elements.clear();
+            self.renderer.positioner.reserved_height =
+                self.renderer.positioner.top(self.renderer.zoom);
+            self.renderer.positioner.anchors.clear();
+            self.renderer.positioner.slides.clear();
+            self.renderer.loading = Some(Instant::now());
+            self.renderer.invalidate();
+        } else {
+            tab.document.elements.clear();
+            tab.document.anchors.clear();
+            tab.document.slides.clear();
+            tab.document.loading = Some(Instant::now());
+        }
+        tab.interpreter_should_queue.store(true, Ordering::Relaxed);
+        tab.interpreter_sender.send(md_string)?;
+        Ok(())
+    }
+
+    pub fn run(mut self, event_loop: EventLoop<InlyneEvent>) {
         let mut pending_resize = None;
         let mut scrollbar_held = None;
+        let mut minimap_held = false;
         let mut mouse_down = false;
         let mut modifiers = ModifiersState::empty();
         let mut last_loc = (0.0, 0.0);
-        let mut selection_cache = String::new();
-        let mut selecting = false;
-        let event_loop_proxy = self.event_loop.create_proxy();
-        self.event_loop.run(move |event, _, control_flow| {
+        // Screen coordinates of the cursor unlike `last_loc` which is in document coordinates
+        let mut cursor_pos = (0.0, 0.0);
+        let event_loop_proxy = self.event_proxy.clone();
+        event_loop.run(move |event, _, control_flow| {
             *control_flow = ControlFlow::Wait;
 
             match event {
                 Event::UserEvent(inlyne_event) => match inlyne_event {
                     InlyneEvent::LoadedImage(src, image_data) => {
                         self.image_cache.lock().unwrap().insert(src, image_data);
-                        self.renderer.reposition(&mut self.elements).unwrap();
+                        self.renderer.reposition().unwrap();
                         self.window.request_redraw()
                     }
-                    InlyneEvent::FileReload => {
-                        self.interpreter_should_queue
-                            .store(false, Ordering::Relaxed);
-                        self.element_queue.lock().unwrap().clear();
-                        self.elements.clear();
-                        self.renderer.positioner.reserved_height =
-                            DEFAULT_PADDING * self.renderer.hidpi_scale;
-                        self.renderer.positioner.anchors.clear();
-                        let md_string = std::fs::read_to_string(&self.args.file_path)
-                            .with_context(|| {
-                                format!("Could not read file at {:?}", self.args.file_path)
-                            })
-                            .unwrap();
-                        self.interpreter_should_queue.store(true, Ordering::Relaxed);
-                        self.interpreter_sender.send(md_string).unwrap();
+                    InlyneEvent::FileReload(file_path) => {
+                        let is_theme_file = self
+                            .theme_file
+                            .as_ref()
+                            .map_or(false, |theme_file| theme_file.path == file_path);
+                        if is_theme_file {
+                            self.reload_theme_file();
+                        }
+                        for index in 0..self.tabs.len() {
+                            let tab = &self.tabs[index];
+                            if tab.source == Source::File && tab.file_path == file_path {
+                                if let Err(err) = self.reload_tab(index) {
+                                    log::warn!("Failed reloading {:?}: {:#}", file_path, err);
+                                }
+                            }
+                        }
+                        self.window.request_redraw();
+                    }
+                    InlyneEvent::PipedInput(md_string) => {
+                        for index in 0..self.tabs.len() {
+                            if self.tabs[index].source == Source::Stdin {
+                                self.load_markdown(index, md_string.clone()).unwrap();
+                            }
+                        }
+                        self.window.request_redraw();
                     }
+                    InlyneEvent::OsThemeChanged(prefers_dark) => self.follow_os_theme(prefers_dark),
                     InlyneEvent::Reposition => {
-                        self.renderer.reposition(&mut self.elements).unwrap();
+                        self.renderer.reposition().unwrap();
                         self.window.request_redraw()
                     }
                 },
                 Event::RedrawRequested(_) => {
+                    let tab = &mut self.tabs[self.active_tab];
+                    // Checked before draining so that every element is in the queue when it's set
+                    let finished_parsing = !tab.interpreter_should_queue.load(Ordering::Relaxed);
                     let queue = {
-                        self.element_queue
+                        tab.element_queue
                             .try_lock()
                             .map(|mut queue| queue.drain(..).collect::<Vec<Element>>())
                     };
                     if let Ok(queue) = queue {
+                        let added_elements = !queue.is_empty();
                         for mut element in queue {
+                            // The interpreter keeps the scale it started with
+                            element.set_hidpi_scale(self.renderer.hidpi_scale);
                             // Adds callback for when image is loaded to reposition and redraw
                             match element {
                                 Element::Image(ref mut image) => {
                                     image.add_callback(event_loop_proxy.clone());
                                 }
+                                Element::TextBox(ref mut text_box) => {
+                                    for text in &mut text_box.texts {
+                                        if let Some(inline_image) = &mut text.inline_image {
+                                            inline_image
+                                                .image
+                                                .add_callback(event_loop_proxy.clone());
+                                        }
+                                    }
+                                }
                                 Element::Row(ref mut row) => {
                                     for element in &mut row.elements {
                                         if let Element::Image(ref mut image) = element.inner {
@@ -313,98 +731,195 @@ impl Inlyne {
                             let mut positioned_element = Positioned::new(element);
                             self.renderer
                                 .positioner
-                                .position(
+                                .place(
                                     &mut self.renderer.glyph_brush,
+                                    positioner::last_block(&self.renderer.elements),
                                     &mut positioned_element,
                                     self.renderer.zoom,
                                 )
                                 .unwrap();
-                            self.renderer.positioner.reserved_height +=
-                                DEFAULT_PADDING * self.renderer.hidpi_scale * self.renderer.zoom
-                                    + positioned_element.bounds.as_ref().unwrap().size.1;
-                            self.elements.push(positioned_element);
+                            self.renderer.elements.push(positioned_element);
+                        }
+                        if added_elements {
+                            text::resolve_checkboxes(&self.renderer.elements);
+                            text::mark_visited(
+                                &self.renderer.elements,
+                                &self.state.visited_links,
+                                &tab.file_path,
+                            );
+                            let heading = text::document_title(&self.renderer.elements);
+                            if heading != tab.heading {
+                                tab.heading = heading;
+                                self.window.set_title(&tab.window_title());
+                            }
+                        }
+                        if finished_parsing {
+                            match tab.scroll_to.take() {
+                                Some(ScrollTo::Fraction(fraction)) => {
+                                    self.renderer.set_scroll_fraction(fraction)
+                                }
+                                Some(ScrollTo::Line(line)) => {
+                                    if !self.renderer.scroll_to_source_line(line) {
+                                        log::warn!("Couldn't find line {} to scroll to", line);
+                                    }
+                                }
+                                None => {}
+                            }
                         }
                     }
+                    // The interpreter stops queuing once it reaches the end of the document
+                    if !self.renderer.elements.is_empty()
+                        || !tab.interpreter_should_queue.load(Ordering::Relaxed)
+                    {
+                        self.renderer.loading = None;
+                    }
                     self.renderer.set_scroll_y(self.renderer.scroll_y);
+                    let frame_start = Instant::now();
                     self.renderer
-                        .redraw(&mut self.elements)
+                        .redraw()
                         .context("Renderer failed to redraw the screen")
                         .unwrap();
-                    if selecting {
-                        selection_cache = self.renderer.selection_text.clone();
+                    if self.renderer.is_animating() {
+                        self.window.request_redraw();
+                    }
+                    if let Some(bench) = &mut self.bench {
+                        // Keeps drawing until the document is in before starting to time frames
+                        if finished_parsing {
+                            bench.record(frame_start.elapsed());
+                            let max_scroll = self.renderer.positioner.reserved_height
+                                - self.renderer.screen_height();
+                            if self.renderer.scroll_y >= max_scroll {
+                                if let Some(report) = bench.report() {
+                                    println!("{}", report);
+                                }
+                                *control_flow = ControlFlow::Exit;
+                                return;
+                            }
+                            self.renderer.set_scroll_y(
+                                self.renderer.scroll_y
+                                    + BENCH_SCROLL_STEP * self.renderer.hidpi_scale,
+                            );
+                        }
+                        self.window.request_redraw();
                     }
                 }
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::Resized(size) => pending_resize = Some(size),
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
+                    } => {
+                        if self.follows_monitor_scale {
+                            self.renderer.set_hidpi_scale(scale_factor as f32).unwrap();
+                        }
+                        pending_resize = Some(*new_inner_size);
+                        self.window.request_redraw();
+                    }
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::MouseWheel { delta, .. } => match delta {
-                        MouseScrollDelta::PixelDelta(pos) => {
-                            Self::scroll_pixels(&mut self.renderer, &self.window, pos.y as f32)
+                    WindowEvent::ThemeChanged(theme) => {
+                        self.follow_os_theme(theme == winit::window::Theme::Dark)
+                    }
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        let direction = if self.invert_scroll { -1.0 } else { 1.0 };
+                        let (x_delta, y_delta) = match delta {
+                            // Trackpads report precise pixel deltas so they aren't scaled by the
+                            // font size like discrete wheel clicks are
+                            MouseScrollDelta::PixelDelta(pos) => (pos.x as f32, pos.y as f32),
+                            MouseScrollDelta::LineDelta(x_delta, y_delta) => {
+                                let line = Self::line_pixels(&self.renderer, self.lines_to_scroll);
+                                (x_delta * line, y_delta * line)
+                            }
+                        };
+                        // Wheels that only go up and down scroll sideways while Shift is held
+                        let (x_delta, y_delta) = if modifiers.shift() && x_delta == 0. {
+                            (y_delta, 0.)
+                        } else {
+                            (x_delta, y_delta)
+                        };
+                        let scale = self.scroll_multiplier * direction;
+                        if x_delta != 0. {
+                            Self::scroll_sideways(
+                                &mut self.renderer,
+                                &self.window,
+                                x_delta * scale,
+                            );
                         }
-                        MouseScrollDelta::LineDelta(_, y_delta) => Self::scroll_lines(
-                            &mut self.renderer,
-                            &self.window,
-                            self.lines_to_scroll,
-                            y_delta,
-                        ),
-                    },
+                        if y_delta != 0. {
+                            Self::scroll_pixels(&mut self.renderer, &self.window, y_delta * scale);
+                        }
+                    }
                     WindowEvent::CursorMoved { position, .. } => {
                         let screen_size = self.renderer.screen_size();
+                        let text_zoom = self.renderer.text_zoom();
                         let loc = (
-                            position.x as f32,
+                            position.x as f32 + self.renderer.scroll_x,
                             position.y as f32 + self.renderer.scroll_y,
                         );
 
-                        let cursor_icon = if let Some(hoverable) = Self::find_hoverable(
-                            &self.elements,
-                            &mut self.renderer.glyph_brush,
-                            loc,
-                            screen_size,
-                            self.renderer.zoom,
-                        ) {
-                            match hoverable {
-                                Hoverable::Image(Image { is_link: None, .. }) => {
-                                    CursorIcon::Default
-                                }
-                                Hoverable::Text(Text { link: None, .. }) => CursorIcon::Text,
-                                _some_link => CursorIcon::Hand,
-                            }
+                        let scrollbar_thumb = self.renderer.scrollbar_thumb_rect();
+                        let cursor_icon = if scrollbar_held.is_some() {
+                            CursorIcon::Grabbing
+                        } else if scrollbar_thumb.contains(position.into()) {
+                            CursorIcon::Grab
+                        } else if self.renderer.tab_at(position.into()).is_some() {
+                            CursorIcon::Hand
+                        } else if self
+                            .renderer
+                            .minimap()
+                            .map_or(false, |minimap| minimap.bounds.contains(position.into()))
+                        {
+                            CursorIcon::Hand
                         } else {
-                            CursorIcon::Default
+                            Self::find_hoverable(
+                                &self.renderer.elements,
+                                &mut self.renderer.glyph_brush,
+                                loc,
+                                screen_size,
+                                self.renderer.zoom,
+                                text_zoom,
+                            )
+                            .map_or(CursorIcon::Default, |hoverable| hoverable.cursor_icon())
                         };
                         self.window.set_cursor_icon(cursor_icon);
 
-                        if scrollbar_held.is_some()
-                            || (Rect::new(
-                                (screen_size.0 - DEFAULT_MARGIN / 4., 0.),
-                                (DEFAULT_MARGIN / 4., screen_size.1),
-                            )
-                            .contains(position.into())
+                        let tooltip = Self::truncated_text(
+                            &self.renderer.elements,
+                            &mut self.renderer.glyph_brush,
+                            loc,
+                            text_zoom,
+                        )
+                        .map(|text| (position.into(), text));
+                        if tooltip != self.renderer.tooltip {
+                            self.renderer.tooltip = tooltip;
+                            self.window.request_redraw();
+                        }
+                        if self.renderer.hover_link(loc) {
+                            self.window.request_redraw();
+                        }
+
+                        if minimap_held {
+                            if let Some(minimap) = self.renderer.minimap() {
+                                let screen_height = self.renderer.screen_height();
+                                self.renderer.set_scroll_y(
+                                    minimap.scroll_y_at(position.y as f32, screen_height),
+                                );
+                                self.window.request_redraw();
+                            }
+                        } else if scrollbar_held.is_some()
+                            || (self
+                                .renderer
+                                .scrollbar_track_rect()
+                                .contains(position.into())
                                 && mouse_down)
                         {
-                            let scrollbar_height = (screen_size.1
-                                / self.renderer.positioner.reserved_height)
-                                * screen_size.1;
+                            let scrollbar_height = scrollbar_thumb.size.1;
                             if scrollbar_held.is_none() {
-                                if Rect::new(
-                                    (
-                                        screen_size.0 - DEFAULT_MARGIN / 4.,
-                                        ((self.renderer.scroll_y
-                                            / self.renderer.positioner.reserved_height)
-                                            * screen_size.1),
-                                    ),
-                                    (DEFAULT_MARGIN / 4., scrollbar_height),
-                                )
-                                .contains(position.into())
-                                {
+                                if scrollbar_thumb.contains(position.into()) {
                                     // If we click in the bounds of the scrollbar, maintain the difference between the
                                     // center of the scrollbar and the mouse
                                     scrollbar_held = Some(
                                         position.y as f32
-                                            - (((self.renderer.scroll_y
-                                                / self.renderer.positioner.reserved_height)
-                                                * screen_size.1)
-                                                + scrollbar_height / 2.),
+                                            - (scrollbar_thumb.pos.1 + scrollbar_height / 2.),
                                     );
                                 } else {
                                     scrollbar_held = Some(0.);
@@ -423,11 +938,22 @@ impl Inlyne {
                         } else if let Some(selection) = &mut self.renderer.selection {
                             if mouse_down {
                                 selection.1 = loc;
-                                selecting = true;
                                 self.window.request_redraw();
                             }
                         }
+                        let scrollbar_state = if scrollbar_held.is_some() {
+                            ScrollbarState::Dragging
+                        } else if scrollbar_thumb.contains(position.into()) {
+                            ScrollbarState::Hover
+                        } else {
+                            ScrollbarState::Idle
+                        };
+                        if self.renderer.scrollbar_state != scrollbar_state {
+                            self.renderer.scrollbar_state = scrollbar_state;
+                            self.window.request_redraw();
+                        }
                         last_loc = loc;
+                        cursor_pos = position.into();
                     }
                     WindowEvent::MouseInput {
                         state,
@@ -435,77 +961,72 @@ impl Inlyne {
                         ..
                     } => match state {
                         ElementState::Pressed => {
-                            // Reset selection
-                            if self.renderer.selection.is_some() {
+                            if let Some(index) = self.renderer.tab_at(cursor_pos) {
+                                self.switch_tab(index).unwrap();
+                                return;
+                            }
+
+                            // Jump to the part of the minimap that was clicked and keep following
+                            // the cursor while it's held
+                            if let Some(minimap) = self.renderer.minimap() {
+                                if minimap.bounds.contains(cursor_pos) {
+                                    let screen_height = self.renderer.screen_height();
+                                    self.renderer.set_scroll_y(
+                                        minimap.scroll_y_at(cursor_pos.1, screen_height),
+                                    );
+                                    self.window.request_redraw();
+                                    minimap_held = true;
+                                    return;
+                                }
+                            }
+
+                            // Reset selection and keyboard focus
+                            if self.renderer.selection.is_some() || self.renderer.focus.is_some() {
                                 self.renderer.selection = None;
+                                self.renderer.focus = None;
                                 self.window.request_redraw();
                             }
 
                             // Try to click a link
                             let screen_size = self.renderer.screen_size();
+                            let text_zoom = self.renderer.text_zoom();
+                            let write_back = self.checkbox_write_back();
                             if let Some(hoverable) = Self::find_hoverable(
-                                &self.elements,
+                                &self.renderer.elements,
                                 &mut self.renderer.glyph_brush,
                                 last_loc,
                                 screen_size,
                                 self.renderer.zoom,
+                                text_zoom,
                             ) {
-                                if let Hoverable::Summary(summary) = hoverable {
-                                    let mut hidden = summary.hidden.borrow_mut();
-                                    *hidden = !*hidden;
+                                if Self::toggle(
+                                    &self.renderer.elements,
+                                    &hoverable,
+                                    write_back.as_deref(),
+                                ) {
                                     event_loop_proxy
                                         .send_event(InlyneEvent::Reposition)
                                         .unwrap();
                                 }
 
+                                // Owned so that the renderer can be used while handling the link
                                 let maybe_link = match hoverable {
-                                    Hoverable::Image(Image { is_link, .. }) => is_link,
-                                    Hoverable::Text(Text { link, .. }) => link,
-                                    Hoverable::Summary(_) => &None,
+                                    Hoverable::Image(Image { is_link, .. }) => is_link.clone(),
+                                    Hoverable::Text(Text { link, .. }) => link.clone(),
+                                    Hoverable::Summary(_) | Hoverable::Checkbox(_) => None,
                                 };
 
                                 if let Some(link) = maybe_link {
-                                    let maybe_path = PathBuf::from_str(link).ok();
-                                    let is_local_md = maybe_path.as_ref().map_or(false, |p| {
-                                        p.extension().map_or(false, |ext| ext == "md")
-                                            && !p.to_str().map_or(false, |s| s.starts_with("http"))
-                                    });
-                                    if is_local_md {
-                                        // Open markdown files ourselves
-                                        let mut args = self.args.clone();
-                                        let maybe_path = maybe_path.expect("not a path");
-                                        // Handle relative paths and make them
-                                        // absolute by prepending current
-                                        // parent
-                                        let maybe_path = if maybe_path.is_relative() {
-                                            // Simply canonicalizing it doesn't suffice and leads to "no such file or directory"
-                                            let current_parent =
-                                                args.file_path.parent().expect("no current parent");
-                                            let link_without_prefix: &Path = maybe_path
-                                                .strip_prefix(std::path::Component::CurDir)
-                                                .expect("no CurDir prefix");
-                                            let mut link = current_parent.to_path_buf();
-                                            link.push(link_without_prefix);
-                                            link
-                                        } else {
-                                            maybe_path
-                                        };
-                                        args.file_path = maybe_path;
-                                        Command::new(
-                                            std::env::current_exe()
-                                                .unwrap_or_else(|_| "inlyne".into()),
-                                        )
-                                        .args(args.program_args())
-                                        .spawn()
-                                        .expect("Could not spawn new inlyne instance");
-                                    } else if open::that(link).is_err() {
-                                        if let Some(anchor_pos) =
-                                            self.renderer.positioner.anchors.get(link)
-                                        {
-                                            self.renderer.set_scroll_y(*anchor_pos);
-                                            self.window.request_redraw();
-                                            self.window.set_cursor_icon(CursorIcon::Default);
-                                        }
+                                    let linked_file = Self::follow_link(
+                                        link,
+                                        &self.tabs[self.active_tab].file_path,
+                                        &mut self.renderer,
+                                        &self.window,
+                                        &mut self.state,
+                                        &self.link_opener,
+                                    );
+                                    if let Some(file_path) = linked_file {
+                                        self.open_linked_file(file_path);
                                     }
                                 } else if self.renderer.selection.is_none() {
                                     // Only set selection when not over link
@@ -518,9 +1039,18 @@ impl Inlyne {
                             mouse_down = true;
                         }
                         ElementState::Released => {
-                            scrollbar_held = None;
+                            if scrollbar_held.take().is_some() {
+                                self.window.set_cursor_icon(CursorIcon::Grab);
+                                self.renderer.scrollbar_state =
+                                    if self.renderer.scrollbar_thumb_rect().contains(cursor_pos) {
+                                        ScrollbarState::Hover
+                                    } else {
+                                        ScrollbarState::Idle
+                                    };
+                                self.window.request_redraw();
+                            }
+                            minimap_held = false;
                             mouse_down = false;
-                            selecting = false;
                         }
                     },
                     WindowEvent::ModifiersChanged(new_state) => modifiers = new_state,
@@ -553,12 +1083,36 @@ impl Inlyne {
                                         _ => unreachable!("This arm is only for scroll actions"),
                                     };
 
-                                    Self::scroll_lines(
+                                    if self.renderer.presentation {
+                                        let num_pixels = lines
+                                            * 16.0
+                                            * self.lines_to_scroll
+                                            * self.renderer.hidpi_scale
+                                            * self.renderer.zoom;
+                                        self.renderer.scroll_slides(-num_pixels);
+                                        self.window.request_redraw();
+                                    } else {
+                                        Self::scroll_lines(
+                                            &mut self.renderer,
+                                            &self.window,
+                                            self.lines_to_scroll,
+                                            lines,
+                                        )
+                                    }
+                                }
+                                a_scroll @ (Action::ScrollLeft | Action::ScrollRight) => {
+                                    let lines = match a_scroll {
+                                        Action::ScrollLeft => 1.0,
+                                        Action::ScrollRight => -1.0,
+                                        _ => unreachable!("This arm is only for scroll actions"),
+                                    };
+                                    let num_pixels = lines
+                                        * Self::line_pixels(&self.renderer, self.lines_to_scroll);
+                                    Self::scroll_sideways(
                                         &mut self.renderer,
                                         &self.window,
-                                        self.lines_to_scroll,
-                                        lines,
-                                    )
+                                        num_pixels,
+                                    );
                                 }
                                 a_zoom @ (Action::ZoomIn | Action::ZoomOut | Action::ZoomReset) => {
                                     let zoom = match a_zoom {
@@ -570,23 +1124,162 @@ impl Inlyne {
 
                                     self.renderer.zoom = zoom;
                                     let old_reserved = self.renderer.positioner.reserved_height;
-                                    self.renderer.reposition(&mut self.elements).unwrap();
+                                    self.renderer.reposition().unwrap();
+                                    let new_reserved = self.renderer.positioner.reserved_height;
+                                    self.renderer.set_scroll_y(
+                                        self.renderer.scroll_y * (new_reserved / old_reserved),
+                                    );
+                                    self.window.request_redraw();
+                                }
+                                a_scale @ (Action::TextScaleUp | Action::TextScaleDown) => {
+                                    let text_scale = self.renderer.positioner.text_scale;
+                                    let text_scale = self.text_scale.clamp(match a_scale {
+                                        Action::TextScaleUp => text_scale * 1.1,
+                                        Action::TextScaleDown => text_scale * 0.9,
+                                        _ => {
+                                            unreachable!("This arm is only for text scale actions")
+                                        }
+                                    });
+
+                                    let old_reserved = self.renderer.positioner.reserved_height;
+                                    self.renderer.set_text_scale(text_scale).unwrap();
                                     let new_reserved = self.renderer.positioner.reserved_height;
                                     self.renderer.set_scroll_y(
                                         self.renderer.scroll_y * (new_reserved / old_reserved),
                                     );
                                     self.window.request_redraw();
+
+                                    self.state.text_scale = Some(text_scale);
+                                    Self::save_state(&self.state);
+                                }
+                                a_focus @ (Action::FocusNext | Action::FocusPrevious) => {
+                                    self.renderer.move_focus(a_focus == Action::FocusPrevious);
+                                    self.window.request_redraw();
+                                }
+                                Action::Activate => {
+                                    if let Some(Focusable { bounds, action, .. }) =
+                                        self.renderer.focused()
+                                    {
+                                        match action {
+                                            FocusAction::Link(link) => {
+                                                let linked_file = Self::follow_link(
+                                                    link,
+                                                    &self.tabs[self.active_tab].file_path,
+                                                    &mut self.renderer,
+                                                    &self.window,
+                                                    &mut self.state,
+                                                    &self.link_opener,
+                                                );
+                                                if let Some(file_path) = linked_file {
+                                                    self.open_linked_file(file_path);
+                                                }
+                                            }
+                                            FocusAction::Checkbox | FocusAction::Summary => {
+                                                // Finds the element the same way a click would
+                                                let screen_size = self.renderer.screen_size();
+                                                let text_zoom = self.renderer.text_zoom();
+                                                let write_back = self.checkbox_write_back();
+                                                let toggled = Self::find_hoverable(
+                                                    &self.renderer.elements,
+                                                    &mut self.renderer.glyph_brush,
+                                                    bounds.center(),
+                                                    screen_size,
+                                                    self.renderer.zoom,
+                                                    text_zoom,
+                                                )
+                                                .map_or(false, |hoverable| {
+                                                    Self::toggle(
+                                                        &self.renderer.elements,
+                                                        &hoverable,
+                                                        write_back.as_deref(),
+                                                    )
+                                                });
+                                                if toggled {
+                                                    event_loop_proxy
+                                                        .send_event(InlyneEvent::Reposition)
+                                                        .unwrap();
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                                Action::Copy => {
+                                    let selection_text = self.renderer.compute_selection_text();
+                                    if !selection_text.trim().is_empty() {
+                                        self.clipboard
+                                            .set_contents(selection_text.trim().to_owned())
+                                            .unwrap()
+                                    }
+                                }
+                                Action::PasteDocument => match self.clipboard.get_contents() {
+                                    Ok(md_string) => {
+                                        let tab = &mut self.tabs[self.active_tab];
+                                        // The file (or stdin) isn't shown anymore, so changes to
+                                        // it shouldn't replace the pasted document
+                                        tab.source = Source::Clipboard;
+                                        self.load_markdown(self.active_tab, md_string).unwrap();
+                                        self.renderer.set_scroll_y(0.);
+                                        self.switch_tab(self.active_tab).unwrap();
+                                    }
+                                    Err(err) => log::warn!("Failed reading the clipboard: {}", err),
+                                },
+                                Action::ToggleStats => {
+                                    self.renderer.show_stats = !self.renderer.show_stats;
+                                    self.window.request_redraw();
+                                }
+                                Action::ToggleMinimap => {
+                                    self.renderer.show_minimap = !self.renderer.show_minimap;
+                                    self.window.request_redraw();
+                                }
+                                Action::TogglePresentation => {
+                                    self.renderer.presentation = !self.renderer.presentation;
+                                }
+                                Action::NextTheme => self.next_theme(),
+                                Action::ToggleSections => {
+                                    // Collapse everything unless it's all collapsed already
+                                    let hidden = !self.renderer.all_sections_hidden();
+                                    self.renderer.set_all_sections(hidden).unwrap();
+                                    self.window.request_redraw();
+                                }
+                                a_tab @ (Action::NextTab | Action::PreviousTab) => {
+                                    let count = self.tabs.len();
+                                    let index = match a_tab {
+                                        Action::NextTab => (self.active_tab + 1) % count,
+                                        Action::PreviousTab => {
+                                            (self.active_tab + count - 1) % count
+                                        }
+                                        _ => unreachable!("This arm is only for tab actions"),
+                                    };
+                                    self.switch_tab(index).unwrap();
+                                }
+                                Action::CloseTab => {
+                                    if !self.close_tab().unwrap() {
+                                        *control_flow = ControlFlow::Exit;
+                                    }
+                                }
+                                Action::SaveImage => {
+                                    if let Some(image) = self.renderer.image_at(cursor_pos) {
+                                        Self::save_image(image);
+                                    }
                                 }
-                                Action::Copy => self
-                                    .clipboard
-                                    .set_contents(selection_cache.trim().to_owned())
-                                    .unwrap(),
                                 Action::Quit => *control_flow = ControlFlow::Exit,
                             }
                         }
                     }
                     _ => {}
                 },
+                Event::LoopDestroyed => {
+                    let tab = &self.tabs[self.active_tab];
+                    if tab.source == Source::File {
+                        let size = self
+                            .window
+                            .inner_size()
+                            .to_logical::<f64>(self.window.scale_factor());
+                        self.state
+                            .remember_window_size(&tab.file_path, [size.width, size.height]);
+                        Self::save_state(&self.state);
+                    }
+                }
                 Event::MainEventsCleared => {
                     // We lazily store the size and only reposition elements and request a redraw when
                     // we recieve a `MainEventsCleared`.  This prevents us from clogging up the queue
@@ -595,11 +1288,11 @@ impl Inlyne {
                         self.renderer.config.width = size.width;
                         self.renderer.config.height = size.height;
                         self.renderer.positioner.screen_size = size.into();
-                        self.renderer
-                            .surface
-                            .configure(&self.renderer.device, &self.renderer.config);
+                        if let Some(surface) = &self.renderer.surface {
+                            surface.configure(&self.renderer.device, &self.renderer.config);
+                        }
                         let old_reserved = self.renderer.positioner.reserved_height;
-                        self.renderer.reposition(&mut self.elements).unwrap();
+                        self.renderer.reposition().unwrap();
                         let new_reserved = self.renderer.positioner.reserved_height;
                         self.renderer
                             .set_scroll_y(self.renderer.scroll_y * (new_reserved / old_reserved));
@@ -617,78 +1310,217 @@ impl Inlyne {
         lines_to_scroll: f32,
         num_lines: f32,
     ) {
-        let num_pixels =
-            num_lines as f32 * 16.0 * lines_to_scroll * renderer.hidpi_scale * renderer.zoom;
+        let num_pixels = num_lines * Self::line_pixels(renderer, lines_to_scroll);
         Self::scroll_pixels(renderer, window, num_pixels);
     }
 
+    // How many pixels scrolling by a single line goes
+    fn line_pixels(renderer: &Renderer, lines_to_scroll: f32) -> f32 {
+        16.0 * lines_to_scroll * renderer.hidpi_scale * renderer.zoom
+    }
+
+    // The file that toggled checkboxes get saved to when that's turned on
+    fn checkbox_write_back(&self) -> Option<PathBuf> {
+        let tab = &self.tabs[self.active_tab];
+        (self.checkbox_write_back && tab.source == Source::File).then(|| tab.file_path.clone())
+    }
+
+    // Toggles sections and checkboxes. Returns whether anything changed
+    fn toggle(
+        elements: &[Positioned<Element>],
+        hoverable: &Hoverable,
+        write_back: Option<&Path>,
+    ) -> bool {
+        match hoverable {
+            Hoverable::Summary(summary) => {
+                let mut hidden = summary.hidden.borrow_mut();
+                *hidden = !*hidden;
+                true
+            }
+            Hoverable::Checkbox(text_box) => {
+                // Subtasks and the tasks above follow along, so they get saved too
+                for task in text::toggle_task(elements, text_box) {
+                    let checkbox = task.is_checkbox.as_ref().map(Cell::get);
+                    if let (Some(file_path), Some(line), Some(checkbox)) =
+                        (write_back, task.source_line, checkbox)
+                    {
+                        if checkbox != Checkbox::Indeterminate {
+                            let checked = checkbox == Checkbox::Checked;
+                            if let Err(err) = utils::write_back_checkbox(file_path, line, checked) {
+                                log::warn!("Failed saving the checkbox: {:#}", err);
+                            }
+                        }
+                    }
+                }
+                true
+            }
+            Hoverable::Image(_) | Hoverable::Text(_) => false,
+        }
+    }
+
+    // Jumps to anchors within the document and opens other links with the link opener. Local
+    // markdown files are returned instead so that they can be opened in a new tab
+    fn follow_link(
+        link: String,
+        file_path: &Path,
+        renderer: &mut Renderer,
+        window: &Window,
+        state: &mut State,
+        link_opener: &LinkOpener,
+    ) -> Option<PathBuf> {
+        if state
+            .visited_links
+            .insert(text::visited_link(&link, file_path))
+        {
+            text::mark_visited(&renderer.elements, &state.visited_links, file_path);
+            Self::save_state(state);
+            window.request_redraw();
+        }
+        let maybe_path = PathBuf::from_str(&link).ok();
+        let is_local_md = maybe_path.as_ref().map_or(false, |p| {
+            p.extension().map_or(false, |ext| ext == "md")
+                && !p.to_str().map_or(false, |s| s.starts_with("http"))
+        });
+        if is_local_md {
+            // Open markdown files ourselves
+            let maybe_path = maybe_path.expect("not a path");
+            // Handle relative paths and make them
+            // absolute by prepending current
+            // parent
+            let maybe_path = if maybe_path.is_relative() {
+                // Simply canonicalizing it doesn't suffice and leads to "no such file or directory"
+                let current_parent = file_path.parent().expect("no current parent");
+                let link_without_prefix: &Path = maybe_path
+                    .strip_prefix(std::path::Component::CurDir)
+                    .expect("no CurDir prefix");
+                let mut link = current_parent.to_path_buf();
+                link.push(link_without_prefix);
+                link
+            } else {
+                maybe_path
+            };
+            return Some(maybe_path);
+        } else if let Some(&anchor_pos) = renderer.positioner.anchors.get(&link) {
+            renderer.set_scroll_y(anchor_pos);
+            window.request_redraw();
+            window.set_cursor_icon(CursorIcon::Default);
+        } else if let Err(err) = link_opener.open(&link) {
+            log::warn!("Failed opening {}: {}", link, err);
+        }
+        None
+    }
+
+    // Switches to the tab that already has the file open instead of opening it again
+    fn open_linked_file(&mut self, file_path: PathBuf) {
+        if let Ok(canonical) = file_path.canonicalize() {
+            let open = self.tabs.iter().position(|tab| {
+                tab.source == Source::File
+                    && tab
+                        .file_path
+                        .canonicalize()
+                        .map_or(false, |path| path == canonical)
+            });
+            if let Some(index) = open {
+                if let Err(err) = self.switch_tab(index) {
+                    log::warn!("Failed switching to the linked file: {}", err);
+                }
+                return;
+            }
+        }
+        if let Err(err) = self.open_tab(file_path, None) {
+            log::warn!("Failed opening linked file: {}", err);
+        }
+    }
+
+    fn save_state(state: &State) {
+        if let Err(err) = state.save() {
+            log::warn!("Failed saving state: {}", err);
+        }
+    }
+
+    // Asks where to save the image and writes its bytes there as they are. The dialog is opened
+    // here, but waiting on it and loading the bytes happen on another thread so that the window
+    // keeps responding
+    fn save_image(image: SavableImage) {
+        let extension = image.format().extension();
+        let dialog = rfd::AsyncFileDialog::new()
+            .set_file_name(&format!("image.{}", extension))
+            .add_filter(extension, &[extension])
+            .save_file();
+        std::thread::spawn(move || {
+            let handle = match pollster::block_on(dialog) {
+                Some(handle) => handle,
+                None => return,
+            };
+            let (bytes, format) = match image.encoded() {
+                Ok(encoded) => encoded,
+                Err(err) => {
+                    log::warn!("Failed loading image to save: {}", err);
+                    return;
+                }
+            };
+            let mut save_path = handle.path().to_path_buf();
+            // Images that couldn't be loaded again get saved as PNGs instead
+            if format.extension() != extension {
+                save_path.set_extension(format.extension());
+            }
+            if let Err(err) = std::fs::write(&save_path, bytes) {
+                log::warn!("Failed saving image to {:?}: {}", save_path, err);
+            }
+        });
+    }
+
     fn scroll_pixels(renderer: &mut Renderer, window: &Window, num_pixels: f32) {
         renderer.set_scroll_y(renderer.scroll_y - num_pixels);
         window.request_redraw();
     }
 
+    // Positive pixels go back towards the left edge of the document
+    fn scroll_sideways(renderer: &mut Renderer, window: &Window, num_pixels: f32) {
+        renderer.set_scroll_x(renderer.scroll_x - num_pixels);
+        window.request_redraw();
+    }
+
+    // The full text of the truncated table cell at `loc`
+    fn truncated_text<T: wgpu_glyph::GlyphCruncher>(
+        elements: &[Positioned<Element>],
+        glyph_brush: &mut T,
+        loc: Point,
+        zoom: f32,
+    ) -> Option<String> {
+        let element = elements.iter().find(|element| element.contains(loc))?;
+        match &element.inner {
+            Element::Table(table) => table.truncated_text_at(glyph_brush, loc, zoom),
+            Element::Row(row) => Self::truncated_text(&row.elements, glyph_brush, loc, zoom),
+            Element::Section(section) if !*section.hidden.borrow() => {
+                Self::truncated_text(&section.elements, glyph_brush, loc, zoom)
+            }
+            _ => None,
+        }
+    }
+
+    // What's under `loc` (in document coordinates) that reacts to the cursor
     fn find_hoverable<'a, T: wgpu_glyph::GlyphCruncher>(
         elements: &'a [Positioned<Element>],
-        glyph_brush: &'a mut T,
+        glyph_brush: &mut T,
         loc: Point,
         screen_size: Size,
         zoom: f32,
+        text_zoom: f32,
     ) -> Option<Hoverable<'a>> {
-        let screen_pos = |screen_size: Size, bounds_offset: f32| {
-            (
-                screen_size.0 - bounds_offset - DEFAULT_MARGIN,
-                screen_size.1,
-            )
-        };
-
-        elements
-            .iter()
-            .find(|&e| e.contains(loc) && !matches!(e.inner, Element::Spacer(_)))
-            .and_then(|element| match &element.inner {
-                Element::TextBox(text_box) => {
-                    let bounds = element.bounds.as_ref().unwrap();
-                    text_box
-                        .find_hoverable(
-                            glyph_brush,
-                            loc,
-                            bounds.pos,
-                            screen_pos(screen_size, bounds.pos.0),
-                            zoom,
-                        )
-                        .map(Hoverable::Text)
-                }
-                Element::Table(table) => {
-                    let bounds = element.bounds.as_ref().unwrap();
-                    table
-                        .find_hoverable(
-                            glyph_brush,
-                            loc,
-                            bounds.pos,
-                            screen_pos(screen_size, bounds.pos.0),
-                            zoom,
-                        )
-                        .map(Hoverable::Text)
-                }
-                Element::Image(image) => Some(Hoverable::Image(image)),
-                Element::Spacer(_) => unreachable!("Spacers are filtered"),
-                Element::Row(row) => {
-                    Self::find_hoverable(&row.elements, glyph_brush, loc, screen_size, zoom)
-                }
-                Element::Section(section) => {
-                    if let Some(ref summary) = *section.summary {
-                        if let Some(ref bounds) = summary.bounds {
-                            if bounds.contains(loc) {
-                                return Some(Hoverable::Summary(section));
-                            }
-                        }
-                    }
-                    if !*section.hidden.borrow() {
-                        Self::find_hoverable(&section.elements, glyph_brush, loc, screen_size, zoom)
-                    } else {
-                        None
-                    }
-                }
-            })
+        let hit = hit::hit_test(elements, glyph_brush, loc, screen_size, zoom, text_zoom)?;
+        if let Some((HitStep::Summary, section_path)) = hit.path.split_last() {
+            return match hit::resolve(elements, section_path)? {
+                Element::Section(section) => Some(Hoverable::Summary(section)),
+                _ => None,
+            };
+        }
+        match hit::resolve(elements, &hit.path)? {
+            Element::TextBox(text_box) if hit.checkbox => Some(Hoverable::Checkbox(text_box)),
+            Element::TextBox(text_box) => text_box.text_at(hit.char_offset?).map(Hoverable::Text),
+            Element::Image(image) => Some(Hoverable::Image(image)),
+            _ => None,
+        }
     }
 }
 
@@ -711,10 +1543,40 @@ fn main() -> anyhow::Result<()> {
     };
     let args = Args::new(&config);
     let opts = Opts::parse_and_load_from(&args, config);
-    let inlyne = pollster::block_on(Inlyne::new(&opts, args))?;
+    if args.list_gpus {
+        for (index, info) in renderer::list_adapters(opts.gpu_backend).iter().enumerate() {
+            println!(
+                "{}: {} ({:?}, {:?})",
+                index, info.name, info.backend, info.device_type
+            );
+        }
+        return Ok(());
+    }
+    let export_width = opts.export_width.unwrap_or(svg::DEFAULT_EXPORT_WIDTH);
+    if let Some(svg_path) = &opts.export_svg {
+        let svg = svg::export(&opts, export_width)?;
+        std::fs::write(svg_path, svg)
+            .with_context(|| format!("Could not write SVG to {:?}", svg_path))?;
+        return Ok(());
+    }
+    if let Some(png_path) = &opts.export_png {
+        let image = png::export(&opts, export_width, opts.scale.unwrap_or(1.))?;
+        image
+            .save(png_path)
+            .with_context(|| format!("Could not write PNG to {:?}", png_path))?;
+        return Ok(());
+    }
+    if let Some(pdf_path) = &opts.export_pdf {
+        let scale = opts.scale.unwrap_or(pdf::DEFAULT_PDF_SCALE);
+        let pdf = pdf::export(&opts, opts.export_width, scale)?;
+        std::fs::write(pdf_path, pdf)
+            .with_context(|| format!("Could not write PDF to {:?}", pdf_path))?;
+        return Ok(());
+    }
+    let event_loop = EventLoop::<InlyneEvent>::with_user_event();
+    let inlyne = pollster::block_on(Inlyne::new(&opts, args, &event_loop))?;
 
-    inlyne.spawn_watcher();
-    inlyne.run();
+    inlyne.run(event_loop);
 
     Ok(())
 }
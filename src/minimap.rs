@@ -0,0 +1,86 @@
+use crate::positioner::{DEFAULT_MARGIN, DEFAULT_PADDING};
+use crate::utils::{Rect, Size};
+
+/// Width of the minimap in physical pixels. It fits in the right margin next to the scrollbar
+pub const MINIMAP_WIDTH: f32 = DEFAULT_MARGIN / 2.;
+
+/// Maps between the document and a scaled down overview of it that's drawn along the right side
+/// of the window
+#[derive(Clone, Debug, PartialEq)]
+pub struct Minimap {
+    /// Where the minimap is on screen
+    pub bounds: Rect,
+    // How much smaller than the document the minimap is horizontally and vertically
+    scale: (f32, f32),
+}
+
+impl Minimap {
+    /// Fits the whole document below `top` (the bottom of the tab bar). `None` when there's no
+    /// room for it
+    pub fn new(screen_size: Size, top: f32, reserved_height: f32) -> Option<Self> {
+        let right = screen_size.0 - DEFAULT_MARGIN / 4. - DEFAULT_PADDING;
+        let height = screen_size.1 - top - 2. * DEFAULT_PADDING;
+        if right < MINIMAP_WIDTH || height <= 0. || reserved_height <= 0. {
+            return None;
+        }
+        let scale_x = MINIMAP_WIDTH / screen_size.0;
+        // Short documents keep their proportions instead of getting stretched to the full height
+        let scale_y = (height / reserved_height).min(scale_x);
+        Some(Self {
+            bounds: Rect::new(
+                (right - MINIMAP_WIDTH, top + DEFAULT_PADDING),
+                (MINIMAP_WIDTH, reserved_height * scale_y),
+            ),
+            scale: (scale_x, scale_y),
+        })
+    }
+
+    /// Where `rect` (in document coordinates) ends up on the minimap
+    pub fn map_rect(&self, rect: &Rect) -> Rect {
+        let (scale_x, scale_y) = self.scale;
+        Rect::new(
+            (
+                self.bounds.pos.0 + rect.pos.0 * scale_x,
+                self.bounds.pos.1 + rect.pos.1 * scale_y,
+            ),
+            (rect.size.0 * scale_x, rect.size.1 * scale_y),
+        )
+    }
+
+    /// The part of the minimap that's on screen when scrolled to `scroll_y`
+    pub fn viewport(&self, scroll_y: f32, screen_height: f32) -> Rect {
+        let width = self.bounds.size.0 / self.scale.0;
+        self.map_rect(&Rect::new((0., scroll_y), (width, screen_height)))
+    }
+
+    /// The scroll position that centers the screen on what's at `y` on the minimap
+    pub fn scroll_y_at(&self, y: f32, screen_height: f32) -> f32 {
+        (y - self.bounds.pos.1) / self.scale.1 - screen_height / 2.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Minimap, MINIMAP_WIDTH};
+    use crate::utils::Rect;
+
+    #[test]
+    fn clicks_map_back_to_the_scroll_position() {
+        let screen = (1_000., 500.);
+        let minimap = Minimap::new(screen, 0., 10_000.).unwrap();
+        assert_eq!(minimap.bounds.size.0, MINIMAP_WIDTH);
+        assert!(minimap.bounds.max().1 <= screen.1);
+
+        let viewport = minimap.viewport(4_000., screen.1);
+        let center = viewport.pos.1 + viewport.size.1 / 2.;
+        let scroll_y = minimap.scroll_y_at(center, screen.1);
+        assert!((scroll_y - 4_000.).abs() < 0.01);
+    }
+
+    #[test]
+    fn short_documents_keep_their_proportions() {
+        let minimap = Minimap::new((1_000., 500.), 0., 200.).unwrap();
+        let mapped = minimap.map_rect(&Rect::new((0., 0.), (100., 100.)));
+        assert_eq!(mapped.size.0, mapped.size.1);
+    }
+}
@@ -1,6 +1,8 @@
 use std::{env, ffi::OsString, path::PathBuf};
 
 use crate::color::{self, Theme};
+use crate::pdf::PageSize;
+use crate::utils;
 
 use super::{config::Config, ThemeType};
 
@@ -11,6 +13,13 @@ impl ThemeType {
         match &self {
             Self::Dark => color::DARK_DEFAULT,
             Self::Light => color::LIGHT_DEFAULT,
+            Self::Auto => {
+                if utils::os_prefers_dark() {
+                    color::DARK_DEFAULT
+                } else {
+                    color::LIGHT_DEFAULT
+                }
+            }
         }
     }
 
@@ -18,13 +27,14 @@ impl ThemeType {
         match self {
             Self::Dark => "dark",
             Self::Light => "light",
+            Self::Auto => "auto",
         }
     }
 }
 
 impl ValueEnum for ThemeType {
     fn value_variants<'a>() -> &'a [Self] {
-        &[Self::Dark, Self::Light]
+        &[Self::Dark, Self::Light, Self::Auto]
     }
 
     fn to_possible_value<'a>(&self) -> Option<PossibleValue<'a>> {
@@ -32,20 +42,74 @@ impl ValueEnum for ThemeType {
     }
 }
 
+impl PageSize {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::A4 => "a4",
+            Self::Letter => "letter",
+        }
+    }
+}
+
+impl ValueEnum for PageSize {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::A4, Self::Letter]
+    }
+
+    fn to_possible_value<'a>(&self) -> Option<PossibleValue<'a>> {
+        Some(PossibleValue::new(self.as_str()))
+    }
+}
+
+/// Where in the document to start out
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ScrollTo {
+    /// How far down the document to go from 0 to 1
+    Fraction(f32),
+    /// A line of the markdown source starting from 1
+    Line(usize),
+}
+
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct Args {
     pub file_path: PathBuf,
+    /// Files opened in tabs next to `file_path`
+    pub extra_files: Vec<PathBuf>,
     pub theme: Option<ThemeType>,
     pub scale: Option<f32>,
+    pub scroll_to: Option<ScrollTo>,
+    pub export_svg: Option<PathBuf>,
+    pub export_png: Option<PathBuf>,
+    pub export_pdf: Option<PathBuf>,
+    pub page_size: Option<PageSize>,
+    /// Width in logical pixels that exports get laid out at
+    pub export_width: Option<f32>,
+    pub bench: bool,
+    pub list_gpus: bool,
+}
+
+// Parses percentages like `50%` into a fraction. The percent sign is optional
+fn parse_percentage(value: &str) -> Result<f32, String> {
+    let percentage: f32 = value
+        .strip_suffix('%')
+        .unwrap_or(value)
+        .parse()
+        .map_err(|_| format!("`{}` isn't a percentage", value))?;
+    if (0. ..=100.).contains(&percentage) {
+        Ok(percentage / 100.)
+    } else {
+        Err(String::from("Percentage must be between 0% and 100%"))
+    }
 }
 
 pub fn command(scale_help: &str, default_theme: ThemeType) -> Command {
     let file_arg = Arg::new("file")
-        .required(true)
+        .required_unless_present("list_gpus")
         .takes_value(true)
+        .multiple_values(true)
         .value_name("FILE")
         .value_parser(value_parser!(PathBuf))
-        .help("Path to the markdown file");
+        .help("Path to the markdown file or `-` to read it from stdin. Any more files get opened in tabs");
     let theme_arg = Arg::new("theme")
         .short('t')
         .long("theme")
@@ -61,7 +125,80 @@ pub fn command(scale_help: &str, default_theme: ThemeType) -> Command {
         .value_parser(value_parser!(f32))
         .help(scale_help);
 
-    command!().arg(file_arg).arg(theme_arg).arg(scale_arg)
+    let scroll_to_arg = Arg::new("scroll_to")
+        .long("scroll-to")
+        .takes_value(true)
+        .value_name("PERCENTAGE")
+        .value_parser(parse_percentage)
+        .conflicts_with("scroll_to_line")
+        .help("Percentage of the way down the document to open at (e.g. 50%)");
+
+    let scroll_to_line_arg = Arg::new("scroll_to_line")
+        .long("scroll-to-line")
+        .takes_value(true)
+        .value_name("LINE")
+        .value_parser(value_parser!(usize))
+        .help("Line of the markdown file to open at");
+
+    let export_svg_arg = Arg::new("export_svg")
+        .long("export-svg")
+        .takes_value(true)
+        .value_name("SVG_FILE")
+        .value_parser(value_parser!(PathBuf))
+        .help("Renders the file to SVG at SVG_FILE instead of opening a window");
+
+    let export_png_arg = Arg::new("export_png")
+        .long("export-png")
+        .takes_value(true)
+        .value_name("PNG_FILE")
+        .value_parser(value_parser!(PathBuf))
+        .conflicts_with("export_svg")
+        .help("Renders the file to PNG at PNG_FILE instead of opening a window. `--scale` sets the DPI");
+
+    let export_pdf_arg = Arg::new("export_pdf")
+        .long("export-pdf")
+        .takes_value(true)
+        .value_name("PDF_FILE")
+        .value_parser(value_parser!(PathBuf))
+        .conflicts_with_all(&["export_svg", "export_png"])
+        .help("Renders the file to PDF at PDF_FILE instead of opening a window. `--scale` sets the DPI [default: 2]");
+
+    let page_size_arg = Arg::new("page_size")
+        .long("page-size")
+        .takes_value(true)
+        .value_parser(value_parser!(PageSize))
+        .requires("export_pdf")
+        .help("Size of the pages of PDF exports [default: a4]");
+
+    let export_width_arg = Arg::new("export_width")
+        .long("export-width")
+        .takes_value(true)
+        .value_name("WIDTH")
+        .value_parser(value_parser!(f32))
+        .help("Width in logical pixels to lay exported files out at [default: 1000 or the page's width for PDFs]");
+
+    let bench_arg = Arg::new("bench")
+        .long("bench")
+        .conflicts_with_all(&["export_svg", "export_png", "export_pdf"])
+        .help("Scrolls through the file timing every frame, prints the frame times and exits");
+
+    let list_gpus_arg = Arg::new("list_gpus")
+        .long("list-gpus")
+        .help("Lists the GPUs that the `gpu` config option can pick from and exits");
+
+    command!()
+        .arg(file_arg)
+        .arg(theme_arg)
+        .arg(scale_arg)
+        .arg(scroll_to_arg)
+        .arg(scroll_to_line_arg)
+        .arg(export_svg_arg)
+        .arg(export_png_arg)
+        .arg(export_pdf_arg)
+        .arg(page_size_arg)
+        .arg(export_width_arg)
+        .arg(bench_arg)
+        .arg(list_gpus_arg)
 }
 
 impl Args {
@@ -73,6 +210,9 @@ impl Args {
     pub fn program_args(&self) -> Vec<String> {
         let mut args = Vec::new();
         args.push(self.file_path.as_os_str().to_str().unwrap().to_string());
+        for file in &self.extra_files {
+            args.push(file.as_os_str().to_str().unwrap().to_string());
+        }
         if let Some(theme) = self.theme {
             args.push("--theme".to_owned());
             args.push(theme.as_str().to_owned());
@@ -96,14 +236,47 @@ impl Args {
         let command = command(&scale_help, config.theme);
         let matches = command.get_matches_from(args);
 
-        let file_path = matches.get_one("file").cloned().expect("required");
+        let mut files = matches
+            .get_many::<PathBuf>("file")
+            .into_iter()
+            .flatten()
+            .cloned();
+        // Only `--list-gpus` goes without a file
+        let file_path = files.next().unwrap_or_default();
+        let extra_files = files.collect();
         let theme = matches.get_one("theme").cloned();
         let scale = matches.get_one("scale").cloned();
+        let scroll_to = matches
+            .get_one("scroll_to")
+            .cloned()
+            .map(ScrollTo::Fraction)
+            .or_else(|| {
+                matches
+                    .get_one("scroll_to_line")
+                    .cloned()
+                    .map(ScrollTo::Line)
+            });
+        let export_svg = matches.get_one("export_svg").cloned();
+        let export_png = matches.get_one("export_png").cloned();
+        let export_pdf = matches.get_one("export_pdf").cloned();
+        let page_size = matches.get_one("page_size").cloned();
+        let export_width = matches.get_one("export_width").cloned();
+        let bench = matches.contains_id("bench");
+        let list_gpus = matches.contains_id("list_gpus");
 
         Self {
             file_path,
+            extra_files,
             theme,
             scale,
+            scroll_to,
+            export_svg,
+            export_png,
+            export_pdf,
+            page_size,
+            export_width,
+            bench,
+            list_gpus,
         }
     }
 }
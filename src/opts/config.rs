@@ -1,9 +1,25 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
+use std::path::PathBuf;
 
 use super::ThemeType;
-use crate::{color, keybindings::Keybindings};
+use crate::{
+    color,
+    interpreter::RawHtml,
+    keybindings::Keybindings,
+    pdf::PageSize,
+    positioner::{BlankLines, Spacing},
+    renderer::{
+        AdapterFallback, AdapterSelector, GpuBackend, GpuPower, DEFAULT_GLYPH_CACHE_SCALE_TOLERANCE,
+    },
+    table::{ColumnLimit, TableLayout},
+    text::{FontWeights, DEFAULT_BLOCK_PADDING},
+    utils::LinkOpener,
+};
 
 use anyhow::Context;
+use serde::de::{self, Unexpected, Visitor};
 use serde::{Deserialize, Deserializer};
 
 #[derive(Deserialize, Debug, PartialEq, Eq, Default, Clone)]
@@ -11,8 +27,29 @@ use serde::{Deserialize, Deserializer};
 pub struct FontOptions {
     #[serde(default)]
     pub regular_font: Option<String>,
-    #[serde(default)]
+    /// Family used for inline code and code blocks without changing the body text's font
+    #[serde(default, alias = "monospace-family")]
     pub monospace_font: Option<String>,
+    /// Weights from 100 to 900 for body text and headings. The nearest of the regular and bold
+    /// faces gets drawn while SVG exports keep the exact weight. The weight axis of variable fonts
+    /// isn't adjusted, so they snap to those two faces too
+    #[serde(default)]
+    pub body_weight: Option<u16>,
+    #[serde(default)]
+    pub heading_weight: Option<u16>,
+}
+
+impl FontOptions {
+    pub fn weights(&self) -> FontWeights {
+        let default = FontWeights::default();
+        FontWeights {
+            body: self.body_weight.unwrap_or(default.body).clamp(100, 900),
+            heading: self
+                .heading_weight
+                .unwrap_or(default.heading)
+                .clamp(100, 900),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -22,28 +59,148 @@ pub struct OptionalTheme {
     pub text_color: Option<[f32; 4]>,
     #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
     pub background_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_pair_to_linear_rgba")]
+    pub background_gradient: Option<([f32; 4], [f32; 4])>,
     #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
     pub code_color: Option<[f32; 4]>,
     #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
     pub code_block_color: Option<[f32; 4]>,
     #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
     pub quote_block_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_list_to_linear_rgba")]
+    pub quote_bar_colors: Option<Vec<[f32; 4]>>,
+    #[serde(default, deserialize_with = "deserialize_hex_list_to_linear_rgba")]
+    pub heading_colors: Option<Vec<[f32; 4]>>,
+    pub heading_scales: Option<[f32; 6]>,
+    pub heading_letter_spacing: Option<[f32; 6]>,
     #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
     pub link_color: Option<[f32; 4]>,
     #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub visited_link_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub link_hover_color: Option<[f32; 4]>,
+    #[serde(default)]
+    pub link_hover_style: Option<color::LinkHoverStyle>,
+    #[serde(default)]
+    pub underline_links: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub rule_color: Option<[f32; 4]>,
+    #[serde(default)]
+    pub rule_thickness: Option<f32>,
+    #[serde(default)]
+    pub rule_width: Option<f32>,
+    #[serde(default)]
+    pub rule_style: Option<color::RuleStyle>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
     pub select_color: Option<[f32; 4]>,
     #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub scrollbar_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub scrollbar_hover_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
     pub checkbox_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub note_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub tip_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub important_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub warning_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub caution_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub inserted_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub deleted_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub marked_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub diff_added_color: Option<[f32; 4]>,
+    #[serde(default, deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub diff_removed_color: Option<[f32; 4]>,
     #[serde(default)]
     pub code_highlighter: Option<color::SyntaxTheme>,
 }
 
+/// A color given as a hex number like `0xadbac7`, a hex string like `"#adbac7"` or a color name
+/// like `"white"`
+struct Hex(u32);
+
+impl<'de> Deserialize<'de> for Hex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HexVisitor;
+
+        impl<'de> Visitor<'de> for HexVisitor {
+            type Value = Hex;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(
+                    r##"a hex color like 0xadbac7 or "#adbac7" or a color name like "white""##,
+                )
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Hex, E> {
+                match u32::try_from(value) {
+                    Ok(hex) if hex <= 0xffffff => Ok(Hex(hex)),
+                    _ => Err(E::invalid_value(Unexpected::Signed(value), &self)),
+                }
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Hex, E> {
+                match u32::try_from(value) {
+                    Ok(hex) if hex <= 0xffffff => Ok(Hex(hex)),
+                    _ => Err(E::invalid_value(Unexpected::Unsigned(value), &self)),
+                }
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Hex, E> {
+                color::parse_color(value)
+                    .map(Hex)
+                    .ok_or_else(|| E::invalid_value(Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_any(HexVisitor)
+    }
+}
+
 fn deserialize_hex_to_linear_rgba<'de, D>(deserializer: D) -> Result<Option<[f32; 4]>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let maybe_hex = <Option<u32>>::deserialize(deserializer)?;
-    Ok(maybe_hex.map(color::hex_to_linear_rgba))
+    let maybe_hex = <Option<Hex>>::deserialize(deserializer)?;
+    Ok(maybe_hex.map(|Hex(hex)| color::hex_to_linear_rgba(hex)))
+}
+
+fn deserialize_hex_pair_to_linear_rgba<'de, D>(
+    deserializer: D,
+) -> Result<Option<([f32; 4], [f32; 4])>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let maybe_hexes = <Option<[Hex; 2]>>::deserialize(deserializer)?;
+    Ok(maybe_hexes.map(|[Hex(first), Hex(second)]| {
+        (
+            color::hex_to_linear_rgba(first),
+            color::hex_to_linear_rgba(second),
+        )
+    }))
+}
+
+fn deserialize_hex_list_to_linear_rgba<'de, D>(
+    deserializer: D,
+) -> Result<Option<Vec<[f32; 4]>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let maybe_hexes = <Option<Vec<Hex>>>::deserialize(deserializer)?;
+    Ok(maybe_hexes.map(|hexes| {
+        hexes
+            .into_iter()
+            .map(|Hex(hex)| color::hex_to_linear_rgba(hex))
+            .collect()
+    }))
 }
 
 impl OptionalTheme {
@@ -59,17 +216,79 @@ impl OptionalTheme {
                     a: a as f64,
                 })
                 .unwrap_or(other.background_color),
+            background_gradient: self.background_gradient.or(other.background_gradient),
             code_color: self.code_color.unwrap_or(other.code_color),
             code_block_color: self.code_block_color.unwrap_or(other.code_block_color),
             quote_block_color: self.quote_block_color.unwrap_or(other.quote_block_color),
+            quote_bar_colors: self.quote_bar_colors.unwrap_or(other.quote_bar_colors),
+            heading_colors: self.heading_colors.unwrap_or(other.heading_colors),
+            heading_scales: self.heading_scales.unwrap_or(other.heading_scales),
+            heading_letter_spacing: self
+                .heading_letter_spacing
+                .unwrap_or(other.heading_letter_spacing),
             link_color: self.link_color.unwrap_or(other.link_color),
+            visited_link_color: self.visited_link_color.unwrap_or(other.visited_link_color),
+            link_hover_color: self.link_hover_color.unwrap_or(other.link_hover_color),
+            link_hover_style: self.link_hover_style.unwrap_or(other.link_hover_style),
+            underline_links: self.underline_links.unwrap_or(other.underline_links),
+            rule_color: self.rule_color.or(other.rule_color),
+            rule_thickness: self.rule_thickness.unwrap_or(other.rule_thickness),
+            rule_width: self.rule_width.unwrap_or(other.rule_width),
+            rule_style: self.rule_style.unwrap_or(other.rule_style),
             select_color: self.select_color.unwrap_or(other.select_color),
+            scrollbar_color: self.scrollbar_color.unwrap_or(other.scrollbar_color),
+            scrollbar_hover_color: self
+                .scrollbar_hover_color
+                .unwrap_or(other.scrollbar_hover_color),
             checkbox_color: self.checkbox_color.unwrap_or(other.checkbox_color),
+            note_color: self.note_color.unwrap_or(other.note_color),
+            tip_color: self.tip_color.unwrap_or(other.tip_color),
+            important_color: self.important_color.unwrap_or(other.important_color),
+            warning_color: self.warning_color.unwrap_or(other.warning_color),
+            caution_color: self.caution_color.unwrap_or(other.caution_color),
+            inserted_color: self.inserted_color.unwrap_or(other.inserted_color),
+            deleted_color: self.deleted_color.unwrap_or(other.deleted_color),
+            marked_color: self.marked_color.unwrap_or(other.marked_color),
+            diff_added_color: self.diff_added_color.unwrap_or(other.diff_added_color),
+            diff_removed_color: self.diff_removed_color.unwrap_or(other.diff_removed_color),
             code_highlighter: self.code_highlighter.unwrap_or(other.code_highlighter),
         }
     }
 }
 
+/// A `[themes.<name>]` section (or a theme file) that can be switched to at runtime. It's built on
+/// top of the light theme (or the dark one with `dark = true`)
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct NamedTheme {
+    #[serde(default)]
+    pub dark: bool,
+    #[serde(flatten)]
+    pub theme: OptionalTheme,
+}
+
+/// The `[code-theme]` section. Anything left out comes from the document's theme
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct OptionalCodeTheme {
+    #[serde(deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub background: Option<[f32; 4]>,
+    #[serde(deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub text_color: Option<[f32; 4]>,
+    pub highlighter: Option<color::SyntaxTheme>,
+}
+
+impl OptionalCodeTheme {
+    pub fn merge(self, theme: &color::Theme) -> color::CodeTheme {
+        let other = color::CodeTheme::from(theme);
+        color::CodeTheme {
+            background: self.background.or(other.background),
+            text_color: self.text_color.unwrap_or(other.text_color),
+            highlighter: self.highlighter.unwrap_or(other.highlighter),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct LinesToScroll(pub f32);
 
@@ -79,6 +298,161 @@ impl Default for LinesToScroll {
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ScrollMultiplier(pub f32);
+
+impl Default for ScrollMultiplier {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Size in bytes of each chunk allocated by the staging belt used to upload glyph data
+///
+/// Larger chunks mean fewer allocations on glyph-heavy frames at the cost of holding onto more
+/// memory
+#[derive(Deserialize, Debug)]
+pub struct StagingBeltSize(pub u64);
+
+impl Default for StagingBeltSize {
+    fn default() -> Self {
+        Self(64 * 1_024)
+    }
+}
+
+/// How far (in pixels) a glyph's scale can be from one that's already in the glyph
+/// cache for the cached rasterization to be reused
+///
+/// Higher values rasterize less often while zooming at the cost of slightly blurrier text until
+/// the zoom settles
+#[derive(Deserialize, Debug)]
+pub struct GlyphCacheScaleTolerance(pub f32);
+
+impl Default for GlyphCacheScaleTolerance {
+    fn default() -> Self {
+        Self(DEFAULT_GLYPH_CACHE_SCALE_TOLERANCE)
+    }
+}
+
+/// Horizontal space (in logical pixels) between the text of blocks and the sides of their
+/// background
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct BlockPadding {
+    pub code: f32,
+    pub quote: f32,
+}
+
+impl Default for BlockPadding {
+    fn default() -> Self {
+        Self {
+            code: DEFAULT_BLOCK_PADDING,
+            quote: DEFAULT_BLOCK_PADDING,
+        }
+    }
+}
+
+/// Range that the text scale can be adjusted within so that text can't become unusably small or
+/// large
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct TextScale {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for TextScale {
+    fn default() -> Self {
+        Self { min: 0.5, max: 3.0 }
+    }
+}
+
+impl TextScale {
+    pub fn clamp(&self, text_scale: f32) -> f32 {
+        text_scale.max(self.min).min(self.max)
+    }
+}
+
+/// Settings for the word count and reading time of the document
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct DocStatsOptions {
+    /// Reading speed used to estimate the reading time
+    pub words_per_minute: f32,
+    /// Whether code blocks count towards the words and characters
+    pub include_code: bool,
+}
+
+impl Default for DocStatsOptions {
+    fn default() -> Self {
+        Self {
+            words_per_minute: 200.,
+            include_code: true,
+        }
+    }
+}
+
+/// How selected text gets marked
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStyle {
+    /// A box drawn behind the text
+    #[default]
+    Highlight,
+    /// A line drawn under the text
+    Underline,
+}
+
+/// Settings for how the selection is drawn
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SelectionOptions {
+    pub style: SelectionStyle,
+    /// Opacity of the selection color from 0 to 1. Multiplies the alpha of the theme's
+    /// `select-color`
+    pub opacity: f32,
+}
+
+impl Default for SelectionOptions {
+    fn default() -> Self {
+        Self {
+            style: SelectionStyle::default(),
+            opacity: 0.5,
+        }
+    }
+}
+
+/// Border and corners drawn around images
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ImageStyle {
+    /// Width of the border in logical pixels. No border gets drawn when it's 0
+    pub border_width: f32,
+    /// Falls back to the theme's text color when unset
+    #[serde(deserialize_with = "deserialize_hex_to_linear_rgba")]
+    pub border_color: Option<[f32; 4]>,
+    /// Radius of the rounded corners in logical pixels
+    pub corner_radius: f32,
+}
+
+/// Settings for the pages of PDF exports
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PdfOptions {
+    pub page_size: PageSize,
+    /// Space around the document on every page in millimeters
+    pub margin: f32,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self {
+            page_size: PageSize::default(),
+            margin: 15.,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Default)]
 pub struct KeybindingsSection {
     pub base: Option<Keybindings>,
@@ -91,8 +465,47 @@ pub struct Config {
     pub theme: ThemeType,
     pub scale: Option<f32>,
     pub lines_to_scroll: LinesToScroll,
+    pub scroll_multiplier: ScrollMultiplier,
+    pub invert_scroll: bool,
+    pub staging_belt_size: StagingBeltSize,
+    pub glyph_cache_size: Option<u32>,
+    pub glyph_cache_max_size: Option<u32>,
+    pub glyph_cache_scale_tolerance: GlyphCacheScaleTolerance,
+    pub reduce_motion: Option<bool>,
+    pub gpu_fallback: AdapterFallback,
+    pub gpu_backend: GpuBackend,
+    pub gpu_power: GpuPower,
+    pub gpu: Option<AdapterSelector>,
+    pub window_width: Option<f32>,
+    pub window_height: Option<f32>,
+    pub max_image_width: Option<f32>,
+    pub max_image_height: Option<f32>,
+    pub dither: bool,
+    pub pixel_snap: bool,
+    pub line_numbers: bool,
+    pub indent_guides: bool,
+    pub minimap: bool,
+    pub presentation: bool,
+    pub checkbox_write_back: bool,
+    pub monospace_grid: bool,
+    pub table_layout: TableLayout,
+    pub table_columns: ColumnLimit,
+    pub blank_lines: BlankLines,
+    pub raw_html: RawHtml,
+    pub link_opener: LinkOpener,
+    pub spacing: Spacing,
+    pub block_padding: BlockPadding,
+    pub text_scale: TextScale,
+    pub doc_stats: DocStatsOptions,
+    pub selection: SelectionOptions,
+    pub image_style: ImageStyle,
+    pub pdf: PdfOptions,
     pub light_theme: Option<OptionalTheme>,
     pub dark_theme: Option<OptionalTheme>,
+    pub themes: BTreeMap<String, NamedTheme>,
+    /// Relative paths are relative to the config's directory
+    pub theme_file: Option<PathBuf>,
+    pub code_theme: OptionalCodeTheme,
     pub font_options: Option<FontOptions>,
     pub keybindings: KeybindingsSection,
 }
@@ -103,7 +516,10 @@ impl Config {
         let config_path = config_dir.join("inlyne").join("inlyne.toml");
         if config_path.is_file() {
             let text = fs::read_to_string(&config_path).context("Failed reading config file")?;
-            let config = toml::from_str(&text)?;
+            let mut config: Self = toml::from_str(&text)?;
+            if let Some(theme_file) = &mut config.theme_file {
+                *theme_file = config_dir.join("inlyne").join(&theme_file);
+            }
             Ok(config)
         } else {
             Ok(Self::default())
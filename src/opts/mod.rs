@@ -1,34 +1,173 @@
 mod cli;
 mod config;
+mod state;
 #[cfg(test)]
 mod tests;
 
+use std::fs;
 use std::path::PathBuf;
 
 use crate::{
     color,
+    interpreter::RawHtml,
     keybindings::{self, Keybindings},
+    positioner::{BlankLines, Spacing},
+    renderer::{AdapterFallback, AdapterSelector, GpuBackend, GpuPower},
+    table::{ColumnLimit, TableLayout},
+    utils::{self, LinkOpener},
 };
 
+use anyhow::Context;
 use serde::Deserialize;
 
-pub use self::cli::Args;
+pub use self::cli::{Args, ScrollTo};
+pub use self::config::BlockPadding;
 pub use self::config::Config;
+pub use self::config::DocStatsOptions;
 pub use self::config::FontOptions;
+pub use self::config::ImageStyle;
+pub use self::config::PdfOptions;
+pub use self::config::SelectionOptions;
+pub use self::config::SelectionStyle;
+pub use self::config::TextScale;
+pub use self::state::State;
 
 #[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum ThemeType {
     Dark,
     #[default]
     Light,
+    /// Follows the OS's dark mode setting
+    Auto,
+}
+
+/// A theme that can be switched to at runtime along with the code theme that goes with it
+#[derive(Debug, PartialEq, Clone)]
+pub struct ThemeChoice {
+    pub name: String,
+    pub theme: color::Theme,
+    pub code_theme: color::CodeTheme,
+}
+
+/// A theme kept in a file of its own that gets loaded again whenever the file changes. The file
+/// holds a `[themes.<name>]` section's fields
+#[derive(Debug, PartialEq, Clone)]
+pub struct ThemeFile {
+    pub path: PathBuf,
+    light: color::Theme,
+    dark: color::Theme,
+    code_theme: config::OptionalCodeTheme,
+}
+
+impl ThemeFile {
+    pub fn load(&self) -> anyhow::Result<ThemeChoice> {
+        let text = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed reading theme file {}", self.path.display()))?;
+        let named_theme: config::NamedTheme = toml::from_str(&text)
+            .with_context(|| format!("Failed parsing theme file {}", self.path.display()))?;
+        let base = if named_theme.dark {
+            self.dark.clone()
+        } else {
+            self.light.clone()
+        };
+        let theme = named_theme.theme.merge(base);
+        let name = self.path.file_stem().unwrap_or(self.path.as_os_str());
+        Ok(ThemeChoice {
+            name: name.to_string_lossy().into_owned(),
+            code_theme: self.code_theme.clone().merge(&theme),
+            theme,
+        })
+    }
+}
+
+/// The light and dark themes to switch between while following the OS's dark mode setting
+#[derive(Debug, PartialEq, Clone)]
+pub struct AutoTheme {
+    pub light: (color::Theme, color::CodeTheme),
+    pub dark: (color::Theme, color::CodeTheme),
+}
+
+impl AutoTheme {
+    pub fn pick(&self, prefers_dark: bool) -> (color::Theme, color::CodeTheme) {
+        if prefers_dark {
+            self.dark.clone()
+        } else {
+            self.light.clone()
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Opts {
     pub file_path: PathBuf,
     pub theme: color::Theme,
+    pub code_theme: color::CodeTheme,
+    /// Set when the theme follows the OS's dark mode setting
+    pub auto_theme: Option<AutoTheme>,
+    /// Themes to cycle through starting with the light and dark ones
+    pub themes: Vec<ThemeChoice>,
+    pub theme_file: Option<ThemeFile>,
     pub scale: Option<f32>,
+    pub scroll_to: Option<ScrollTo>,
+    /// Where to render the file to as SVG instead of opening a window
+    pub export_svg: Option<PathBuf>,
+    /// Where to render the file to as PNG instead of opening a window
+    pub export_png: Option<PathBuf>,
+    /// Where to render the file to as PDF instead of opening a window
+    pub export_pdf: Option<PathBuf>,
+    /// Width in logical pixels that exports get laid out at
+    pub export_width: Option<f32>,
+    /// Scrolls through the file timing every frame instead of waiting for input
+    pub bench: bool,
     pub lines_to_scroll: f32,
+    pub scroll_multiplier: f32,
+    pub invert_scroll: bool,
+    pub staging_belt_size: u64,
+    /// Width and height of the glyph cache texture. Picked from the scale factor when not set
+    pub glyph_cache_size: Option<u32>,
+    /// Largest the glyph cache texture can grow to before it's started over
+    pub glyph_cache_max_size: Option<u32>,
+    /// How far a glyph's scale can be from a cached one for it to be reused
+    pub glyph_cache_scale_tolerance: f32,
+    /// Overrides the OS's reduced motion setting when set
+    pub reduce_motion: Option<bool>,
+    /// Whether to render in software without a GPU. The environment can override it
+    pub gpu_fallback: AdapterFallback,
+    /// Graphics API to render through. Every one gets tried when it doesn't work out
+    pub gpu_backend: GpuBackend,
+    /// Which kind of GPU to prefer when `gpu` doesn't pick one
+    pub gpu_power: GpuPower,
+    /// A specific GPU to render with
+    pub gpu: Option<AdapterSelector>,
+    /// Size of the window in logical pixels for documents that weren't opened before
+    pub window_width: Option<f32>,
+    pub window_height: Option<f32>,
+    pub max_image_width: Option<f32>,
+    pub max_image_height: Option<f32>,
+    pub dither: bool,
+    pub pixel_snap: bool,
+    pub line_numbers: bool,
+    pub indent_guides: bool,
+    /// Draws an overview of the document next to the scrollbar
+    pub minimap: bool,
+    /// Starts out stepping through the document's slides
+    pub presentation: bool,
+    /// Saves checkboxes that get toggled back to the markdown file
+    pub checkbox_write_back: bool,
+    pub monospace_grid: bool,
+    pub table_layout: TableLayout,
+    pub column_limit: ColumnLimit,
+    pub blank_lines: BlankLines,
+    pub raw_html: RawHtml,
+    pub link_opener: LinkOpener,
+    pub spacing: Spacing,
+    pub block_padding: BlockPadding,
+    pub text_scale: TextScale,
+    pub doc_stats: DocStatsOptions,
+    pub selection: SelectionOptions,
+    pub image_style: ImageStyle,
+    /// The page size can be picked from the CLI too
+    pub pdf: PdfOptions,
     pub font_opts: FontOptions,
     pub keybindings: Keybindings,
 }
@@ -39,8 +178,46 @@ impl Opts {
             theme: config_theme,
             scale: config_scale,
             lines_to_scroll: config_lines_to_scroll,
+            scroll_multiplier: config_scroll_multiplier,
+            invert_scroll: config_invert_scroll,
+            staging_belt_size: config_staging_belt_size,
+            glyph_cache_size: config_glyph_cache_size,
+            glyph_cache_max_size: config_glyph_cache_max_size,
+            glyph_cache_scale_tolerance: config_glyph_cache_scale_tolerance,
+            reduce_motion: config_reduce_motion,
+            gpu_fallback: config_gpu_fallback,
+            gpu_backend: config_gpu_backend,
+            gpu_power: config_gpu_power,
+            gpu: config_gpu,
+            window_width: config_window_width,
+            window_height: config_window_height,
+            max_image_width: config_max_image_width,
+            max_image_height: config_max_image_height,
+            dither: config_dither,
+            pixel_snap: config_pixel_snap,
+            line_numbers: config_line_numbers,
+            indent_guides: config_indent_guides,
+            minimap: config_minimap,
+            presentation: config_presentation,
+            checkbox_write_back: config_checkbox_write_back,
+            monospace_grid: config_monospace_grid,
+            table_layout: config_table_layout,
+            table_columns: config_table_columns,
+            blank_lines: config_blank_lines,
+            raw_html: config_raw_html,
+            link_opener: config_link_opener,
+            spacing: config_spacing,
+            block_padding: config_block_padding,
+            text_scale: config_text_scale,
+            doc_stats: config_doc_stats,
+            selection: config_selection,
+            image_style: config_image_style,
+            pdf: config_pdf,
             light_theme: config_light_theme,
             dark_theme: config_dark_theme,
+            themes: config_themes,
+            theme_file: config_theme_file,
+            code_theme: config_code_theme,
             font_options: config_font_options,
             keybindings:
                 config::KeybindingsSection {
@@ -49,16 +226,80 @@ impl Opts {
                 },
         } = config;
 
-        let theme = match args.theme.unwrap_or(config_theme) {
-            ThemeType::Dark => match config_dark_theme {
-                Some(config_dark_theme) => config_dark_theme.merge(color::DARK_DEFAULT),
-                None => color::DARK_DEFAULT,
+        let dark_theme = match config_dark_theme {
+            Some(config_dark_theme) => config_dark_theme.merge(color::DARK_DEFAULT),
+            None => color::DARK_DEFAULT,
+        };
+        let light_theme = match config_light_theme {
+            Some(config_light_theme) => config_light_theme.merge(color::LIGHT_DEFAULT),
+            None => color::LIGHT_DEFAULT,
+        };
+        let theme_file = config_theme_file.map(|path| ThemeFile {
+            path,
+            light: light_theme.clone(),
+            dark: dark_theme.clone(),
+            code_theme: config_code_theme.clone(),
+        });
+        let mut themes = vec![
+            ThemeChoice {
+                name: "light".to_owned(),
+                code_theme: config_code_theme.clone().merge(&light_theme),
+                theme: light_theme.clone(),
             },
-            ThemeType::Light => match config_light_theme {
-                Some(config_light_theme) => config_light_theme.merge(color::LIGHT_DEFAULT),
-                None => color::LIGHT_DEFAULT,
+            ThemeChoice {
+                name: "dark".to_owned(),
+                code_theme: config_code_theme.clone().merge(&dark_theme),
+                theme: dark_theme.clone(),
             },
+        ];
+        for (name, named_theme) in config_themes {
+            let base = if named_theme.dark {
+                dark_theme.clone()
+            } else {
+                light_theme.clone()
+            };
+            let theme = named_theme.theme.merge(base);
+            themes.push(ThemeChoice {
+                name,
+                code_theme: config_code_theme.clone().merge(&theme),
+                theme,
+            });
+        }
+        let (mut theme, mut code_theme, mut auto_theme) = match args.theme.unwrap_or(config_theme) {
+            ThemeType::Dark => {
+                let code_theme = config_code_theme.merge(&dark_theme);
+                (dark_theme, code_theme, None)
+            }
+            ThemeType::Light => {
+                let code_theme = config_code_theme.merge(&light_theme);
+                (light_theme, code_theme, None)
+            }
+            ThemeType::Auto => {
+                let auto_theme = AutoTheme {
+                    light: (
+                        light_theme.clone(),
+                        config_code_theme.clone().merge(&light_theme),
+                    ),
+                    dark: (dark_theme.clone(), config_code_theme.merge(&dark_theme)),
+                };
+                let (theme, code_theme) = auto_theme.pick(utils::os_prefers_dark());
+                (theme, code_theme, Some(auto_theme))
+            }
         };
+        if let Some(theme_file) = &theme_file {
+            match theme_file.load() {
+                Ok(choice) => {
+                    // The theme file wins over the config's theme unless the CLI picks one
+                    if args.theme.is_none() {
+                        theme = choice.theme.clone();
+                        code_theme = choice.code_theme;
+                        auto_theme = None;
+                    }
+                    themes.push(choice);
+                }
+                Err(err) => log::warn!("{:#}", err),
+            }
+        }
 
         let font_opts = config_font_options.unwrap_or_default();
 
@@ -73,8 +314,56 @@ impl Opts {
         Self {
             file_path: args.file_path.clone(),
             theme,
+            code_theme,
+            auto_theme,
+            themes,
+            theme_file,
             scale: args.scale.or(config_scale),
+            scroll_to: args.scroll_to,
+            export_svg: args.export_svg.clone(),
+            export_png: args.export_png.clone(),
+            export_pdf: args.export_pdf.clone(),
+            export_width: args.export_width,
+            bench: args.bench,
             lines_to_scroll: config_lines_to_scroll.0,
+            scroll_multiplier: config_scroll_multiplier.0,
+            invert_scroll: config_invert_scroll,
+            staging_belt_size: config_staging_belt_size.0,
+            glyph_cache_size: config_glyph_cache_size,
+            glyph_cache_max_size: config_glyph_cache_max_size,
+            glyph_cache_scale_tolerance: config_glyph_cache_scale_tolerance.0,
+            reduce_motion: config_reduce_motion,
+            gpu_fallback: config_gpu_fallback,
+            gpu_backend: config_gpu_backend,
+            gpu_power: config_gpu_power,
+            gpu: config_gpu,
+            window_width: config_window_width,
+            window_height: config_window_height,
+            max_image_width: config_max_image_width,
+            max_image_height: config_max_image_height,
+            dither: config_dither,
+            pixel_snap: config_pixel_snap,
+            line_numbers: config_line_numbers,
+            indent_guides: config_indent_guides,
+            minimap: config_minimap,
+            presentation: config_presentation,
+            checkbox_write_back: config_checkbox_write_back,
+            monospace_grid: config_monospace_grid,
+            table_layout: config_table_layout,
+            column_limit: config_table_columns,
+            blank_lines: config_blank_lines,
+            raw_html: config_raw_html,
+            link_opener: config_link_opener,
+            spacing: config_spacing,
+            block_padding: config_block_padding,
+            text_scale: config_text_scale,
+            doc_stats: config_doc_stats,
+            selection: config_selection,
+            image_style: config_image_style,
+            pdf: PdfOptions {
+                page_size: args.page_size.unwrap_or(config_pdf.page_size),
+                ..config_pdf
+            },
             font_opts,
             keybindings,
         }
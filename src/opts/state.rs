@@ -0,0 +1,66 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Preferences that are changed while running and remembered across runs
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct State {
+    pub text_scale: Option<f32>,
+    /// Name of the theme last switched to
+    pub theme: Option<String>,
+    /// Links that have been followed so that they can be colored differently
+    pub visited_links: BTreeSet<String>,
+    /// Logical size of the window that each document was last open in keyed by its path
+    pub window_sizes: BTreeMap<String, [f64; 2]>,
+}
+
+impl State {
+    fn path() -> anyhow::Result<PathBuf> {
+        let data_dir = dirs::data_local_dir().context("Failed detecting data dir")?;
+        Ok(data_dir.join("inlyne").join("state.toml"))
+    }
+
+    // Documents are remembered by their full path so that they're found no matter where inlyne
+    // gets started from
+    fn document_key(file_path: &Path) -> String {
+        fs::canonicalize(file_path)
+            .unwrap_or_else(|_| file_path.to_owned())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn window_size(&self, file_path: &Path) -> Option<[f64; 2]> {
+        self.window_sizes
+            .get(&Self::document_key(file_path))
+            .copied()
+    }
+
+    pub fn remember_window_size(&mut self, file_path: &Path, size: [f64; 2]) {
+        self.window_sizes
+            .insert(Self::document_key(file_path), size);
+    }
+
+    pub fn load() -> anyhow::Result<Self> {
+        let state_path = Self::path()?;
+        if state_path.is_file() {
+            let text = fs::read_to_string(&state_path).context("Failed reading state file")?;
+            let state = toml::from_str(&text)?;
+            Ok(state)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let state_path = Self::path()?;
+        if let Some(parent) = state_path.parent() {
+            fs::create_dir_all(parent).context("Failed creating state dir")?;
+        }
+        let text = toml::to_string(self)?;
+        fs::write(&state_path, text).context("Failed writing state file")
+    }
+}
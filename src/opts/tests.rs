@@ -1,9 +1,20 @@
 use std::{ffi::OsString, path::PathBuf};
 
-use super::{cli, config, Opts, ThemeType};
+use super::{cli, config, Opts, ScrollTo, ThemeChoice, ThemeType};
+use crate::color::{CodeTheme, LinkHoverStyle, SyntaxTheme, Theme, DARK_DEFAULT, LIGHT_DEFAULT};
+use crate::interpreter::RawHtml;
 use crate::keybindings;
-use crate::opts::config::{FontOptions, LinesToScroll};
+use crate::opts::config::{
+    BlockPadding, DocStatsOptions, FontOptions, GlyphCacheScaleTolerance, ImageStyle,
+    LinesToScroll, PdfOptions, ScrollMultiplier, SelectionOptions, SelectionStyle, StagingBeltSize,
+    TextScale,
+};
 use crate::opts::Args;
+use crate::pdf::PageSize;
+use crate::positioner::{BlankLines, Spacing};
+use crate::renderer::{AdapterFallback, AdapterSelector, GpuBackend, GpuPower};
+use crate::table::{ColumnLimit, ColumnOverflow, TableLayout};
+use crate::utils::LinkOpener;
 
 fn gen_args(args: Vec<&str>) -> Vec<OsString> {
     std::iter::once("inlyne")
@@ -12,14 +23,72 @@ fn gen_args(args: Vec<&str>) -> Vec<OsString> {
         .collect()
 }
 
+impl ThemeChoice {
+    fn from_default(name: &str, theme: Theme) -> Self {
+        Self {
+            name: name.to_owned(),
+            code_theme: CodeTheme::from(&theme),
+            theme,
+        }
+    }
+}
+
 impl Opts {
     fn mostly_default(file_path: impl Into<PathBuf>) -> Self {
         Self {
             file_path: file_path.into(),
             theme: ThemeType::default().as_theme(),
+            code_theme: CodeTheme::from(&ThemeType::default().as_theme()),
+            auto_theme: None,
+            themes: vec![
+                ThemeChoice::from_default("light", LIGHT_DEFAULT),
+                ThemeChoice::from_default("dark", DARK_DEFAULT),
+            ],
+            theme_file: None,
             scale: None,
+            scroll_to: None,
+            export_svg: None,
+            export_png: None,
+            export_pdf: None,
+            export_width: None,
+            bench: false,
             font_opts: FontOptions::default(),
             lines_to_scroll: LinesToScroll::default().0,
+            scroll_multiplier: ScrollMultiplier::default().0,
+            invert_scroll: false,
+            staging_belt_size: StagingBeltSize::default().0,
+            glyph_cache_size: None,
+            glyph_cache_max_size: None,
+            glyph_cache_scale_tolerance: GlyphCacheScaleTolerance::default().0,
+            reduce_motion: None,
+            gpu_fallback: AdapterFallback::default(),
+            gpu_backend: GpuBackend::default(),
+            gpu_power: GpuPower::default(),
+            gpu: None,
+            window_width: None,
+            window_height: None,
+            max_image_width: None,
+            max_image_height: None,
+            dither: false,
+            pixel_snap: false,
+            line_numbers: false,
+            indent_guides: false,
+            minimap: false,
+            presentation: false,
+            checkbox_write_back: false,
+            monospace_grid: false,
+            table_layout: TableLayout::default(),
+            column_limit: ColumnLimit::default(),
+            blank_lines: BlankLines::default(),
+            raw_html: RawHtml::default(),
+            link_opener: LinkOpener::default(),
+            spacing: Spacing::default(),
+            block_padding: BlockPadding::default(),
+            text_scale: TextScale::default(),
+            doc_stats: DocStatsOptions::default(),
+            selection: SelectionOptions::default(),
+            image_style: ImageStyle::default(),
+            pdf: PdfOptions::default(),
             keybindings: keybindings::defaults(),
         }
     }
@@ -59,6 +128,7 @@ fn config_overrides_default() {
         ),
         Opts {
             theme: ThemeType::Dark.as_theme(),
+            code_theme: CodeTheme::from(&ThemeType::Dark.as_theme()),
             ..Opts::mostly_default("file.md")
         }
     );
@@ -88,6 +158,7 @@ fn from_cli() {
         ),
         Opts {
             theme: ThemeType::Dark.as_theme(),
+            code_theme: CodeTheme::from(&ThemeType::Dark.as_theme()),
             ..Opts::mostly_default("file.md")
         }
     );
@@ -105,8 +176,487 @@ fn from_cli() {
         ),
         Opts {
             theme: ThemeType::Dark.as_theme(),
+            code_theme: CodeTheme::from(&ThemeType::Dark.as_theme()),
             scale: Some(1.5),
             ..Opts::mostly_default("file.md")
         }
     );
 }
+
+#[test]
+fn scroll_to() {
+    let config = config::Config::default();
+    let args = Args::parse_from(gen_args(vec!["--scroll-to", "50%", "file.md"]), &config);
+    assert_eq!(args.scroll_to, Some(ScrollTo::Fraction(0.5)));
+    let args = Args::parse_from(gen_args(vec!["--scroll-to", "25", "file.md"]), &config);
+    assert_eq!(args.scroll_to, Some(ScrollTo::Fraction(0.25)));
+    let args = Args::parse_from(
+        gen_args(vec!["--scroll-to-line", "120", "file.md"]),
+        &config,
+    );
+    assert_eq!(args.scroll_to, Some(ScrollTo::Line(120)));
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(args.scroll_to, None);
+}
+
+#[test]
+fn extra_files() {
+    let config = config::Config::default();
+    let args = Args::parse_from(gen_args(vec!["file.md", "other.md", "more.md"]), &config);
+    assert_eq!(args.file_path, PathBuf::from("file.md"));
+    assert_eq!(
+        args.extra_files,
+        [PathBuf::from("other.md"), PathBuf::from("more.md")]
+    );
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert!(args.extra_files.is_empty());
+}
+
+#[test]
+fn export_svg() {
+    let config = config::Config::default();
+    let args = Args::parse_from(
+        gen_args(vec!["--export-svg", "out.svg", "file.md"]),
+        &config,
+    );
+    assert_eq!(args.export_svg, Some(PathBuf::from("out.svg")));
+}
+
+#[test]
+fn export_png() {
+    let config = config::Config::default();
+    let args = Args::parse_from(
+        gen_args(vec![
+            "--export-png",
+            "out.png",
+            "--export-width",
+            "600",
+            "--scale",
+            "2",
+            "file.md",
+        ]),
+        &config,
+    );
+    assert_eq!(args.export_png, Some(PathBuf::from("out.png")));
+    assert_eq!(args.export_width, Some(600.));
+    let opts = Opts::parse_and_load_from(&args, config);
+    assert_eq!(opts.export_png, Some(PathBuf::from("out.png")));
+    assert_eq!(opts.export_width, Some(600.));
+    assert_eq!(opts.scale, Some(2.));
+}
+
+#[test]
+fn export_pdf() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [pdf]
+        page-size = "letter"
+        margin = 20
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(
+        gen_args(vec!["--export-pdf", "out.pdf", "file.md"]),
+        &config,
+    );
+    assert_eq!(args.export_pdf, Some(PathBuf::from("out.pdf")));
+    let opts = Opts::parse_and_load_from(&args, config);
+    assert_eq!(opts.export_pdf, Some(PathBuf::from("out.pdf")));
+    assert_eq!(
+        opts.pdf,
+        PdfOptions {
+            page_size: PageSize::Letter,
+            margin: 20.,
+        }
+    );
+
+    // The CLI's page size wins over the config's
+    let config: config::Config = toml::from_str("pdf = { page-size = \"letter\" }").unwrap();
+    let args = Args::parse_from(
+        gen_args(vec![
+            "--export-pdf",
+            "out.pdf",
+            "--page-size",
+            "a4",
+            "file.md",
+        ]),
+        &config,
+    );
+    let opts = Opts::parse_and_load_from(&args, config);
+    assert_eq!(opts.pdf.page_size, PageSize::A4);
+    assert_eq!(opts.pdf.margin, PdfOptions::default().margin);
+}
+
+#[test]
+fn bench() {
+    let config = config::Config::default();
+    let args = Args::parse_from(gen_args(vec!["--bench", "file.md"]), &config);
+    assert!(args.bench);
+    assert!(Opts::parse_and_load_from(&args, config).bench);
+}
+
+#[test]
+fn auto_theme() {
+    let config: config::Config = toml::from_str(
+        r#"
+        theme = "Auto"
+        [dark-theme]
+        underline-links = true
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let opts = Opts::parse_and_load_from(&args, config);
+    let auto_theme = opts.auto_theme.unwrap();
+    assert_eq!(auto_theme.light.0, LIGHT_DEFAULT);
+    assert!(auto_theme.dark.0.underline_links);
+    assert_eq!(auto_theme.dark.1, CodeTheme::from(&auto_theme.dark.0));
+    // The theme picked at startup is one of the two
+    assert!(opts.theme == auto_theme.light.0 || opts.theme == auto_theme.dark.0);
+
+    // The CLI can still force a theme
+    let config = config::Config {
+        theme: ThemeType::Auto,
+        ..Default::default()
+    };
+    let args = Args::parse_from(gen_args(vec!["--theme", "light", "file.md"]), &config);
+    assert_eq!(Opts::parse_and_load_from(&args, config).auto_theme, None);
+}
+
+#[test]
+fn named_themes() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [dark-theme]
+        underline-links = true
+        [themes.solarized]
+        dark = true
+        text-color = 0xffffff
+        [themes.paper]
+        text-color = 0x000000
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let themes = Opts::parse_and_load_from(&args, config).themes;
+    let names: Vec<_> = themes.iter().map(|choice| choice.name.as_str()).collect();
+    assert_eq!(names, ["light", "dark", "paper", "solarized"]);
+    // Named themes build on top of the configured light or dark theme
+    let solarized = &themes[3];
+    assert_eq!(solarized.theme.text_color, [1., 1., 1., 1.]);
+    assert_eq!(
+        solarized.theme.background_color,
+        DARK_DEFAULT.background_color
+    );
+    assert!(solarized.theme.underline_links);
+    assert_eq!(
+        themes[2].theme.background_color,
+        LIGHT_DEFAULT.background_color
+    );
+    assert_eq!(solarized.code_theme, CodeTheme::from(&solarized.theme));
+}
+
+#[test]
+fn colors_from_hex_strings_and_names() {
+    let config: config::Config = toml::from_str(
+        r##"
+        [light-theme]
+        text-color = "#ffffff"
+        code-color = "#fff"
+        link-color = "White"
+        select-color = 0xffffff
+        quote-bar-colors = ["white", 0x000000]
+        "##,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let theme = Opts::parse_and_load_from(&args, config).theme;
+    let white = [1., 1., 1., 1.];
+    assert_eq!(theme.text_color, white);
+    assert_eq!(theme.code_color, white);
+    assert_eq!(theme.link_color, white);
+    assert_eq!(theme.select_color, white);
+    assert_eq!(theme.quote_bar_colors, [white, [0., 0., 0., 1.]]);
+
+    // Malformed colors say what was expected and where
+    for malformed in [
+        r##"text-color = "#ffff""##,
+        r#"text-color = "blurple""#,
+        "text-color = 0x1000000",
+    ] {
+        let err = toml::from_str::<config::Config>(&format!(
+            "[light-theme]
+{malformed}"
+        ))
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("expected a hex color"), "{err}");
+        assert!(err.contains("text-color"), "{err}");
+    }
+}
+
+#[test]
+fn monospace_family_alias() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [font-options]
+        monospace-family = "Fira Code"
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).font_opts,
+        FontOptions {
+            monospace_font: Some("Fira Code".to_owned()),
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn selection_options() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [selection]
+        style = "underline"
+        opacity = 0.25
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).selection,
+        SelectionOptions {
+            style: SelectionStyle::Underline,
+            opacity: 0.25,
+        }
+    );
+}
+
+#[test]
+fn image_style() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [image-style]
+        border-width = 1.5
+        border-color = 0x000000
+        corner-radius = 6
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).image_style,
+        ImageStyle {
+            border_width: 1.5,
+            border_color: Some([0., 0., 0., 1.]),
+            corner_radius: 6.,
+        }
+    );
+}
+
+#[test]
+fn table_columns() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [table-columns]
+        max-width = 300
+        overflow = "truncate"
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).column_limit,
+        ColumnLimit {
+            max_width: Some(300.),
+            overflow: ColumnOverflow::Truncate,
+        }
+    );
+}
+
+#[test]
+fn blank_lines() {
+    let config: config::Config = toml::from_str(r#"blank-lines = "preserve""#).unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).blank_lines,
+        BlankLines::Preserve
+    );
+}
+
+#[test]
+fn spacing() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [spacing]
+        paragraph = 12
+        after-heading = 2
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).spacing,
+        Spacing {
+            paragraph: 12.,
+            after_heading: 2.,
+            ..Spacing::default()
+        }
+    );
+}
+
+#[test]
+fn raw_html() {
+    // HTML is shown as code unless asked for otherwise
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config::Config::default());
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config::Config::default()).raw_html,
+        RawHtml::Escape
+    );
+
+    let config: config::Config = toml::from_str(r#"raw-html = "render-subset""#).unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).raw_html,
+        RawHtml::RenderSubset
+    );
+}
+
+#[test]
+fn link_opener() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [link-opener]
+        command = "firefox --new-tab {url}"
+        blocked-schemes = []
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).link_opener,
+        LinkOpener {
+            command: Some("firefox --new-tab {url}".to_owned()),
+            allowed_schemes: None,
+            blocked_schemes: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn window_size() {
+    let config: config::Config =
+        toml::from_str("window-width = 1000\nwindow-height = 700").unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let opts = Opts::parse_and_load_from(&args, config);
+    assert_eq!(
+        (opts.window_width, opts.window_height),
+        (Some(1000.), Some(700.))
+    );
+}
+
+#[test]
+fn code_theme() {
+    let config: config::Config = toml::from_str(
+        r#"
+        theme = "Light"
+
+        [code-theme]
+        background = 0x000000
+        highlighter = "base16-ocean-dark"
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let opts = Opts::parse_and_load_from(&args, config);
+    assert_eq!(
+        opts.code_theme,
+        CodeTheme {
+            background: Some([0., 0., 0., 1.]),
+            // Left out, so it comes from the document's theme
+            text_color: opts.theme.code_color,
+            highlighter: SyntaxTheme::Base16OceanDark,
+        }
+    );
+}
+
+#[test]
+fn link_hover() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [light-theme]
+        link-hover-style = "background"
+        link-hover-color = 0xffffff
+        underline-links = true
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let theme = Opts::parse_and_load_from(&args, config).theme;
+    assert_eq!(theme.link_hover_style, LinkHoverStyle::Background);
+    assert_eq!(theme.link_hover_color, [1., 1., 1., 1.]);
+    assert!(theme.underline_links);
+}
+
+#[test]
+fn quote_bar_colors() {
+    let config: config::Config = toml::from_str(
+        r#"
+        [light-theme]
+        quote-bar-colors = [0xffffff, 0x000000]
+        "#,
+    )
+    .unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let theme = Opts::parse_and_load_from(&args, config).theme;
+    assert_eq!(theme.quote_bar_color(0), [1., 1., 1., 1.]);
+    assert_eq!(theme.quote_bar_color(1), [0., 0., 0., 1.]);
+    assert_eq!(theme.quote_bar_color(2), [1., 1., 1., 1.]);
+    // Every bar shares the selection color by default
+    assert_eq!(LIGHT_DEFAULT.quote_bar_color(3), LIGHT_DEFAULT.select_color);
+}
+
+#[test]
+fn gpu_fallback() {
+    let config: config::Config = toml::from_str(r#"gpu-fallback = "always""#).unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    assert_eq!(
+        Opts::parse_and_load_from(&args, config).gpu_fallback,
+        AdapterFallback::Always
+    );
+}
+
+#[test]
+fn gpu_selection() {
+    let config: config::Config =
+        toml::from_str("gpu-power = \"high-performance\"\ngpu = 1").unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let opts = Opts::parse_and_load_from(&args, config);
+    assert_eq!(opts.gpu_power, GpuPower::HighPerformance);
+    assert_eq!(opts.gpu, Some(AdapterSelector::Index(1)));
+
+    let config: config::Config = toml::from_str(r#"gpu = "NVIDIA""#).unwrap();
+    assert_eq!(config.gpu, Some(AdapterSelector::Name("NVIDIA".to_owned())));
+}
+
+#[test]
+fn gpu_backend() {
+    let config: config::Config = toml::from_str(r#"gpu-backend = "dx12""#).unwrap();
+    let args = Args::parse_from(gen_args(vec!["file.md"]), &config);
+    let backend = Opts::parse_and_load_from(&args, config).gpu_backend;
+    assert_eq!(backend, GpuBackend::Dx12);
+    assert_eq!(wgpu::Backends::from(backend), wgpu::Backends::DX12);
+    assert_eq!(
+        wgpu::Backends::from(GpuBackend::Auto),
+        wgpu::Backends::all()
+    );
+}
+
+#[test]
+fn list_gpus_without_a_file() {
+    let config = config::Config::default();
+    let args = Args::parse_from(gen_args(vec!["--list-gpus"]), &config);
+    assert!(args.list_gpus);
+}
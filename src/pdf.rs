@@ -0,0 +1,244 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::ops::Range;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+
+use crate::color::linear_rgba_to_hex;
+use crate::opts::{Opts, PdfOptions};
+use crate::png;
+
+// Points in an inch and logical pixels in an inch
+const POINTS_PER_INCH: f32 = 72.;
+const PIXELS_PER_INCH: f32 = 96.;
+const MM_PER_INCH: f32 = 25.4;
+
+/// Scale factor that PDFs get drawn at when `--scale` isn't set. Higher than on screen so that
+/// printed text stays sharp
+pub const DEFAULT_PDF_SCALE: f32 = 2.;
+
+/// Size of the pages of PDF exports
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PageSize {
+    #[default]
+    A4,
+    Letter,
+}
+
+impl PageSize {
+    /// Width and height in points
+    pub fn size(self) -> (f32, f32) {
+        match self {
+            Self::A4 => (595.28, 841.89),
+            Self::Letter => (612., 792.),
+        }
+    }
+}
+
+/// Parses the markdown file from `opts` and draws it as a PDF with pages set up by `opts.pdf`.
+/// The document is laid out `width` logical pixels wide (as wide as the space on the pages at 96
+/// DPI when unset) and drawn at `scale`. Each page gets an image of its part of the document
+pub fn export(opts: &Opts, width: Option<f32>, scale: f32) -> anyhow::Result<Vec<u8>> {
+    let PdfOptions { page_size, margin } = opts.pdf;
+    let (page_width, page_height) = page_size.size();
+    let margin = margin * POINTS_PER_INCH / MM_PER_INCH;
+    let content_width = page_width - 2. * margin;
+    let content_height = page_height - 2. * margin;
+    anyhow::ensure!(
+        content_width > 0. && content_height > 0.,
+        "The margins leave no space on the page"
+    );
+    let width = width.unwrap_or(content_width * PIXELS_PER_INCH / POINTS_PER_INCH);
+
+    let (image, blocks) = png::render(opts, width, scale)?;
+    // Size of a pixel of the image in points
+    let pixel_size = content_width / image.width() as f32;
+    let pages = page_breaks(&blocks, image.height() as f32, content_height / pixel_size);
+
+    let wgpu::Color { r, g, b, a } = opts.theme.background_color;
+    let background = linear_rgba_to_hex([r as f32, g as f32, b as f32, a as f32]);
+    let background = [16, 8, 0].map(|shift| ((background >> shift) & 0xff) as f32 / 255.);
+    let mut pdf = PdfWriter::default();
+    pdf.bytes.extend_from_slice(b"%PDF-1.4\n");
+    // The catalog and the page tree come first while every page takes up three more objects
+    let page_ids: Vec<_> = (0..pages.len()).map(|page| 3 + page * 3).collect();
+    pdf.object(1, "<< /Type /Catalog /Pages 2 0 R >>");
+    let kids: Vec<_> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    pdf.object(
+        2,
+        &format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        ),
+    );
+    for (rows, id) in pages.iter().zip(page_ids) {
+        let rows = rows.start.round() as u32..(rows.end.round() as u32).min(image.height());
+        let height = rows.end - rows.start;
+        let mut pixels = Vec::with_capacity((image.width() * height * 3) as usize);
+        for y in rows {
+            for x in 0..image.width() {
+                pixels.extend_from_slice(&image.get_pixel(x, y).0[..3]);
+            }
+        }
+
+        pdf.object(
+            id,
+            &format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /XObject << /Page {} 0 R >> >> /Contents {} 0 R >>",
+                page_width,
+                page_height,
+                id + 2,
+                id + 1
+            ),
+        );
+        let drawn_height = height as f32 * pixel_size;
+        let mut contents = String::new();
+        // The margins get the document's background
+        writeln!(
+            contents,
+            "{} {} {} rg 0 0 {} {} re f",
+            background[0], background[1], background[2], page_width, page_height
+        )?;
+        writeln!(
+            contents,
+            "q {} 0 0 {} {} {} cm /Page Do Q",
+            content_width,
+            drawn_height,
+            margin,
+            page_height - margin - drawn_height
+        )?;
+        pdf.stream(id + 1, "", contents.as_bytes())?;
+        pdf.stream(
+            id + 2,
+            &format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} \
+                 /ColorSpace /DeviceRGB /BitsPerComponent 8",
+                image.width(),
+                height
+            ),
+            &pixels,
+        )?;
+    }
+    Ok(pdf.finish())
+}
+
+// Splits a document `height` pixels tall into pages. Pages end above blocks (the rows of
+// top-level elements) that would otherwise get cut in half unless they're too tall for a page
+fn page_breaks(blocks: &[Range<f32>], height: f32, page_height: f32) -> Vec<Range<f32>> {
+    let mut pages = Vec::new();
+    let mut top = 0.;
+    while top < height {
+        let mut bottom = (top + page_height).min(height);
+        if let Some(cut) = blocks.iter().find(|block| {
+            block.start > top
+                && block.start < bottom
+                && block.end > bottom
+                && block.end - block.start <= page_height
+        }) {
+            bottom = cut.start;
+        }
+        pages.push(top..bottom);
+        top = bottom;
+    }
+    pages
+}
+
+// Writes objects while keeping track of where they start for the cross-reference table
+#[derive(Default)]
+struct PdfWriter {
+    bytes: Vec<u8>,
+    // Offsets of the objects by their id - 1
+    offsets: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn start_object(&mut self, id: usize) {
+        if self.offsets.len() < id {
+            self.offsets.resize(id, 0);
+        }
+        self.offsets[id - 1] = self.bytes.len();
+        self.bytes
+            .extend_from_slice(format!("{} 0 obj\n", id).as_bytes());
+    }
+
+    fn object(&mut self, id: usize, body: &str) {
+        self.start_object(id);
+        self.bytes.extend_from_slice(body.as_bytes());
+        self.bytes.extend_from_slice(b"\nendobj\n");
+    }
+
+    // A compressed stream with `dict` being the entries of its dictionary other than its length
+    // and filter
+    fn stream(&mut self, id: usize, dict: &str, data: &[u8]) -> anyhow::Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let data = encoder.finish()?;
+        self.start_object(id);
+        self.bytes.extend_from_slice(
+            format!(
+                "<< {} /Length {} /Filter /FlateDecode >>\nstream\n",
+                dict,
+                data.len()
+            )
+            .as_bytes(),
+        );
+        self.bytes.extend_from_slice(&data);
+        self.bytes.extend_from_slice(b"\nendstream\nendobj\n");
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let xref = self.bytes.len();
+        let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            trailer.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        trailer.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            xref
+        ));
+        self.bytes.extend_from_slice(trailer.as_bytes());
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_avoid_splitting_blocks() {
+        // Fits on a single page
+        assert_eq!(page_breaks(&[0. ..50.], 50., 100.), [0. ..50.]);
+        // The second block would be cut in half, so it starts the next page
+        assert_eq!(
+            page_breaks(&[0. ..60., 70. ..130.], 130., 100.),
+            [0. ..70., 70. ..130.]
+        );
+        // Blocks taller than a page get split anyway
+        assert_eq!(
+            page_breaks(&[0. ..10., 20. ..250.], 250., 100.),
+            [0. ..100., 100. ..200., 200. ..250.]
+        );
+    }
+
+    #[test]
+    fn cross_references_point_at_objects() {
+        let mut pdf = PdfWriter::default();
+        pdf.bytes.extend_from_slice(b"%PDF-1.4\n");
+        pdf.object(1, "<< /Type /Catalog /Pages 2 0 R >>");
+        pdf.object(2, "<< /Type /Pages /Kids [] /Count 0 >>");
+        let offsets = pdf.offsets.clone();
+        let bytes = pdf.finish();
+        for (id, offset) in offsets.into_iter().enumerate() {
+            assert!(bytes[offset..].starts_with(format!("{} 0 obj", id + 1).as_bytes()));
+        }
+        assert!(bytes.ends_with(b"%%EOF\n"));
+    }
+}
@@ -0,0 +1,166 @@
+use std::num::NonZeroU32;
+use std::ops::Range;
+
+use anyhow::Context;
+use image::RgbaImage;
+use winit::dpi::PhysicalSize;
+
+use crate::opts::Opts;
+use crate::renderer::{Renderer, HEADLESS_FORMAT};
+use crate::svg::load_elements;
+
+// Bytes per pixel of `HEADLESS_FORMAT`
+const PIXEL_SIZE: u32 = 4;
+
+/// Parses the markdown file from `opts` and draws it `width` logical pixels wide with the GPU.
+/// `scale` is the scale factor (DPI) to draw at. The document is drawn in tiles no taller than a
+/// texture can be which get stitched together, so even long documents fit. Images that don't load
+/// in time are left out
+pub fn export(opts: &Opts, width: f32, scale: f32) -> anyhow::Result<RgbaImage> {
+    render(opts, width, scale).map(|(image, _)| image)
+}
+
+// Draws the document like `export` along with the rows that each of its top-level elements cover
+pub(crate) fn render(
+    opts: &Opts,
+    width: f32,
+    scale: f32,
+) -> anyhow::Result<(RgbaImage, Vec<Range<f32>>)> {
+    let elements = load_elements(opts, scale)?;
+    let width = (width * scale).round() as u32;
+    // Only the width matters for laying the document out. The tiles get their height after
+    let mut renderer =
+        pollster::block_on(Renderer::headless(PhysicalSize::new(width, 1), scale, opts))?;
+    let max_size = renderer.device.limits().max_texture_dimension_2d;
+    anyhow::ensure!(
+        width <= max_size,
+        "Exports can be at most {} pixels wide with this GPU",
+        max_size
+    );
+    renderer.positioner.max_image_width = opts.max_image_width;
+    renderer.positioner.max_image_height = opts.max_image_height;
+    renderer.positioner.line_numbers = opts.line_numbers;
+    renderer.positioner.spacing = opts.spacing;
+    renderer.set_dither(opts.dither);
+    renderer.pixel_snap = opts.pixel_snap;
+    renderer.indent_guides = opts.indent_guides;
+    renderer.image_style = opts.image_style;
+    renderer.code_theme = opts.code_theme;
+    renderer.elements = elements;
+    renderer.reposition()?;
+
+    let height = (renderer.positioner.reserved_height.ceil() as u32).max(1);
+    let tile_height = height.min(max_size);
+    renderer.config.height = tile_height;
+    renderer.positioner.screen_size.1 = tile_height as f32;
+    let mut elements = std::mem::take(&mut renderer.elements);
+    let blocks = elements
+        .iter()
+        .filter_map(|element| element.bounds.as_ref())
+        .map(|bounds| bounds.pos.1..bounds.pos.1 + bounds.size.1)
+        .collect();
+
+    let texture = renderer.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Export Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height: tile_height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HEADLESS_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let bytes_per_row = padded_bytes_per_row(width);
+    let buffer = renderer.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Export Buffer"),
+        size: u64::from(bytes_per_row) * u64::from(tile_height),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut image = RgbaImage::new(width, height);
+    for rows in tiles(height, tile_height) {
+        renderer.scroll_y = rows.start as f32;
+        renderer.draw_to(&view, &mut elements)?;
+
+        let mut encoder = renderer
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height: tile_height,
+                depth_or_array_layers: 1,
+            },
+        );
+        renderer.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        renderer.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .context("The GPU went away while exporting")?
+            .context("Failed to read back an exported tile")?;
+        {
+            let data = slice.get_mapped_range();
+            // The last tile only partly covers the document
+            for (tile_row, y) in rows.enumerate() {
+                let start = tile_row * bytes_per_row as usize;
+                let pixels = &data[start..start + (width * PIXEL_SIZE) as usize];
+                let row_start = y as usize * (width * PIXEL_SIZE) as usize;
+                (*image)[row_start..row_start + pixels.len()].copy_from_slice(pixels);
+            }
+        }
+        buffer.unmap();
+    }
+    Ok((image, blocks))
+}
+
+// Rows of each tile covering a document `height` pixels tall
+fn tiles(height: u32, tile_height: u32) -> impl Iterator<Item = Range<u32>> {
+    (0..height)
+        .step_by(tile_height as usize)
+        .map(move |start| start..(start + tile_height).min(height))
+}
+
+// Rows copied out of textures have to be aligned in the buffer they're copied to
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * PIXEL_SIZE + align - 1) / align * align
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_the_document() {
+        assert_eq!(tiles(10, 10).collect::<Vec<_>>(), [0..10]);
+        assert_eq!(tiles(25, 10).collect::<Vec<_>>(), [0..10, 10..20, 20..25]);
+        assert_eq!(tiles(20, 10).collect::<Vec<_>>(), [0..10, 10..20]);
+    }
+
+    #[test]
+    fn rows_are_padded() {
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+        assert_eq!(padded_bytes_per_row(1), 256);
+    }
+}
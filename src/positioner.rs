@@ -1,11 +1,15 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    time::Instant,
+};
 
 use anyhow::Context;
-use wgpu_glyph::GlyphBrush;
+use serde::Deserialize;
+use wgpu_glyph::GlyphCruncher;
 
 use crate::{
-    table::{TABLE_COL_GAP, TABLE_ROW_GAP},
-    text::TextBox,
+    text::{DefinitionPart, TextBox},
     utils::{Align, Point, Rect, Size},
     Element,
 };
@@ -17,6 +21,8 @@ pub const DEFAULT_MARGIN: f32 = 100.;
 pub struct Positioned<T> {
     pub inner: T,
     pub bounds: Option<Rect>,
+    /// When the element first showed up on screen. Used to fade it in
+    pub first_rendered: Cell<Option<Instant>>,
 }
 
 impl<T> Positioned<T> {
@@ -34,6 +40,7 @@ impl<T> Positioned<T> {
         Positioned {
             inner: item,
             bounds: None,
+            first_rendered: Cell::new(None),
         }
     }
 }
@@ -42,45 +49,95 @@ impl<T> Positioned<T> {
 pub struct Positioner {
     pub screen_size: Size,
     pub reserved_height: f32,
+    /// Right edge of the widest element. Tables that don't wrap their cells can go past the edge
+    /// of the screen
+    pub reserved_width: f32,
     pub hidpi_scale: f32,
     pub anchors: HashMap<String, f32>,
+    /// Where each slide after the first starts. Horizontal rules split the document into slides
+    pub slides: Vec<f32>,
+    /// Images get scaled down to fit within these (in logical pixels) keeping their aspect ratio
+    pub max_image_width: Option<f32>,
+    pub max_image_height: Option<f32>,
+    /// Extra scale applied only to text on top of the zoom, leaving margins and images alone
+    pub text_scale: f32,
+    /// Makes room for line numbers to the left of code blocks
+    pub line_numbers: bool,
+    /// Space kept free above the document for things drawn over it like the tab bar
+    pub top_inset: f32,
+    pub spacing: Spacing,
+    // Floated images that the text below them hasn't cleared yet
+    floats: Vec<FloatBox>,
 }
 
 impl Positioner {
     pub fn new(screen_size: Size, hidpi_scale: f32) -> Self {
         Self {
             reserved_height: DEFAULT_PADDING * hidpi_scale,
+            reserved_width: 0.,
             hidpi_scale,
             screen_size,
             anchors: HashMap::new(),
+            slides: Vec::new(),
+            max_image_width: None,
+            max_image_height: None,
+            text_scale: 1.,
+            line_numbers: false,
+            top_inset: 0.,
+            spacing: Spacing::default(),
+            floats: Vec::new(),
         }
     }
 
+    /// How far the document can be scrolled to the right to see the elements that go past the
+    /// edge of the screen
+    pub fn overflow_width(&self) -> f32 {
+        (self.reserved_width + DEFAULT_MARGIN - self.screen_size.0).max(0.)
+    }
+
+    /// Where the first element of the document goes
+    pub fn top(&self, zoom: f32) -> f32 {
+        self.top_inset + DEFAULT_PADDING * self.hidpi_scale * zoom
+    }
+
     // Positions the element but does not update reserved_height
-    pub fn position(
+    pub fn position<T: GlyphCruncher>(
         &mut self,
-        glyph_brush: &mut GlyphBrush<()>,
+        glyph_brush: &mut T,
         element: &mut Positioned<Element>,
         zoom: f32,
     ) -> anyhow::Result<()> {
+        let text_zoom = zoom * self.text_scale;
         let bounds = match &mut element.inner {
             Element::TextBox(text_box) => {
                 let indent = text_box.indent;
-                let pos = (DEFAULT_MARGIN + indent, self.reserved_height);
+                let gutter = if self.line_numbers {
+                    text_box.gutter_width(glyph_brush, text_zoom)
+                } else {
+                    0.
+                };
+                let (left, right) = self.float_insets();
+                let pos = (
+                    DEFAULT_MARGIN + left + indent + gutter,
+                    self.reserved_height,
+                );
+                let available = (self.screen_size.0 - pos.0 - DEFAULT_MARGIN - right).max(0.);
+                text_box.float_width = (left > 0. || right > 0.).then_some(available);
 
                 let size = text_box.size(
                     glyph_brush,
                     pos,
-                    (
-                        (self.screen_size.0 - pos.0 - DEFAULT_MARGIN).max(0.),
-                        f32::INFINITY,
-                    ),
-                    zoom,
+                    (text_box.wrap_width(available, zoom), f32::INFINITY),
+                    text_zoom,
                 );
 
                 if let Some(ref anchor_name) = text_box.is_anchor {
                     let _ = self.anchors.insert(anchor_name.clone(), pos.1);
                 }
+                // Every reference to the same footnote shares an id, so the first one wins
+                for id in &text_box.ids {
+                    self.anchors.entry(id.clone()).or_insert(pos.1);
+                }
 
                 Rect::new(pos, size)
             }
@@ -89,42 +146,39 @@ impl Positioner {
                 (0., spacer.space * self.hidpi_scale * zoom),
             ),
             Element::Image(image) => {
-                let size = image.size(self.screen_size, zoom);
-                match image.is_aligned {
-                    Some(Align::Center) => Rect::new(
-                        (self.screen_size.0 / 2. - size.0 / 2., self.reserved_height),
-                        size,
-                    ),
-                    _ => Rect::new((DEFAULT_MARGIN, self.reserved_height), size),
+                let size = self.clamp_image_size(image.size(self.screen_size, zoom), zoom);
+                if let Some(side) = image.float {
+                    let (left, right) = self.float_insets();
+                    let x = match side {
+                        Float::Left => DEFAULT_MARGIN + left,
+                        Float::Right => self.screen_size.0 - DEFAULT_MARGIN - right - size.0,
+                    };
+                    // Text beside the image keeps a gap twice the padding away from it
+                    let padding = DEFAULT_PADDING * self.hidpi_scale * zoom;
+                    self.floats.push(FloatBox {
+                        side,
+                        width: size.0 + 2. * padding,
+                        bottom: self.reserved_height + size.1 + padding,
+                    });
+                    Rect::new((x, self.reserved_height), size)
+                } else {
+                    match image.is_aligned {
+                        Some(Align::Center) => Rect::new(
+                            (self.screen_size.0 / 2. - size.0 / 2., self.reserved_height),
+                            size,
+                        ),
+                        _ => Rect::new((DEFAULT_MARGIN, self.reserved_height), size),
+                    }
                 }
             }
             Element::Table(table) => {
                 let pos = (DEFAULT_MARGIN, self.reserved_height);
-                let width = table
-                    .column_widths(
-                        glyph_brush,
-                        pos,
-                        (self.screen_size.0 - pos.0 - DEFAULT_MARGIN, f32::INFINITY),
-                        zoom,
-                    )
-                    .iter()
-                    .fold(0., |acc, x| acc + x);
-                let height = table
-                    .row_heights(
-                        glyph_brush,
-                        pos,
-                        (self.screen_size.0 - pos.0 - DEFAULT_MARGIN, f32::INFINITY),
-                        zoom,
-                    )
-                    .iter()
-                    .fold(0., |acc, x| acc + x);
-                Rect::new(
-                    pos,
-                    (
-                        width * (TABLE_COL_GAP * table.headers.len() as f32),
-                        height + (TABLE_ROW_GAP * (table.rows.len() + 1) as f32),
-                    ),
-                )
+                let bounds = (self.screen_size.0 - pos.0 - DEFAULT_MARGIN, f32::INFINITY);
+                table.position_cells(glyph_brush, pos, bounds, text_zoom);
+                Rect::new(pos, table.size(glyph_brush, pos, bounds, text_zoom))
+            }
+            Element::Row(row) if row.is_flexible() => {
+                self.position_flexible_row(glyph_brush, row, zoom)?
             }
             Element::Row(row) => {
                 let mut reserved_width = DEFAULT_MARGIN;
@@ -204,45 +258,306 @@ impl Positioner {
         Ok(())
     }
 
+    // Space taken up on the left and right by the floats that are still beside the current height.
+    // Floats that text has moved past get dropped
+    fn float_insets(&mut self) -> (f32, f32) {
+        let height = self.reserved_height;
+        self.floats.retain(|float| float.bottom > height);
+        self.floats
+            .iter()
+            .fold((0., 0.), |(left, right), float| match float.side {
+                Float::Left => (left + float.width, right),
+                Float::Right => (left, right + float.width),
+            })
+    }
+
+    // Moves reserved height below every float so that the next element gets the full width
+    fn clear_floats(&mut self) {
+        for float in self.floats.drain(..) {
+            self.reserved_height = self.reserved_height.max(float.bottom);
+        }
+    }
+
+    fn clamp_image_size(&self, size: Size, zoom: f32) -> Size {
+        let scale = self.hidpi_scale * zoom;
+        let max_width = self
+            .max_image_width
+            .map_or(f32::INFINITY, |width| width * scale);
+        let max_height = self
+            .max_image_height
+            .map_or(f32::INFINITY, |height| height * scale);
+        if size.0 <= max_width && size.1 <= max_height {
+            return size;
+        }
+        let ratio = (max_width / size.0).min(max_height / size.1);
+        (size.0 * ratio, size.1 * ratio)
+    }
+
+    // Lays the row's elements out on a single line with widths distributed by their `RowWidth`.
+    // Elements without a width keep their natural width
+    fn position_flexible_row<T: GlyphCruncher>(
+        &mut self,
+        glyph_brush: &mut T,
+        row: &mut Row,
+        zoom: f32,
+    ) -> anyhow::Result<Rect> {
+        let padding = DEFAULT_PADDING * self.hidpi_scale * zoom;
+        let gaps = row.elements.len().saturating_sub(1) as f32 * padding;
+        let available = (self.screen_size.0 - 2. * DEFAULT_MARGIN - gaps).max(0.);
+
+        let mut widths = vec![0.; row.elements.len()];
+        let mut total_weight = 0.;
+        let mut remaining = available;
+        for (i, element) in row.elements.iter_mut().enumerate() {
+            match row.widths.get(i).copied().flatten() {
+                Some(RowWidth::Fixed(width)) => widths[i] = width * self.hidpi_scale * zoom,
+                Some(RowWidth::Flex(weight)) => {
+                    total_weight += weight;
+                    continue;
+                }
+                None => {
+                    self.position(glyph_brush, element, zoom)?;
+                    widths[i] = element
+                        .bounds
+                        .as_ref()
+                        .context("Element didn't have bounds")?
+                        .size
+                        .0;
+                }
+            }
+            remaining -= widths[i];
+        }
+        if total_weight > 0. {
+            for (i, width) in widths.iter_mut().enumerate() {
+                if let Some(RowWidth::Flex(weight)) = row.widths.get(i).copied().flatten() {
+                    *width = remaining.max(0.) * weight / total_weight;
+                }
+            }
+        }
+
+        let mut x = DEFAULT_MARGIN;
+        let mut max_height: f32 = 0.;
+        for (i, element) in row.elements.iter_mut().enumerate() {
+            let width = widths[i];
+            if row.widths.get(i).copied().flatten().is_some() {
+                if let Element::TextBox(text_box) = &mut element.inner {
+                    text_box.max_width = Some(width);
+                }
+                self.position(glyph_brush, element, zoom)?;
+            }
+            let bounds = element
+                .bounds
+                .as_mut()
+                .context("Element didn't have bounds")?;
+            // Shrink images that don't fit in their column while keeping their aspect ratio
+            if matches!(element.inner, Element::Image(_)) && bounds.size.0 > width {
+                bounds.size = (width, bounds.size.1 * width / bounds.size.0);
+            }
+            bounds.pos = (x, self.reserved_height);
+            max_height = max_height.max(bounds.size.1);
+            x += width + padding;
+        }
+
+        Ok(Rect::new(
+            (DEFAULT_MARGIN, self.reserved_height),
+            ((x - padding - DEFAULT_MARGIN).max(0.), max_height + padding),
+        ))
+    }
+
+    /// Positions the element after `prev` and reserves room for it. `reserved_height` always
+    /// includes the padding below the last element, so that gets swapped out for the spacing
+    /// between the two elements first. `prev` is the last element that isn't a spacer (see
+    /// [`last_block`]) and spacers themselves keep the default padding, so the spacing gets added
+    /// once no matter how many spacers come in between. Floated images don't reserve any room and
+    /// only text wraps around them, so anything else goes below the floats first
+    pub fn place<T: GlyphCruncher>(
+        &mut self,
+        glyph_brush: &mut T,
+        prev: Option<&Element>,
+        element: &mut Positioned<Element>,
+        zoom: f32,
+    ) -> anyhow::Result<()> {
+        let scale = self.hidpi_scale * zoom;
+        match &element.inner {
+            Element::Image(image) if image.float.is_some() => {
+                return self.position(glyph_brush, element, zoom);
+            }
+            Element::TextBox(_) => {}
+            Element::Spacer(spacer) if !spacer.clears_floats => {}
+            _ => self.clear_floats(),
+        }
+        match (prev, &element.inner) {
+            (_, Element::Spacer(_)) | (None, _) => {}
+            (Some(prev), next) => {
+                self.reserved_height +=
+                    (self.spacing.between(prev, next) - DEFAULT_PADDING) * scale;
+            }
+        }
+        self.position(glyph_brush, element, zoom)?;
+        let bounds = element
+            .bounds
+            .as_ref()
+            .context("Element didn't have bounds")?;
+        self.reserved_height += DEFAULT_PADDING * scale + bounds.size.1;
+        self.reserved_width = self.reserved_width.max(bounds.max().0);
+        if let Element::Spacer(Spacer { visibile: true, .. }) = element.inner {
+            self.slides.push(self.reserved_height);
+        }
+        Ok(())
+    }
+
     // Resets reserved height and positions every element again
-    pub fn reposition(
+    pub fn reposition<T: GlyphCruncher>(
         &mut self,
-        glyph_brush: &mut GlyphBrush<()>,
+        glyph_brush: &mut T,
         elements: &mut [Positioned<Element>],
         zoom: f32,
     ) -> anyhow::Result<()> {
-        self.reserved_height = DEFAULT_PADDING * self.hidpi_scale * zoom;
+        self.reserved_height = self.top(zoom);
+        self.reserved_width = 0.;
+        self.floats.clear();
+        self.slides.clear();
+        self.anchors.clear();
 
+        let mut prev: Option<&Positioned<Element>> = None;
         for element in elements {
-            self.position(glyph_brush, element, zoom)?;
-            self.reserved_height += DEFAULT_PADDING * self.hidpi_scale * zoom
-                + element
-                    .bounds
-                    .as_ref()
-                    .context("Element didn't have bounds")?
-                    .size
-                    .1;
+            self.place(glyph_brush, prev.map(|prev| &prev.inner), element, zoom)?;
+            if !matches!(element.inner, Element::Spacer(_)) {
+                prev = Some(element);
+            }
         }
+        self.clear_floats();
         Ok(())
     }
 }
 
+/// Side of the document that an image floats on with text wrapping around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Float {
+    Left,
+    Right,
+}
+
+// Room taken up by a floated image next to the text
+#[derive(Debug, Clone, Copy)]
+struct FloatBox {
+    side: Float,
+    width: f32,
+    bottom: f32,
+}
+
+/// Vertical space (in logical pixels) left between blocks of the document
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Spacing {
+    pub paragraph: f32,
+    pub before_heading: f32,
+    pub after_heading: f32,
+    /// Above each term of a definition list that follows another term's definition
+    pub between_definitions: f32,
+}
+
+impl Default for Spacing {
+    fn default() -> Self {
+        Self {
+            paragraph: DEFAULT_PADDING,
+            before_heading: DEFAULT_PADDING,
+            after_heading: DEFAULT_PADDING,
+            between_definitions: 2. * DEFAULT_PADDING,
+        }
+    }
+}
+
+impl Spacing {
+    /// Space between `prev` and the `next` element right below it
+    pub fn between(&self, prev: &Element, next: &Element) -> f32 {
+        if is_heading(next) {
+            self.before_heading
+        } else if is_heading(prev) {
+            self.after_heading
+        } else if definition_part(prev) == Some(DefinitionPart::Definition)
+            && definition_part(next) == Some(DefinitionPart::Term)
+        {
+            self.between_definitions
+        } else {
+            self.paragraph
+        }
+    }
+}
+
+/// The last of `elements` that isn't a spacer. The spacing of the next element is based on it
+pub fn last_block(elements: &[Positioned<Element>]) -> Option<&Element> {
+    elements
+        .iter()
+        .rev()
+        .map(|element| &element.inner)
+        .find(|element| !matches!(element, Element::Spacer(_)))
+}
+
+// Headings are the only text boxes that get an anchor
+fn is_heading(element: &Element) -> bool {
+    matches!(element, Element::TextBox(text_box) if text_box.is_anchor.is_some())
+}
+
+fn definition_part(element: &Element) -> Option<DefinitionPart> {
+    match element {
+        Element::TextBox(text_box) => text_box.definition_part,
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 pub struct Spacer {
     pub space: f32,
     pub visibile: bool,
+    /// Moves everything after it below any floated images
+    pub clears_floats: bool,
 }
 
 impl Spacer {
     pub fn new(space: f32, visibile: bool) -> Spacer {
-        Spacer { space, visibile }
+        Spacer {
+            space,
+            visibile,
+            clears_floats: false,
+        }
+    }
+
+    pub fn clearing() -> Spacer {
+        Spacer {
+            clears_floats: true,
+            ..Spacer::new(0., false)
+        }
     }
 }
 
+/// What happens to runs of more than one blank line between blocks
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlankLines {
+    /// Any number of blank lines separate blocks the same as a single one
+    #[default]
+    Collapse,
+    /// Each blank line past the first adds a line's worth of space between the blocks
+    Preserve,
+}
+
+/// How much of a flexible row's width an element takes up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowWidth {
+    /// A share of the width left over after fixed and natural widths relative to the other
+    /// flexible elements
+    Flex(f32),
+    /// A fixed width in logical pixels
+    Fixed(f32),
+}
+
 #[derive(Debug)]
 pub struct Row {
     pub elements: Vec<Positioned<Element>>,
     pub hidpi_scale: f32,
+    /// Per-element widths. Rows where every element lacks a width wrap like inline content
+    pub widths: Vec<Option<RowWidth>>,
 }
 
 impl Row {
@@ -250,8 +565,18 @@ impl Row {
         Self {
             elements,
             hidpi_scale,
+            widths: Vec::new(),
         }
     }
+
+    pub fn with_widths(mut self, widths: Vec<Option<RowWidth>>) -> Self {
+        self.widths = widths;
+        self
+    }
+
+    pub fn is_flexible(&self) -> bool {
+        self.widths.iter().any(Option::is_some)
+    }
 }
 
 #[derive(Debug)]
@@ -276,3 +601,165 @@ impl Section {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{Float, Positioned, Positioner, RowWidth, Spacer, Spacing, DEFAULT_MARGIN};
+    use crate::image::{Image, ImageSize, Length};
+    use crate::table::{Table, TableLayout, TABLE_COL_GAP};
+    use crate::text::{tests::NoGlyphs, DefinitionPart, TextBox};
+    use crate::Element;
+
+    #[test]
+    fn headings_get_their_own_spacing() {
+        let spacing = Spacing {
+            paragraph: 1.,
+            before_heading: 2.,
+            after_heading: 3.,
+            between_definitions: 4.,
+        };
+        let paragraph = || Element::TextBox(TextBox::new(Vec::new(), 1.));
+        let heading = || {
+            let mut text_box = TextBox::new(Vec::new(), 1.);
+            text_box.set_anchor(Some("#heading".to_owned()));
+            Element::TextBox(text_box)
+        };
+        let spacer = || Element::from(Spacer::new(5., false));
+
+        assert_eq!(spacing.between(&paragraph(), &paragraph()), 1.);
+        assert_eq!(spacing.between(&spacer(), &heading()), 2.);
+        assert_eq!(spacing.between(&heading(), &spacer()), 3.);
+        assert_eq!(spacing.between(&heading(), &heading()), 2.);
+
+        let definition_part = |part| {
+            let mut text_box = TextBox::new(Vec::new(), 1.);
+            text_box.definition_part = Some(part);
+            Element::TextBox(text_box)
+        };
+        let (term, definition) = (DefinitionPart::Term, DefinitionPart::Definition);
+        assert_eq!(spacing.between(&paragraph(), &definition_part(term)), 1.);
+        assert_eq!(
+            spacing.between(&definition_part(term), &definition_part(definition)),
+            1.
+        );
+        assert_eq!(
+            spacing.between(&definition_part(definition), &definition_part(term)),
+            4.
+        );
+    }
+
+    #[test]
+    fn spacers_dont_add_the_spacing_twice() {
+        let text_box = |part| {
+            let mut text_box = TextBox::new(Vec::new(), 1.);
+            text_box.definition_part = part;
+            Positioned::new(Element::TextBox(text_box))
+        };
+        let spacer = || Positioned::new(Element::from(Spacer::new(5., false)));
+        let mut elements = vec![
+            text_box(None),
+            spacer(),
+            text_box(None),
+            text_box(Some(DefinitionPart::Definition)),
+            spacer(),
+            text_box(Some(DefinitionPart::Term)),
+        ];
+
+        let mut positioner = Positioner::new((800., 600.), 1.);
+        positioner.spacing = Spacing {
+            paragraph: 30.,
+            between_definitions: 50.,
+            ..Spacing::default()
+        };
+        positioner
+            .reposition(&mut NoGlyphs, &mut elements, 1.)
+            .unwrap();
+
+        let top = |index: usize| elements[index].bounds.as_ref().unwrap().pos.1;
+        // Only the space of the spacer gets added on top of the spacing
+        assert_eq!(top(2) - top(1), 5. + 30.);
+        // Definition spacing applies across the spacer that ends the definition
+        assert_eq!(top(5) - top(4), 5. + 50.);
+    }
+
+    #[test]
+    fn text_wraps_around_floats_until_cleared() {
+        let floated = |float| {
+            let size = ImageSize {
+                width: Some(Length::Px(100.)),
+                height: Some(Length::Px(50.)),
+            };
+            let image = Image::from_image_data(Arc::new(Mutex::new(None)), 1.)
+                .with_size(size)
+                .with_float(float);
+            Positioned::new(Element::from(image))
+        };
+        let paragraph = || Positioned::new(Element::TextBox(TextBox::new(Vec::new(), 1.)));
+        let mut elements = vec![
+            floated(Float::Left),
+            floated(Float::Right),
+            paragraph(),
+            Positioned::new(Element::from(Spacer::clearing())),
+            paragraph(),
+        ];
+
+        let mut positioner = Positioner::new((800., 600.), 1.);
+        positioner
+            .reposition(&mut NoGlyphs, &mut elements, 1.)
+            .unwrap();
+
+        let float_width = |element: &Positioned<Element>| match &element.inner {
+            Element::TextBox(text_box) => text_box.float_width,
+            _ => unreachable!(),
+        };
+        let bounds: Vec<_> = elements.iter().map(|e| e.bounds.clone().unwrap()).collect();
+        assert_eq!(bounds[0].pos, (DEFAULT_MARGIN, bounds[2].pos.1));
+        assert_eq!(
+            bounds[1].pos,
+            (800. - DEFAULT_MARGIN - 100., bounds[2].pos.1)
+        );
+        assert_eq!(bounds[2].pos.0, DEFAULT_MARGIN + 110.);
+        assert_eq!(
+            float_width(&elements[2]),
+            Some(800. - 2. * DEFAULT_MARGIN - 220.)
+        );
+
+        assert_eq!(bounds[4].pos.0, DEFAULT_MARGIN);
+        assert!(bounds[4].pos.1 > bounds[0].pos.1 + bounds[0].size.1);
+        assert_eq!(float_width(&elements[4]), None);
+    }
+
+    #[test]
+    fn expanded_tables_overflow_the_screen() {
+        let table = |layout| {
+            let mut table = Table::new()
+                .with_layout(layout)
+                .with_header(TextBox::new(Vec::new(), 1.));
+            table.set_column_width(0, RowWidth::Fixed(1_000.));
+            Positioned::new(Element::Table(table))
+        };
+        let paragraph = || Positioned::new(Element::TextBox(TextBox::new(Vec::new(), 1.)));
+        let mut positioner = Positioner::new((800., 600.), 1.);
+
+        let mut elements = vec![paragraph(), table(TableLayout::Expand), paragraph()];
+        positioner
+            .reposition(&mut NoGlyphs, &mut elements, 1.)
+            .unwrap();
+        let table_bounds = elements[1].bounds.clone().unwrap();
+        assert_eq!(table_bounds.size.0, 1_000. + TABLE_COL_GAP);
+        assert_eq!(positioner.reserved_width, table_bounds.max().0);
+        assert_eq!(
+            positioner.overflow_width(),
+            DEFAULT_MARGIN + 1_000. + TABLE_COL_GAP + DEFAULT_MARGIN - 800.
+        );
+
+        // Laying out a document that fits starts over instead of keeping the old overflow
+        let mut elements = vec![paragraph(), paragraph()];
+        positioner
+            .reposition(&mut NoGlyphs, &mut elements, 1.)
+            .unwrap();
+        assert_eq!(positioner.overflow_width(), 0.);
+    }
+}
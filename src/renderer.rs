@@ -1,23 +1,44 @@
-use crate::color::Theme;
+use crate::color::{CodeTheme, LinkHoverStyle, Theme};
 use crate::fonts;
-use crate::image::ImageRenderer;
-use crate::opts::FontOptions;
-use crate::positioner::{Positioned, Positioner, DEFAULT_MARGIN};
-use crate::table::{TABLE_COL_GAP, TABLE_ROW_GAP};
+use crate::glyph_cache::{
+    GlyphCacheBound, DEFAULT_GLYPH_CACHE_MAX_SIZE, GLYPH_CACHE_POSITION_TOLERANCE,
+};
+use crate::hit::{self, Hit};
+use crate::image::{self, Image, ImageRenderer, SavableImage};
+use crate::minimap::Minimap;
+use crate::opts::{
+    DocStatsOptions, FontOptions, ImageStyle, Opts, SelectionOptions, SelectionStyle,
+};
+use crate::positioner::{Positioned, Positioner, Section, DEFAULT_MARGIN};
+use crate::shapes::{Shape, ShapeContext};
+use crate::table::{Table, TableLayout, TABLE_COL_GAP, TABLE_ROW_GAP};
+use crate::text::{self, ListMarker, TextBox, TextSelection};
 use crate::utils::{Point, Rect, Selection, Size};
 use crate::Element;
 use anyhow::{Context, Ok};
 use bytemuck::{Pod, Zeroable};
 use lyon::geom::euclid::Point2D;
 use lyon::geom::Box2D;
-use lyon::path::Polygon;
+use lyon::path::builder::BorderRadii;
+use lyon::path::{Path, Polygon, Winding};
 use lyon::tessellation::*;
+use serde::Deserialize;
 use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::iter;
+use std::ops::Range;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use wgpu::util::DeviceExt;
 use wgpu::{util::StagingBelt, TextureFormat};
 use wgpu::{BindGroup, Buffer, IndexFormat};
-use wgpu_glyph::{GlyphBrush, GlyphBrushBuilder};
+use wgpu_glyph::ab_glyph::FontArc;
+use wgpu_glyph::{GlyphBrush, GlyphBrushBuilder, GlyphCruncher, GlyphPositioner, OwnedSection};
+use winit::dpi::PhysicalSize;
 use winit::window::Window;
 
 #[repr(C)]
@@ -27,23 +48,921 @@ pub struct Vertex {
     pub color: [f32; 4],
 }
 
+/// How colors get written to the swapchain. Colors stay linear up until then. Matches `encode` in
+/// `shader.wgsl`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputEncoding {
+    /// The format encodes linear colors to sRGB itself
+    Hardware = 0,
+    /// Float formats take linear colors as they are, but anything brighter than white would come
+    /// out brighter than the rest of the desktop's white on HDR displays, so colors get clamped
+    ClampedLinear = 1,
+    /// The format takes colors as they get displayed, so the shaders encode them to sRGB
+    Srgb = 2,
+}
+
+impl OutputEncoding {
+    fn for_format(format: TextureFormat) -> Self {
+        if format.describe().srgb {
+            Self::Hardware
+        } else if matches!(
+            format,
+            TextureFormat::Rgba16Float | TextureFormat::Rgba32Float | TextureFormat::Rg11b10Float
+        ) {
+            Self::ClampedLinear
+        } else {
+            Self::Srgb
+        }
+    }
+
+    fn uniform(self, dither: bool) -> [u32; 4] {
+        [dither as u32, self as u32, 0, 0]
+    }
+
+    fn encode_channel(self, x: f64) -> f64 {
+        match self {
+            Self::Hardware => x,
+            Self::ClampedLinear => x.clamp(0., 1.),
+            Self::Srgb if x > 0.0031308 => 1.055 * x.clamp(0., 1.).powf(1. / 2.4) - 0.055,
+            Self::Srgb => x.max(0.) * 12.92,
+        }
+    }
+
+    /// Encodes a color that doesn't go through the shaders like the clear color
+    fn encode(self, color: wgpu::Color) -> wgpu::Color {
+        wgpu::Color {
+            r: self.encode_channel(color.r),
+            g: self.encode_channel(color.g),
+            b: self.encode_channel(color.b),
+            a: color.a,
+        }
+    }
+
+    /// Encodes the colors of text since `wgpu_glyph` writes them to the swapchain as they are
+    fn encode_text(self, mut section: wgpu_glyph::Section) -> wgpu_glyph::Section {
+        for text in &mut section.text {
+            let [r, g, b, _] = &mut text.extra.color;
+            for x in [r, g, b] {
+                *x = self.encode_channel((*x).into()) as f32;
+            }
+        }
+        section
+    }
+}
+
+/// Per-frame statistics used for debugging layout and tessellation performance
+#[derive(Default, Debug)]
+pub struct RenderStats {
+    last_frame: Option<Instant>,
+    /// Exponential moving average of the time between frames in seconds
+    pub frame_time: f32,
+    pub vertices: usize,
+    pub indices: usize,
+    pub image_draws: usize,
+    pub glyph_sections: usize,
+}
+
+impl RenderStats {
+    const SMOOTHING: f32 = 0.1;
+
+    fn start_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            let elapsed = now.duration_since(last_frame).as_secs_f32();
+            self.frame_time = if self.frame_time == 0. {
+                elapsed
+            } else {
+                self.frame_time + Self::SMOOTHING * (elapsed - self.frame_time)
+            };
+        }
+        self.last_frame = Some(now);
+        self.glyph_sections = 0;
+    }
+
+    pub fn fps(&self) -> f32 {
+        if self.frame_time > 0. {
+            1. / self.frame_time
+        } else {
+            0.
+        }
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "fps: {:.1} ({:.2} ms)",
+            self.fps(),
+            self.frame_time * 1000.
+        )?;
+        writeln!(f, "vertices: {}", self.vertices)?;
+        writeln!(f, "indices: {}", self.indices)?;
+        writeln!(f, "images: {}", self.image_draws)?;
+        write!(f, "glyph sections: {}", self.glyph_sections)
+    }
+}
+
+/// Length of the document along with an estimate of how long it takes to read
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DocStats {
+    pub words: usize,
+    /// Characters excluding whitespace
+    pub characters: usize,
+    pub reading_time: Duration,
+}
+
+impl DocStats {
+    /// Counts the text of every text box in `elements` including ones in tables and collapsed
+    /// sections
+    pub fn new(elements: &[Positioned<Element>], options: DocStatsOptions) -> Self {
+        let mut stats = Self::default();
+        visit_text_boxes(elements, &mut |text_box| {
+            if text_box.is_code_block && !options.include_code {
+                return;
+            }
+            // Words can be split across multiple texts (like with partially bold words), so the
+            // box's text gets joined first
+            let text: String = text_box
+                .texts
+                .iter()
+                .map(|text| text.text.as_str())
+                .collect();
+            stats.words += text.split_whitespace().count();
+            stats.characters += text.chars().filter(|c| !c.is_whitespace()).count();
+        });
+        if options.words_per_minute > 0. {
+            let seconds = stats.words as f64 / f64::from(options.words_per_minute) * 60.;
+            // Reading slowly enough takes longer than a `Duration` can hold
+            stats.reading_time = if seconds < Duration::MAX.as_secs_f64() {
+                Duration::from_secs_f64(seconds)
+            } else {
+                Duration::MAX
+            };
+        }
+        stats
+    }
+}
+
+impl fmt::Display for DocStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "words: {}", self.words)?;
+        writeln!(f, "characters: {}", self.characters)?;
+        write!(
+            f,
+            "reading time: {} min",
+            (self.reading_time.as_secs_f32() / 60.).ceil()
+        )
+    }
+}
+
+/// Something that can be focused and activated with the keyboard
+#[derive(Clone, Debug)]
+pub struct Focusable {
+    pub bounds: Rect,
+    /// The bounds of each line that a wrapped link spans
+    pub lines: Vec<Rect>,
+    pub action: FocusAction,
+}
+
+impl Focusable {
+    fn new(bounds: Rect, action: FocusAction) -> Self {
+        Self {
+            lines: vec![bounds.clone()],
+            bounds,
+            action,
+        }
+    }
+}
+
+/// Which focusable element has the keyboard focus. It's told apart by what it does and how many
+/// elements before it do the same rather than by its position, so that the focus stays on the
+/// same element when sections collapse or expand in front of it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Focus {
+    action: FocusAction,
+    nth: usize,
+}
+
+impl Focus {
+    fn new(focusables: &[Focusable], index: usize) -> Self {
+        let action = focusables[index].action.clone();
+        let nth = focusables[..index]
+            .iter()
+            .filter(|focusable| focusable.action == action)
+            .count();
+        Self { action, nth }
+    }
+
+    // Where the focused element is in `focusables` if it's still there
+    fn index(&self, focusables: &[Focusable]) -> Option<usize> {
+        focusables
+            .iter()
+            .enumerate()
+            .filter(|(_, focusable)| focusable.action == self.action)
+            .nth(self.nth)
+            .map(|(index, _)| index)
+    }
+}
+
+/// What activating a focused element does
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FocusAction {
+    Link(String),
+    Checkbox,
+    /// Collapses or expands the section
+    Summary,
+}
+
+/// What changed since the last frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Damage {
+    /// At most the scroll position changed, so the cached geometry can be reused
+    Scroll,
+    /// Elements, zoom, theme or selection changed, so everything gets tessellated again
+    Full,
+}
+
+// Everything that invalidates the cached geometry when it changes. Mutations that don't show up
+// here (like a theme change) need to call `Renderer::invalidate()`
+#[derive(Clone, Debug, PartialEq)]
+struct GeometryKey {
+    element_count: usize,
+    reserved_height: f32,
+    zoom: f32,
+    screen_size: Size,
+    selection: Option<Selection>,
+    focus: Option<Focus>,
+    hovered_link: Vec<Rect>,
+}
+
+// Where the ends of the selection fall in document order. Each end is the index of the text box it
+// belongs to along with the point itself
+#[derive(Clone, Copy, Debug)]
+struct SelectionAnchors {
+    start: (usize, Point),
+    end: (usize, Point),
+}
+
+impl SelectionAnchors {
+    fn new(elements: &[Positioned<Element>], selection: Selection) -> Self {
+        let mut anchors = [(None, selection.0), (None, selection.1)];
+        let mut index = 0;
+        visit_leaves(elements, &mut |leaf| {
+            if !matches!(leaf.inner, Element::TextBox(_)) {
+                return;
+            }
+            for (anchor, point) in &mut anchors {
+                let reached = leaf
+                    .bounds
+                    .as_ref()
+                    .map_or(false, |bounds| reaches(bounds, *point));
+                if anchor.is_none() && reached {
+                    *anchor = Some(index);
+                }
+            }
+            index += 1;
+        });
+        // Ends past the last text box belong after it
+        let [first, second] = anchors.map(|(anchor, point)| (anchor.unwrap_or(index), point));
+        if first.0 <= second.0 {
+            Self {
+                start: first,
+                end: second,
+            }
+        } else {
+            Self {
+                start: second,
+                end: first,
+            }
+        }
+    }
+
+    fn text_selection(&self, index: usize) -> Option<TextSelection> {
+        let (start, end) = (self.start, self.end);
+        if index < start.0 || index > end.0 {
+            None
+        } else if start.0 == end.0 {
+            Some(TextSelection::Between(start.1, end.1))
+        } else if index == start.0 {
+            Some(TextSelection::From(start.1))
+        } else if index == end.0 {
+            Some(TextSelection::To(end.1))
+        } else {
+            Some(TextSelection::Whole)
+        }
+    }
+}
+
+// Whether a point belongs to the text box with `bounds` or to something before it in document
+// order. Points in the margin or gaps before a box belong to it
+fn reaches(bounds: &Rect, point: Point) -> bool {
+    point.1 < bounds.pos.1 || (point.1 < bounds.max().1 && point.0 < bounds.max().0)
+}
+
+// Calls `f` with every text box, image and spacer in the same order that `render_elements` draws
+// them in. Collapsed sections are skipped
+fn visit_leaves(elements: &[Positioned<Element>], f: &mut dyn FnMut(&Positioned<Element>)) {
+    for element in elements {
+        visit_leaf(element, f);
+    }
+}
+
+fn visit_leaf(element: &Positioned<Element>, f: &mut dyn FnMut(&Positioned<Element>)) {
+    match &element.inner {
+        Element::TextBox(_) | Element::Image(_) | Element::Spacer(_) => f(element),
+        Element::Table(table) => {
+            for cell in table.cells() {
+                visit_leaf(cell, f);
+            }
+        }
+        Element::Row(row) => visit_leaves(&row.elements, f),
+        Element::Section(section) => {
+            if let Some(summary) = &*section.summary {
+                visit_leaf(summary, f);
+            }
+            if !*section.hidden.borrow() {
+                visit_leaves(&section.elements, f);
+            }
+        }
+    }
+}
+
+// Calls `f` with every section including the ones nested in collapsed sections
+fn visit_sections(elements: &[Positioned<Element>], f: &mut dyn FnMut(&Section)) {
+    for element in elements {
+        match &element.inner {
+            Element::Section(section) => {
+                f(section);
+                if let Some(summary) = &*section.summary {
+                    visit_sections(std::slice::from_ref(summary), f);
+                }
+                visit_sections(&section.elements, f);
+            }
+            Element::Row(row) => visit_sections(&row.elements, f),
+            Element::Table(table) => {
+                for cell in table.cells() {
+                    visit_sections(std::slice::from_ref(cell), f);
+                }
+            }
+            Element::TextBox(_) | Element::Image(_) | Element::Spacer(_) => {}
+        }
+    }
+}
+
+// Calls `f` with every text box including the ones in collapsed sections
+fn visit_text_boxes(elements: &[Positioned<Element>], f: &mut dyn FnMut(&TextBox)) {
+    for element in elements {
+        match &element.inner {
+            Element::TextBox(text_box) => f(text_box),
+            Element::Table(table) => {
+                for cell in table.cells() {
+                    visit_text_boxes(std::slice::from_ref(cell), f);
+                }
+            }
+            Element::Row(row) => visit_text_boxes(&row.elements, f),
+            Element::Section(section) => {
+                if let Some(summary) = &*section.summary {
+                    visit_text_boxes(std::slice::from_ref(summary), f);
+                }
+                visit_text_boxes(&section.elements, f);
+            }
+            Element::Image(_) | Element::Spacer(_) => {}
+        }
+    }
+}
+
+// Lets the image decoder start on the pending images closest to the `visible` part of the document
+fn prioritize_images(elements: &[Positioned<Element>], visible: (f32, f32)) {
+    visit_leaves(elements, &mut |leaf| {
+        let distance = match &leaf.bounds {
+            Some(bounds) => (bounds.pos.1 - visible.1)
+                .max(visible.0 - bounds.max().1)
+                .max(0.),
+            None => return,
+        };
+        match &leaf.inner {
+            Element::Image(image) => image.set_viewport_distance(distance),
+            Element::TextBox(text_box) => {
+                for text in &text_box.texts {
+                    if let Some(inline_image) = &text.inline_image {
+                        inline_image.image.set_viewport_distance(distance);
+                    }
+                }
+            }
+            _ => {}
+        }
+    });
+}
+
+struct CachedGeometry {
+    key: GeometryKey,
+    // The span of the document (in document coordinates) that was tessellated
+    range: (f32, f32),
+    buffers: LayerBuffers,
+}
+
+// Uploaded geometry of every layer
+struct LayerBuffers {
+    vertex_buf: Buffer,
+    index_buf: Buffer,
+    // Range of indices making up each layer
+    layers: [Range<u32>; Layer::COUNT],
+}
+
+/// Where tessellated geometry gets drawn relative to images and text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    /// Block backgrounds, rules, bars and other decorations. Drawn first
+    Background,
+    /// Marks over the content like the selection and image borders. Drawn over images, but behind
+    /// text
+    Highlight,
+    /// Drawn over everything in the document including its text
+    Foreground,
+}
+
+impl Layer {
+    const COUNT: usize = 3;
+    const ALL: [Self; Self::COUNT] = [Self::Background, Self::Highlight, Self::Foreground];
+}
+
+// Width in physical pixels of the edge that circles fade out over so that they look smooth
+const CIRCLE_FEATHER: f32 = 1.;
+
+/// Default for how far a glyph's scale can be from a cached one to reuse it
+pub const DEFAULT_GLYPH_CACHE_SCALE_TOLERANCE: f32 = 0.5;
+
+// Glyphs get rasterized bigger on high-DPI displays, so they start with a bigger glyph cache to
+// avoid growing it (and rasterizing every glyph again) while first scrolling through a document
+fn default_glyph_cache_size(hidpi_scale: f32) -> u32 {
+    ((512. * hidpi_scale.max(1.)) as u32)
+        .next_power_of_two()
+        .min(4096)
+}
+
+// The GPU can't make an empty texture or one bigger than its limit, so those sizes get swapped
+// out for ones that it can
+fn pick_glyph_cache_size(size: Option<u32>, hidpi_scale: f32, limit: u32) -> u32 {
+    match size {
+        Some(0) => {
+            log::warn!("The glyph cache can't be empty. Using the default size instead");
+            default_glyph_cache_size(hidpi_scale).min(limit)
+        }
+        Some(size) if size > limit => {
+            log::warn!(
+                "A glyph cache size of {} is more than the GPU allows. Using {} instead",
+                size,
+                limit
+            );
+            limit
+        }
+        Some(size) => size,
+        None => default_glyph_cache_size(hidpi_scale).min(limit),
+    }
+}
+
+fn build_glyph_brush(
+    device: &wgpu::Device,
+    fonts: Vec<FontArc>,
+    format: TextureFormat,
+    glyph_cache: &GlyphCacheBound,
+) -> GlyphBrush<()> {
+    let size = glyph_cache.initial_size();
+    GlyphBrushBuilder::using_fonts(fonts)
+        .draw_cache_position_tolerance(GLYPH_CACHE_POSITION_TOLERANCE)
+        .draw_cache_scale_tolerance(glyph_cache.scale_tolerance())
+        .initial_cache_size((size, size))
+        .build(device, format)
+}
+
+/// Whether to render in software when there's no GPU to render with
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdapterFallback {
+    /// Tries the GPU first and only falls back to software rendering without one
+    #[default]
+    Auto,
+    /// Fails to start without a GPU
+    Never,
+    /// Always renders in software
+    Always,
+}
+
+impl FromStr for AdapterFallback {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "never" => Ok(Self::Never),
+            "always" => Ok(Self::Always),
+            _ => anyhow::bail!(
+                "Expected one of `auto`, `never` or `always` but got `{}`",
+                s
+            ),
+        }
+    }
+}
+
+impl AdapterFallback {
+    /// Name of the environment variable that overrides the configured fallback
+    pub const ENV_VAR: &'static str = "INLYNE_GPU_FALLBACK";
+
+    /// The fallback set through [`Self::ENV_VAR`] if any
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(Self::ENV_VAR).ok()?;
+        value
+            .parse()
+            .map_err(|err| log::warn!("Ignoring {}: {}", Self::ENV_VAR, err))
+            .ok()
+    }
+}
+
+/// Graphics API to render through. Some drivers have bugs that only show up with one of them
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GpuBackend {
+    /// Whichever one works best on the platform
+    #[default]
+    Auto,
+    Vulkan,
+    Metal,
+    Dx12,
+    Dx11,
+    Gl,
+}
+
+impl From<GpuBackend> for wgpu::Backends {
+    fn from(backend: GpuBackend) -> Self {
+        match backend {
+            GpuBackend::Auto => Self::all(),
+            GpuBackend::Vulkan => Self::VULKAN,
+            GpuBackend::Metal => Self::METAL,
+            GpuBackend::Dx12 => Self::DX12,
+            GpuBackend::Dx11 => Self::DX11,
+            GpuBackend::Gl => Self::GL,
+        }
+    }
+}
+
+/// Which kind of GPU to prefer on systems with more than one
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GpuPower {
+    /// Usually the integrated GPU of laptops
+    #[default]
+    LowPower,
+    /// Usually the discrete GPU of laptops
+    HighPerformance,
+}
+
+impl From<GpuPower> for wgpu::PowerPreference {
+    fn from(power: GpuPower) -> Self {
+        match power {
+            GpuPower::LowPower => Self::LowPower,
+            GpuPower::HighPerformance => Self::HighPerformance,
+        }
+    }
+}
+
+/// A specific adapter to render with. See `--list-gpus` for what's available
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AdapterSelector {
+    /// Position in the list of adapters
+    Index(usize),
+    /// Part of the adapter's name. Case is ignored
+    Name(String),
+}
+
+impl AdapterSelector {
+    fn matches(&self, index: usize, info: &wgpu::AdapterInfo) -> bool {
+        match self {
+            Self::Index(wanted) => *wanted == index,
+            Self::Name(name) => info.name.to_lowercase().contains(&name.to_lowercase()),
+        }
+    }
+}
+
+/// Every adapter of `backend` in the order that [`AdapterSelector::Index`] counts them
+pub fn list_adapters(backend: GpuBackend) -> Vec<wgpu::AdapterInfo> {
+    wgpu::Instance::new(backend.into())
+        .enumerate_adapters(wgpu::Backends::all())
+        .map(|adapter| adapter.get_info())
+        .collect()
+}
+
+fn describe_adapter(info: &wgpu::AdapterInfo) -> String {
+    format!("{} ({:?}, {:?})", info.name, info.backend, info.device_type)
+}
+
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+    power: GpuPower,
+    force_fallback_adapter: bool,
+) -> Option<wgpu::Adapter> {
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: power.into(),
+            force_fallback_adapter,
+            compatible_surface: surface,
+        })
+        .await
+}
+
+// The adapter picked by `selector` as long as it can draw to the window (if there is one)
+fn select_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+    selector: &AdapterSelector,
+) -> Option<wgpu::Adapter> {
+    let adapter = instance
+        .enumerate_adapters(wgpu::Backends::all())
+        .enumerate()
+        .find(|(index, adapter)| selector.matches(*index, &adapter.get_info()))
+        .map(|(_, adapter)| adapter);
+    match adapter {
+        Some(adapter) if surface.map_or(true, |surface| adapter.is_surface_supported(surface)) => {
+            Some(adapter)
+        }
+        Some(adapter) => {
+            log::warn!(
+                "The selected GPU {} can't draw to the window. Picking one instead",
+                describe_adapter(&adapter.get_info())
+            );
+            None
+        }
+        None => {
+            log::warn!(
+                "No GPU matches {:?}. See `--list-gpus` for the available ones. Picking one instead",
+                selector
+            );
+            None
+        }
+    }
+}
+
+// Tries the selected adapter, then a real one and then a software one (when allowed). Logs which
+// one it ended up with since software rendering is a lot slower
+async fn find_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface>,
+    fallback: AdapterFallback,
+    power: GpuPower,
+    selector: Option<&AdapterSelector>,
+) -> anyhow::Result<wgpu::Adapter> {
+    if fallback != AdapterFallback::Always {
+        let selected = selector.and_then(|selector| select_adapter(instance, surface, selector));
+        let adapter = match selected {
+            Some(adapter) => Some(adapter),
+            None => request_adapter(instance, surface, power, false).await,
+        };
+        if let Some(adapter) = adapter {
+            log::info!("Rendering with {}", describe_adapter(&adapter.get_info()));
+            return Ok(adapter);
+        }
+        if fallback == AdapterFallback::Never {
+            anyhow::bail!(
+                "Failed to find an appropriate adapter. Setting `gpu-fallback` to \"auto\" \
+                 allows falling back to software rendering"
+            );
+        }
+        log::warn!(
+            "Failed to find a GPU adapter. Falling back to software rendering, which is a lot \
+             slower. Setting `gpu-fallback` to \"never\" fails to start instead"
+        );
+    }
+    let adapter = request_adapter(instance, surface, power, true)
+        .await
+        .context("Failed to find an appropriate adapter including a software fallback")?;
+    log::info!(
+        "Rendering in software with {}",
+        describe_adapter(&adapter.get_info())
+    );
+    Ok(adapter)
+}
+
+/// Format of the textures that get drawn into without a window. Encodes to sRGB itself, so the
+/// pixels read back from it are ready to be saved
+pub const HEADLESS_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+/// Width of the scrollbar along the right edge of the window
+pub const SCROLLBAR_WIDTH: f32 = DEFAULT_MARGIN / 4.;
+
+/// How the cursor is interacting with the scrollbar thumb
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScrollbarState {
+    #[default]
+    Idle,
+    Hover,
+    Dragging,
+}
+
+/// Bounds of the scrollbar thumb when scrolled to `scroll_y` of a document that's
+/// `reserved_height` tall
+pub fn scrollbar_thumb_rect(screen_size: Size, scroll_y: f32, reserved_height: f32) -> Rect {
+    let (screen_width, screen_height) = screen_size;
+    Rect::new(
+        (
+            screen_width - SCROLLBAR_WIDTH,
+            (scroll_y / reserved_height) * screen_height,
+        ),
+        (
+            SCROLLBAR_WIDTH,
+            (screen_height / reserved_height) * screen_height,
+        ),
+    )
+}
+
+/// Where scrolling by `step` pixels (negative goes up) from `scroll_y` ends up when presenting the
+/// slides starting at `slide_starts`. Slides taller than the screen scroll like usual until the
+/// screen reaches their edge and it moves on to the next or previous slide after that
+pub fn slide_scroll(
+    slide_starts: &[f32],
+    scroll_y: f32,
+    screen_height: f32,
+    reserved_height: f32,
+    step: f32,
+) -> f32 {
+    // Leeway for positions that were rounded when scrolled to
+    const EPSILON: f32 = 1.;
+    let current = slide_starts
+        .iter()
+        .rposition(|&start| start <= scroll_y + EPSILON)
+        .unwrap_or(0);
+    let start = slide_starts.get(current).copied().unwrap_or(0.);
+    if step > 0. {
+        let end = slide_starts
+            .get(current + 1)
+            .copied()
+            .unwrap_or(reserved_height);
+        if scroll_y + screen_height < end - EPSILON {
+            (scroll_y + step).min(end - screen_height)
+        } else {
+            slide_starts.get(current + 1).copied().unwrap_or(scroll_y)
+        }
+    } else if scroll_y > start + EPSILON {
+        (scroll_y + step).max(start)
+    } else {
+        current
+            .checked_sub(1)
+            .and_then(|previous| slide_starts.get(previous))
+            .copied()
+            .unwrap_or(scroll_y)
+    }
+}
+
+const FADE_IN_DURATION: Duration = Duration::from_millis(200);
+
+// Height of the tab bar in logical pixels
+const TAB_BAR_HEIGHT: f32 = 28.;
+// Tabs share the width of the window up to this many logical pixels each
+const TAB_MAX_WIDTH: f32 = 200.;
+
+// Tooltips wrap their text past this many logical pixels
+const TOOLTIP_MAX_WIDTH: f32 = 400.;
+// Space (in logical pixels) between a tooltip's text and the edge of its background
+const TOOLTIP_PADDING: f32 = 6.;
+const TOOLTIP_TEXT_SIZE: f32 = 14.;
+
+/// Everything about a document that isn't shared with other open documents. The renderer keeps
+/// the state of the document it shows itself and trades it for another document's with
+/// [`Renderer::swap_document`]
+pub struct DocumentState {
+    pub elements: Vec<Positioned<Element>>,
+    pub scroll_y: f32,
+    pub scroll_x: f32,
+    pub selection: Option<Selection>,
+    pub focus: Option<Focus>,
+    pub zoom: f32,
+    pub reserved_height: f32,
+    pub anchors: HashMap<String, f32>,
+    pub slides: Vec<f32>,
+    pub loading: Option<Instant>,
+}
+
+impl Default for DocumentState {
+    /// An empty document that's waiting on its elements
+    fn default() -> Self {
+        Self {
+            elements: Vec::new(),
+            scroll_y: 0.,
+            scroll_x: 0.,
+            selection: None,
+            focus: None,
+            zoom: 1.,
+            reserved_height: 0.,
+            anchors: HashMap::new(),
+            slides: Vec::new(),
+            loading: Some(Instant::now()),
+        }
+    }
+}
+
+// A table header pinned to the top of the screen while the rest of its table is scrolled through
+struct StickyHeader {
+    sections: Vec<OwnedSection>,
+    // Screen coordinates
+    bounds: Rect,
+}
+
 pub struct Renderer {
     pub config: wgpu::SurfaceConfiguration,
-    pub surface: wgpu::Surface,
+    /// `None` when rendering off screen (see [`Renderer::headless()`])
+    pub surface: Option<wgpu::Surface>,
     pub device: wgpu::Device,
     pub render_pipeline: wgpu::RenderPipeline,
     pub queue: wgpu::Queue,
     pub glyph_brush: GlyphBrush<()>,
+    // Tells when the glyph brush needs to be built again to shrink its cache
+    glyph_cache: GlyphCacheBound,
     pub staging_belt: StagingBelt,
     pub scroll_y: f32,
-    pub lyon_buffer: VertexBuffers<Vertex, u16>,
+    /// How far the document is scrolled to the right when it's wider than the screen
+    pub scroll_x: f32,
+    pub lyon_buffer: VertexBuffers<Vertex, u32>,
+    overlay_buffer: VertexBuffers<Vertex, u32>,
+    // Geometry of the layers other than the background while tessellating. Merged into
+    // `lyon_buffer` once everything is tessellated
+    layer_buffers: [VertexBuffers<Vertex, u32>; Layer::COUNT - 1],
+    // Geometry of the elements that are still fading in, starting with the background. It's
+    // tessellated every frame so that the rest can stay cached while they fade
+    fade_buffers: [VertexBuffers<Vertex, u32>; Layer::COUNT],
+    fade_geometry: Option<LayerBuffers>,
+    scroll_buffer: Buffer,
+    scroll_bind_group: BindGroup,
+    fixed_bind_group: BindGroup,
+    // How colors get written to the swapchain along with whether they're dithered
+    output_buffer: Buffer,
+    output_bind_group: BindGroup,
+    output_encoding: OutputEncoding,
+    geometry: Option<CachedGeometry>,
+    pub damage: Damage,
     pub hidpi_scale: f32,
     pub image_renderer: ImageRenderer,
     pub theme: Theme,
     pub selection: Option<Selection>,
     pub selection_text: String,
+    selection_anchors: Option<SelectionAnchors>,
+    /// The element with the keyboard focus out of [`Renderer::focusable_elements()`]
+    pub focus: Option<Focus>,
+    // The focusable elements along with the element count they were collected for. Dropped by
+    // `invalidate()` since finding the links means laying out every text box
+    focusables: Option<(usize, Vec<Focusable>)>,
+    // Document order index of the next text box to be rendered
+    text_box_index: usize,
     pub zoom: f32,
     pub positioner: Positioner,
+    pub stats: RenderStats,
+    pub show_stats: bool,
+    // Set when there's no GPU to render with. A badge in the corner says so since everything is
+    // a lot slower
+    software_rendering: bool,
+    /// Draws an overview of the whole document along the right side of the window
+    pub show_minimap: bool,
+    /// Picks the color of the scrollbar thumb
+    pub scrollbar_state: ScrollbarState,
+    /// Scrolling with the keyboard steps through the slides that rules split the document into
+    pub presentation: bool,
+    pub doc_stats_options: DocStatsOptions,
+    // Stats of the document along with how many elements it had when they were counted. They're
+    // counted again once the document changes or more of it comes in
+    doc_stats: Cell<Option<(usize, DocStats)>>,
+    pub selection_options: SelectionOptions,
+    pub image_style: ImageStyle,
+    pub code_theme: CodeTheme,
+    /// Draws guides along nested lists
+    pub indent_guides: bool,
+    /// Rounds the edges of rectangles and the scroll position to whole pixels for crisp lines
+    pub pixel_snap: bool,
+    /// Set while the document is still being parsed to draw a loading spinner
+    pub loading: Option<Instant>,
+    /// Disables animations. Every animated feature should check this
+    pub reduce_motion: bool,
+    sticky_header: Option<StickyHeader>,
+    /// Text shown next to the cursor at a point in screen coordinates. Holds the full text of
+    /// truncated table cells
+    pub tooltip: Option<(Point, String)>,
+    // Lines of the link under the cursor in document coordinates
+    hovered_link: Vec<Rect>,
+    /// The document being rendered
+    pub elements: Vec<Positioned<Element>>,
+    // Titles of the open documents. The tab bar only shows up with more than one of them
+    tabs: Vec<String>,
+    active_tab: usize,
+    // Opacity of the element currently being rendered
+    opacity: f32,
+    // Set while any element is still fading in
+    fading: bool,
+    // When the cached geometry was tessellated. Elements that hadn't finished fading in by then
+    // aren't part of it
+    cached_at: Instant,
+    // Whether the element currently being rendered is left out of the cached geometry
+    uncached: bool,
+    // Set once an element that's left out of the cached geometry finished fading in
+    faded_in: bool,
 }
 
 impl Renderer {
@@ -60,19 +979,104 @@ impl Renderer {
         theme: Theme,
         hidpi_scale: f32,
         font_opts: FontOptions,
+        staging_belt_size: u64,
+        glyph_cache_size: Option<u32>,
+        glyph_cache_scale_tolerance: f32,
+        reduce_motion: bool,
+        adapter_fallback: AdapterFallback,
+        gpu_backend: GpuBackend,
+        gpu_power: GpuPower,
+        gpu: Option<&AdapterSelector>,
     ) -> anyhow::Result<Self> {
-        let size = window.inner_size();
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let instance = wgpu::Instance::new(gpu_backend.into());
         let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .context("Failed to find an appropriate adapter")?;
+        // A backend that was picked but doesn't work out shouldn't keep inlyne from starting. A GPU
+        // on any other backend beats rendering in software on the picked one
+        let picked_fallback = match adapter_fallback {
+            AdapterFallback::Auto if gpu_backend != GpuBackend::Auto => AdapterFallback::Never,
+            fallback => fallback,
+        };
+        let found = find_adapter(&instance, Some(&surface), picked_fallback, gpu_power, gpu).await;
+        let (surface, adapter) = match found {
+            Ok(adapter) => (surface, adapter),
+            // The retry reports why it failed if it does too
+            Err(_) if gpu_backend != GpuBackend::Auto => {
+                log::warn!(
+                    "No GPU adapter on {:?}. Trying every backend instead",
+                    gpu_backend
+                );
+                let instance = wgpu::Instance::new(wgpu::Backends::all());
+                let surface = unsafe { instance.create_surface(window) };
+                let adapter =
+                    find_adapter(&instance, Some(&surface), adapter_fallback, gpu_power, gpu)
+                        .await?;
+                (surface, adapter)
+            }
+            Err(err) => return Err(err),
+        };
+
+        Self::with_adapter(
+            adapter,
+            Some(surface),
+            window.inner_size(),
+            theme,
+            hidpi_scale,
+            font_opts,
+            staging_belt_size,
+            glyph_cache_size,
+            glyph_cache_scale_tolerance,
+            reduce_motion,
+        )
+        .await
+    }
+
+    /// A renderer that draws into textures instead of a window (see [`Renderer::draw_to()`]).
+    /// `size` is the size of the textures in physical pixels
+    pub async fn headless(
+        size: PhysicalSize<u32>,
+        hidpi_scale: f32,
+        opts: &Opts,
+    ) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(opts.gpu_backend.into());
+        let adapter = find_adapter(
+            &instance,
+            None,
+            AdapterFallback::from_env().unwrap_or(opts.gpu_fallback),
+            opts.gpu_power,
+            opts.gpu.as_ref(),
+        )
+        .await?;
+        let mut renderer = Self::with_adapter(
+            adapter,
+            None,
+            size,
+            opts.theme.clone(),
+            hidpi_scale,
+            opts.font_opts.clone(),
+            opts.staging_belt_size,
+            opts.glyph_cache_size,
+            opts.glyph_cache_scale_tolerance,
+            // Everything has to be there in the one frame that gets drawn
+            true,
+        )
+        .await?;
+        renderer.loading = None;
+        Ok(renderer)
+    }
 
+    async fn with_adapter(
+        adapter: wgpu::Adapter,
+        surface: Option<wgpu::Surface>,
+        size: PhysicalSize<u32>,
+        theme: Theme,
+        hidpi_scale: f32,
+        font_opts: FontOptions,
+        staging_belt_size: u64,
+        glyph_cache_size: Option<u32>,
+        glyph_cache_scale_tolerance: f32,
+        reduce_motion: bool,
+    ) -> anyhow::Result<Self> {
+        let software_rendering = adapter.get_info().device_type == wgpu::DeviceType::Cpu;
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
@@ -85,30 +1089,117 @@ impl Renderer {
             )
             .await?;
 
-        let staging_belt = wgpu::util::StagingBelt::new(1024);
+        let staging_belt = wgpu::util::StagingBelt::new(staging_belt_size);
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
             source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shaders/shader.wgsl"))),
         });
 
+        let offset_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Offset Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        // Document geometry is tessellated in document coordinates and gets scrolled by this
+        // offset while screen-fixed geometry uses a zero offset
+        let offset_bind_group = |label, buffer: &Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &offset_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            })
+        };
+        let no_offset: [f32; 4] = [0.; 4];
+        let scroll_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Scroll Offset Buffer"),
+            contents: bytemuck::cast_slice(&no_offset),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let fixed_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fixed Offset Buffer"),
+            contents: bytemuck::cast_slice(&no_offset),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let scroll_bind_group = offset_bind_group("Scroll Offset Bind Group", &scroll_buffer);
+        let fixed_bind_group = offset_bind_group("Fixed Offset Bind Group", &fixed_buffer);
+
+        let swapchain_format = match &surface {
+            Some(surface) => {
+                let supported_formats = surface.get_supported_formats(&adapter);
+                // Colors are linear, so float formats and ones that encode to sRGB themselves
+                // are preferred. Anything else gets its colors encoded by the shaders
+                supported_formats
+                    .iter()
+                    .copied()
+                    .find(|format| *format == TextureFormat::Rgba16Float)
+                    .or_else(|| {
+                        supported_formats
+                            .iter()
+                            .copied()
+                            .find(|format| format.describe().srgb)
+                    })
+                    .unwrap_or(supported_formats[0])
+            }
+            // Read back as 8-bit RGBA by exports
+            None => HEADLESS_FORMAT,
+        };
+        let output_encoding = OutputEncoding::for_format(swapchain_format);
+        log::debug!(
+            "Using the {:?} swapchain format with {:?} output",
+            swapchain_format,
+            output_encoding
+        );
+
+        let output_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Output Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        // Dithering starts out disabled (see `set_dither`)
+        let output_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Output Buffer"),
+            contents: bytemuck::cast_slice(&output_encoding.uniform(false)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let output_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Output Bind Group"),
+            layout: &output_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: output_buffer.as_entire_binding(),
+            }],
+        });
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[],
+            bind_group_layouts: &[&offset_layout, &output_layout],
             push_constant_ranges: &[],
         });
 
-        let supported_formats = surface.get_supported_formats(&adapter);
-        let swapchain_format = if supported_formats.contains(&TextureFormat::Rgba16Float) {
-            TextureFormat::Rgba16Float
-        } else {
-            supported_formats[0]
-        };
-
         let vertex_buffers = [wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
+            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4],
         }];
 
         let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -122,7 +1213,12 @@ impl Renderer {
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
                 entry_point: "fs_main",
-                targets: &[Some(swapchain_format.into())],
+                // Blended so that elements can fade in
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: swapchain_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
             }),
             primitive: wgpu::PrimitiveState::default(),
             depth_stencil: None,
@@ -138,16 +1234,29 @@ impl Renderer {
             present_mode: wgpu::PresentMode::Fifo,
         };
 
-        surface.configure(&device, &config);
-        let image_renderer = ImageRenderer::new(&device, &swapchain_format);
+        if let Some(surface) = &surface {
+            surface.configure(&device, &config);
+        }
+        let image_renderer = ImageRenderer::new(&device, &swapchain_format, &output_layout);
 
-        let glyph_brush = GlyphBrushBuilder::using_fonts(fonts::get_fonts(&font_opts)?)
-            .draw_cache_position_tolerance(0.5)
-            .build(&device, swapchain_format);
+        let texture_limit = device.limits().max_texture_dimension_2d;
+        let glyph_cache_size = pick_glyph_cache_size(glyph_cache_size, hidpi_scale, texture_limit);
+        let glyph_cache = GlyphCacheBound::new(
+            glyph_cache_size,
+            DEFAULT_GLYPH_CACHE_MAX_SIZE.min(texture_limit),
+            glyph_cache_scale_tolerance,
+        );
+        let glyph_brush = build_glyph_brush(
+            &device,
+            fonts::get_fonts(&font_opts)?,
+            swapchain_format,
+            &glyph_cache,
+        );
 
-        let lyon_buffer: VertexBuffers<Vertex, u16> = VertexBuffers::new();
+        let lyon_buffer: VertexBuffers<Vertex, u32> = VertexBuffers::new();
 
-        let positioner = Positioner::new(window.inner_size().into(), hidpi_scale);
+        let positioner = Positioner::new(size.into(), hidpi_scale);
+        let code_theme = CodeTheme::from(&theme);
         Ok(Self {
             config,
             surface,
@@ -155,355 +1264,828 @@ impl Renderer {
             render_pipeline,
             queue,
             glyph_brush,
+            glyph_cache,
             staging_belt,
             scroll_y: 0.,
+            scroll_x: 0.,
             lyon_buffer,
+            overlay_buffer: VertexBuffers::new(),
+            layer_buffers: [VertexBuffers::new(), VertexBuffers::new()],
+            fade_buffers: [
+                VertexBuffers::new(),
+                VertexBuffers::new(),
+                VertexBuffers::new(),
+            ],
+            fade_geometry: None,
+            scroll_buffer,
+            scroll_bind_group,
+            fixed_bind_group,
+            output_buffer,
+            output_bind_group,
+            output_encoding,
+            geometry: None,
+            damage: Damage::Full,
             hidpi_scale,
             zoom: 1.,
             image_renderer,
             theme,
             selection: None,
+            focusables: None,
             selection_text: String::new(),
+            selection_anchors: None,
+            focus: None,
+            text_box_index: 0,
             positioner,
+            stats: RenderStats::default(),
+            show_stats: false,
+            software_rendering,
+            show_minimap: false,
+            scrollbar_state: ScrollbarState::default(),
+            presentation: false,
+            doc_stats_options: DocStatsOptions::default(),
+            doc_stats: Cell::new(None),
+            selection_options: SelectionOptions::default(),
+            image_style: ImageStyle::default(),
+            code_theme,
+            indent_guides: false,
+            pixel_snap: false,
+            loading: Some(Instant::now()),
+            reduce_motion,
+            sticky_header: None,
+            tooltip: None,
+            hovered_link: Vec::new(),
+            elements: Vec::new(),
+            tabs: Vec::new(),
+            active_tab: 0,
+            opacity: 1.,
+            fading: false,
+            cached_at: Instant::now(),
+            uncached: false,
+            faded_in: false,
         })
     }
 
-    fn draw_scrollbar(&mut self) -> anyhow::Result<()> {
-        let (screen_width, screen_height) = self.screen_size();
-        let height = (screen_height / self.positioner.reserved_height) * screen_height;
-        self.draw_rectangle(
-            Rect::new(
-                (
-                    screen_width - DEFAULT_MARGIN / 4.,
-                    ((self.scroll_y / self.positioner.reserved_height) * screen_height),
-                ),
-                (DEFAULT_MARGIN / 4., height),
-            ),
-            [0.3, 0.3, 0.3, 1.0],
-        )?;
-        Ok(())
+    // Opacity of an element fading in after it first entered the screen
+    fn fade_in_opacity(&mut self, element: &Positioned<Element>) -> f32 {
+        if self.reduce_motion {
+            return 1.;
+        }
+        let first_rendered = match element.first_rendered.get() {
+            Some(first_rendered) => first_rendered,
+            None => {
+                let on_screen = element.bounds.as_ref().map_or(false, |bounds| {
+                    bounds.pos.1 < self.scroll_y + self.screen_height()
+                        && bounds.pos.1 + bounds.size.1 > self.scroll_y
+                });
+                if !on_screen {
+                    return 0.;
+                }
+                let now = Instant::now();
+                element.first_rendered.set(Some(now));
+                now
+            }
+        };
+        let progress = first_rendered.elapsed().as_secs_f32() / FADE_IN_DURATION.as_secs_f32();
+        if progress < 1. {
+            self.fading = true;
+        }
+        progress.min(1.)
     }
 
-    fn render_elements(&mut self, elements: &[Positioned<Element>]) -> anyhow::Result<()> {
-        let screen_size = self.screen_size();
+    // Whether the element had finished fading in when the cached geometry was tessellated
+    fn is_cached(&self, element: &Positioned<Element>) -> bool {
+        self.reduce_motion
+            || element
+                .first_rendered
+                .get()
+                .map_or(false, |first_rendered| {
+                    first_rendered + FADE_IN_DURATION <= self.cached_at
+                })
+    }
+
+    // Swaps the buffers that get tessellated into for the ones of the elements fading in
+    fn swap_fade_buffers(&mut self) {
+        let [background, layers @ ..] = &mut self.fade_buffers;
+        std::mem::swap(&mut self.lyon_buffer, background);
+        for (buffer, fade_buffer) in self.layer_buffers.iter_mut().zip(layers) {
+            std::mem::swap(buffer, fade_buffer);
+        }
+    }
+
+    // Queues text with the opacity of the element being rendered
+    fn queue_text(&mut self, section: wgpu_glyph::Section) {
+        let layout = section.layout;
+        self.queue_text_with_layout(section, &layout);
+    }
+
+    fn queue_text_with_layout<G: GlyphPositioner + Hash>(
+        &mut self,
+        mut section: wgpu_glyph::Section,
+        layout: &G,
+    ) {
+        if self.opacity < 1. {
+            for text in &mut section.text {
+                text.extra.color[3] *= self.opacity;
+            }
+        }
+        let section = self.output_encoding.encode_text(section);
+        self.glyph_cache
+            .queue(self.glyph_brush.glyphs_custom_layout(&section, layout));
+        self.glyph_brush.queue_custom_layout(&section, layout);
+        self.stats.glyph_sections += 1;
+    }
+
+    /// Scrolls by `step` pixels (negative goes up) while keeping to the slides of the document
+    pub fn scroll_slides(&mut self, step: f32) {
+        // Slides line up with the top of the screen like the start of the document does
+        let top = self.positioner.top(self.zoom);
+        let slide_starts: Vec<_> = std::iter::once(0.)
+            .chain(
+                self.positioner
+                    .slides
+                    .iter()
+                    .map(|start| (start - top).max(0.)),
+            )
+            .collect();
+        self.set_scroll_y(slide_scroll(
+            &slide_starts,
+            self.scroll_y,
+            self.screen_height(),
+            self.positioner.reserved_height,
+            step,
+        ));
+    }
+
+    /// Screen-space bounds of the part of the scrollbar that can be dragged. Both drawing and
+    /// hit-testing go through this so that they always agree
+    pub fn scrollbar_thumb_rect(&self) -> Rect {
+        scrollbar_thumb_rect(
+            self.screen_size(),
+            self.scroll_y,
+            self.positioner.reserved_height,
+        )
+    }
+
+    /// Screen-space bounds of the whole strip that the scrollbar thumb moves along
+    pub fn scrollbar_track_rect(&self) -> Rect {
+        let (screen_width, screen_height) = self.screen_size();
+        Rect::new(
+            (screen_width - SCROLLBAR_WIDTH, 0.),
+            (SCROLLBAR_WIDTH, screen_height),
+        )
+    }
+
+    fn draw_scrollbar(&mut self, state: ScrollbarState) -> anyhow::Result<()> {
+        let color = match state {
+            ScrollbarState::Idle => self.theme.scrollbar_color,
+            ScrollbarState::Hover | ScrollbarState::Dragging => self.theme.scrollbar_hover_color,
+        };
+        let thumb = self.scrollbar_thumb_rect();
+        let radius = thumb.size.0 / 2.;
+        self.draw_rounded_rectangle(thumb, radius, color)
+    }
+
+    /// Placement of the minimap when it's shown
+    pub fn minimap(&self) -> Option<Minimap> {
+        if !self.show_minimap {
+            return None;
+        }
+        Minimap::new(
+            self.screen_size(),
+            self.tab_bar_height(),
+            self.positioner.reserved_height,
+        )
+    }
+
+    // Draws every text box and image as a block of color with the part that's on screen outlined
+    fn draw_minimap(&mut self, minimap: Minimap) -> anyhow::Result<()> {
+        let [r, g, b, a] = self.theme.text_color;
+        let code_block_color = self
+            .code_theme
+            .background
+            .unwrap_or(self.theme.code_block_color);
+        let mut blocks = Vec::new();
+        visit_leaves(&self.elements, &mut |element| {
+            let color = match &element.inner {
+                Element::TextBox(text_box) if text_box.is_code_block => code_block_color,
+                Element::TextBox(text_box) => {
+                    text_box.background_color.unwrap_or([r, g, b, a * 0.3])
+                }
+                Element::Image(_) => self.theme.link_color,
+                _ => return,
+            };
+            if let Some(bounds) = &element.bounds {
+                let mut block = minimap.map_rect(bounds);
+                // Keeps single lines of text from disappearing in long documents
+                block.size.1 = block.size.1.max(1.);
+                blocks.push((block, color));
+            }
+        });
+
+        self.draw_rectangle(minimap.bounds.clone(), [r, g, b, a * 0.05])?;
+        for (block, color) in blocks {
+            self.draw_rectangle(block, color)?;
+        }
+        let viewport = minimap.viewport(self.scroll_y, self.screen_height());
+        let [r, g, b, a] = self.theme.select_color;
+        self.draw_rectangle(viewport.clone(), [r, g, b, a * 0.2])?;
+        self.stroke_rectangle(viewport, [r, g, b, a], self.hidpi_scale)
+    }
+
+    // Queues the glyphs of every element within `range` (document coordinates) and when
+    // `tessellate` is set also tessellates their lyon geometry. Elements that haven't finished
+    // fading in by the time the cached geometry was tessellated are left out of it and get
+    // tessellated into `fade_buffers` every frame instead
+    fn render_elements(
+        &mut self,
+        elements: &[Positioned<Element>],
+        range: (f32, f32),
+        tessellate: bool,
+    ) -> anyhow::Result<()> {
+        let parent_opacity = self.opacity;
+        let parent_uncached = self.uncached;
+        if tessellate && self.indent_guides {
+            self.draw_indent_guides(elements, range)?;
+        }
         for element in elements.iter() {
             let Rect { pos, size } = element.bounds.as_ref().context("Element not positioned")?;
-            let scrolled_pos = (pos.0, pos.1 - self.scroll_y);
-            // Dont render off screen elements
-            if scrolled_pos.1 + size.1 <= 0. {
+            // Dont render elements outside of the range
+            if pos.1 + size.1 <= range.0 {
+                visit_leaf(element, &mut |leaf| {
+                    if let Element::TextBox(_) = leaf.inner {
+                        self.text_box_index += 1;
+                    }
+                });
                 continue;
-            } else if scrolled_pos.1 >= screen_size.1 {
+            } else if pos.1 >= range.1 {
                 break;
             }
+            self.opacity = parent_opacity * self.fade_in_opacity(element);
+            self.uncached = parent_uncached || !self.is_cached(element);
+            if self.uncached && self.opacity >= 1. {
+                self.faded_in = true;
+            }
+            let rendered = if self.uncached && !parent_uncached {
+                self.swap_fade_buffers();
+                let rendered = self.render_element(element, range, self.opacity > 0.);
+                self.swap_fade_buffers();
+                rendered
+            } else if self.uncached {
+                self.render_element(element, range, self.opacity > 0.)
+            } else {
+                self.render_element(element, range, tessellate)
+            };
+            rendered?;
+        }
 
-            match &element.inner {
-                Element::TextBox(text_box) => {
-                    let bounds = (
-                        (screen_size.0 - pos.0 - DEFAULT_MARGIN).max(0.),
-                        f32::INFINITY,
-                    );
-                    self.glyph_brush
-                        .queue(&text_box.glyph_section(*pos, bounds, self.zoom));
-                    if text_box.is_code_block || text_box.is_quote_block.is_some() {
-                        let color = if let Some(bg_color) = text_box.background_color {
-                            bg_color
-                        } else if text_box.is_code_block {
-                            self.theme.code_block_color
-                        } else {
-                            self.theme.quote_block_color
-                        };
-
-                        let mut min = ((scrolled_pos.0 - 10.), scrolled_pos.1);
-                        let max = (
-                            (min.0 + bounds.0 + 10.).min(screen_size.0 - DEFAULT_MARGIN),
-                            min.1 + size.1 + 5. * self.hidpi_scale * self.zoom,
-                        );
-                        if let Some(nest) = text_box.is_quote_block {
-                            min.0 -= (nest - 1) as f32 * DEFAULT_MARGIN / 2.;
-                        }
-                        if min.0 < screen_size.0 - DEFAULT_MARGIN {
-                            self.draw_rectangle(Rect::from_min_max(min, max), color)?;
-                        }
-                    }
-                    if let Some(nest) = text_box.is_quote_block {
-                        for n in 0..nest {
-                            let nest_indent = n as f32 * DEFAULT_MARGIN / 2.;
-                            let min = (
-                                (scrolled_pos.0
-                                    - 10.
-                                    - 5. * self.hidpi_scale * self.zoom
-                                    - nest_indent)
-                                    .min(screen_size.0 - DEFAULT_MARGIN),
-                                scrolled_pos.1,
-                            );
-                            let max = (
-                                (scrolled_pos.0 - 10. - nest_indent)
-                                    .min(screen_size.0 - DEFAULT_MARGIN),
-                                min.1 + size.1 + 5. * self.hidpi_scale * self.zoom,
-                            );
-                            self.draw_rectangle(
-                                Rect::from_min_max(min, max),
-                                self.theme.select_color,
-                            )?;
-                        }
-                    }
-                    if let Some(is_checked) = text_box.is_checkbox {
-                        let box_size = text_box.texts.first().map(|last| last.size).unwrap_or(16.)
-                            * self.hidpi_scale
-                            * self.zoom
-                            * 0.75;
-                        let min = (
-                            scrolled_pos.0 - box_size - 10.,
-                            scrolled_pos.1 + size.1 / 2. - box_size / 2.,
-                        );
-                        let max = (
-                            scrolled_pos.0 - 10.,
-                            scrolled_pos.1 + size.1 / 2. + box_size / 2.,
-                        );
-                        if max.0 < screen_size.0 - DEFAULT_MARGIN {
-                            if is_checked {
-                                self.draw_rectangle(
-                                    Rect::from_min_max(min, max),
-                                    self.theme.checkbox_color,
-                                )?;
-                                self.draw_tick(min, box_size, self.theme.text_color, 4.)?;
-                            }
-                            self.stroke_rectangle(
-                                Rect::from_min_max(min, max),
-                                self.theme.text_color,
-                                2.,
-                            )?;
-                        }
-                    }
-                    for line in text_box.render_lines(
-                        &mut self.glyph_brush,
-                        scrolled_pos,
-                        bounds,
-                        self.zoom,
-                    ) {
-                        let min = (
-                            line.0 .0.min(screen_size.0 - DEFAULT_MARGIN).max(pos.0),
-                            line.0 .1,
-                        );
-                        let max = (
-                            line.1 .0.min(screen_size.0 - DEFAULT_MARGIN).max(pos.0),
-                            line.1 .1 + 2. * self.hidpi_scale * self.zoom,
-                        );
-                        self.draw_rectangle(Rect::from_min_max(min, max), self.theme.text_color)?;
-                    }
-                    if let Some(selection) = self.selection {
-                        let (selection_rects, selection_text) = text_box.render_selection(
-                            &mut self.glyph_brush,
+        self.opacity = parent_opacity;
+        self.uncached = parent_uncached;
+        Ok(())
+    }
+
+    fn render_element(
+        &mut self,
+        element: &Positioned<Element>,
+        range: (f32, f32),
+        tessellate: bool,
+    ) -> anyhow::Result<()> {
+        let screen_size = self.screen_size();
+        let element_bounds = element.bounds.as_ref().context("Element not positioned")?;
+        let Rect { pos, size } = element_bounds;
+        match &element.inner {
+            Element::TextBox(text_box) => {
+                let index = self.next_text_box_index();
+                let text_zoom = self.text_zoom();
+                let bounds = (
+                    text_box
+                        .wrap_width((screen_size.0 - pos.0 - DEFAULT_MARGIN).max(0.), self.zoom),
+                    f32::INFINITY,
+                );
+                self.queue_text_with_layout(
+                    text_box.glyph_section(*pos, bounds, text_zoom),
+                    &text_box.text_layout(),
+                );
+                text_box.position_inline_images(&mut self.glyph_brush, *pos, bounds, text_zoom);
+                // The positioner already moved the text over to make room for the gutter
+                let gutter = match text_box.line_number {
+                    Some(line_number) if self.positioner.line_numbers => {
+                        let [r, g, b, a] = self.theme.text_color;
+                        let number = line_number.number.to_string();
+                        self.queue_text(text_box.line_number_section(
+                            &number,
                             *pos,
-                            bounds,
-                            self.zoom,
-                            selection,
-                        );
-                        self.selection_text.push_str(&selection_text);
-                        for rect in selection_rects {
-                            self.draw_rectangle(
-                                Rect::from_min_max(
-                                    (rect.pos.0, rect.pos.1 - self.scroll_y),
-                                    (rect.max().0, rect.max().1 - self.scroll_y),
-                                ),
-                                self.theme.select_color,
-                            )?;
-                        }
-                    }
-                }
-                Element::Table(table) => {
-                    let row_heights = table.row_heights(
-                        &mut self.glyph_brush,
-                        *pos,
-                        (screen_size.0 - pos.0 - DEFAULT_MARGIN, f32::INFINITY),
-                        self.zoom,
-                    );
-                    let column_widths = table.column_widths(
-                        &mut self.glyph_brush,
-                        *pos,
-                        (screen_size.0 - pos.0 - DEFAULT_MARGIN, f32::INFINITY),
-                        self.zoom,
-                    );
-                    let mut x = 0.;
-                    let mut y = 0.;
-
-                    let header_height = row_heights.first().unwrap();
-                    for (col, width) in column_widths.iter().enumerate() {
-                        let text_box = table.headers.get(col).unwrap();
-                        let bounds = (
-                            (screen_size.0 - pos.0 - x - DEFAULT_MARGIN)
-                                .min(screen_size.0 - DEFAULT_MARGIN),
-                            f32::INFINITY,
-                        );
-                        self.glyph_brush.queue(&text_box.glyph_section(
-                            (pos.0 + x, pos.1 + y),
-                            bounds,
-                            self.zoom,
+                            text_zoom,
+                            [r, g, b, a * 0.5],
                         ));
-                        if let Some(selection) = self.selection {
-                            let (selection_rects, selection_text) = text_box.render_selection(
-                                &mut self.glyph_brush,
-                                (pos.0 + x, pos.1 + y),
-                                bounds,
-                                self.zoom,
-                                selection,
-                            );
-                            self.selection_text.push_str(&selection_text);
-                            for rect in selection_rects {
-                                self.draw_rectangle(
-                                    Rect::from_min_max(
-                                        (rect.pos.0, rect.pos.1 - self.scroll_y),
-                                        (rect.max().0, rect.max().1 - self.scroll_y),
-                                    ),
-                                    self.theme.select_color,
-                                )?;
-                            }
-                        }
-                        x += width + TABLE_COL_GAP;
-                    }
-                    y += header_height + (TABLE_ROW_GAP / 2.);
-                    {
-                        let min = (
-                            scrolled_pos.0.min(screen_size.0 - DEFAULT_MARGIN),
-                            scrolled_pos.1 + y,
-                        );
-                        let max = (
-                            (scrolled_pos.0 + x)
-                                .max(scrolled_pos.0)
-                                .min(screen_size.0 - DEFAULT_MARGIN),
-                            scrolled_pos.1 + y + 3. * self.hidpi_scale * self.zoom,
-                        );
-                        self.draw_rectangle(Rect::from_min_max(min, max), self.theme.text_color)?;
-                    }
-
-                    y += TABLE_ROW_GAP / 2.;
-                    for (row, height) in row_heights.iter().skip(1).enumerate() {
-                        let mut x = 0.;
-                        for (col, width) in column_widths.iter().enumerate() {
-                            if let Some(row) = table.rows.get(row) {
-                                if let Some(text_box) = row.get(col) {
-                                    let bounds =
-                                        (screen_size.0 - pos.0 - x - DEFAULT_MARGIN, f32::INFINITY);
-                                    self.glyph_brush.queue(&text_box.glyph_section(
-                                        (pos.0 + x, pos.1 + y),
-                                        bounds,
-                                        self.zoom,
-                                    ));
-
-                                    if let Some(selection) = self.selection {
-                                        let (selection_rects, selection_text) = text_box
-                                            .render_selection(
-                                                &mut self.glyph_brush,
-                                                (pos.0 + x, pos.1 + y),
-                                                bounds,
-                                                self.zoom,
-                                                selection,
-                                            );
-                                        self.selection_text.push_str(&selection_text);
-                                        for rect in selection_rects {
-                                            self.draw_rectangle(
-                                                Rect::from_min_max(
-                                                    (rect.pos.0, rect.pos.1 - self.scroll_y),
-                                                    (rect.max().0, rect.max().1 - self.scroll_y),
-                                                ),
-                                                self.theme.select_color,
-                                            )?;
-                                        }
-                                    }
-                                }
-                            }
-                            x += width + TABLE_COL_GAP;
-                        }
-                        y += height + (TABLE_COL_GAP / 2.);
-                        {
-                            let min = (
-                                scrolled_pos.0.min(screen_size.0 - DEFAULT_MARGIN),
-                                scrolled_pos.1 + y,
-                            );
-                            let max = (
-                                (scrolled_pos.0 + x)
-                                    .max(scrolled_pos.0)
-                                    .min(screen_size.0 - DEFAULT_MARGIN),
-                                scrolled_pos.1 + y + 3. * self.hidpi_scale * self.zoom,
-                            );
-                            let color = self.theme.code_block_color;
-                            self.draw_rectangle(Rect::from_min_max(min, max), color)?;
-                        }
-                        y += TABLE_ROW_GAP / 2.;
+                        text_box.gutter_width(&mut self.glyph_brush, text_zoom)
                     }
+                    _ => 0.,
+                };
+                if let Some(ListMarker::Number(number)) = text_box.list_marker {
+                    let number = format!("{}.", number);
+                    self.queue_text(text_box.list_number_section(
+                        &number,
+                        *pos,
+                        text_zoom,
+                        self.theme.text_color,
+                    ));
                 }
-                Element::Image(_) => {}
-                Element::Spacer(spacer) => {
-                    if spacer.visibile {
-                        self.draw_rectangle(
-                            Rect::new(
-                                (
-                                    DEFAULT_MARGIN,
-                                    scrolled_pos.1 + size.1 / 2.
-                                        - 2. * self.hidpi_scale * self.zoom,
-                                ),
-                                (
-                                    screen_size.0 - 2. * DEFAULT_MARGIN,
-                                    2. * self.hidpi_scale * self.zoom,
-                                ),
-                            ),
-                            self.theme.text_color,
-                        )?;
-                    }
+                if !tessellate {
+                    return Ok(());
                 }
-                Element::Row(row) => self.render_elements(&row.elements)?,
-                Element::Section(section) => {
-                    if let Some(ref summary) = *section.summary {
-                        let bounds = summary.bounds.as_ref().unwrap();
-                        self.draw_hidden_marker(
-                            (
-                                bounds.pos.0 - 5. * self.hidpi_scale * self.zoom,
-                                bounds.pos.1 + bounds.size.1 / 2. - self.scroll_y,
-                            ),
-                            10.,
-                            self.theme.text_color,
-                            *section.hidden.borrow(),
-                        )?;
-                        self.render_elements(std::slice::from_ref(summary))?
-                    }
-                    if !*section.hidden.borrow() {
-                        self.render_elements(&section.elements)?
+                let content_edge = screen_size.0 - DEFAULT_MARGIN;
+                let shapes =
+                    self.text_box_shapes(text_box, element_bounds, bounds, gutter, content_edge);
+                self.draw_shapes(shapes)?;
+                self.draw_selection(text_box, index, *pos, bounds)?;
+            }
+            Element::Table(table) => self.render_table(table, *pos, false, tessellate)?,
+            Element::Image(image) => {
+                if tessellate {
+                    self.draw_image_border(image, *pos, *size)?;
+                }
+            }
+            Element::Spacer(spacer) => {
+                if spacer.visibile && tessellate {
+                    let content_edge = screen_size.0 - DEFAULT_MARGIN;
+                    let shapes = self.shape_context(content_edge).rule(element_bounds);
+                    self.draw_shapes(shapes)?;
+                }
+            }
+            Element::Row(row) => self.render_elements(&row.elements, range, tessellate)?,
+            Element::Section(section) => {
+                if let Some(ref summary) = *section.summary {
+                    let bounds = summary.bounds.as_ref().unwrap();
+                    if tessellate {
+                        let content_edge = screen_size.0 - DEFAULT_MARGIN;
+                        let marker = self
+                            .shape_context(content_edge)
+                            .section_marker(bounds, *section.hidden.borrow());
+                        self.draw_shapes(vec![marker])?;
                     }
+                    self.render_elements(std::slice::from_ref(summary), range, tessellate)?
+                }
+                if !*section.hidden.borrow() {
+                    self.render_elements(&section.elements, range, tessellate)?
                 }
             }
         }
+        Ok(())
+    }
+
+    // Draws a line down the side of every nested list group at each indent level. A group runs
+    // until the next text box that's nested less deeply, so code blocks and images in list items
+    // don't break it up
+    fn draw_indent_guides(
+        &mut self,
+        elements: &[Positioned<Element>],
+        range: (f32, f32),
+    ) -> anyhow::Result<()> {
+        // The x position, top and bottom of each open group starting with the least nested one
+        let mut open: Vec<(f32, f32, f32)> = Vec::new();
+        let mut guides = Vec::new();
+        for element in elements {
+            let (text_box, bounds) = match (&element.inner, &element.bounds) {
+                (Element::TextBox(text_box), Some(bounds)) => (text_box, bounds),
+                _ => continue,
+            };
+            // Items of top level lists don't get a guide
+            let nest = text_box.list_depth.saturating_sub(1);
+            while open.len() > nest {
+                guides.extend(open.pop());
+            }
+            while open.len() < nest {
+                // Halfway between the markers of the group's list and the list it's nested in
+                let levels_up = (nest - open.len() - 1) as f32;
+                let x = DEFAULT_MARGIN + text_box.indent - (levels_up + 0.5) * DEFAULT_MARGIN / 2.;
+                open.push((x, bounds.pos.1, bounds.max().1));
+            }
+            for group in &mut open {
+                group.2 = bounds.max().1;
+            }
+        }
+        guides.extend(open);
 
-        self.draw_scrollbar()?;
+        let width = self.hidpi_scale * self.zoom;
+        let [r, g, b, a] = self.theme.text_color;
+        for (x, top, bottom) in guides {
+            if bottom > range.0 && top < range.1 {
+                self.draw_rectangle(
+                    Rect::from_min_max((x - width / 2., top), (x + width / 2., bottom)),
+                    [r, g, b, a * 0.2],
+                )?;
+            }
+        }
         Ok(())
     }
 
-    fn draw_hidden_marker(
+    fn next_text_box_index(&mut self) -> usize {
+        let index = self.text_box_index;
+        self.text_box_index += 1;
+        index
+    }
+
+    fn draw_selection(
         &mut self,
+        text_box: &TextBox,
+        index: usize,
         pos: Point,
-        size: f32,
-        color: [f32; 4],
-        hidden: bool,
+        bounds: Size,
+    ) -> anyhow::Result<()> {
+        let selection = self
+            .selection_anchors
+            .and_then(|anchors| anchors.text_selection(index));
+        if let Some(selection) = selection {
+            let selection_rects = text_box.render_selection(
+                &mut self.glyph_brush,
+                pos,
+                bounds,
+                self.text_zoom(),
+                selection,
+                &mut self.selection_text,
+            );
+            let [r, g, b, a] = self.theme.select_color;
+            let color = [r, g, b, a * self.selection_options.opacity];
+            let style = self.selection_options.style;
+            let thickness = 2. * self.hidpi_scale * self.zoom;
+            self.draw_in_layer(Layer::Highlight, |renderer| {
+                for rect in selection_rects {
+                    let rect = match style {
+                        SelectionStyle::Highlight => rect,
+                        SelectionStyle::Underline => Rect::new(
+                            (rect.pos.0, rect.max().1 - thickness),
+                            (rect.size.0, thickness),
+                        ),
+                    };
+                    renderer.draw_rectangle(rect, color)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    // Tessellates everything that `draw` draws into `layer` instead of the background
+    fn draw_in_layer(
+        &mut self,
+        layer: Layer,
+        draw: impl FnOnce(&mut Self) -> anyhow::Result<()>,
     ) -> anyhow::Result<()> {
-        let points = if hidden {
-            [
-                point(pos.0, pos.1, self.screen_size()).into(),
-                point(pos.0 - size, pos.1 + size, self.screen_size()).into(),
-                point(pos.0 - size, pos.1 - size, self.screen_size()).into(),
-            ]
+        if layer == Layer::Background {
+            return draw(self);
+        }
+        let buffer = &mut self.layer_buffers[layer as usize - 1];
+        std::mem::swap(&mut self.lyon_buffer, buffer);
+        let result = draw(self);
+        std::mem::swap(
+            &mut self.lyon_buffer,
+            &mut self.layer_buffers[layer as usize - 1],
+        );
+        result
+    }
+
+    // Appends the other layers after the background in `lyon_buffer` so that everything can be
+    // uploaded at once. Returns the range of indices for each layer
+    fn merge_layers(&mut self) -> [Range<u32>; Layer::COUNT] {
+        let mut ranges = [0..0, 0..0, 0..0];
+        for layer in Layer::ALL {
+            let start = self.lyon_buffer.indices.len() as u32;
+            if layer != Layer::Background {
+                let buffer = &mut self.layer_buffers[layer as usize - 1];
+                let offset = self.lyon_buffer.vertices.len() as u32;
+                self.lyon_buffer.vertices.append(&mut buffer.vertices);
+                self.lyon_buffer
+                    .indices
+                    .extend(buffer.indices.drain(..).map(|index| index + offset));
+            }
+            ranges[layer as usize] = start..self.lyon_buffer.indices.len() as u32;
+        }
+        ranges
+    }
+
+    // Screen-fixed geometry like the scrollbar doesn't move with the document, so it gets
+    // tessellated into its own buffer every frame
+    fn render_overlay(&mut self) -> anyhow::Result<()> {
+        std::mem::swap(&mut self.lyon_buffer, &mut self.overlay_buffer);
+        self.lyon_buffer.vertices.clear();
+        self.lyon_buffer.indices.clear();
+        let mut result = Ok(());
+        if let Some(bounds) = self
+            .sticky_header
+            .as_ref()
+            .map(|header| header.bounds.clone())
+        {
+            result = self.draw_sticky_header_background(bounds);
+        }
+        if self.tab_bar_height() > 0. {
+            result = result.and_then(|_| self.draw_tab_bar());
+        }
+        result = result.and_then(|_| self.draw_scrollbar(self.scrollbar_state));
+        if let Some(minimap) = self.minimap() {
+            result = result.and_then(|_| self.draw_minimap(minimap));
+        }
+        if let Some(started) = self.loading {
+            result = result.and_then(|_| self.draw_spinner(started));
+        }
+        if let Some((bounds, _)) = self.tooltip_layout() {
+            result = result.and_then(|_| self.draw_tooltip(bounds));
+        }
+        std::mem::swap(&mut self.lyon_buffer, &mut self.overlay_buffer);
+        result
+    }
+
+    // Covers the document behind a pinned table header and redraws its divider
+    fn draw_sticky_header_background(&mut self, bounds: Rect) -> anyhow::Result<()> {
+        let height = self.screen_size().1;
+        let top = self.theme.background_at(bounds.pos.1 / height);
+        let bottom = self.theme.background_at(bounds.max().1 / height);
+        let divider_height = 3. * self.hidpi_scale * self.zoom;
+        let divider = Rect::new(
+            (bounds.pos.0, bounds.pos.1 + bounds.size.1 - divider_height),
+            (bounds.size.0, divider_height),
+        );
+        let quad = gradient_quad(&bounds, top, bottom, self.screen_size());
+        let offset = self.lyon_buffer.vertices.len() as u32;
+        self.lyon_buffer.vertices.extend(quad.vertices);
+        self.lyon_buffer
+            .indices
+            .extend(quad.indices.into_iter().map(|index| index + offset));
+        self.draw_rectangle(divider, self.theme.text_color)
+    }
+
+    /// Height of the tab bar in physical pixels. It's only shown with more than one tab open
+    pub fn tab_bar_height(&self) -> f32 {
+        if self.tabs.len() > 1 {
+            TAB_BAR_HEIGHT * self.hidpi_scale
+        } else {
+            0.
+        }
+    }
+
+    // Screen-space bounds of each tab in the tab bar
+    fn tab_bounds(&self) -> Vec<Rect> {
+        let height = self.tab_bar_height();
+        let width =
+            (self.screen_size().0 / self.tabs.len() as f32).min(TAB_MAX_WIDTH * self.hidpi_scale);
+        (0..self.tabs.len())
+            .map(|index| Rect::new((index as f32 * width, 0.), (width, height)))
+            .collect()
+    }
+
+    /// The tab at `point` in screen coordinates if there's a tab bar
+    pub fn tab_at(&self, point: Point) -> Option<usize> {
+        if self.tab_bar_height() == 0. {
+            return None;
+        }
+        self.tab_bounds()
+            .iter()
+            .position(|bounds| bounds.contains(point))
+    }
+
+    /// Shows a tab bar with a tab for each title when there's more than one. The document gets
+    /// laid out again when the tab bar shows up or goes away
+    pub fn set_tabs(&mut self, tabs: Vec<String>, active_tab: usize) -> anyhow::Result<()> {
+        self.tabs = tabs;
+        self.active_tab = active_tab;
+        let top_inset = self.tab_bar_height();
+        if self.positioner.top_inset != top_inset {
+            self.positioner.top_inset = top_inset;
+            self.reposition()?;
+        }
+        Ok(())
+    }
+
+    fn draw_tab_bar(&mut self) -> anyhow::Result<()> {
+        let height = self.tab_bar_height();
+        let wgpu::Color { r, g, b, a } = self.theme.background_color;
+        let background = [r as f32, g as f32, b as f32, a as f32];
+        let [r, g, b, a] = self.theme.text_color;
+        let separator = [r, g, b, a * 0.2];
+        let accent_height = 2. * self.hidpi_scale;
+        self.draw_rectangle(
+            Rect::new((0., 0.), (self.screen_size().0, height)),
+            self.theme.code_block_color,
+        )?;
+        for (index, bounds) in self.tab_bounds().into_iter().enumerate() {
+            if index == self.active_tab {
+                self.draw_rectangle(bounds.clone(), background)?;
+                let accent = Rect::new(
+                    (bounds.pos.0, bounds.max().1 - accent_height),
+                    (bounds.size.0, accent_height),
+                );
+                self.draw_rectangle(accent, self.theme.select_color)?;
+            }
+            let divider = Rect::new(
+                (bounds.max().0 - self.hidpi_scale, 0.),
+                (self.hidpi_scale, height),
+            );
+            self.draw_rectangle(divider, separator)?;
+        }
+        Ok(())
+    }
+
+    // Titles are cut off at the edge of their tab instead of wrapping
+    fn queue_tab_titles(&mut self) {
+        let font_size = 14. * self.hidpi_scale;
+        let padding = 8. * self.hidpi_scale;
+        let [r, g, b, a] = self.theme.text_color;
+        for (index, bounds) in self.tab_bounds().into_iter().enumerate() {
+            let color = if index == self.active_tab {
+                self.theme.text_color
+            } else {
+                [r, g, b, a * 0.6]
+            };
+            let y = self.scroll_y + bounds.pos.1 + (bounds.size.1 - font_size) / 2.;
+            let section = wgpu_glyph::Section {
+                screen_position: (bounds.pos.0 + padding, y),
+                bounds: ((bounds.size.0 - 2. * padding).max(0.), bounds.size.1),
+                text: vec![wgpu_glyph::Text::new(&self.tabs[index])
+                    .with_scale(font_size)
+                    .with_color(color)],
+                layout: wgpu_glyph::Layout::default_single_line(),
+            };
+            self.glyph_brush
+                .queue(self.output_encoding.encode_text(section));
+        }
+    }
+
+    fn tooltip_section<'a>(
+        &self,
+        text: &'a str,
+        screen_position: Point,
+    ) -> wgpu_glyph::Section<'a> {
+        wgpu_glyph::Section {
+            screen_position,
+            bounds: (TOOLTIP_MAX_WIDTH * self.hidpi_scale, f32::INFINITY),
+            text: vec![wgpu_glyph::Text::new(text)
+                .with_scale(TOOLTIP_TEXT_SIZE * self.hidpi_scale)
+                .with_color(self.theme.text_color)],
+            ..Default::default()
+        }
+    }
+
+    // Screen-space bounds of the tooltip's background along with where its text goes. It sits
+    // below and to the right of the cursor unless that would run off the screen
+    fn tooltip_layout(&mut self) -> Option<(Rect, Point)> {
+        let (cursor, text) = self.tooltip.clone()?;
+        let section = self.tooltip_section(&text, (0., 0.));
+        let text_bounds = self.glyph_brush.glyph_bounds(section)?;
+        let padding = TOOLTIP_PADDING * self.hidpi_scale;
+        let size = (
+            text_bounds.width() + 2. * padding,
+            text_bounds.height() + 2. * padding,
+        );
+        let offset = 12. * self.hidpi_scale;
+        let screen_size = self.screen_size();
+        let x = (cursor.0 + offset).min(screen_size.0 - size.0).max(0.);
+        let y = if cursor.1 + offset + size.1 > screen_size.1 {
+            (cursor.1 - offset - size.1).max(0.)
+        } else {
+            cursor.1 + offset
+        };
+        Some((Rect::new((x, y), size), (x + padding, y + padding)))
+    }
+
+    fn draw_tooltip(&mut self, bounds: Rect) -> anyhow::Result<()> {
+        let [r, g, b, a] = self.theme.text_color;
+        self.draw_rectangle(bounds.clone(), self.theme.code_block_color)?;
+        self.stroke_rectangle(bounds, [r, g, b, a * 0.2], self.hidpi_scale)
+    }
+
+    fn queue_tooltip(&mut self) {
+        let (text_pos, text) = match (self.tooltip_layout(), &self.tooltip) {
+            (Some((_, text_pos)), Some((_, text))) => (text_pos, text.clone()),
+            _ => return,
+        };
+        let section = self.tooltip_section(&text, (text_pos.0, self.scroll_y + text_pos.1));
+        self.glyph_brush
+            .queue(self.output_encoding.encode_text(section));
+    }
+
+    // Queued after everything else so that it gets drawn over the document
+    fn queue_stats(&mut self) {
+        let stats = format!("{}\n{}", self.stats, self.document_stats());
+        let padding = 5. * self.hidpi_scale;
+        let screen_size = self.screen_size();
+        let section = wgpu_glyph::Section {
+            screen_position: (padding, self.scroll_y + self.tab_bar_height() + padding),
+            bounds: screen_size,
+            text: vec![wgpu_glyph::Text::new(&stats)
+                .with_scale(14. * self.hidpi_scale)
+                .with_color(self.theme.text_color)
+                .with_font_id(fonts::MONOSPACE_REGULAR)],
+            ..Default::default()
+        };
+        self.glyph_brush
+            .queue(self.output_encoding.encode_text(section));
+    }
+
+    // Sits in the bottom left corner where it stays out of the way of the scrollbar and minimap
+    fn queue_software_badge(&mut self) {
+        let padding = 5. * self.hidpi_scale;
+        let screen_size = self.screen_size();
+        let [r, g, b, a] = self.theme.text_color;
+        let section = wgpu_glyph::Section {
+            screen_position: (padding, self.scroll_y + screen_size.1 - padding),
+            bounds: screen_size,
+            text: vec![wgpu_glyph::Text::new("Software rendering")
+                .with_scale(12. * self.hidpi_scale)
+                .with_color([r, g, b, a * 0.6])],
+            layout: wgpu_glyph::Layout::default_single_line()
+                .v_align(wgpu_glyph::VerticalAlign::Bottom),
+        };
+        self.glyph_brush
+            .queue(self.output_encoding.encode_text(section));
+    }
+
+    fn draw_spinner(&mut self, started: Instant) -> anyhow::Result<()> {
+        let (screen_width, screen_height) = self.screen_size();
+        let radius = 16. * self.hidpi_scale;
+        let width = 3. * self.hidpi_scale;
+        let center = (screen_width / 2., screen_height / 2.);
+        let rotation = if self.reduce_motion {
+            0.
         } else {
-            [
-                point(pos.0, pos.1 - size / 2., self.screen_size()).into(),
-                point(pos.0 - size * 2., pos.1 - size / 2., self.screen_size()).into(),
-                point(pos.0 - size, pos.1 + size / 2., self.screen_size()).into(),
-            ]
+            started.elapsed().as_secs_f32() * std::f32::consts::TAU
         };
-        let triangle = Polygon {
+        let sweep = std::f32::consts::TAU * 0.75;
+        let [r, g, b, a] = self.theme.text_color;
+        self.stroke_circle(center, radius, [r, g, b, a * 0.2], width)?;
+        self.draw_arc(
+            center,
+            radius,
+            rotation,
+            sweep,
+            self.theme.text_color,
+            width,
+        )?;
+        // Round off the ends of the arc
+        for angle in [rotation, rotation + sweep] {
+            let end = (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            );
+            self.draw_circle(end, width / 2., self.theme.text_color)?;
+        }
+        Ok(())
+    }
+
+    // Strokes an arc going clockwise from `start_angle` for `sweep` radians
+    fn draw_arc(
+        &mut self,
+        center: Point,
+        radius: f32,
+        start_angle: f32,
+        sweep: f32,
+        color: [f32; 4],
+        width: f32,
+    ) -> anyhow::Result<()> {
+        let color = self.faded(color);
+        const SEGMENTS: usize = 32;
+
+        let screen_size = self.screen_size();
+        let mut stroke_tessellator = StrokeTessellator::new();
+        let stroke_opts = StrokeOptions::default().with_line_width(width);
+        let mut vertex_builder =
+            BuffersBuilder::new(&mut self.lyon_buffer, |vertex: StrokeVertex| {
+                let point = point(vertex.position().x, vertex.position().y, screen_size);
+                Vertex {
+                    pos: [point[0], point[1], 0.0],
+                    color,
+                }
+            });
+        let mut builder = stroke_tessellator.builder(&stroke_opts, &mut vertex_builder);
+
+        let arc_point = |i: usize| {
+            let angle = start_angle + sweep * i as f32 / SEGMENTS as f32;
+            lyon::math::point(
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        };
+        builder.begin(arc_point(0));
+        for i in 1..=SEGMENTS {
+            builder.line_to(arc_point(i));
+        }
+        builder.end(false);
+        builder.build()?;
+        Ok(())
+    }
+
+    fn fill_polygon(&mut self, points: &[Point], color: [f32; 4]) -> anyhow::Result<()> {
+        let color = self.faded(color);
+        let screen_size = self.screen_size();
+        let points: Vec<_> = points
+            .iter()
+            .map(|&(x, y)| point(x, y, screen_size).into())
+            .collect();
+        let polygon = Polygon {
             points: &points,
             closed: true,
         };
         let mut fill_tessellator = FillTessellator::new();
         fill_tessellator.tessellate_polygon(
-            triangle,
+            polygon,
             &FillOptions::default(),
             &mut BuffersBuilder::new(&mut self.lyon_buffer, |vertex: FillVertex| Vertex {
                 pos: [vertex.position().x, vertex.position().y, 0.0],
@@ -513,7 +2095,95 @@ impl Renderer {
         Ok(())
     }
 
+    fn faded(&self, mut color: [f32; 4]) -> [f32; 4] {
+        color[3] *= self.opacity;
+        color
+    }
+
+    fn draw_circle(&mut self, center: Point, radius: f32, color: [f32; 4]) -> anyhow::Result<()> {
+        let color = self.faded(color);
+        let screen_size = self.screen_size();
+        // The outermost pixel of the circle is left to the feathered edge
+        let inner_radius = (radius - CIRCLE_FEATHER / 2.).max(0.);
+        FillTessellator::new().tessellate_circle(
+            lyon::math::point(center.0, center.1),
+            inner_radius,
+            &FillOptions::default(),
+            &mut BuffersBuilder::new(&mut self.lyon_buffer, |vertex: FillVertex| {
+                let point = point(vertex.position().x, vertex.position().y, screen_size);
+                Vertex {
+                    pos: [point[0], point[1], 0.0],
+                    color,
+                }
+            }),
+        )?;
+        let edge_radius = inner_radius + CIRCLE_FEATHER / 2.;
+        self.draw_ring(center, edge_radius, CIRCLE_FEATHER, color, false, true)
+    }
+
+    fn stroke_circle(
+        &mut self,
+        center: Point,
+        radius: f32,
+        color: [f32; 4],
+        width: f32,
+    ) -> anyhow::Result<()> {
+        let color = self.faded(color);
+        // A solid core with a feathered edge on either side of it
+        let core = (width - CIRCLE_FEATHER).max(0.);
+        let fringe_offset = (core + CIRCLE_FEATHER) / 2.;
+        self.draw_ring(center, radius, core, color, false, false)?;
+        let outer = radius + fringe_offset;
+        self.draw_ring(center, outer, CIRCLE_FEATHER, color, false, true)?;
+        let inner = (radius - fringe_offset).max(0.);
+        self.draw_ring(center, inner, CIRCLE_FEATHER, color, true, false)
+    }
+
+    // Strokes a circle of `width` centered on `radius`. Vertices on a faded side are transparent
+    // so that the ring blends into whatever is around it
+    fn draw_ring(
+        &mut self,
+        center: Point,
+        radius: f32,
+        width: f32,
+        color: [f32; 4],
+        fade_inner: bool,
+        fade_outer: bool,
+    ) -> anyhow::Result<()> {
+        if width <= 0. || radius <= 0. {
+            return Ok(());
+        }
+        let screen_size = self.screen_size();
+        let [r, g, b, a] = color;
+        StrokeTessellator::new().tessellate_circle(
+            lyon::math::point(center.0, center.1),
+            radius,
+            &StrokeOptions::default().with_line_width(width),
+            &mut BuffersBuilder::new(&mut self.lyon_buffer, |vertex: StrokeVertex| {
+                let position = vertex.position();
+                let distance = (position.x - center.0).hypot(position.y - center.1);
+                let faded = if distance > radius {
+                    fade_outer
+                } else {
+                    fade_inner
+                };
+                let point = point(position.x, position.y, screen_size);
+                Vertex {
+                    pos: [point[0], point[1], 0.0],
+                    color: [r, g, b, if faded { 0. } else { a }],
+                }
+            }),
+        )?;
+        Ok(())
+    }
+
     fn draw_rectangle(&mut self, rect: Rect, color: [f32; 4]) -> anyhow::Result<()> {
+        let color = self.faded(color);
+        let rect = if self.pixel_snap {
+            rect.snap_to_pixels()
+        } else {
+            rect
+        };
         let min = point(rect.pos.0, rect.pos.1, self.screen_size());
         let max = point(rect.max().0, rect.max().1, self.screen_size());
         let mut fill_tessellator = FillTessellator::new();
@@ -528,7 +2198,55 @@ impl Renderer {
         Ok(())
     }
 
+    fn draw_rounded_rectangle(
+        &mut self,
+        rect: Rect,
+        radius: f32,
+        color: [f32; 4],
+    ) -> anyhow::Result<()> {
+        let color = self.faded(color);
+        let rect = if self.pixel_snap {
+            rect.snap_to_pixels()
+        } else {
+            rect
+        };
+        let screen_size = self.screen_size();
+        let radius = radius.min(rect.size.0 / 2.).min(rect.size.1 / 2.);
+        let mut builder = Path::builder();
+        builder.add_rounded_rectangle(
+            &Box2D::new(Point2D::from(rect.pos), Point2D::from(rect.max())),
+            &BorderRadii::new(radius),
+            Winding::Positive,
+        );
+        FillTessellator::new().tessellate_path(
+            &builder.build(),
+            &FillOptions::default(),
+            &mut BuffersBuilder::new(&mut self.lyon_buffer, |vertex: FillVertex| {
+                let point = point(vertex.position().x, vertex.position().y, screen_size);
+                Vertex {
+                    pos: [point[0], point[1], 0.0],
+                    color,
+                }
+            }),
+        )?;
+        Ok(())
+    }
+
     fn stroke_rectangle(&mut self, rect: Rect, color: [f32; 4], width: f32) -> anyhow::Result<()> {
+        let color = self.faded(color);
+        let (rect, width) = if self.pixel_snap {
+            // Strokes are centered on the edge, so odd widths need to be shifted by half a pixel
+            // to cover whole pixels
+            let width = width.round().max(1.);
+            let offset = if width % 2. == 1. { 0.5 } else { 0. };
+            let rect = rect.snap_to_pixels();
+            (
+                Rect::new((rect.pos.0 + offset, rect.pos.1 + offset), rect.size),
+                width,
+            )
+        } else {
+            (rect, width)
+        };
         let mut stroke_tessellator = StrokeTessellator::new();
         let screen_size = self.screen_size();
         stroke_tessellator.tessellate_rectangle(
@@ -545,13 +2263,48 @@ impl Renderer {
         Ok(())
     }
 
-    fn draw_tick(
+    fn stroke_rounded_rectangle(
         &mut self,
-        pos: Point,
-        box_size: f32,
+        rect: Rect,
+        radius: f32,
+        color: [f32; 4],
+        width: f32,
+    ) -> anyhow::Result<()> {
+        let color = self.faded(color);
+        let screen_size = self.screen_size();
+        let radius = radius.min(rect.size.0 / 2.).min(rect.size.1 / 2.);
+        let mut builder = Path::builder();
+        builder.add_rounded_rectangle(
+            &Box2D::new(Point2D::from(rect.pos), Point2D::from(rect.max())),
+            &BorderRadii::new(radius),
+            Winding::Positive,
+        );
+        let mut stroke_tessellator = StrokeTessellator::new();
+        stroke_tessellator.tessellate_path(
+            &builder.build(),
+            &StrokeOptions::default().with_line_width(width),
+            &mut BuffersBuilder::new(&mut self.lyon_buffer, |vertex: StrokeVertex| {
+                let point = point(vertex.position().x, vertex.position().y, screen_size);
+                Vertex {
+                    pos: [point[0], point[1], 0.0],
+                    color,
+                }
+            }),
+        )?;
+        Ok(())
+    }
+
+    fn stroke_polyline(
+        &mut self,
+        points: &[Point],
         color: [f32; 4],
         width: f32,
     ) -> anyhow::Result<()> {
+        let (first, rest) = match points.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let color = self.faded(color);
         let screen_size = self.screen_size();
         let mut stroke_tessellator = StrokeTessellator::new();
         let stroke_opts = StrokeOptions::default().with_line_width(width);
@@ -564,220 +2317,1339 @@ impl Renderer {
                 }
             });
         let mut builder = stroke_tessellator.builder(&stroke_opts, &mut vertex_builder);
+        builder.begin((*first).into());
+        for point in rest {
+            builder.line_to((*point).into());
+        }
+        builder.end(false);
+        builder.build()?;
+        Ok(())
+    }
+
+    // Shapes that are shared with the SVG export (see `shapes`). Anything that would stick out
+    // past `content_edge` gets cut off or left out
+    fn shape_context(&self, content_edge: f32) -> ShapeContext<'_> {
+        ShapeContext {
+            theme: &self.theme,
+            code_theme: self.code_theme,
+            scale: self.hidpi_scale * self.zoom,
+            zoom: self.zoom,
+            text_zoom: self.text_zoom(),
+            content_edge,
+        }
+    }
+
+    // The context can't be borrowed from all of `self` here since the glyph brush is needed too
+    fn text_box_shapes(
+        &mut self,
+        text_box: &TextBox,
+        bounds: &Rect,
+        text_bounds: Size,
+        gutter: f32,
+        content_edge: f32,
+    ) -> Vec<Shape> {
+        let context = ShapeContext {
+            theme: &self.theme,
+            code_theme: self.code_theme,
+            scale: self.hidpi_scale * self.zoom,
+            zoom: self.zoom,
+            text_zoom: self.text_zoom(),
+            content_edge,
+        };
+        context.text_box(&mut self.glyph_brush, text_box, bounds, text_bounds, gutter)
+    }
+
+    fn draw_shapes(&mut self, shapes: Vec<Shape>) -> anyhow::Result<()> {
+        for shape in shapes {
+            match shape {
+                Shape::Rect(rect, color) => self.draw_rectangle(rect, color)?,
+                Shape::StrokeRect { rect, color, width } => {
+                    self.stroke_rectangle(rect, color, width)?
+                }
+                Shape::Circle {
+                    center,
+                    radius,
+                    color,
+                } => self.draw_circle(center, radius, color)?,
+                Shape::Ring {
+                    center,
+                    radius,
+                    color,
+                    width,
+                } => self.stroke_circle(center, radius, color, width)?,
+                Shape::Polygon(points, color) => self.fill_polygon(&points, color)?,
+                Shape::Polyline {
+                    points,
+                    color,
+                    width,
+                } => self.stroke_polyline(&points, color, width)?,
+            }
+        }
+        Ok(())
+    }
+
+    // `nested` is set for tables inside of the cells of another table
+    fn render_table(
+        &mut self,
+        table: &Table,
+        pos: Point,
+        nested: bool,
+        tessellate: bool,
+    ) -> anyhow::Result<()> {
+        let screen_size = self.screen_size();
+        let bounds = (screen_size.0 - pos.0 - DEFAULT_MARGIN, f32::INFINITY);
+        let text_zoom = self.text_zoom();
+        let row_heights = table.row_heights(&mut self.glyph_brush, pos, bounds, text_zoom);
+        let width: f32 = table
+            .column_widths(&mut self.glyph_brush, pos, bounds, text_zoom)
+            .iter()
+            .map(|width| width + TABLE_COL_GAP)
+            .sum();
+        let header_height = row_heights.first().copied().unwrap_or_default();
+
+        self.render_table_header(table, pos, (width, header_height), false, tessellate)?;
+        let mut y = header_height + TABLE_ROW_GAP;
+        for (row, height) in table.rows.iter().zip(row_heights.iter().skip(1)) {
+            for cell in row {
+                self.render_table_cell(cell, table.layout, tessellate)?;
+            }
+            y += height + TABLE_ROW_GAP;
+        }
+        if tessellate {
+            let dividers = self
+                .shape_context(self.table_content_edge())
+                .table_dividers(table, pos, width, &row_heights);
+            self.draw_shapes(dividers)?;
+        }
+
+        // Only the outermost table gets pinned when tables are nested
+        let top = self.scroll_y + self.tab_bar_height();
+        let scrolled_past = pos.1 < top && top < pos.1 + y;
+        if scrolled_past && !nested {
+            self.render_table_header(table, pos, (width, header_height), true, tessellate)?;
+        }
+        Ok(())
+    }
+
+    // Draws the header row of the table at `pos`. A pinned header is drawn at the top of the
+    // screen (below the tab bar) over the rest of the document instead of in its place in the
+    // table
+    fn render_table_header(
+        &mut self,
+        table: &Table,
+        pos: Point,
+        size: Size,
+        pinned: bool,
+        tessellate: bool,
+    ) -> anyhow::Result<()> {
+        if !pinned {
+            for cell in &table.headers {
+                self.render_table_cell(cell, table.layout, tessellate)?;
+            }
+            return Ok(());
+        }
+
+        let top = self.tab_bar_height();
+        let offset = self.scroll_y + top - pos.1;
+        let mut sections = Vec::with_capacity(table.headers.len());
+        for cell in &table.headers {
+            // Nested tables in the header aren't pinned
+            if let (Element::TextBox(text_box), Some(bounds)) = (&cell.inner, &cell.bounds) {
+                let cell_pos = (bounds.pos.0 - self.scroll_x, bounds.pos.1 + offset);
+                let text_bounds = self.table_cell_bounds(bounds, table.layout);
+                sections.push(
+                    text_box
+                        .glyph_section(cell_pos, text_bounds, self.text_zoom())
+                        .to_owned(),
+                );
+            }
+        }
+        let height = size.1 + TABLE_ROW_GAP / 2. + 3. * self.hidpi_scale * self.zoom;
+        self.sticky_header = Some(StickyHeader {
+            sections,
+            bounds: Rect::new((pos.0 - self.scroll_x, top), (size.0, height)),
+        });
+        Ok(())
+    }
+
+    // Tables that don't wrap their cells can be scrolled to past the edge of the screen
+    fn table_content_edge(&self) -> f32 {
+        (self.screen_size().0 - DEFAULT_MARGIN).max(self.positioner.reserved_width)
+    }
+
+    // Wraps within the column so that the text can be aligned inside of it
+    fn table_cell_bounds(&self, cell_bounds: &Rect, layout: TableLayout) -> Size {
+        let available = self.screen_size().0 - cell_bounds.pos.0 - DEFAULT_MARGIN;
+        (
+            layout.cell_width(cell_bounds.size.0, available),
+            f32::INFINITY,
+        )
+    }
+
+    fn render_table_cell(
+        &mut self,
+        cell: &Positioned<Element>,
+        layout: TableLayout,
+        tessellate: bool,
+    ) -> anyhow::Result<()> {
+        let cell_bounds = cell.bounds.as_ref().context("Table cell not positioned")?;
+        let pos = cell_bounds.pos;
+        match &cell.inner {
+            Element::TextBox(text_box) => {
+                let index = self.next_text_box_index();
+                let mut bounds = self.table_cell_bounds(cell_bounds, layout);
+                let text_zoom = self.text_zoom();
+                if let Some((clipped, ellipsis_pos)) =
+                    text_box.ellipsis(&mut self.glyph_brush, pos, bounds, text_zoom)
+                {
+                    bounds = clipped;
+                    self.queue_text(text_box.ellipsis_section(ellipsis_pos, text_zoom));
+                }
+                self.queue_text_with_layout(
+                    text_box.glyph_section(pos, bounds, text_zoom),
+                    &text_box.text_layout(),
+                );
+                text_box.position_inline_images(&mut self.glyph_brush, pos, bounds, text_zoom);
+                if tessellate {
+                    let content_edge = self.table_content_edge();
+                    let shapes =
+                        self.text_box_shapes(text_box, cell_bounds, bounds, 0., content_edge);
+                    self.draw_shapes(shapes)?;
+                    self.draw_selection(text_box, index, pos, bounds)?;
+                }
+            }
+            Element::Table(table) => self.render_table(table, pos, true, tessellate)?,
+            // Images are drawn along with the rest of the image bind groups
+            Element::Image(image) => {
+                if tessellate {
+                    self.draw_image_border(image, pos, cell_bounds.size)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Images keep their aspect ratio and are centered in their box when their decoded dimensions
+    // don't match it
+    fn image_vertex_buf(&self, image: &Image, pos: Point, size: Size) -> Buffer {
+        let (pos, size) = image::contain(pos, size, image.buffer_dimensions());
+        let corner_radius = self.image_style.corner_radius * self.hidpi_scale * self.zoom;
+        ImageRenderer::vertex_buf(&self.device, pos, size, corner_radius, self.screen_size())
+    }
+
+    // The border goes over the edge of the image, so it's drawn in the layer above images
+    fn draw_image_border(&mut self, image: &Image, pos: Point, size: Size) -> anyhow::Result<()> {
+        let scale = self.hidpi_scale * self.zoom;
+        let width = self.image_style.border_width * scale;
+        if width <= 0. {
+            return Ok(());
+        }
+        let (pos, size) = image::contain(pos, size, image.buffer_dimensions());
+        let color = self
+            .image_style
+            .border_color
+            .unwrap_or(self.theme.text_color);
+        let corner_radius = self.image_style.corner_radius * scale;
+        self.draw_in_layer(Layer::Highlight, |renderer| {
+            renderer.stroke_rounded_rectangle(Rect::new(pos, size), corner_radius, color, width)
+        })
+    }
+
+    fn image_bindgroup(
+        &self,
+        image: &mut Image,
+        pos: Point,
+        size: Size,
+    ) -> Option<(Arc<BindGroup>, Buffer)> {
+        if image.bind_group.is_none() {
+            image.create_bind_group(
+                &self.device,
+                &self.queue,
+                &self.image_renderer.sampler,
+                &self.image_renderer.bindgroup_layout,
+            );
+        }
+        let bind_group = image.bind_group.clone()?;
+        Some((bind_group, self.image_vertex_buf(image, pos, size)))
+    }
+
+    fn inline_image_bindgroups(&self, text_box: &mut TextBox) -> Vec<(Arc<BindGroup>, Buffer)> {
+        let mut bind_groups = Vec::new();
+        for text in text_box.texts.iter_mut() {
+            if let Some(inline_image) = &mut text.inline_image {
+                if let Some(Rect { pos, size }) = inline_image.bounds() {
+                    let pos = (pos.0 - self.scroll_x, pos.1 - self.scroll_y);
+                    bind_groups.extend(self.image_bindgroup(&mut inline_image.image, pos, size));
+                }
+            }
+        }
+        bind_groups
+    }
+
+    fn image_bindgroups(
+        &mut self,
+        elements: &mut [Positioned<Element>],
+    ) -> Vec<(Arc<BindGroup>, Buffer)> {
+        let screen_size = self.screen_size();
+        let mut bind_groups = Vec::new();
+        for element in elements.iter_mut() {
+            let Rect { pos, size } = element.bounds.as_ref().unwrap();
+            let pos = (pos.0 - self.scroll_x, pos.1 - self.scroll_y);
+            if pos.1 + size.1 <= 0. {
+                continue;
+            } else if pos.1 >= screen_size.1 {
+                break;
+            }
+            match &mut element.inner {
+                Element::Image(ref mut image) => {
+                    bind_groups.extend(self.image_bindgroup(image, pos, *size));
+                }
+                Element::TextBox(ref mut text_box) => {
+                    bind_groups.extend(self.inline_image_bindgroups(text_box));
+                }
+                Element::Row(ref mut row) => {
+                    for element in row.elements.iter_mut() {
+                        let Rect { pos, size } = element.bounds.as_ref().unwrap();
+                        let pos = (pos.0 - self.scroll_x, pos.1 - self.scroll_y);
+                        match &mut element.inner {
+                            Element::Image(ref mut image) => {
+                                bind_groups.extend(self.image_bindgroup(image, pos, *size));
+                            }
+                            Element::TextBox(ref mut text_box) => {
+                                bind_groups.extend(self.inline_image_bindgroups(text_box));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Element::Table(ref mut table) => {
+                    bind_groups.extend(self.image_bindgroups(&mut table.headers));
+                    for row in table.rows.iter_mut() {
+                        bind_groups.extend(self.image_bindgroups(row));
+                    }
+                }
+                Element::Section(ref mut section) => {
+                    if *section.hidden.borrow() {
+                        continue;
+                    }
+                    for element in section.elements.iter_mut() {
+                        let Rect { pos, size } = element.bounds.as_ref().unwrap();
+                        let pos = (pos.0 - self.scroll_x, pos.1 - self.scroll_y);
+                        match &mut element.inner {
+                            Element::Image(ref mut image) => {
+                                bind_groups.extend(self.image_bindgroup(image, pos, *size));
+                            }
+                            Element::TextBox(ref mut text_box) => {
+                                bind_groups.extend(self.inline_image_bindgroups(text_box));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        bind_groups
+    }
+
+    // The bounds that a text box's text gets laid out in when it's rendered
+    fn text_bounds(
+        &self,
+        text_box: &TextBox,
+        bounds: &Rect,
+        in_table: Option<TableLayout>,
+    ) -> Size {
+        if let Some(layout) = in_table {
+            self.table_cell_bounds(bounds, layout)
+        } else {
+            let available = self.screen_size().0 - bounds.pos.0 - DEFAULT_MARGIN;
+            (
+                text_box.wrap_width(available.max(0.), self.zoom),
+                f32::INFINITY,
+            )
+        }
+    }
+
+    /// Everything that can be focused with the keyboard in document order. Collapsed sections
+    /// only have their summary included
+    pub fn focusable_elements(&mut self) -> Vec<Focusable> {
+        let elements = std::mem::take(&mut self.elements);
+        let focusables = self.cached_focusables(&elements).to_vec();
+        self.elements = elements;
+        focusables
+    }
+
+    fn cached_focusables(&mut self, elements: &[Positioned<Element>]) -> &[Focusable] {
+        let count = elements.len();
+        let cached = matches!(&self.focusables, Some((cached, _)) if *cached == count);
+        if !cached {
+            let mut focusables = Vec::new();
+            self.push_focusables(elements, None, &mut focusables);
+            self.focusables = Some((count, focusables));
+        }
+        match &self.focusables {
+            Some((_, focusables)) => focusables,
+            None => &[],
+        }
+    }
+
+    fn push_focusables(
+        &mut self,
+        elements: &[Positioned<Element>],
+        in_table: Option<TableLayout>,
+        focusables: &mut Vec<Focusable>,
+    ) {
+        for element in elements {
+            let bounds = match &element.bounds {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+            match &element.inner {
+                Element::TextBox(text_box) => {
+                    let checkbox_bounds = text_box.checkbox_bounds(bounds, self.zoom);
+                    if let Some(checkbox_bounds) = checkbox_bounds {
+                        focusables.push(Focusable::new(checkbox_bounds, FocusAction::Checkbox));
+                    }
+                    let text_bounds = self.text_bounds(text_box, bounds, in_table);
+                    let glyphs = text_box.glyph_bounds(
+                        &mut self.glyph_brush,
+                        bounds.pos,
+                        text_bounds,
+                        self.text_zoom(),
+                    );
+                    // Neighboring glyphs of the same link make up a single focusable
+                    let mut current: Option<Focusable> = None;
+                    for (glyph_bounds, glyph) in glyphs {
+                        let link = text_box.texts[glyph.section_index].link.as_ref();
+                        match (&mut current, link) {
+                            (Some(focusable), Some(link))
+                                if focusable.action == FocusAction::Link(link.clone()) =>
+                            {
+                                focusable.bounds = focusable.bounds.union(&glyph_bounds);
+                                text::join_lines(&mut focusable.lines, &glyph_bounds);
+                            }
+                            _ => {
+                                focusables.extend(current.take());
+                                current = link.map(|link| {
+                                    Focusable::new(glyph_bounds, FocusAction::Link(link.clone()))
+                                });
+                            }
+                        }
+                    }
+                    focusables.extend(current);
+                }
+                Element::Image(image) => {
+                    if let Some(link) = &image.is_link {
+                        let action = FocusAction::Link(link.clone());
+                        focusables.push(Focusable::new(bounds.clone(), action));
+                    }
+                }
+                Element::Table(table) => {
+                    for cell in table.cells() {
+                        let cell = std::slice::from_ref(cell);
+                        self.push_focusables(cell, Some(table.layout), focusables);
+                    }
+                }
+                Element::Row(row) => self.push_focusables(&row.elements, in_table, focusables),
+                Element::Section(section) => {
+                    if let Some(summary) = &*section.summary {
+                        if let Some(summary_bounds) = &summary.bounds {
+                            let summary_bounds = summary_bounds.clone();
+                            focusables.push(Focusable::new(summary_bounds, FocusAction::Summary));
+                        }
+                    }
+                    if !*section.hidden.borrow() {
+                        self.push_focusables(&section.elements, in_table, focusables);
+                    }
+                }
+                Element::Spacer(_) => {}
+            }
+        }
+    }
+
+    /// Moves the keyboard focus to the next (or previous) focusable element wrapping around at
+    /// the ends and scrolls it into view
+    pub fn move_focus(&mut self, backwards: bool) -> Option<Focusable> {
+        let current = self.focus.clone();
+        let elements = std::mem::take(&mut self.elements);
+        let focusables = self.cached_focusables(&elements);
+        if focusables.is_empty() {
+            self.elements = elements;
+            self.focus = None;
+            return None;
+        }
+        let len = focusables.len();
+        let focus = current.and_then(|focus| focus.index(focusables));
+        let index = match (focus, backwards) {
+            (None, false) => 0,
+            (None, true) => len - 1,
+            (Some(focus), false) => (focus + 1) % len,
+            (Some(focus), true) => (focus + len - 1) % len,
+        };
+        let focus = Focus::new(focusables, index);
+        let focused = focusables[index].clone();
+        self.elements = elements;
+        self.focus = Some(focus);
+        self.scroll_into_view(&focused.bounds);
+        Some(focused)
+    }
+
+    /// The element that currently has the keyboard focus
+    pub fn focused(&mut self) -> Option<Focusable> {
+        let focus = self.focus.clone()?;
+        let elements = std::mem::take(&mut self.elements);
+        let focusables = self.cached_focusables(&elements);
+        let focused = focus
+            .index(focusables)
+            .map(|index| focusables[index].clone());
+        self.elements = elements;
+        focused
+    }
+
+    fn scroll_into_view(&mut self, bounds: &Rect) {
+        let screen_width = self.screen_size().0;
+        if bounds.pos.0 < self.scroll_x {
+            self.set_scroll_x(bounds.pos.0 - DEFAULT_MARGIN);
+        } else if bounds.max().0 > self.scroll_x + screen_width {
+            self.set_scroll_x(bounds.max().0 + DEFAULT_MARGIN - screen_width);
+        }
+        let screen_height = self.screen_height();
+        if bounds.pos.1 < self.scroll_y {
+            self.set_scroll_y(bounds.pos.1 - DEFAULT_MARGIN);
+        } else if bounds.max().1 > self.scroll_y + screen_height {
+            self.set_scroll_y(bounds.max().1 + DEFAULT_MARGIN - screen_height);
+        }
+    }
+
+    fn draw_focus_ring(&mut self, elements: &[Positioned<Element>]) -> anyhow::Result<()> {
+        let focus = match self.focus.clone() {
+            Some(focus) => focus,
+            None => return Ok(()),
+        };
+        let focusables = self.cached_focusables(elements);
+        // A link that wraps gets a ring around each of its lines
+        let lines = focus
+            .index(focusables)
+            .map(|index| focusables[index].lines.clone());
+        if let Some(lines) = lines {
+            let gap = 3. * self.hidpi_scale;
+            let color = self.theme.link_color;
+            self.draw_in_layer(Layer::Foreground, |renderer| {
+                for Rect { pos, size } in lines {
+                    let ring = Rect::new(
+                        (pos.0 - gap, pos.1 - gap),
+                        (size.0 + 2. * gap, size.1 + 2. * gap),
+                    );
+                    renderer.stroke_rectangle(ring, color, 2.)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn draw_link_hover(&mut self) -> anyhow::Result<()> {
+        let [r, g, b, a] = self.theme.link_hover_color;
+        let thickness = 2. * self.hidpi_scale * self.zoom;
+        let lines = self.hovered_link.clone();
+        match self.theme.link_hover_style {
+            LinkHoverStyle::None => Ok(()),
+            LinkHoverStyle::Underline => self.draw_in_layer(Layer::Foreground, |renderer| {
+                for line in lines {
+                    let underline = Rect::new(
+                        (line.pos.0, line.max().1 - thickness),
+                        (line.size.0, thickness),
+                    );
+                    renderer.draw_rectangle(underline, [r, g, b, a])?;
+                }
+                Ok(())
+            }),
+            LinkHoverStyle::Background => self.draw_in_layer(Layer::Highlight, |renderer| {
+                for line in lines {
+                    renderer.draw_rectangle(line, [r, g, b, a * 0.2])?;
+                }
+                Ok(())
+            }),
+        }
+    }
 
-        // Build a simple path.
-        builder.begin((pos.0 + box_size * 0.2, pos.1 + box_size * 0.5).into());
-        builder.line_to((pos.0 + box_size * 0.4, pos.1 + box_size * 0.7).into());
-        builder.line_to((pos.0 + box_size * 0.8, pos.1 + box_size * 0.2).into());
-        builder.end(false);
-        builder.build()?;
-        Ok(())
+    /// Highlights the link at `loc` (in document coordinates). Returns whether the highlighted
+    /// link changed and needs to be redrawn
+    pub fn hover_link(&mut self, loc: Point) -> bool {
+        let hovered_link = if self.theme.link_hover_style == LinkHoverStyle::None {
+            Vec::new()
+        } else {
+            let elements = std::mem::take(&mut self.elements);
+            let lines = self.link_run_at(&elements, None, loc);
+            self.elements = elements;
+            lines
+        };
+        if hovered_link == self.hovered_link {
+            return false;
+        }
+        self.hovered_link = hovered_link;
+        true
     }
 
-    fn image_bindgroups(
+    // Walks down to the text box at `loc` the same way `push_focusables` does
+    fn link_run_at(
         &mut self,
-        elements: &mut [Positioned<Element>],
-    ) -> Vec<(Arc<BindGroup>, Buffer)> {
-        let screen_size = self.screen_size();
-        let mut bind_groups = Vec::new();
-        for element in elements.iter_mut() {
-            let Rect { pos, size } = element.bounds.as_ref().unwrap();
-            let pos = (pos.0, pos.1 - self.scroll_y);
-            if pos.1 + size.1 <= 0. {
-                continue;
-            } else if pos.1 >= screen_size.1 {
-                break;
+        elements: &[Positioned<Element>],
+        in_table: Option<TableLayout>,
+        loc: Point,
+    ) -> Vec<Rect> {
+        let element = elements.iter().find(|element| {
+            element
+                .bounds
+                .as_ref()
+                .map_or(false, |bounds| bounds.contains(loc))
+        });
+        let (element, bounds) = match element.and_then(|e| Some((e, e.bounds.as_ref()?))) {
+            Some(found) => found,
+            None => return Vec::new(),
+        };
+        match &element.inner {
+            Element::TextBox(text_box) => {
+                let text_bounds = self.text_bounds(text_box, bounds, in_table);
+                text_box.link_run_at(
+                    &mut self.glyph_brush,
+                    loc,
+                    bounds.pos,
+                    text_bounds,
+                    self.text_zoom(),
+                )
             }
-            match &mut element.inner {
-                Element::Image(ref mut image) => {
-                    if image.bind_group.is_none() {
-                        image.create_bind_group(
-                            &self.device,
-                            &self.queue,
-                            &self.image_renderer.sampler,
-                            &self.image_renderer.bindgroup_layout,
+            Element::Table(table) => match table.cell_at(loc) {
+                Some((_, _, cell)) => {
+                    self.link_run_at(std::slice::from_ref(cell), Some(table.layout), loc)
+                }
+                None => Vec::new(),
+            },
+            Element::Row(row) => self.link_run_at(&row.elements, in_table, loc),
+            Element::Section(section) => {
+                let summary = section.summary.as_ref().as_ref();
+                let hovered = |summary: &&Positioned<Element>| {
+                    summary
+                        .bounds
+                        .as_ref()
+                        .map_or(false, |bounds| bounds.contains(loc))
+                };
+                if let Some(summary) = summary.filter(hovered) {
+                    self.link_run_at(std::slice::from_ref(summary), in_table, loc)
+                } else if !*section.hidden.borrow() {
+                    self.link_run_at(&section.elements, in_table, loc)
+                } else {
+                    Vec::new()
+                }
+            }
+            Element::Image(_) | Element::Spacer(_) => Vec::new(),
+        }
+    }
+
+    /// Gathers the text of the current selection without drawing anything. Unlike
+    /// `selection_text` this covers all of the selection including the parts that are off screen
+    pub fn compute_selection_text(&mut self) -> String {
+        let mut selection_text = String::new();
+        let elements = std::mem::take(&mut self.elements);
+        let anchors = self
+            .selection
+            .filter(|selection| selection.0 != selection.1)
+            .map(|selection| SelectionAnchors::new(&elements, selection));
+        if let Some(anchors) = anchors {
+            let mut index = 0;
+            self.push_selection_text(&elements, &anchors, None, &mut index, &mut selection_text);
+        }
+        self.elements = elements;
+        selection_text
+    }
+
+    // Walks the text boxes in the same order as `visit_leaves` so that `index` matches what the
+    // selection anchors were found with
+    fn push_selection_text(
+        &mut self,
+        elements: &[Positioned<Element>],
+        anchors: &SelectionAnchors,
+        in_table: Option<TableLayout>,
+        index: &mut usize,
+        selection_text: &mut String,
+    ) {
+        for element in elements {
+            match &element.inner {
+                Element::TextBox(text_box) => {
+                    let selection = anchors.text_selection(*index);
+                    *index += 1;
+                    if let (Some(selection), Some(bounds)) = (selection, &element.bounds) {
+                        let text_bounds = self.text_bounds(text_box, bounds, in_table);
+                        text_box.render_selection(
+                            &mut self.glyph_brush,
+                            bounds.pos,
+                            text_bounds,
+                            self.text_zoom(),
+                            selection,
+                            selection_text,
                         );
                     }
-                    if let Some(ref bind_group) = image.bind_group {
-                        let vertex_buf =
-                            ImageRenderer::vertex_buf(&self.device, pos, *size, screen_size);
-                        bind_groups.push((bind_group.clone(), vertex_buf));
-                    }
                 }
-                Element::Row(ref mut row) => {
-                    for element in row.elements.iter_mut() {
-                        let Rect { pos, size } = element.bounds.as_ref().unwrap();
-                        let pos = (pos.0, pos.1 - self.scroll_y);
-                        if let Element::Image(ref mut image) = &mut element.inner {
-                            if image.bind_group.is_none() {
-                                image.create_bind_group(
-                                    &self.device,
-                                    &self.queue,
-                                    &self.image_renderer.sampler,
-                                    &self.image_renderer.bindgroup_layout,
-                                );
-                            }
-                            if let Some(ref bind_group) = image.bind_group {
-                                let vertex_buf = ImageRenderer::vertex_buf(
-                                    &self.device,
-                                    pos,
-                                    *size,
-                                    screen_size,
-                                );
-                                bind_groups.push((bind_group.clone(), vertex_buf));
-                            }
-                        }
+                Element::Table(table) => {
+                    for cell in table.cells() {
+                        let cell = std::slice::from_ref(cell);
+                        let layout = Some(table.layout);
+                        self.push_selection_text(cell, anchors, layout, index, selection_text);
                     }
                 }
-                Element::Section(ref mut section) => {
-                    if *section.hidden.borrow() {
-                        continue;
+                Element::Row(row) => self.push_selection_text(
+                    &row.elements,
+                    anchors,
+                    in_table,
+                    index,
+                    selection_text,
+                ),
+                Element::Section(section) => {
+                    if let Some(summary) = &*section.summary {
+                        let summary = std::slice::from_ref(summary);
+                        self.push_selection_text(summary, anchors, in_table, index, selection_text);
                     }
-                    for element in section.elements.iter_mut() {
-                        let Rect { pos, size } = element.bounds.as_ref().unwrap();
-                        let pos = (pos.0, pos.1 - self.scroll_y);
-                        if let Element::Image(ref mut image) = &mut element.inner {
-                            if image.bind_group.is_none() {
-                                image.create_bind_group(
-                                    &self.device,
-                                    &self.queue,
-                                    &self.image_renderer.sampler,
-                                    &self.image_renderer.bindgroup_layout,
-                                );
-                            }
-                            if let Some(ref bind_group) = image.bind_group {
-                                let vertex_buf = ImageRenderer::vertex_buf(
-                                    &self.device,
-                                    pos,
-                                    *size,
-                                    screen_size,
-                                );
-                                bind_groups.push((bind_group.clone(), vertex_buf));
-                            }
-                        }
+                    if !*section.hidden.borrow() {
+                        self.push_selection_text(
+                            &section.elements,
+                            anchors,
+                            in_table,
+                            index,
+                            selection_text,
+                        );
                     }
                 }
-                _ => {}
+                Element::Image(_) | Element::Spacer(_) => {}
             }
         }
-        bind_groups
     }
 
-    pub fn redraw(&mut self, elements: &mut [Positioned<Element>]) -> anyhow::Result<()> {
+    pub fn redraw(&mut self) -> anyhow::Result<()> {
+        // Taken out while drawing since rendering needs `self` mutably
+        let mut elements = std::mem::take(&mut self.elements);
+        let result = self.draw(&mut elements);
+        self.elements = elements;
+        result
+    }
+
+    fn draw(&mut self, elements: &mut [Positioned<Element>]) -> anyhow::Result<()> {
         let frame = self
             .surface
+            .as_ref()
+            .context("Drawing to a window without one")?
             .get_current_texture()
             .context("Failed to acquire next swap chain texture")?;
         let view = frame
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        self.draw_to(&view, elements)?;
+        frame.present();
+        Ok(())
+    }
+
+    /// Draws `elements` scrolled to `scroll_y` into `view`. Only the document itself gets drawn
+    /// without a window, so none of the screen-fixed overlays end up in exports
+    pub fn draw_to(
+        &mut self,
+        view: &wgpu::TextureView,
+        elements: &mut [Positioned<Element>],
+    ) -> anyhow::Result<()> {
+        let headless = self.surface.is_none();
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
+        self.stats.start_frame();
+
+        let screen_size = self.screen_size();
+        let visible = (self.scroll_y, self.scroll_y + screen_size.1);
+        prioritize_images(elements, visible);
+        let key = GeometryKey {
+            element_count: elements.len(),
+            reserved_height: self.positioner.reserved_height,
+            zoom: self.zoom,
+            screen_size,
+            selection: self.selection,
+            focus: self.focus.clone(),
+            hovered_link: self.hovered_link.clone(),
+        };
+        let covered = self.geometry.as_ref().map_or(false, |geometry| {
+            geometry.key == key && geometry.range.0 <= visible.0 && visible.1 <= geometry.range.1
+        });
+        if !covered {
+            self.damage = Damage::Full;
+        }
+
+        self.sticky_header = None;
+        self.fading = false;
+        self.faded_in = false;
+        self.text_box_index = 0;
+        for buffer in &mut self.fade_buffers {
+            buffer.indices.clear();
+            buffer.vertices.clear();
+        }
         // Prepare and render elements that use lyon
-        self.lyon_buffer.indices.clear();
-        self.lyon_buffer.vertices.clear();
-        self.selection_text = String::new();
-        self.render_elements(elements)?;
-        let vertex_buf = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&self.lyon_buffer.vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-        let index_buffer = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(&self.lyon_buffer.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
+        match self.damage {
+            Damage::Scroll => self.render_elements(elements, visible, false)?,
+            Damage::Full => {
+                // Tessellate an extra screen above and below so that scrolling can reuse it
+                let range = (visible.0 - screen_size.1, visible.1 + screen_size.1);
+                self.cached_at = Instant::now();
+                self.lyon_buffer.indices.clear();
+                self.lyon_buffer.vertices.clear();
+                for buffer in &mut self.layer_buffers {
+                    buffer.indices.clear();
+                    buffer.vertices.clear();
+                }
+                // Reuse the allocation since this runs often
+                self.selection_text.clear();
+                self.selection_anchors = self
+                    .selection
+                    .filter(|selection| selection.0 != selection.1)
+                    .map(|selection| SelectionAnchors::new(elements, selection));
+                self.render_elements(elements, range, true)?;
+                self.draw_focus_ring(elements)?;
+                self.draw_link_hover()?;
+                let buffers = self.upload_layers();
+                self.geometry = Some(CachedGeometry {
+                    key,
+                    range,
+                    buffers,
+                });
+                self.damage = Damage::Scroll;
+            }
+        }
+        self.fade_geometry = if self
+            .fade_buffers
+            .iter()
+            .all(|buffer| buffer.indices.is_empty())
+        {
+            None
+        } else {
+            self.swap_fade_buffers();
+            let buffers = self.upload_layers();
+            self.swap_fade_buffers();
+            Some(buffers)
+        };
+        // Elements that finished fading in join the cached geometry once the rest are done too
+        if self.faded_in && !self.fading {
+            self.damage = Damage::Full;
+        }
+        if !headless {
+            self.render_overlay()?;
+        }
+        let (overlay_vertex_buf, overlay_index_buf) = self.lyon_buffers(&self.overlay_buffer);
+        let scroll_offset: [f32; 4] = [
+            -self.scroll_x * 2. / screen_size.0,
+            self.scroll_y * 2. / screen_size.1,
+            0.,
+            0.,
+        ];
+        self.queue
+            .write_buffer(&self.scroll_buffer, 0, bytemuck::cast_slice(&scroll_offset));
 
+        let gradient_bufs = self.background_gradient_buffers();
         // Prepare image bind groups for drawing
         let image_bindgroups = self.image_bindgroups(elements);
 
+        self.stats.vertices = self.lyon_buffer.vertices.len()
+            + self.fade_buffers[0].vertices.len()
+            + self.overlay_buffer.vertices.len();
+        self.stats.indices = self.lyon_buffer.indices.len()
+            + self.fade_buffers[0].indices.len()
+            + self.overlay_buffer.indices.len();
+        self.stats.image_draws = image_bindgroups.len();
+
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.theme.background_color),
+                        load: wgpu::LoadOp::Clear(
+                            self.output_encoding.encode(self.theme.background_color),
+                        ),
                         store: true,
                     },
                 })],
                 depth_stencil_attachment: None,
             });
 
-            // Draw lyon elements
-            rpass.set_pipeline(&self.render_pipeline);
-            rpass.set_vertex_buffer(0, vertex_buf.slice(..));
-            rpass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            rpass.draw_indexed(0..self.lyon_buffer.indices.len() as u32, 0, 0..1);
+            if let Some((vertex_buf, index_buf)) = &gradient_bufs {
+                rpass.set_pipeline(&self.render_pipeline);
+                rpass.set_bind_group(0, &self.fixed_bind_group, &[]);
+                rpass.set_bind_group(1, &self.output_bind_group, &[]);
+                rpass.set_vertex_buffer(0, vertex_buf.slice(..));
+                rpass.set_index_buffer(index_buf.slice(..), IndexFormat::Uint32);
+                rpass.draw_indexed(0..6, 0, 0..1);
+            }
+            self.draw_layer(&mut rpass, Layer::Background);
 
             // Draw images
             rpass.set_pipeline(&self.image_renderer.render_pipeline);
+            rpass.set_bind_group(1, &self.output_bind_group, &[]);
             rpass.set_index_buffer(self.image_renderer.index_buf.slice(..), IndexFormat::Uint16);
             for (bindgroup, vertex_buf) in image_bindgroups.iter() {
                 rpass.set_bind_group(0, bindgroup, &[]);
                 rpass.set_vertex_buffer(0, vertex_buf.slice(..));
                 rpass.draw_indexed(0..6, 0, 0..1);
             }
-        }
 
-        let screen_size = self.screen_size();
+            self.draw_layer(&mut rpass, Layer::Highlight);
+        }
 
         // Draw wgpu brush elements
+        let glyph_transform = [
+            2.0 / screen_size.0,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            -2.0 / screen_size.1,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+            0.0,
+            -1.0 - (self.scroll_x * 2. / screen_size.0),
+            1.0 + (self.scroll_y * 2. / (screen_size.1)),
+            0.0,
+            1.0,
+        ];
         self.glyph_brush
             .draw_queued_with_transform(
                 &self.device,
                 &mut self.staging_belt,
                 &mut encoder,
-                &view,
-                [
-                    2.0 / screen_size.0,
-                    0.0,
-                    0.0,
-                    0.0,
-                    0.0,
-                    -2.0 / screen_size.1,
-                    0.0,
-                    0.0,
-                    0.0,
-                    0.0,
-                    1.0,
-                    0.0,
-                    -1.0,
-                    1.0 + (self.scroll_y * 2. / (screen_size.1)),
-                    0.0,
-                    1.0,
-                ],
+                view,
+                glyph_transform,
             )
             .expect("Failed to draw queued glyphs");
+        let glyph_cache_outgrown = self.glyph_cache.finish_frame();
+
+        // Draw lyon elements that go over the text
+        let has_foreground = self
+            .uploaded_layers()
+            .any(|buffers| !buffers.layers[Layer::Foreground as usize].is_empty());
+        if has_foreground {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.draw_layer(&mut rpass, Layer::Foreground);
+        }
+
+        // Draw screen-fixed lyon elements over the document
+        if !self.overlay_buffer.indices.is_empty() {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            rpass.set_pipeline(&self.render_pipeline);
+            rpass.set_bind_group(0, &self.fixed_bind_group, &[]);
+            rpass.set_bind_group(1, &self.output_bind_group, &[]);
+            rpass.set_vertex_buffer(0, overlay_vertex_buf.slice(..));
+            rpass.set_index_buffer(overlay_index_buf.slice(..), IndexFormat::Uint32);
+            rpass.draw_indexed(0..self.overlay_buffer.indices.len() as u32, 0, 0..1);
+        }
+
+        // Glyphs that go over the overlay
+        if !headless {
+            let mut overlay_glyphs = false;
+            if let Some(header) = &self.sticky_header {
+                for section in &header.sections {
+                    self.glyph_brush
+                        .queue(self.output_encoding.encode_text(section.to_borrowed()));
+                }
+                overlay_glyphs = true;
+            }
+            if self.tab_bar_height() > 0. {
+                self.queue_tab_titles();
+                overlay_glyphs = true;
+            }
+            if self.show_stats {
+                self.queue_stats();
+                overlay_glyphs = true;
+            }
+            if self.software_rendering {
+                self.queue_software_badge();
+                overlay_glyphs = true;
+            }
+            if self.tooltip.is_some() {
+                self.queue_tooltip();
+                overlay_glyphs = true;
+            }
+            if overlay_glyphs {
+                // The overlay stays put when scrolling sideways
+                let mut overlay_transform = glyph_transform;
+                overlay_transform[12] = -1.0;
+                self.glyph_brush
+                    .draw_queued_with_transform(
+                        &self.device,
+                        &mut self.staging_belt,
+                        &mut encoder,
+                        view,
+                        overlay_transform,
+                    )
+                    .expect("Failed to draw queued glyphs");
+            }
+        }
 
         self.staging_belt.finish();
         self.queue.submit(Some(encoder.finish()));
-        frame.present();
 
         self.staging_belt.recall();
+        // The frame is done with the old cache, so the next one can start over with a small one
+        if glyph_cache_outgrown {
+            log::debug!(
+                "Glyph cache outgrew {0}x{0}. Starting it over",
+                self.glyph_cache.max_size()
+            );
+            let fonts = self.glyph_brush.fonts().to_vec();
+            self.glyph_brush =
+                build_glyph_brush(&self.device, fonts, self.config.format, &self.glyph_cache);
+        }
         Ok(())
     }
 
-    pub fn reposition(&mut self, elements: &mut [Positioned<Element>]) -> anyhow::Result<()> {
+    /// Largest that the glyph cache texture can get (in texels a side) before it's started over.
+    /// Can't be more than the GPU allows
+    pub fn set_glyph_cache_max_size(&mut self, max_size: u32) {
+        let limit = self.device.limits().max_texture_dimension_2d;
+        self.glyph_cache.set_max_size(max_size.min(limit));
+    }
+
+    // A quad covering the screen that fades from the top color of the theme's gradient to the
+    // bottom one. It's drawn without the scroll offset so the document scrolls over it
+    fn background_gradient_buffers(&self) -> Option<(Buffer, Buffer)> {
+        let (top, bottom) = self.theme.background_gradient?;
+        let screen_size = self.screen_size();
+        let screen = Rect::new((0., 0.), screen_size);
+        Some(self.lyon_buffers(&gradient_quad(&screen, top, bottom, screen_size)))
+    }
+
+    // The cached geometry followed by the geometry of the elements fading in
+    fn uploaded_layers(&self) -> impl Iterator<Item = &LayerBuffers> {
+        let cached = self.geometry.as_ref().map(|geometry| &geometry.buffers);
+        cached.into_iter().chain(&self.fade_geometry)
+    }
+
+    // Draws the geometry of a single layer scrolled along with the document
+    fn draw_layer<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>, layer: Layer) {
+        for buffers in self.uploaded_layers() {
+            let range = buffers.layers[layer as usize].clone();
+            if range.is_empty() {
+                continue;
+            }
+            rpass.set_pipeline(&self.render_pipeline);
+            rpass.set_bind_group(0, &self.scroll_bind_group, &[]);
+            rpass.set_bind_group(1, &self.output_bind_group, &[]);
+            rpass.set_vertex_buffer(0, buffers.vertex_buf.slice(..));
+            rpass.set_index_buffer(buffers.index_buf.slice(..), IndexFormat::Uint32);
+            rpass.draw_indexed(range, 0, 0..1);
+        }
+    }
+
+    // Merges the layers being tessellated and uploads them
+    fn upload_layers(&mut self) -> LayerBuffers {
+        let layers = self.merge_layers();
+        let (vertex_buf, index_buf) = self.lyon_buffers(&self.lyon_buffer);
+        LayerBuffers {
+            vertex_buf,
+            index_buf,
+            layers,
+        }
+    }
+
+    fn lyon_buffers(&self, lyon_buffer: &VertexBuffers<Vertex, u32>) -> (Buffer, Buffer) {
+        let vertex_buf = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Vertex Buffer"),
+                contents: bytemuck::cast_slice(&lyon_buffer.vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        let index_buf = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Index Buffer"),
+                contents: bytemuck::cast_slice(&lyon_buffer.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+        (vertex_buf, index_buf)
+    }
+
+    /// Whether something is being animated and needs continuous redraws
+    pub fn is_animating(&self) -> bool {
+        !self.reduce_motion && (self.loading.is_some() || self.fading)
+    }
+
+    /// Toggles ordered dithering of lyon geometry to reduce banding in flat colors
+    pub fn set_dither(&mut self, enabled: bool) {
+        let output = self.output_encoding.uniform(enabled);
+        self.queue
+            .write_buffer(&self.output_buffer, 0, bytemuck::cast_slice(&output));
+    }
+
+    /// Word count and reading time of the whole document
+    pub fn document_stats(&self) -> DocStats {
+        let element_count = self.elements.len();
+        match self.doc_stats.get() {
+            Some((counted, stats)) if counted == element_count => stats,
+            _ => {
+                let stats = DocStats::new(&self.elements, self.doc_stats_options);
+                self.doc_stats.set(Some((element_count, stats)));
+                stats
+            }
+        }
+    }
+
+    /// Swaps in a new theme. The colors of text get picked while parsing, so documents only pick
+    /// it up once they get parsed again
+    pub fn set_theme(&mut self, theme: Theme, code_theme: CodeTheme) {
+        self.theme = theme;
+        self.code_theme = code_theme;
+        self.invalidate();
+    }
+
+    /// Forces the next frame to tessellate everything again
+    pub fn invalidate(&mut self) {
+        self.damage = Damage::Full;
+        self.doc_stats.set(None);
+        self.focusables = None;
+    }
+
+    pub fn reposition(&mut self) -> anyhow::Result<()> {
+        self.invalidate();
+        self.hovered_link.clear();
+        text::resolve_checkboxes(&self.elements);
         self.positioner
-            .reposition(&mut self.glyph_brush, elements, self.zoom)
+            .reposition(&mut self.glyph_brush, &mut self.elements, self.zoom)?;
+        // The document may not be as wide anymore
+        self.set_scroll_x(self.scroll_x);
+        // The focused element may have been hidden away in a collapsed section
+        if self.focus.is_some() && self.focused().is_none() {
+            self.focus = None;
+        }
+        Ok(())
+    }
+
+    /// Lays the document out again for a new scale factor like when the window moves to a
+    /// monitor with a different DPI
+    pub fn set_hidpi_scale(&mut self, hidpi_scale: f32) -> anyhow::Result<()> {
+        self.hidpi_scale = hidpi_scale;
+        self.positioner.hidpi_scale = hidpi_scale;
+        self.positioner.top_inset = self.tab_bar_height();
+        for element in &mut self.elements {
+            element.inner.set_hidpi_scale(hidpi_scale);
+        }
+        self.reposition()
+    }
+
+    /// The innermost element at `loc` (in screen coordinates like the cursor's position) with
+    /// its bounds in screen coordinates too. Points over the tab bar don't hit anything
+    pub fn hit_test(&mut self, loc: Point) -> Option<Hit> {
+        if loc.1 < self.tab_bar_height() {
+            return None;
+        }
+        let doc_loc = (loc.0 + self.scroll_x, loc.1 + self.scroll_y);
+        let screen_size = self.screen_size();
+        let text_zoom = self.text_zoom();
+        let mut hit = hit::hit_test(
+            &self.elements,
+            &mut self.glyph_brush,
+            doc_loc,
+            screen_size,
+            self.zoom,
+            text_zoom,
+        )?;
+        hit.bounds.pos.0 -= self.scroll_x;
+        hit.bounds.pos.1 -= self.scroll_y;
+        Some(hit)
+    }
+
+    /// The loaded image at `loc` (in screen coordinates) in a form that can be saved
+    pub fn image_at(&mut self, loc: Point) -> Option<SavableImage> {
+        let hit = self.hit_test(loc)?;
+        match hit::resolve(&self.elements, &hit.path)? {
+            Element::Image(image) => image.savable(),
+            _ => None,
+        }
+    }
+
+    /// The zoom that text gets rendered at which includes the text scale on top of the zoom
+    pub fn text_zoom(&self) -> f32 {
+        self.zoom * self.positioner.text_scale
+    }
+
+    /// Scales only the text of the document (unlike the zoom) and lays it out again
+    pub fn set_text_scale(&mut self, text_scale: f32) -> anyhow::Result<()> {
+        self.positioner.text_scale = text_scale;
+        self.reposition()
+    }
+
+    /// Collapses (or expands) every section in the document including nested ones
+    pub fn set_all_sections(&mut self, hidden: bool) -> anyhow::Result<()> {
+        visit_sections(&self.elements, &mut |section| {
+            *section.hidden.borrow_mut() = hidden
+        });
+        self.reposition()
+    }
+
+    /// Whether every section in the document is collapsed
+    pub fn all_sections_hidden(&self) -> bool {
+        let mut all_hidden = true;
+        visit_sections(&self.elements, &mut |section| {
+            all_hidden &= *section.hidden.borrow()
+        });
+        all_hidden
+    }
+
+    /// Replaces the document with `elements` (see [`DocumentBuilder`]) and positions them from
+    /// the top
+    ///
+    /// [`DocumentBuilder`]: crate::document::DocumentBuilder
+    pub fn set_elements(&mut self, elements: Vec<Positioned<Element>>) -> anyhow::Result<()> {
+        self.elements = elements;
+        self.selection = None;
+        self.loading = None;
+        self.positioner.anchors.clear();
+        self.reposition()?;
+        self.set_scroll_y(0.);
+        self.set_scroll_x(0.);
+        Ok(())
+    }
+
+    /// Shows `document` in place of the active document which gets stored in `document` instead.
+    /// The new document gets laid out again since the window may have changed while it was away
+    pub fn swap_document(&mut self, document: &mut DocumentState) -> anyhow::Result<()> {
+        std::mem::swap(&mut self.elements, &mut document.elements);
+        std::mem::swap(&mut self.scroll_y, &mut document.scroll_y);
+        std::mem::swap(&mut self.scroll_x, &mut document.scroll_x);
+        std::mem::swap(&mut self.selection, &mut document.selection);
+        std::mem::swap(&mut self.focus, &mut document.focus);
+        std::mem::swap(&mut self.zoom, &mut document.zoom);
+        std::mem::swap(
+            &mut self.positioner.reserved_height,
+            &mut document.reserved_height,
+        );
+        std::mem::swap(&mut self.positioner.anchors, &mut document.anchors);
+        std::mem::swap(&mut self.positioner.slides, &mut document.slides);
+        std::mem::swap(&mut self.loading, &mut document.loading);
+        self.selection_anchors = None;
+        self.sticky_header = None;
+        self.tooltip = None;
+        self.hovered_link.clear();
+        self.set_hidpi_scale(self.hidpi_scale)?;
+        self.set_scroll_y(self.scroll_y);
+        Ok(())
     }
 
     pub fn set_scroll_y(&mut self, scroll_y: f32) {
+        let previous = self.scroll_y;
         self.scroll_y = scroll_y
             .min(self.positioner.reserved_height - self.screen_height())
             .max(0.);
+        // Snapped shapes only stay on the pixel grid when scrolled by whole pixels
+        if self.pixel_snap {
+            self.scroll_y = self.scroll_y.round();
+        }
+        // Tooltips belong to whatever was under the cursor before scrolling
+        if self.scroll_y != previous {
+            self.tooltip = None;
+            self.hovered_link.clear();
+        }
+    }
+
+    /// Scrolls sideways to see the parts of the document that are wider than the screen like
+    /// tables that don't wrap their cells
+    pub fn set_scroll_x(&mut self, scroll_x: f32) {
+        let previous = self.scroll_x;
+        self.scroll_x = scroll_x.min(self.positioner.overflow_width()).max(0.);
+        if self.pixel_snap {
+            self.scroll_x = self.scroll_x.round();
+        }
+        if self.scroll_x != previous {
+            self.tooltip = None;
+            self.hovered_link.clear();
+        }
+    }
+
+    /// Scrolls `fraction` (from 0 to 1) of the way down the document
+    pub fn set_scroll_fraction(&mut self, fraction: f32) {
+        let max_scroll = self.positioner.reserved_height - self.screen_height();
+        self.set_scroll_y(fraction * max_scroll);
+    }
+
+    /// Scrolls to the text that `line` of the markdown source ended up in. Lines without any
+    /// text of their own go to the closest text before them. Returns whether any was found
+    pub fn scroll_to_source_line(&mut self, line: usize) -> bool {
+        let mut closest: Option<(usize, f32)> = None;
+        visit_leaves(&self.elements, &mut |element| {
+            if let (Element::TextBox(text_box), Some(bounds)) = (&element.inner, &element.bounds) {
+                if let Some(source_line) = text_box.source_line {
+                    let is_closer = closest.map_or(true, |(closest, _)| source_line > closest);
+                    if source_line <= line && is_closer {
+                        closest = Some((source_line, bounds.pos.1));
+                    }
+                }
+            }
+        });
+        match closest {
+            Some((_, y)) => {
+                self.set_scroll_y(y);
+                true
+            }
+            None => false,
+        }
     }
 }
 
+// Two triangles covering `rect` that fade from `top` at its top edge to `bottom` at its bottom one
+fn gradient_quad(
+    rect: &Rect,
+    top: [f32; 4],
+    bottom: [f32; 4],
+    screen: Size,
+) -> VertexBuffers<Vertex, u32> {
+    let vertex = |x, y, color| {
+        let [x, y] = point(x, y, screen);
+        Vertex {
+            pos: [x, y, 0.],
+            color,
+        }
+    };
+    let (min, max) = (rect.pos, rect.max());
+    let mut quad = VertexBuffers::new();
+    quad.vertices = vec![
+        vertex(min.0, min.1, top),
+        vertex(max.0, min.1, top),
+        vertex(max.0, max.1, bottom),
+        vertex(min.0, max.1, bottom),
+    ];
+    quad.indices = vec![0, 1, 2, 0, 2, 3];
+    quad
+}
+
 // Translates points from pixel coordinates to wgpu coordinates
 pub fn point(x: f32, y: f32, screen: Size) -> [f32; 2] {
     let scale_x = 2. / screen.0;
@@ -786,3 +3658,176 @@ pub fn point(x: f32, y: f32, screen: Size) -> [f32; 2] {
     let new_y = 1. - (y * scale_y);
     [new_x, new_y]
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        pick_glyph_cache_size, scrollbar_thumb_rect, slide_scroll, AdapterFallback, DocStats,
+        Focus, FocusAction, Focusable, OutputEncoding, SCROLLBAR_WIDTH,
+    };
+    use crate::opts::DocStatsOptions;
+    use crate::positioner::Positioned;
+    use crate::text::{Text, TextBox};
+    use crate::utils::Rect;
+    use crate::Element;
+
+    #[test]
+    fn output_encoding_matches_the_swapchain() {
+        use wgpu::TextureFormat;
+        assert_eq!(
+            OutputEncoding::for_format(TextureFormat::Bgra8UnormSrgb),
+            OutputEncoding::Hardware
+        );
+        assert_eq!(
+            OutputEncoding::for_format(TextureFormat::Rgba16Float),
+            OutputEncoding::ClampedLinear
+        );
+        assert_eq!(
+            OutputEncoding::for_format(TextureFormat::Bgra8Unorm),
+            OutputEncoding::Srgb
+        );
+        // Exports read the pixels back as they are
+        assert_eq!(
+            OutputEncoding::for_format(super::HEADLESS_FORMAT),
+            OutputEncoding::Hardware
+        );
+
+        // Linear colors come out as the hex they came from on formats that don't encode them
+        let [r, g, b, a] = crate::color::hex_to_linear_rgba(0x80c0ff);
+        let color = wgpu::Color {
+            r: r.into(),
+            g: g.into(),
+            b: b.into(),
+            a: a.into(),
+        };
+        let encoded = OutputEncoding::Srgb.encode(color);
+        let bytes = [encoded.r, encoded.g, encoded.b].map(|x| (x * 255.).round() as u8);
+        assert_eq!(bytes, [0x80, 0xc0, 0xff]);
+        assert_eq!(OutputEncoding::Hardware.encode(color), color);
+        let too_bright = wgpu::Color { r: 1.5, ..color };
+        assert_eq!(OutputEncoding::ClampedLinear.encode(too_bright).r, 1.);
+
+        // Text gets the same encoding without its alpha changing
+        let half_clear = [r, g, b, 0.5];
+        let section = wgpu_glyph::Section::default()
+            .add_text(wgpu_glyph::Text::new("text").with_color(half_clear));
+        let encoded = OutputEncoding::Srgb.encode_text(section).text[0]
+            .extra
+            .color;
+        let bytes = encoded.map(|x| (x * 255.).round() as u8);
+        assert_eq!(bytes, [0x80, 0xc0, 0xff, 0x80]);
+        let section = wgpu_glyph::Section::default()
+            .add_text(wgpu_glyph::Text::new("text").with_color(half_clear));
+        let unchanged = OutputEncoding::Hardware.encode_text(section).text[0]
+            .extra
+            .color;
+        assert_eq!(unchanged, half_clear);
+    }
+
+    #[test]
+    fn adapter_fallback_from_env_values() {
+        assert_eq!(
+            "auto".parse::<AdapterFallback>().unwrap(),
+            AdapterFallback::Auto
+        );
+        assert_eq!(
+            "Never".parse::<AdapterFallback>().unwrap(),
+            AdapterFallback::Never
+        );
+        assert_eq!(
+            "ALWAYS".parse::<AdapterFallback>().unwrap(),
+            AdapterFallback::Always
+        );
+        assert!("software".parse::<AdapterFallback>().is_err());
+    }
+
+    #[test]
+    fn reading_time_handles_any_reading_speed() {
+        let text = |text: &str| Text::new(text.to_owned(), 1., [1.; 4]);
+        let mut code = TextBox::new(vec![text("let x = 1;")], 1.);
+        code.is_code_block = true;
+        let elements = [
+            // Half bold words still count once
+            TextBox::new(vec![text("one tw"), text("o  three\n")], 1.),
+            code,
+        ]
+        .map(|text_box| Positioned::new(Element::from(text_box)));
+        let stats = |words_per_minute, include_code| {
+            let options = DocStatsOptions {
+                words_per_minute,
+                include_code,
+            };
+            DocStats::new(&elements, options)
+        };
+
+        let prose = stats(3., false);
+        assert_eq!((prose.words, prose.characters), (3, 11));
+        assert_eq!(prose.reading_time, Duration::from_secs(60));
+        let all = stats(3., true);
+        assert_eq!((all.words, all.characters), (7, 18));
+
+        assert_eq!(stats(0., true).reading_time, Duration::ZERO);
+        assert_eq!(stats(-1., true).reading_time, Duration::ZERO);
+        assert_eq!(stats(f32::NAN, true).reading_time, Duration::ZERO);
+        assert_eq!(stats(f32::INFINITY, true).reading_time, Duration::ZERO);
+        assert_eq!(stats(1e-30, true).reading_time, Duration::MAX);
+        assert_eq!(stats(f32::MIN_POSITIVE, true).reading_time, Duration::MAX);
+    }
+
+    #[test]
+    fn glyph_cache_fits_on_the_gpu() {
+        assert_eq!(pick_glyph_cache_size(Some(2048), 1., 8192), 2048);
+        assert_eq!(pick_glyph_cache_size(Some(16384), 1., 8192), 8192);
+        assert_eq!(pick_glyph_cache_size(Some(0), 2., 8192), 1024);
+        assert_eq!(pick_glyph_cache_size(None, 1., 8192), 512);
+        assert_eq!(pick_glyph_cache_size(None, 4., 1024), 1024);
+    }
+
+    #[test]
+    fn slides_scroll_a_screen_at_a_time() {
+        // A short slide, one that's taller than the screen and a last short one
+        let slides = [0., 500., 2_000.];
+        let step = |scroll_y, step| slide_scroll(&slides, scroll_y, 1_000., 2_500., step);
+        assert_eq!(step(0., 100.), 500.);
+        assert_eq!(step(500., 100.), 600.);
+        assert_eq!(step(950., 100.), 1_000.);
+        assert_eq!(step(1_000., 100.), 2_000.);
+        assert_eq!(step(2_000., -100.), 500.);
+        assert_eq!(step(600., -100.), 500.);
+        assert_eq!(step(500., -100.), 0.);
+        assert_eq!(step(0., -100.), 0.);
+    }
+
+    #[test]
+    fn scrollbar_thumb_spans_the_visible_part() {
+        let screen_size = (800., 1_000.);
+        let top = scrollbar_thumb_rect(screen_size, 0., 4_000.);
+        assert_eq!(top.pos, (800. - SCROLLBAR_WIDTH, 0.));
+        assert_eq!(top.size, (SCROLLBAR_WIDTH, 250.));
+        let bottom = scrollbar_thumb_rect(screen_size, 3_000., 4_000.);
+        assert_eq!(bottom.max().1, screen_size.1);
+    }
+
+    #[test]
+    fn focus_follows_its_element_when_others_come_and_go() {
+        let focusables = |actions: &[FocusAction]| -> Vec<Focusable> {
+            actions
+                .iter()
+                .map(|action| Focusable::new(Rect::new((0., 0.), (1., 1.)), action.clone()))
+                .collect()
+        };
+        let link = |link: &str| FocusAction::Link(link.to_owned());
+        let before = focusables(&[FocusAction::Summary, link("a"), link("b")]);
+        let focus = Focus::new(&before, 2);
+        assert_eq!(focus.index(&before), Some(2));
+
+        // A section in front of it expanded
+        let expanded = focusables(&[FocusAction::Summary, link("a"), link("c"), link("b")]);
+        assert_eq!(focus.index(&expanded), Some(3));
+        // The section that it was in collapsed
+        let collapsed = focusables(&[FocusAction::Summary]);
+        assert_eq!(focus.index(&collapsed), None);
+    }
+}
@@ -1,11 +1,15 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) size: vec2<f32>,
+    @location(3) corner_radius: f32,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) size: vec2<f32>,
+    @location(2) corner_radius: f32,
 };
 
 @vertex
@@ -15,6 +19,8 @@ fn vs_main(
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
     out.clip_position = vec4<f32>(model.position, 1.0);
+    out.size = model.size;
+    out.corner_radius = model.corner_radius;
     return out;
 }
 
@@ -23,7 +29,42 @@ var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
 
+// How colors get written to the swapchain. Matches `shader.wgsl`
+struct Swapchain {
+    dither: u32,
+    encoding: u32,
+    _padding0: u32,
+    _padding1: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> swapchain: Swapchain;
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if (x <= 0.0031308) {
+        return max(x, 0.0) * 12.92;
+    }
+    return 1.055 * pow(min(x, 1.0), 1.0 / 2.4) - 0.055;
+}
+
+// Textures are sRGB, so sampling them gives linear colors
+fn encode(color: vec4<f32>) -> vec4<f32> {
+    if (swapchain.encoding == 1u) {
+        return clamp(color, vec4<f32>(0.0), vec4<f32>(1.0));
+    } else if (swapchain.encoding == 2u) {
+        return vec4<f32>(linear_to_srgb(color.r), linear_to_srgb(color.g), linear_to_srgb(color.b), color.a);
+    }
+    return color;
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    var color = encode(textureSample(t_diffuse, s_diffuse, in.tex_coords));
+    // Signed distance in pixels from the edge of the rounded rectangle. Negative inside
+    let half_size = in.size / 2.0;
+    let radius = min(in.corner_radius, min(half_size.x, half_size.y));
+    let corner = abs(in.tex_coords * in.size - half_size) - (half_size - vec2<f32>(radius));
+    let dist = length(max(corner, vec2<f32>(0.0))) + min(max(corner.x, corner.y), 0.0) - radius;
+    color.a = color.a * clamp(0.5 - dist, 0.0, 1.0);
+    return color;
+}
@@ -1,24 +1,77 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) color: vec3<f32>,
+    @location(1) color: vec4<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec3<f32>,
+    @location(0) color: vec4<f32>,
 };
 
+// Translation applied to every vertex, used to scroll the document without re-tessellating
+struct Offset {
+    offset: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> offset: Offset;
+
+// How colors get written to the swapchain
+struct Swapchain {
+    // Non-zero when ordered dithering breaks up banding in flat colors
+    dither: u32,
+    // See `OutputEncoding` in `renderer.rs`
+    encoding: u32,
+    _padding0: u32,
+    _padding1: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> swapchain: Swapchain;
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if (x <= 0.0031308) {
+        return max(x, 0.0) * 12.92;
+    }
+    return 1.055 * pow(min(x, 1.0), 1.0 / 2.4) - 0.055;
+}
+
+// Colors are linear up until here
+fn encode(color: vec4<f32>) -> vec4<f32> {
+    if (swapchain.encoding == 1u) {
+        return clamp(color, vec4<f32>(0.0), vec4<f32>(1.0));
+    } else if (swapchain.encoding == 2u) {
+        return vec4<f32>(linear_to_srgb(color.r), linear_to_srgb(color.g), linear_to_srgb(color.b), color.a);
+    }
+    return color;
+}
+
+// Threshold from a 4x4 Bayer matrix in the range [-0.5, 0.5)
+fn bayer4(pixel: vec2<u32>) -> f32 {
+    let x = pixel.x % 4u;
+    let y = pixel.y % 4u;
+    let a = x ^ y;
+    let index = ((a & 1u) << 3u) | ((y & 1u) << 2u) | (a & 2u) | ((y & 2u) >> 1u);
+    return (f32(index) + 0.5) / 16.0 - 0.5;
+}
+
 @vertex
 fn vs_main(
     model: VertexInput,
 ) -> VertexOutput {
     var out: VertexOutput;
     out.color = model.color;
-    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.clip_position = vec4<f32>(model.position + offset.offset.xyz, 1.0);
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    var color = encode(in.color);
+    if (swapchain.dither != 0u) {
+        // Fragment positions are at pixel centers
+        let pixel = vec2<u32>(in.clip_position.xy);
+        color = vec4<f32>(color.rgb + vec3<f32>(bayer4(pixel) / 255.0), color.a);
+    }
+    return color;
 }
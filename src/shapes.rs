@@ -0,0 +1,423 @@
+use std::cell::Cell;
+
+use wgpu_glyph::GlyphCruncher;
+
+use crate::color::{CodeTheme, RuleStyle, Theme};
+use crate::positioner::{DEFAULT_MARGIN, DEFAULT_PADDING};
+use crate::table::{Table, TABLE_ROW_GAP};
+use crate::text::{Bullet, Checkbox, ListMarker, TextBox};
+use crate::utils::{Point, Rect, Size};
+
+// Width of the bar to the left of quote blocks in logical pixels
+pub(crate) const QUOTE_BAR_WIDTH: f32 = 5.;
+
+// Horizontal distance between the bar of the innermost quote and the bar `level` nestings out
+pub(crate) fn quote_indent(level: usize) -> f32 {
+    level as f32 * DEFAULT_MARGIN / 2.
+}
+
+/// Pieces of a horizontal rule between `left` and `right` that's vertically centered on `middle`.
+/// Sizes from the theme get multiplied by `scale`
+pub(crate) fn rule_segments(
+    theme: &Theme,
+    left: f32,
+    right: f32,
+    middle: f32,
+    scale: f32,
+) -> Vec<Rect> {
+    let thickness = (theme.rule_thickness * scale).max(1.);
+    let span = (right - left).max(0.);
+    let width = span * theme.rule_width.clamp(0., 1.);
+    let start = left + (span - width) / 2.;
+    let end = start + width;
+    let top = middle - thickness / 2.;
+    let (dash, gap) = match theme.rule_style {
+        RuleStyle::Solid => return vec![Rect::new((start, top), (width, thickness))],
+        RuleStyle::Dashed => (4. * thickness, 2. * thickness),
+        RuleStyle::Dotted => (thickness, thickness),
+    };
+    let mut segments = Vec::new();
+    let mut x = start;
+    while x < end {
+        segments.push(Rect::new((x, top), (dash.min(end - x), thickness)));
+        x += dash + gap;
+    }
+    segments
+}
+
+/// Something drawn around or behind the text of a block like a background, a quote bar or a
+/// checkbox. The window tessellates these and the SVG export writes them out, so both draw the
+/// same thing
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Rect(Rect, [f32; 4]),
+    /// The outline of a rectangle centered on its edge
+    StrokeRect {
+        rect: Rect,
+        color: [f32; 4],
+        width: f32,
+    },
+    Circle {
+        center: Point,
+        radius: f32,
+        color: [f32; 4],
+    },
+    /// The outline of a circle centered on `radius`
+    Ring {
+        center: Point,
+        radius: f32,
+        color: [f32; 4],
+        width: f32,
+    },
+    Polygon(Vec<Point>, [f32; 4]),
+    /// Connected line segments that are left open
+    Polyline {
+        points: Vec<Point>,
+        color: [f32; 4],
+        width: f32,
+    },
+}
+
+/// What the shapes of a block depend on besides the block itself
+pub struct ShapeContext<'a> {
+    pub theme: &'a Theme,
+    pub code_theme: CodeTheme,
+    /// Physical pixels per logical pixel, so the hidpi scale times the zoom
+    pub scale: f32,
+    /// The zoom that padding and margins scale with
+    pub zoom: f32,
+    /// The zoom that text gets laid out with, which includes the text scale
+    pub text_zoom: f32,
+    /// Shapes that would stick out past this get cut off or left out
+    pub content_edge: f32,
+}
+
+impl ShapeContext<'_> {
+    /// The background, quote bars, highlights, checkbox, bullet and lines of a text box. `bounds`
+    /// are the element's and `text_bounds` the bounds its text was laid out within. `gutter` is
+    /// the room the positioner made for line numbers
+    pub fn text_box<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        text_box: &TextBox,
+        bounds: &Rect,
+        text_bounds: Size,
+        gutter: f32,
+    ) -> Vec<Shape> {
+        let theme = self.theme;
+        let Rect { pos, size } = bounds;
+        let mut shapes = Vec::new();
+        let block_left = pos.0 - gutter;
+        let padding = text_box.block_padding(self.zoom);
+        // Blocks extend down through the gap to the next element so that consecutive lines of a
+        // block form one background
+        let block_bottom = pos.1 + size.1 + DEFAULT_PADDING * self.scale;
+
+        if text_box.has_background() {
+            let color = if let Some(bg_color) = text_box.background_color {
+                bg_color
+            } else if text_box.is_code_block {
+                self.code_theme.background.unwrap_or(theme.code_block_color)
+            } else if let Some(kind) = text_box.admonition {
+                theme.admonition_background(kind)
+            } else {
+                theme.quote_block_color
+            };
+            let color = match text_box.diff {
+                Some(change) => theme.diff_background(change, color),
+                None => color,
+            };
+            // Quotes extend left to the bar of their outermost level
+            let left = match text_box.is_quote_block {
+                Some(nest) => block_left - padding - quote_indent(nest - 1),
+                None => block_left - padding,
+            };
+            let right = (pos.0 + text_bounds.0 + padding).min(self.content_edge);
+            if left < right {
+                let background = Rect::from_min_max((left, pos.1), (right, block_bottom));
+                shapes.push(Shape::Rect(background, color));
+            }
+        }
+        if let Some(nest) = text_box.is_quote_block {
+            let bar_width = QUOTE_BAR_WIDTH * self.scale;
+            for n in 0..nest {
+                // The innermost bar takes the accent color of a callout
+                let color = match text_box.admonition {
+                    Some(kind) if n == 0 => theme.admonition_color(kind),
+                    _ => theme.quote_bar_color(nest - 1 - n),
+                };
+                let bar_right = block_left - padding - quote_indent(n);
+                let bar = Rect::from_min_max(
+                    ((bar_right - bar_width).min(self.content_edge), pos.1),
+                    (bar_right.min(self.content_edge), block_bottom),
+                );
+                shapes.push(Shape::Rect(bar, color));
+            }
+        }
+
+        let highlights =
+            text_box.text_highlights(glyph_brush, *pos, text_bounds, self.text_zoom, |change| {
+                theme.change_color(change)
+            });
+        shapes.extend(
+            highlights
+                .into_iter()
+                .map(|(rect, color)| Shape::Rect(rect, color)),
+        );
+
+        let checkbox = text_box.is_checkbox.as_ref().map(Cell::get);
+        let checkbox_bounds = text_box.checkbox_bounds(bounds, self.zoom);
+        if let (Some(checkbox), Some(checkbox_bounds)) = (checkbox, checkbox_bounds) {
+            if checkbox_bounds.max().0 < self.content_edge {
+                self.checkbox(checkbox, checkbox_bounds, &mut shapes);
+            }
+        }
+
+        if let Some(ListMarker::Bullet(bullet)) = text_box.list_marker {
+            let (center, radius) =
+                text_box.bullet_position(glyph_brush, *pos, text_bounds, self.text_zoom);
+            shapes.push(self.bullet(bullet, center, radius));
+        }
+
+        for (line, color) in text_box.render_lines(glyph_brush, *pos, text_bounds, self.text_zoom) {
+            let line = Rect::from_min_max(
+                (line.pos.0.min(self.content_edge).max(pos.0), line.pos.1),
+                (line.max().0.min(self.content_edge).max(pos.0), line.max().1),
+            );
+            shapes.push(Shape::Rect(line, color));
+        }
+        shapes
+    }
+
+    fn checkbox(&self, checkbox: Checkbox, bounds: Rect, shapes: &mut Vec<Shape>) {
+        let (min, box_size) = (bounds.pos, bounds.size.0);
+        let text_color = self.theme.text_color;
+        if checkbox != Checkbox::Unchecked {
+            shapes.push(Shape::Rect(bounds.clone(), self.theme.checkbox_color));
+        }
+        match checkbox {
+            Checkbox::Checked => shapes.push(Shape::Polyline {
+                points: vec![
+                    (min.0 + box_size * 0.2, min.1 + box_size * 0.5),
+                    (min.0 + box_size * 0.4, min.1 + box_size * 0.7),
+                    (min.0 + box_size * 0.8, min.1 + box_size * 0.2),
+                ],
+                color: text_color,
+                width: 4.,
+            }),
+            Checkbox::Indeterminate => {
+                let thickness = 2. * self.scale;
+                let dash = Rect::new(
+                    (
+                        min.0 + box_size * 0.2,
+                        min.1 + box_size / 2. - thickness / 2.,
+                    ),
+                    (box_size * 0.6, thickness),
+                );
+                shapes.push(Shape::Rect(dash, text_color));
+            }
+            Checkbox::Unchecked => {}
+        }
+        shapes.push(Shape::StrokeRect {
+            rect: bounds,
+            color: text_color,
+            width: 2.,
+        });
+    }
+
+    fn bullet(&self, bullet: Bullet, center: Point, radius: f32) -> Shape {
+        let color = self.theme.text_color;
+        match bullet {
+            Bullet::Disc => Shape::Circle {
+                center,
+                radius,
+                color,
+            },
+            Bullet::Circle => {
+                let width = (radius / 2.).max(1.);
+                Shape::Ring {
+                    center,
+                    radius: radius - width / 2.,
+                    color,
+                    width,
+                }
+            }
+            Bullet::Square => {
+                let side = radius * 1.8;
+                let min = (center.0 - side / 2., center.1 - side / 2.);
+                Shape::Rect(Rect::new(min, (side, side)), color)
+            }
+        }
+    }
+
+    /// The segments of a horizontal rule centered in `bounds`
+    pub fn rule(&self, bounds: &Rect) -> Vec<Shape> {
+        let color = self.theme.rule_color.unwrap_or(self.theme.text_color);
+        let middle = bounds.pos.1 + bounds.size.1 / 2.;
+        rule_segments(
+            self.theme,
+            DEFAULT_MARGIN,
+            self.content_edge,
+            middle,
+            self.scale,
+        )
+        .into_iter()
+        .map(|segment| Shape::Rect(segment, color))
+        .collect()
+    }
+
+    /// The dividers under the header and each row of a table at `pos` that's `width` wide
+    pub fn table_dividers(
+        &self,
+        table: &Table,
+        pos: Point,
+        width: f32,
+        row_heights: &[f32],
+    ) -> Vec<Shape> {
+        // Cells with text around a nested table are drawn as a table of their own, but without
+        // the look of one
+        if table.stacked {
+            return Vec::new();
+        }
+        let thickness = 3. * self.scale;
+        let divider = |y: f32, color| {
+            let min = (pos.0.min(self.content_edge), pos.1 + y);
+            let max = (
+                (pos.0 + width).max(pos.0).min(self.content_edge),
+                pos.1 + y + thickness,
+            );
+            Shape::Rect(Rect::from_min_max(min, max), color)
+        };
+        let mut y = row_heights.first().copied().unwrap_or_default() + TABLE_ROW_GAP / 2.;
+        let mut dividers = vec![divider(y, self.theme.text_color)];
+        y += TABLE_ROW_GAP / 2.;
+        for height in row_heights.iter().skip(1).take(table.rows.len()) {
+            y += height + TABLE_ROW_GAP / 2.;
+            dividers.push(divider(y, self.theme.code_block_color));
+            y += TABLE_ROW_GAP / 2.;
+        }
+        dividers
+    }
+
+    /// The triangle next to the summary of a collapsible section. It points right when the
+    /// section is collapsed and down when it's expanded
+    pub fn section_marker(&self, summary: &Rect, hidden: bool) -> Shape {
+        let (x, y) = (
+            summary.pos.0 - 5. * self.scale,
+            summary.pos.1 + summary.size.1 / 2.,
+        );
+        let size = 10.;
+        let points = if hidden {
+            vec![(x, y), (x - size, y + size), (x - size, y - size)]
+        } else {
+            vec![
+                (x, y - size / 2.),
+                (x - size * 2., y - size / 2.),
+                (x - size, y + size / 2.),
+            ]
+        };
+        Shape::Polygon(points, self.theme.text_color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::{rule_segments, Shape, ShapeContext};
+    use crate::color::{CodeTheme, RuleStyle, Theme, DARK_DEFAULT};
+    use crate::text::{tests::NoGlyphs, Checkbox, Text, TextBox};
+    use crate::utils::Rect;
+
+    #[test]
+    fn quoted_tasks_get_their_block_and_checkbox() {
+        let context = ShapeContext {
+            theme: &DARK_DEFAULT,
+            code_theme: CodeTheme::from(&DARK_DEFAULT),
+            scale: 1.,
+            zoom: 1.,
+            text_zoom: 1.,
+            content_edge: 800.,
+        };
+        let mut text_box = TextBox::new(vec![Text::new("task".to_owned(), 1., [1.; 4])], 1.);
+        text_box.is_quote_block = Some(1);
+        text_box.is_checkbox = Some(Cell::new(Checkbox::Checked));
+        let bounds = Rect::new((100., 100.), (200., 20.));
+        let shapes = context.text_box(&mut NoGlyphs, &text_box, &bounds, (200., 20.), 0.);
+
+        let fills: Vec<_> = shapes
+            .iter()
+            .filter_map(|shape| match shape {
+                Shape::Rect(_, color) => Some(*color),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            fills,
+            [
+                DARK_DEFAULT.quote_block_color,
+                DARK_DEFAULT.quote_bar_color(0),
+                DARK_DEFAULT.checkbox_color,
+            ]
+        );
+        assert!(matches!(shapes[3], Shape::Polyline { .. }));
+        assert!(matches!(shapes[4], Shape::StrokeRect { .. }));
+
+        // Checkboxes that would stick out past the content are left out
+        let context = ShapeContext {
+            content_edge: 90.,
+            ..context
+        };
+        let shapes = context.text_box(&mut NoGlyphs, &text_box, &bounds, (200., 20.), 0.);
+        assert!(shapes.iter().all(|shape| matches!(shape, Shape::Rect(..))));
+    }
+
+    #[test]
+    fn text_scale_leaves_decorations_alone() {
+        let context = |text_zoom| ShapeContext {
+            theme: &DARK_DEFAULT,
+            code_theme: CodeTheme::from(&DARK_DEFAULT),
+            scale: 1.,
+            zoom: 1.,
+            text_zoom,
+            content_edge: 800.,
+        };
+        let mut text_box = TextBox::new(vec![Text::new("task".to_owned(), 1., [1.; 4])], 1.);
+        text_box.is_quote_block = Some(1);
+        text_box.is_checkbox = Some(Cell::new(Checkbox::Unchecked));
+        let bounds = Rect::new((100., 100.), (200., 20.));
+        let shapes = |text_zoom| {
+            context(text_zoom).text_box(&mut NoGlyphs, &text_box, &bounds, (200., 20.), 0.)
+        };
+        assert_eq!(shapes(1.), shapes(2.));
+    }
+
+    #[test]
+    fn rules_split_into_segments_by_style() {
+        let theme = |rule_style, rule_width| Theme {
+            rule_thickness: 2.,
+            rule_width,
+            rule_style,
+            ..DARK_DEFAULT
+        };
+
+        let solid = rule_segments(&theme(RuleStyle::Solid, 1.), 0., 100., 50., 1.);
+        assert_eq!(solid.len(), 1);
+        assert_eq!(solid[0].pos, (0., 49.));
+        assert_eq!(solid[0].size, (100., 2.));
+
+        let half = rule_segments(&theme(RuleStyle::Solid, 0.5), 0., 100., 50., 1.);
+        assert_eq!(half[0].pos.0, 25.);
+        assert_eq!(half[0].size.0, 50.);
+
+        // 8px dashes with 4px gaps where the last one gets cut short at the end
+        let dashed = rule_segments(&theme(RuleStyle::Dashed, 1.), 0., 100., 50., 1.);
+        assert_eq!(dashed.len(), 9);
+        assert!(dashed.iter().all(|dash| dash.size.0 <= 8.));
+        assert_eq!(dashed.last().unwrap().max().0, 100.);
+
+        let dotted = rule_segments(&theme(RuleStyle::Dotted, 1.), 0., 100., 50., 2.);
+        assert_eq!(dotted.len(), 13);
+        assert!(dotted.iter().all(|dot| dot.size == (4., 4.)));
+    }
+}
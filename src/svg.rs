@@ -0,0 +1,581 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use glyph_brush::{GlyphBrush, GlyphBrushBuilder};
+use wgpu_glyph::ab_glyph::FontArc;
+use wgpu_glyph::GlyphCruncher;
+
+use crate::color::{linear_rgba_to_hex, CodeTheme, Theme};
+use crate::fonts;
+use crate::image::{Image, ImageDecoder};
+use crate::interpreter::{HtmlInterpreter, InterpreterOptions, WindowInteractor};
+use crate::opts::{FontOptions, Opts};
+use crate::positioner::{Positioned, Positioner, Section, DEFAULT_MARGIN};
+use crate::shapes::{Shape, ShapeContext};
+use crate::table::{Table, TableLayout, TABLE_COL_GAP};
+use crate::text::{ListMarker, Text, TextBox, ELLIPSIS, REGULAR_WEIGHT};
+use crate::utils::{self, Point, Rect};
+use crate::Element;
+
+/// Width (in logical pixels) that documents get exported at
+pub const DEFAULT_EXPORT_WIDTH: f32 = 1000.;
+
+// Images that fail to load never finish decoding, so exports only wait this long for them
+const IMAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Draws documents as SVG. Everything is laid out by the same [`Positioner`] as on screen, but
+/// only the fonts are needed to do so, so no GPU (or window) is involved
+pub struct SvgRenderer {
+    glyph_brush: GlyphBrush<()>,
+    positioner: Positioner,
+    theme: Theme,
+    pub code_theme: CodeTheme,
+    // Families of the regular and the monospace fonts
+    font_families: [String; 2],
+}
+
+impl SvgRenderer {
+    /// Lays documents out `width` logical pixels wide. The elements drawn should be made with a
+    /// scale of 1
+    pub fn new(width: f32, theme: Theme, fonts: Vec<FontArc>, font_opts: &FontOptions) -> Self {
+        let family = |name: &Option<String>, generic: &str| match name {
+            Some(name) => format!("'{}', {}", name, generic),
+            None => generic.to_owned(),
+        };
+        Self {
+            glyph_brush: GlyphBrushBuilder::using_fonts(fonts).build(),
+            positioner: Positioner::new((width, width), 1.),
+            code_theme: CodeTheme::from(&theme),
+            theme,
+            font_families: [
+                family(&font_opts.regular_font, "sans-serif"),
+                family(&font_opts.monospace_font, "monospace"),
+            ],
+        }
+    }
+
+    fn width(&self) -> f32 {
+        self.positioner.screen_size.0
+    }
+
+    // Tables that don't wrap their cells can be wider than the page like on screen
+    fn table_content_edge(&self) -> f32 {
+        (self.width() - DEFAULT_MARGIN).max(self.positioner.reserved_width)
+    }
+
+    // Shapes are drawn the same as on screen at a scale of 1
+    fn shape_context(&self, content_edge: f32) -> ShapeContext<'_> {
+        ShapeContext {
+            theme: &self.theme,
+            code_theme: self.code_theme,
+            scale: 1.,
+            zoom: 1.,
+            text_zoom: 1.,
+            content_edge,
+        }
+    }
+
+    /// Positions `elements` and draws them into an SVG document as tall as they need
+    pub fn render(&mut self, elements: &mut [Positioned<Element>]) -> anyhow::Result<String> {
+        self.positioner
+            .reposition(&mut self.glyph_brush, elements, 1.)?;
+        let (width, height) = (self.width(), self.positioner.reserved_height);
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+            width, height
+        )?;
+        let wgpu::Color { r, g, b, a } = self.theme.background_color;
+        let background = [r as f32, g as f32, b as f32, a as f32];
+        draw_rect(&mut svg, &Rect::new((0., 0.), (width, height)), background)?;
+        self.render_elements(&mut svg, elements, None)?;
+        svg.push_str("</svg>\n");
+        Ok(svg)
+    }
+
+    fn render_elements(
+        &mut self,
+        svg: &mut String,
+        elements: &[Positioned<Element>],
+        in_table: Option<TableLayout>,
+    ) -> anyhow::Result<()> {
+        for element in elements {
+            let bounds = element.bounds.as_ref().context("Element not positioned")?;
+            match &element.inner {
+                Element::TextBox(text_box) => {
+                    self.render_text_box(svg, text_box, bounds, in_table)?
+                }
+                Element::Image(image) => draw_image(svg, image, bounds)?,
+                Element::Spacer(spacer) => {
+                    if spacer.visibile {
+                        let content_edge = self.width() - DEFAULT_MARGIN;
+                        draw_shapes(svg, self.shape_context(content_edge).rule(bounds))?;
+                    }
+                }
+                Element::Table(table) => self.render_table(svg, table, bounds.pos)?,
+                Element::Row(row) => self.render_elements(svg, &row.elements, None)?,
+                Element::Section(section) => self.render_section(svg, section)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn render_text_box(
+        &mut self,
+        svg: &mut String,
+        text_box: &TextBox,
+        bounds: &Rect,
+        in_table: Option<TableLayout>,
+    ) -> anyhow::Result<()> {
+        let Rect { pos, size } = bounds;
+        let content_edge = self.width() - DEFAULT_MARGIN;
+        let available = (content_edge - pos.0).max(0.);
+        let mut text_bounds = if let Some(layout) = in_table {
+            (layout.cell_width(size.0, available), f32::INFINITY)
+        } else {
+            (text_box.wrap_width(available, 1.), f32::INFINITY)
+        };
+        let ellipsis = text_box.ellipsis(&mut self.glyph_brush, *pos, text_bounds, 1.);
+        if let Some((clipped, _)) = ellipsis {
+            text_bounds = clipped;
+        }
+
+        // Same as `shape_context` which can't borrow all of `self` with the glyph brush in use
+        let shapes = ShapeContext {
+            theme: &self.theme,
+            code_theme: self.code_theme,
+            scale: 1.,
+            zoom: 1.,
+            text_zoom: 1.,
+            content_edge: match in_table {
+                Some(_) => self.table_content_edge(),
+                None => content_edge,
+            },
+        }
+        .text_box(&mut self.glyph_brush, text_box, bounds, text_bounds, 0.);
+        draw_shapes(svg, shapes)?;
+
+        if let Some(ListMarker::Number(number)) = text_box.list_marker {
+            let number = format!("{}.", number);
+            let color = self.theme.text_color;
+            let section = text_box.list_number_section(&number, *pos, 1., color);
+            let glyph = self.glyph_brush.glyphs(section).next().map(|glyph| {
+                let position = (glyph.glyph.position.x, glyph.glyph.position.y);
+                (position, glyph.glyph.scale.y)
+            });
+            if let Some((position, font_size)) = glyph {
+                let text = Text::new(number.clone(), text_box.hidpi_scale, color).make_bold(true);
+                self.draw_text(svg, &text, &number, position, font_size)?;
+            }
+        }
+
+        let glyphs = text_box.glyph_bounds(&mut self.glyph_brush, *pos, text_bounds, 1.);
+        // Consecutive glyphs of the same text on the same line are drawn as one run. The byte
+        // range covers any whitespace between them that wasn't given a glyph. Grids place each
+        // glyph themselves, so they don't get merged
+        let mut runs: Vec<(usize, usize, usize, Point, f32)> = Vec::new();
+        for (glyph_bounds, glyph) in &glyphs {
+            let text = &text_box.texts[glyph.section_index];
+            if let Some(inline_image) = &text.inline_image {
+                draw_image(svg, &inline_image.image, glyph_bounds)?;
+                continue;
+            }
+            let char_len = text.text[glyph.byte_index..]
+                .chars()
+                .next()
+                .map_or(0, char::len_utf8);
+            let position = (glyph.glyph.position.x, glyph.glyph.position.y);
+            match runs.last_mut() {
+                Some((section_index, _, end, start, _))
+                    if !text_box.monospace_grid
+                        && *section_index == glyph.section_index
+                        && start.1 == position.1 =>
+                {
+                    *end = glyph.byte_index + char_len;
+                }
+                _ => runs.push((
+                    glyph.section_index,
+                    glyph.byte_index,
+                    glyph.byte_index + char_len,
+                    position,
+                    glyph.glyph.scale.y,
+                )),
+            }
+        }
+        for (section_index, start, end, position, font_size) in runs {
+            let text = &text_box.texts[section_index];
+            self.draw_text(svg, text, &text.text[start..end], position, font_size)?;
+        }
+        if let (Some((_, ellipsis_pos)), Some(first)) = (ellipsis, text_box.texts.first()) {
+            let section = text_box.ellipsis_section(ellipsis_pos, 1.);
+            let glyph = self.glyph_brush.glyphs(section).next().map(|glyph| {
+                let position = (glyph.glyph.position.x, glyph.glyph.position.y);
+                (position, glyph.glyph.scale.y)
+            });
+            if let Some((position, font_size)) = glyph {
+                self.draw_text(svg, first, ELLIPSIS, position, font_size)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Draws `content` in the style of `text` with its baseline starting at `position`
+    fn draw_text(
+        &self,
+        svg: &mut String,
+        text: &Text,
+        content: &str,
+        (x, y): Point,
+        font_size: f32,
+    ) -> anyhow::Result<()> {
+        let family = if text.font == fonts::MONOSPACE_FONT {
+            &self.font_families[1]
+        } else {
+            &self.font_families[0]
+        };
+        write!(
+            svg,
+            r#"<text x="{}" y="{}" font-family="{}" font-size="{}""#,
+            x,
+            y,
+            escape(family),
+            font_size
+        )?;
+        // SVG viewers can pick the exact weight instead of only the regular or bold face
+        if text.weight() != REGULAR_WEIGHT {
+            write!(svg, r#" font-weight="{}""#, text.weight())?;
+        }
+        if text.is_italic {
+            svg.push_str(r#" font-style="italic""#);
+        }
+        if text.letter_spacing != 0. {
+            write!(
+                svg,
+                r#" letter-spacing="{}""#,
+                text.letter_spacing * font_size
+            )?;
+        }
+        writeln!(
+            svg,
+            r#" {} xml:space="preserve">{}</text>"#,
+            fill(text.color()),
+            escape(content)
+        )?;
+        Ok(())
+    }
+
+    fn render_table(&mut self, svg: &mut String, table: &Table, pos: Point) -> anyhow::Result<()> {
+        let bounds = (self.width() - pos.0 - DEFAULT_MARGIN, f32::INFINITY);
+        let row_heights = table.row_heights(&mut self.glyph_brush, pos, bounds, 1.);
+        let width: f32 = table
+            .column_widths(&mut self.glyph_brush, pos, bounds, 1.)
+            .iter()
+            .map(|width| width + TABLE_COL_GAP)
+            .sum();
+        let dividers = self
+            .shape_context(self.table_content_edge())
+            .table_dividers(table, pos, width, &row_heights);
+        draw_shapes(svg, dividers)?;
+
+        self.render_elements(svg, &table.headers, Some(table.layout))?;
+        for row in &table.rows {
+            self.render_elements(svg, row, Some(table.layout))?;
+        }
+        Ok(())
+    }
+
+    fn render_section(&mut self, svg: &mut String, section: &Section) -> anyhow::Result<()> {
+        let hidden = *section.hidden.borrow();
+        if let Some(summary) = &*section.summary {
+            let bounds = summary.bounds.as_ref().context("Summary not positioned")?;
+            let content_edge = self.width() - DEFAULT_MARGIN;
+            let marker = self
+                .shape_context(content_edge)
+                .section_marker(bounds, hidden);
+            draw_shapes(svg, vec![marker])?;
+            self.render_elements(svg, std::slice::from_ref(summary), None)?;
+        }
+        if !hidden {
+            self.render_elements(svg, &section.elements, None)?;
+        }
+        Ok(())
+    }
+}
+
+// The interpreter only asks for redraws, which don't mean anything without a window
+struct NoWindow;
+
+impl WindowInteractor for NoWindow {
+    fn request_redraw(&self) {}
+}
+
+/// Parses the markdown file from `opts` and draws it as SVG `width` logical pixels wide. Images
+/// that don't load in time are left out
+pub fn export(opts: &Opts, width: f32) -> anyhow::Result<String> {
+    let mut elements = load_elements(opts, 1.)?;
+    let fonts = fonts::get_fonts(&opts.font_opts)?;
+    let mut renderer = SvgRenderer::new(width, opts.theme.clone(), fonts, &opts.font_opts);
+    renderer.code_theme = opts.code_theme;
+    renderer.positioner.spacing = opts.spacing;
+    renderer.render(&mut elements)
+}
+
+// Parses the markdown file from `opts` without a window and waits for its images to load for
+// exports
+pub(crate) fn load_elements(
+    opts: &Opts,
+    hidpi_scale: f32,
+) -> anyhow::Result<Vec<Positioned<Element>>> {
+    let md_string = utils::read_markdown(&opts.file_path)?;
+    let element_queue = Arc::new(Mutex::new(VecDeque::new()));
+    let interpreter = HtmlInterpreter::new(
+        Arc::new(NoWindow),
+        element_queue.clone(),
+        InterpreterOptions::from(opts),
+        hidpi_scale,
+        opts.file_path.clone(),
+        Arc::new(Mutex::new(HashMap::new())),
+        ImageDecoder::new(),
+    );
+    let (interpreter_sender, interpreter_reciever) = mpsc::channel();
+    interpreter_sender.send(md_string)?;
+    // The interpreter stops once every sent document is done
+    drop(interpreter_sender);
+    interpreter.intepret_md(interpreter_reciever);
+    let elements: Vec<_> = element_queue
+        .lock()
+        .unwrap()
+        .drain(..)
+        .map(Positioned::new)
+        .collect();
+
+    let mut images = Vec::new();
+    collect_images(&elements, &mut images);
+    let started = Instant::now();
+    while images
+        .iter()
+        .any(|image| image.image.lock().unwrap().is_none())
+        && started.elapsed() < IMAGE_TIMEOUT
+    {
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(elements)
+}
+
+fn collect_images<'a>(elements: &'a [Positioned<Element>], images: &mut Vec<&'a Image>) {
+    for element in elements {
+        match &element.inner {
+            Element::Image(image) => images.push(image),
+            Element::TextBox(text_box) => images.extend(
+                text_box
+                    .texts
+                    .iter()
+                    .filter_map(|text| text.inline_image.as_ref())
+                    .map(|inline_image| &inline_image.image),
+            ),
+            Element::Row(row) => collect_images(&row.elements, images),
+            Element::Section(section) => {
+                if let Some(summary) = &*section.summary {
+                    collect_images(std::slice::from_ref(summary), images);
+                }
+                collect_images(&section.elements, images);
+            }
+            Element::Table(table) => {
+                collect_images(&table.headers, images);
+                for row in &table.rows {
+                    collect_images(row, images);
+                }
+            }
+            Element::Spacer(_) => {}
+        }
+    }
+}
+
+fn draw_shapes(svg: &mut String, shapes: Vec<Shape>) -> anyhow::Result<()> {
+    let points = |points: &[Point]| -> String {
+        let points: Vec<String> = points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+        points.join(" ")
+    };
+    for shape in shapes {
+        match shape {
+            Shape::Rect(rect, color) => draw_rect(svg, &rect, color)?,
+            Shape::StrokeRect { rect, color, width } => writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" {} stroke-width="{}"/>"#,
+                rect.pos.0,
+                rect.pos.1,
+                rect.size.0,
+                rect.size.1,
+                stroke(color),
+                width
+            )?,
+            Shape::Circle {
+                center,
+                radius,
+                color,
+            } => writeln!(
+                svg,
+                r#"<circle cx="{}" cy="{}" r="{}" {}/>"#,
+                center.0,
+                center.1,
+                radius,
+                fill(color)
+            )?,
+            Shape::Ring {
+                center,
+                radius,
+                color,
+                width,
+            } => writeln!(
+                svg,
+                r#"<circle cx="{}" cy="{}" r="{}" fill="none" {} stroke-width="{}"/>"#,
+                center.0,
+                center.1,
+                radius,
+                stroke(color),
+                width
+            )?,
+            Shape::Polygon(polygon, color) => writeln!(
+                svg,
+                r#"<polygon points="{}" {}/>"#,
+                points(&polygon),
+                fill(color)
+            )?,
+            Shape::Polyline {
+                points: polyline,
+                color,
+                width,
+            } => writeln!(
+                svg,
+                r#"<polyline points="{}" fill="none" {} stroke-width="{}"/>"#,
+                points(&polyline),
+                stroke(color),
+                width
+            )?,
+        }
+    }
+    Ok(())
+}
+
+fn draw_rect(svg: &mut String, rect: &Rect, color: [f32; 4]) -> anyhow::Result<()> {
+    writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" {}/>"#,
+        rect.pos.0,
+        rect.pos.1,
+        rect.size.0,
+        rect.size.1,
+        fill(color)
+    )?;
+    Ok(())
+}
+
+// Images keep their aspect ratio within their bounds which matches SVG's default
+fn draw_image(svg: &mut String, image: &Image, bounds: &Rect) -> anyhow::Result<()> {
+    let png = match &*image.image.lock().unwrap() {
+        Some(image_data) => image_data.to_png()?,
+        None => return Ok(()),
+    };
+    let Rect { pos, size } = bounds;
+    writeln!(
+        svg,
+        r#"<image x="{}" y="{}" width="{}" height="{}" xlink:href="data:image/png;base64,{}"/>"#,
+        pos.0,
+        pos.1,
+        size.0,
+        size.1,
+        base64(&png)
+    )?;
+    Ok(())
+}
+
+fn fill(color: [f32; 4]) -> String {
+    paint("fill", color)
+}
+
+fn stroke(color: [f32; 4]) -> String {
+    paint("stroke", color)
+}
+
+// Colors are kept in linear RGB while SVG expects sRGB
+fn paint(attribute: &str, color: [f32; 4]) -> String {
+    let mut paint = format!(r##"{}="#{:06x}""##, attribute, linear_rgba_to_hex(color));
+    if color[3] < 1. {
+        let _ = write!(paint, r#" {}-opacity="{}""#, attribute, color[3]);
+    }
+    paint
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64, escape, paint};
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape(r#"<a href="x">&'"#),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&apos;"
+        );
+    }
+
+    #[test]
+    fn paint_is_srgb() {
+        assert_eq!(paint("fill", [1., 1., 1., 1.]), r##"fill="#ffffff""##);
+        assert_eq!(
+            paint("fill", [0., 0., 0., 0.5]),
+            r##"fill="#000000" fill-opacity="0.5""##
+        );
+    }
+}
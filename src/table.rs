@@ -1,78 +1,308 @@
+use std::{cell::RefCell, iter};
+
+use serde::Deserialize;
 use wgpu_glyph::GlyphCruncher;
 
 use crate::{
-    text::{Text, TextBox},
-    utils::{Point, Rect, Size},
+    positioner::{Positioned, RowWidth},
+    text::TextBox,
+    utils::{Align, Point, Rect, Size},
+    Element,
 };
 
 pub const TABLE_ROW_GAP: f32 = 20.;
 pub const TABLE_COL_GAP: f32 = 20.;
 
+/// How the columns of a table fit into the space that's available
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TableLayout {
+    /// Cells wrap their text so that the table fits on screen
+    #[default]
+    Wrap,
+    /// Columns are as wide as their widest unwrapped cell. The table can overflow past the edge
+    /// of the screen with the document scrolling sideways to show the rest
+    Expand,
+}
+
+impl TableLayout {
+    /// Parses the value of a table's `data-layout` attribute
+    pub fn from_attr(value: &str) -> Option<Self> {
+        match value {
+            "wrap" => Some(Self::Wrap),
+            "expand" => Some(Self::Expand),
+            _ => None,
+        }
+    }
+
+    /// The bounds that cells get measured within given the bounds of the table
+    pub fn measure_bounds(self, bounds: Size) -> Size {
+        match self {
+            Self::Wrap => bounds,
+            Self::Expand => (f32::INFINITY, bounds.1),
+        }
+    }
+
+    /// Width the text of a cell wraps at given the width of its column and the space that's left
+    /// before the edge of the screen
+    pub fn cell_width(self, column_width: f32, available: f32) -> f32 {
+        match self {
+            Self::Wrap => available.min(column_width),
+            Self::Expand => column_width,
+        }
+    }
+}
+
+/// What happens to the text of cells that don't fit within a column's maximum width
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColumnOverflow {
+    /// The text wraps onto more lines
+    #[default]
+    Wrap,
+    /// The text stays on one line and gets cut off with an ellipsis. The full text still gets
+    /// copied with the selection and shows up when hovering over the cell
+    Truncate,
+}
+
+/// Caps how wide the columns of a table can get
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ColumnLimit {
+    /// Maximum width of a column in logical pixels. Columns are as wide as they need to be
+    /// without it
+    pub max_width: Option<f32>,
+    pub overflow: ColumnOverflow,
+}
+
+impl ColumnLimit {
+    /// Narrows the bounds that cells get measured within down to the maximum column width
+    pub fn clamp(self, bounds: Size, scale: f32) -> Size {
+        match self.max_width {
+            Some(max_width) => (bounds.0.min(max_width * scale), bounds.1),
+            None => bounds,
+        }
+    }
+
+    fn truncates(self) -> bool {
+        self.max_width.is_some() && self.overflow == ColumnOverflow::Truncate
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Table {
-    pub headers: Vec<TextBox>,
-    pub rows: Vec<Vec<TextBox>>,
+    pub headers: Vec<Positioned<Element>>,
+    pub rows: Vec<Vec<Positioned<Element>>>,
+    /// Alignment of each column's text taken from the header cells
+    pub alignments: Vec<Align>,
+    /// Widths that columns asked for with the `width` of their cells. Flexible columns share the
+    /// width left over after the rest of the columns
+    pub widths: Vec<Option<RowWidth>>,
+    pub layout: TableLayout,
+    pub column_limit: ColumnLimit,
+    pub hidpi_scale: f32,
+    /// Stacks the content of a table cell that holds more than a single element, like text around
+    /// a nested table. Every element gets a row of its own and no dividers get drawn
+    pub stacked: bool,
+    measurements: RefCell<Option<Measurements>>,
+}
+
+// Column widths and row heights from the last time the table was measured. Measuring lays out the
+// glyphs of every cell, so they're reused until the table gets measured within other bounds or at
+// another zoom
+#[derive(Clone, Debug)]
+struct Measurements {
+    key: MeasurementKey,
+    column_widths: Vec<f32>,
+    row_heights: Vec<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct MeasurementKey {
+    bounds: Size,
+    zoom: f32,
+    hidpi_scale: f32,
 }
 
 impl Table {
     pub fn new() -> Table {
-        Table::default()
+        Table {
+            hidpi_scale: 1.,
+            ..Default::default()
+        }
     }
 
-    pub fn find_hoverable<'a, T: GlyphCruncher>(
-        &'a self,
-        glyph_brush: &'a mut T,
-        loc: Point,
+    pub fn with_layout(mut self, layout: TableLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Applies to the cells pushed after it's set
+    pub fn with_column_limit(mut self, column_limit: ColumnLimit) -> Self {
+        self.column_limit = column_limit;
+        self
+    }
+
+    pub fn with_hidpi_scale(mut self, hidpi_scale: f32) -> Self {
+        self.hidpi_scale = hidpi_scale;
+        self
+    }
+
+    /// Stacks `elements` on top of each other to fit them in a single table cell
+    pub fn stacked(mut self, elements: Vec<Positioned<Element>>) -> Self {
+        self.stacked = true;
+        let mut elements = elements.into_iter();
+        if let Some(first) = elements.next() {
+            self.push_header(first);
+        }
+        for element in elements {
+            self.push_row(vec![element]);
+        }
+        self
+    }
+
+    /// Sets the width of a column unless an earlier cell already set it
+    pub fn set_column_width(&mut self, column: usize, width: RowWidth) {
+        if self.widths.len() <= column {
+            self.widths.resize(column + 1, None);
+        }
+        let column_width = &mut self.widths[column];
+        if column_width.is_none() {
+            self.measurements.take();
+            *column_width = Some(width);
+        }
+    }
+
+    /// The bounds that cells get measured within given the bounds of the table
+    fn cell_measure_bounds(&self, bounds: Size, zoom: f32) -> Size {
+        self.column_limit
+            .clamp(self.layout.measure_bounds(bounds), self.hidpi_scale * zoom)
+    }
+
+    /// The cell at `loc` along with its row and column. Rows count from the header row at 0
+    pub fn cell_at(&self, loc: Point) -> Option<(usize, usize, &Positioned<Element>)> {
+        let rows = iter::once(&self.headers).chain(self.rows.iter());
+        rows.enumerate().find_map(|(row, cells)| {
+            cells.iter().enumerate().find_map(|(column, cell)| {
+                let bounds = cell.bounds.as_ref()?;
+                bounds.contains(loc).then_some((row, column, cell))
+            })
+        })
+    }
+
+    /// The bounds the text of a cell gets laid out in for a table at `pos` within `bounds`.
+    /// Leaves room for the ellipsis of truncated cells
+    pub fn cell_text_bounds<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        text_box: &TextBox,
+        cell_bounds: &Rect,
         pos: Point,
         bounds: Size,
         zoom: f32,
-    ) -> Option<&'a Text> {
-        let row_heights = self.row_heights(glyph_brush, pos, bounds, zoom);
-        let column_widths = self.column_widths(glyph_brush, pos, bounds, zoom);
-        let mut x = 0.;
-        let mut y = 0.;
-        for (i, header) in self.headers.iter().enumerate() {
-            let size = header.size(
-                glyph_brush,
-                (pos.0 + x, pos.1 + y),
-                (bounds.0 - x, bounds.1),
-                zoom,
-            );
-            if Rect::new((pos.0 + x, pos.1 + y), size).contains(loc) {
-                return header.find_hoverable(
-                    glyph_brush,
-                    loc,
-                    (pos.0 + x, pos.1 + y),
-                    (bounds.0 - x, bounds.1),
-                    zoom,
-                );
+    ) -> Size {
+        let inner_bounds = cell_inner_bounds(cell_bounds, pos, bounds);
+        let width = self.layout.cell_width(cell_bounds.size.0, inner_bounds.0);
+        let text_bounds = (width, inner_bounds.1);
+        text_box
+            .ellipsis(glyph_brush, cell_bounds.pos, text_bounds, zoom)
+            .map_or(text_bounds, |(clipped, _)| clipped)
+    }
+
+    /// The full text of the truncated cell at `loc` when it got cut off
+    pub fn truncated_text_at<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        loc: Point,
+        zoom: f32,
+    ) -> Option<String> {
+        for cell in self.cells() {
+            match (&cell.inner, &cell.bounds) {
+                (Element::TextBox(text_box), Some(bounds)) if bounds.contains(loc) => {
+                    return text_box
+                        .overflows(glyph_brush, bounds.size.0, zoom)
+                        .then(|| {
+                            text_box
+                                .texts
+                                .iter()
+                                .map(|text| text.text.as_str())
+                                .collect()
+                        });
+                }
+                (Element::Table(table), Some(bounds)) if bounds.contains(loc) => {
+                    return table.truncated_text_at(glyph_brush, loc, zoom);
+                }
+                _ => {}
             }
-            x += column_widths.get(i).unwrap() + TABLE_COL_GAP;
         }
-        y += row_heights.first().unwrap() + TABLE_ROW_GAP;
-        for (row_num, row) in self.rows.iter().enumerate() {
+        None
+    }
+
+    /// Every cell in the table starting with the headers
+    pub fn cells(&self) -> impl Iterator<Item = &Positioned<Element>> {
+        self.headers.iter().chain(self.rows.iter().flatten())
+    }
+
+    pub fn size<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+    ) -> Size {
+        let bounds = self.cell_measure_bounds(bounds, zoom);
+        let width: f32 = self
+            .column_widths(glyph_brush, screen_position, bounds, zoom)
+            .iter()
+            .sum();
+        let height: f32 = self
+            .row_heights(glyph_brush, screen_position, bounds, zoom)
+            .iter()
+            .sum();
+        (
+            width + TABLE_COL_GAP * self.headers.len() as f32,
+            height + TABLE_ROW_GAP * (self.rows.len() + 1) as f32,
+        )
+    }
+
+    /// Sets the bounds of every cell (and the cells of any nested tables) for a table at `pos`
+    pub fn position_cells<T: GlyphCruncher>(
+        &mut self,
+        glyph_brush: &mut T,
+        pos: Point,
+        bounds: Size,
+        zoom: f32,
+    ) {
+        // Repositioning happens when anything the measurements depend on changes, like the size of
+        // a loaded image within a cell
+        self.measurements.take();
+        let bounds = self.cell_measure_bounds(bounds, zoom);
+        let Measurements {
+            column_widths,
+            row_heights,
+            ..
+        } = self.measure(glyph_brush, pos, bounds, zoom);
+        let mut y = 0.;
+        let rows = iter::once(&mut self.headers).chain(self.rows.iter_mut());
+        for (row, height) in rows.zip(row_heights) {
             let mut x = 0.;
-            for (i, row_text_box) in row.iter().enumerate() {
-                let size = row_text_box.size(
-                    glyph_brush,
-                    (pos.0 + x, pos.1 + y),
-                    (bounds.0 - x, bounds.1),
-                    zoom,
-                );
-                if Rect::new((pos.0 + x, pos.1 + y), size).contains(loc) {
-                    return row_text_box.find_hoverable(
-                        glyph_brush,
-                        loc,
-                        (pos.0 + x, pos.1 + y),
-                        (bounds.0 - x, bounds.1),
-                        zoom,
-                    );
+            for (column, (cell, width)) in row.iter_mut().zip(&column_widths).enumerate() {
+                let cell_pos = (pos.0 + x, pos.1 + y);
+                let cell_bounds =
+                    self.column_bounds(column, *width, (bounds.0 - x, bounds.1), zoom);
+                if let Element::Table(table) = &mut cell.inner {
+                    table.position_cells(glyph_brush, cell_pos, cell_bounds, zoom);
+                }
+                let mut size = cell_size(cell, glyph_brush, cell_pos, cell_bounds, zoom);
+                // Text takes up the whole column so that it can be aligned within it
+                if let Element::TextBox(_) = cell.inner {
+                    size.0 = *width;
                 }
-                x += column_widths[i] + TABLE_COL_GAP;
+                cell.bounds = Some(Rect::new(cell_pos, size));
+                x += width + TABLE_COL_GAP;
             }
-            y += row_heights.get(row_num + 1).unwrap() + TABLE_ROW_GAP;
+            y += height + TABLE_ROW_GAP;
         }
-        None
     }
 
     pub fn column_widths<T: GlyphCruncher>(
@@ -81,15 +311,67 @@ impl Table {
         screen_position: Point,
         bounds: Size,
         zoom: f32,
+    ) -> Vec<f32> {
+        self.measure(glyph_brush, screen_position, bounds, zoom)
+            .column_widths
+    }
+
+    /// Height of the header row followed by the height of every other row
+    pub fn row_heights<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+    ) -> Vec<f32> {
+        self.measure(glyph_brush, screen_position, bounds, zoom)
+            .row_heights
+    }
+
+    // Measures the table or reuses the last measurements when nothing they depend on changed
+    fn measure<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+    ) -> Measurements {
+        let bounds = self.cell_measure_bounds(bounds, zoom);
+        let key = MeasurementKey {
+            bounds,
+            zoom,
+            hidpi_scale: self.hidpi_scale,
+        };
+        if let Some(measurements) = self.measurements.borrow().as_ref() {
+            if measurements.key == key {
+                return measurements.clone();
+            }
+        }
+        let column_widths = self.measure_column_widths(glyph_brush, screen_position, bounds, zoom);
+        let row_heights =
+            self.measure_row_heights(glyph_brush, screen_position, bounds, zoom, &column_widths);
+        let measurements = Measurements {
+            key,
+            column_widths,
+            row_heights,
+        };
+        *self.measurements.borrow_mut() = Some(measurements.clone());
+        measurements
+    }
+
+    fn measure_column_widths<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
     ) -> Vec<f32> {
         let mut widths = Vec::with_capacity(self.headers.len());
-        for (i, header_text_box) in self.headers.iter().enumerate() {
-            let mut max_width = header_text_box
-                .size(glyph_brush, screen_position, bounds, zoom)
-                .0;
+        for (i, header) in self.headers.iter().enumerate() {
+            let mut max_width = cell_size(header, glyph_brush, screen_position, bounds, zoom).0;
             for row in &self.rows {
-                if let Some(text_box) = row.get(i) {
-                    let width = text_box.size(glyph_brush, screen_position, bounds, zoom).0;
+                if let Some(cell) = row.get(i) {
+                    let width = cell_size(cell, glyph_brush, screen_position, bounds, zoom).0;
                     if width > max_width {
                         max_width = width;
                     }
@@ -97,30 +379,67 @@ impl Table {
             }
             widths.push(max_width);
         }
+        self.apply_column_widths(&mut widths, bounds.0, zoom);
         widths
     }
 
-    pub fn row_heights<T: GlyphCruncher>(
+    // Columns that asked for a fixed width get it. Flexible columns share what's left of the
+    // available width and keep their natural width when there's no end to it
+    fn apply_column_widths(&self, widths: &mut [f32], available: f32, zoom: f32) {
+        let scale = self.hidpi_scale * zoom;
+        let mut total_weight = 0.;
+        let mut remaining = available - TABLE_COL_GAP * widths.len() as f32;
+        for (i, width) in widths.iter_mut().enumerate() {
+            match self.widths.get(i).copied().flatten() {
+                Some(RowWidth::Fixed(fixed)) => *width = fixed * scale,
+                Some(RowWidth::Flex(weight)) if available.is_finite() => {
+                    total_weight += weight;
+                    continue;
+                }
+                _ => {}
+            }
+            remaining -= *width;
+        }
+        if total_weight > 0. {
+            for (i, width) in widths.iter_mut().enumerate() {
+                if let Some(RowWidth::Flex(weight)) = self.widths.get(i).copied().flatten() {
+                    *width = remaining.max(0.) * weight / total_weight;
+                }
+            }
+        }
+    }
+
+    // The bounds a cell in `column` gets measured within. Columns that asked for a width wrap
+    // their cells at it
+    fn column_bounds(&self, column: usize, width: f32, bounds: Size, zoom: f32) -> Size {
+        let bounds = self.cell_measure_bounds(bounds, zoom);
+        match self.widths.get(column) {
+            Some(Some(_)) => (bounds.0.min(width), bounds.1),
+            _ => bounds,
+        }
+    }
+
+    fn measure_row_heights<T: GlyphCruncher>(
         &self,
         glyph_brush: &mut T,
         screen_position: Point,
         bounds: Size,
         zoom: f32,
+        widths: &[f32],
     ) -> Vec<f32> {
-        let widths = self.column_widths(glyph_brush, screen_position, bounds, zoom);
         let mut heights = Vec::with_capacity(self.rows.len() + 1);
         let mut max_height = 0.;
         let mut x = 0.;
         let mut y = 0.;
-        for (i, header_text_box) in self.headers.iter().enumerate() {
-            let height = header_text_box
-                .size(
-                    glyph_brush,
-                    (screen_position.0 + x, screen_position.1 + y),
-                    (bounds.0 - x, bounds.1),
-                    zoom,
-                )
-                .1;
+        for (i, header) in self.headers.iter().enumerate() {
+            let height = cell_size(
+                header,
+                glyph_brush,
+                (screen_position.0 + x, screen_position.1 + y),
+                self.column_bounds(i, widths[i], (bounds.0 - x, bounds.1), zoom),
+                zoom,
+            )
+            .1;
             if height > max_height {
                 max_height = height;
             }
@@ -131,15 +450,15 @@ impl Table {
         for row in &self.rows {
             let mut x = 0.;
             let mut max_height = 0.;
-            for (i, row_text_box) in row.iter().enumerate() {
-                let height = row_text_box
-                    .size(
-                        glyph_brush,
-                        (screen_position.0 + x, screen_position.1 + y),
-                        (bounds.0 - x, bounds.1),
-                        zoom,
-                    )
-                    .1;
+            for (i, cell) in row.iter().enumerate() {
+                let height = cell_size(
+                    cell,
+                    glyph_brush,
+                    (screen_position.0 + x, screen_position.1 + y),
+                    self.column_bounds(i, widths[i], (bounds.0 - x, bounds.1), zoom),
+                    zoom,
+                )
+                .1;
                 if height > max_height {
                     max_height = height;
                 }
@@ -151,11 +470,219 @@ impl Table {
         heights
     }
 
-    pub fn push_header(&mut self, header: TextBox) {
+    pub fn column_align(&self, column: usize) -> Align {
+        self.alignments.get(column).copied().unwrap_or_default()
+    }
+
+    pub fn with_header(mut self, header: impl Into<Element>) -> Self {
+        self.push_header(Positioned::new(header.into()));
+        self
+    }
+
+    pub fn with_row<E: Into<Element>>(mut self, row: impl IntoIterator<Item = E>) -> Self {
+        self.push_row(
+            row.into_iter()
+                .map(|cell| Positioned::new(cell.into()))
+                .collect(),
+        );
+        self
+    }
+
+    pub fn push_header(&mut self, mut header: Positioned<Element>) {
+        self.measurements.take();
+        self.limit_cell(&mut header);
         self.headers.push(header);
     }
 
-    pub fn push_row(&mut self, row: Vec<TextBox>) {
+    pub fn push_row(&mut self, mut row: Vec<Positioned<Element>>) {
+        self.measurements.take();
+        for cell in &mut row {
+            self.limit_cell(cell);
+        }
         self.rows.push(row);
     }
+
+    fn limit_cell(&self, cell: &mut Positioned<Element>) {
+        if let Element::TextBox(text_box) = &mut cell.inner {
+            text_box.truncate = self.column_limit.truncates();
+        }
+    }
+}
+
+/// What's left of the bounds of a table at `pos` for a cell that starts further along
+pub fn cell_inner_bounds(cell_bounds: &Rect, pos: Point, bounds: Size) -> Size {
+    (bounds.0 - (cell_bounds.pos.0 - pos.0), bounds.1)
+}
+
+// Text boxes and tables are measured from their contents. Any other element keeps the size it was
+// positioned with
+fn cell_size<T: GlyphCruncher>(
+    cell: &Positioned<Element>,
+    glyph_brush: &mut T,
+    screen_position: Point,
+    bounds: Size,
+    zoom: f32,
+) -> Size {
+    match &cell.inner {
+        Element::TextBox(text_box) => text_box.size(glyph_brush, screen_position, bounds, zoom),
+        Element::Table(table) => table.size(glyph_brush, screen_position, bounds, zoom),
+        _ => cell.bounds.as_ref().map_or((0., 0.), |bounds| bounds.size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, hash::Hash};
+
+    use wgpu_glyph::{
+        ab_glyph::{self, FontArc},
+        GlyphCruncher, GlyphPositioner, Section, SectionGlyphIter,
+    };
+
+    use super::{ColumnLimit, ColumnOverflow, Table, TableLayout, TABLE_COL_GAP};
+    use crate::positioner::RowWidth;
+    use crate::text::{tests::NoGlyphs, TextBox, DEFAULT_TEXT_SIZE};
+    use crate::Element;
+
+    // Lays out sections like `NoGlyphs` while counting how many times text gets laid out
+    #[derive(Default)]
+    struct CountingGlyphs(usize);
+
+    impl GlyphCruncher for CountingGlyphs {
+        fn glyphs_custom_layout<'a, 'b, S, L>(
+            &'b mut self,
+            _section: S,
+            _custom_layout: &L,
+        ) -> SectionGlyphIter<'b>
+        where
+            L: GlyphPositioner + Hash,
+            S: Into<Cow<'a, Section<'a>>>,
+        {
+            self.0 += 1;
+            [].iter()
+        }
+
+        fn glyph_bounds_custom_layout<'a, S, L>(
+            &mut self,
+            _section: S,
+            _custom_layout: &L,
+        ) -> Option<ab_glyph::Rect>
+        where
+            L: GlyphPositioner + Hash,
+            S: Into<Cow<'a, Section<'a>>>,
+        {
+            self.0 += 1;
+            None
+        }
+
+        fn fonts(&self) -> &[FontArc] {
+            &[]
+        }
+    }
+
+    #[test]
+    fn measurements_are_reused_until_the_bounds_change() {
+        let cell = || TextBox::new(Vec::new(), 1.);
+        let table = Table::new().with_header(cell()).with_row([cell()]);
+        let mut glyphs = CountingGlyphs::default();
+        let bounds = (500., f32::INFINITY);
+
+        let heights = table.row_heights(&mut glyphs, (0., 0.), bounds, 1.);
+        let measured = glyphs.0;
+        assert!(measured > 0);
+        // Scrolling only moves the table
+        assert_eq!(
+            table.row_heights(&mut glyphs, (0., -300.), bounds, 1.),
+            heights
+        );
+        table.column_widths(&mut glyphs, (0., -300.), bounds, 1.);
+        assert_eq!(glyphs.0, measured);
+
+        table.row_heights(&mut glyphs, (0., 0.), (400., f32::INFINITY), 1.);
+        assert!(glyphs.0 > measured);
+        let measured = glyphs.0;
+        table.row_heights(&mut glyphs, (0., 0.), (400., f32::INFINITY), 2.);
+        assert!(glyphs.0 > measured);
+    }
+
+    #[test]
+    fn empty_cell() {
+        let empty = || TextBox::new(Vec::new(), 1.);
+        let table = Table::new()
+            .with_header(empty())
+            .with_header(empty())
+            .with_row([empty(), empty()]);
+        let heights = table.row_heights(&mut NoGlyphs, (0., 0.), (500., f32::INFINITY), 1.);
+        assert_eq!(heights, [DEFAULT_TEXT_SIZE; 2]);
+    }
+
+    #[test]
+    fn columns_take_the_widths_they_ask_for() {
+        let empty = || TextBox::new(Vec::new(), 1.);
+        let table = |layout| {
+            let mut table = Table::new()
+                .with_layout(layout)
+                .with_header(empty())
+                .with_header(empty())
+                .with_header(empty());
+            table.set_column_width(0, RowWidth::Fixed(100.));
+            table.set_column_width(1, RowWidth::Flex(1.));
+            table.set_column_width(2, RowWidth::Flex(3.));
+            // Only the first cell that sets a width counts
+            table.set_column_width(0, RowWidth::Fixed(50.));
+            table
+        };
+        let bounds = (500. + 3. * TABLE_COL_GAP, f32::INFINITY);
+
+        let widths = table(TableLayout::Wrap).column_widths(&mut NoGlyphs, (0., 0.), bounds, 1.);
+        assert_eq!(widths, [100., 100., 300.]);
+        // Twice the zoom doubles fixed widths
+        let widths = table(TableLayout::Wrap).column_widths(&mut NoGlyphs, (0., 0.), bounds, 2.);
+        assert_eq!(widths, [200., 75., 225.]);
+        // There's nothing to share when the table can grow forever
+        let widths = table(TableLayout::Expand).column_widths(&mut NoGlyphs, (0., 0.), bounds, 1.);
+        assert_eq!(widths, [100., 0., 0.]);
+    }
+
+    #[test]
+    fn expanded_cells_ignore_the_screen_edge() {
+        let bounds = (500., f32::INFINITY);
+        assert_eq!(TableLayout::Wrap.measure_bounds(bounds), bounds);
+        assert_eq!(
+            TableLayout::Expand.measure_bounds(bounds),
+            (f32::INFINITY, f32::INFINITY)
+        );
+        assert_eq!(TableLayout::Wrap.cell_width(800., 300.), 300.);
+        assert_eq!(TableLayout::Expand.cell_width(800., 300.), 800.);
+    }
+
+    #[test]
+    fn column_limit() {
+        let bounds = (500., f32::INFINITY);
+        assert_eq!(ColumnLimit::default().clamp(bounds, 2.), bounds);
+        let wrap = ColumnLimit {
+            max_width: Some(100.),
+            overflow: ColumnOverflow::Wrap,
+        };
+        assert_eq!(wrap.clamp(bounds, 2.), (200., f32::INFINITY));
+        assert_eq!(wrap.clamp((150., 40.), 2.), (150., 40.));
+
+        let truncate = ColumnLimit {
+            overflow: ColumnOverflow::Truncate,
+            ..wrap
+        };
+        let truncates = |limit| {
+            let table = Table::new()
+                .with_column_limit(limit)
+                .with_header(TextBox::new(Vec::new(), 1.));
+            matches!(&table.headers[0].inner, Element::TextBox(text_box) if text_box.truncate)
+        };
+        assert!(truncates(truncate));
+        assert!(!truncates(wrap));
+        // Nothing gets cut off without a maximum width
+        assert!(!truncates(ColumnLimit {
+            max_width: None,
+            ..truncate
+        }));
+    }
 }
@@ -1,21 +1,421 @@
-use crate::utils::{Align, Line, Point, Rect, Selection, Size};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::iter;
+use std::path::{Component, Path, PathBuf};
+
+use crate::color::Theme;
+use crate::fonts;
+use crate::image::Image;
+use crate::positioner::Positioned;
+use crate::utils::{Align, Point, Rect, Size};
+use crate::Element;
+use glyph_brush_layout::{SectionGeometry, SectionText, ToSectionText};
 use wgpu_glyph::{
-    ab_glyph::{Font, FontArc, PxScale},
-    Extra, FontId, GlyphCruncher, HorizontalAlign, Layout, Section, SectionGlyph,
+    ab_glyph::{self, point, Font, FontArc, PxScale, ScaleFont},
+    BuiltInLineBreaker, Extra, FontId, GlyphCruncher, GlyphPositioner, HorizontalAlign, Layout,
+    Section, SectionGlyph,
 };
 
+/// Space (in logical pixels) between the text of code and quote blocks and the sides of their
+/// background
+pub const DEFAULT_BLOCK_PADDING: f32 = 10.;
+
+pub const DEFAULT_TEXT_SIZE: f32 = 16.;
+/// Weights of the regular and bold faces that every font family gets loaded with
+pub const REGULAR_WEIGHT: u16 = 400;
+pub const BOLD_WEIGHT: u16 = 700;
+/// Thickness of underlines and strike-throughs relative to the font size
+const LINE_THICKNESS: f32 = 1. / 12.;
+/// How far underlines sit below the baseline relative to the font size
+const UNDERLINE_OFFSET: f32 = 0.12;
+
+// Space (in logical pixels) between line numbers and the code they number. List markers keep the
+// same distance from their item's text
+const GUTTER_GAP: f32 = 10.;
+// Radius of list bullets relative to the font size
+const BULLET_RADIUS: f32 = 0.17;
+
+/// Where a line sits in its code block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineNumber {
+    pub number: usize,
+    /// Digits in the block's last line number. Every line in a block reserves a gutter this wide
+    /// so that the code stays aligned
+    pub digits: usize,
+}
+
+/// The bullet or number in front of a list item. It sits in the indent to the left of the item's
+/// first line so that the lines after it wrap back to the text rather than to the marker
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListMarker {
+    Bullet(Bullet),
+    Number(usize),
+}
+
+/// Bullets change shape with each level of unordered lists that they're nested in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bullet {
+    Disc,
+    Circle,
+    Square,
+}
+
+impl Bullet {
+    /// The bullet of a list within `depth` other unordered lists
+    pub fn at_depth(depth: usize) -> Self {
+        match depth % 3 {
+            0 => Self::Disc,
+            1 => Self::Circle,
+            _ => Self::Square,
+        }
+    }
+}
+
+/// The parts of a definition list. Terms are bold and their definitions are indented below them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefinitionPart {
+    Term,
+    Definition,
+}
+
+// Stands in for inline images while laying out text. An em space is a full em wide in just about
+// every font, so the width of the image can be set through the horizontal scale
+const INLINE_IMAGE_PLACEHOLDER: &str = "\u{2003}";
+
+/// Marks where the text of a truncated box got cut off
+pub const ELLIPSIS: &str = "\u{2026}";
+
+/// Size of superscripts and subscripts relative to the text around them
+const SCRIPT_SCALE: f32 = 0.7;
+/// How far superscripts get raised and subscripts get lowered relative to their own font size
+const SUPERSCRIPT_SHIFT: f32 = 0.4;
+const SUBSCRIPT_SHIFT: f32 = 0.2;
+
+/// Places the glyphs of a text box with its [`TextLayout`] and then raises or lowers the glyphs of
+/// superscripts and subscripts off of the baseline
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextBoxLayout {
+    layout: TextLayout,
+    /// The vertical alignment of each text. Left empty when every text sits on the baseline
+    vertical_aligns: Vec<VerticalAlign>,
+    /// The letter spacing of each text. Left empty when no text is spaced out
+    letter_spacings: Vec<f32>,
+}
+
+impl Hash for TextBoxLayout {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.layout.hash(state);
+        self.vertical_aligns.hash(state);
+        for letter_spacing in &self.letter_spacings {
+            letter_spacing.to_bits().hash(state);
+        }
+    }
+}
+
+impl GlyphPositioner for TextBoxLayout {
+    fn calculate_glyphs<F, S>(
+        &self,
+        fonts: &[F],
+        geometry: &SectionGeometry,
+        sections: &[S],
+    ) -> Vec<SectionGlyph>
+    where
+        F: Font,
+        S: ToSectionText,
+    {
+        let mut glyphs = match self.layout {
+            // The glyph brush's layout has no way to add space between glyphs
+            TextLayout::Flow(layout) if !self.letter_spacings.is_empty() => {
+                spaced_glyphs(fonts, geometry, sections, &self.letter_spacings, layout)
+            }
+            _ => self.layout.calculate_glyphs(fonts, geometry, sections),
+        };
+        for glyph in &mut glyphs {
+            let font_size = glyph.glyph.scale.y;
+            glyph.glyph.position.y += match self.vertical_aligns.get(glyph.section_index) {
+                Some(VerticalAlign::Super) => -font_size * SUPERSCRIPT_SHIFT,
+                Some(VerticalAlign::Sub) => font_size * SUBSCRIPT_SHIFT,
+                Some(VerticalAlign::Baseline) | None => continue,
+            };
+        }
+        glyphs
+    }
+
+    fn bounds_rect(&self, geometry: &SectionGeometry) -> ab_glyph::Rect {
+        self.layout.bounds_rect(geometry)
+    }
+}
+
+/// How the glyphs of a text box are placed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextLayout {
+    /// The glyph brush's own layout which places glyphs by their advance and wraps at words
+    Flow(Layout<BuiltInLineBreaker>),
+    /// Every character goes in the next cell of a grid as wide as a space so that box-drawing
+    /// characters and ASCII art line up even when the font's advances vary. Wraps at the edge of
+    /// the bounds instead of at words
+    Grid,
+}
+
+impl GlyphPositioner for TextLayout {
+    fn calculate_glyphs<F, S>(
+        &self,
+        fonts: &[F],
+        geometry: &SectionGeometry,
+        sections: &[S],
+    ) -> Vec<SectionGlyph>
+    where
+        F: Font,
+        S: ToSectionText,
+    {
+        match self {
+            Self::Flow(layout) => layout.calculate_glyphs(fonts, geometry, sections),
+            Self::Grid => grid_glyphs(fonts, geometry, sections),
+        }
+    }
+
+    fn bounds_rect(&self, geometry: &SectionGeometry) -> ab_glyph::Rect {
+        match self {
+            Self::Flow(layout) => layout.bounds_rect(geometry),
+            // Grids fill their bounds from the top left the same way the default layout does
+            Self::Grid => Layout::default_wrap().bounds_rect(geometry),
+        }
+    }
+}
+
+fn grid_glyphs<F: Font, S: ToSectionText>(
+    fonts: &[F],
+    geometry: &SectionGeometry,
+    sections: &[S],
+) -> Vec<SectionGlyph> {
+    let sections: Vec<SectionText> = sections.iter().map(|s| s.to_section_text()).collect();
+    let (left, top) = geometry.screen_position;
+    let right = left + geometry.bounds.0;
+    // Rows are all as tall as the tallest text so that they stay evenly spaced
+    let (ascent, line_height) = sections.iter().fold((0f32, 0f32), |(ascent, height), s| {
+        let font = fonts[s.font_id.0].as_scaled(s.scale);
+        (
+            ascent.max(font.ascent()),
+            height.max(font.height() + font.line_gap()),
+        )
+    });
+
+    let mut glyphs = Vec::new();
+    let (mut x, mut line_top) = (left, top);
+    for (section_index, section) in sections.iter().enumerate() {
+        let font = fonts[section.font_id.0].as_scaled(section.scale);
+        let cell_width = font.h_advance(font.glyph_id(' ')).max(1.);
+        for (byte_index, c) in section.text.char_indices() {
+            if c == '\n' {
+                x = left;
+                line_top += line_height;
+                continue;
+            } else if c.is_control() {
+                continue;
+            }
+            let id = font.glyph_id(c);
+            // Wide characters like CJK and emoji take up as many whole cells as they need
+            let width = (font.h_advance(id) / cell_width).round().max(1.) * cell_width;
+            if x + width > right && x > left {
+                x = left;
+                line_top += line_height;
+            }
+            glyphs.push(SectionGlyph {
+                section_index,
+                byte_index,
+                glyph: id.with_scale_and_position(section.scale, point(x, line_top + ascent)),
+                font_id: section.font_id,
+            });
+            x += width;
+        }
+    }
+    glyphs
+}
+
+// Lays out text the way the glyph brush's layout does while adding each text's letter spacing (in
+// ems) after every glyph. Lines wrap after whitespace and are all as tall as the tallest text like
+// they are on a grid
+fn spaced_glyphs<F: Font, S: ToSectionText>(
+    fonts: &[F],
+    geometry: &SectionGeometry,
+    sections: &[S],
+    letter_spacings: &[f32],
+    layout: Layout<BuiltInLineBreaker>,
+) -> Vec<SectionGlyph> {
+    let (h_align, wrap) = match layout {
+        Layout::SingleLine { h_align, .. } => (h_align, false),
+        Layout::Wrap { h_align, .. } => (h_align, true),
+    };
+    let sections: Vec<SectionText> = sections.iter().map(|s| s.to_section_text()).collect();
+    let (ascent, line_height) = sections.iter().fold((0f32, 0f32), |(ascent, height), s| {
+        let font = fonts[s.font_id.0].as_scaled(s.scale);
+        (
+            ascent.max(font.ascent()),
+            height.max(font.height() + font.line_gap()),
+        )
+    });
+
+    // The glyphs of each line placed relative to the start of the line
+    let mut lines: Vec<Vec<SectionGlyph>> = vec![Vec::new()];
+    let mut x = 0.;
+    // Index in the current line right after its last whitespace, which is where it can wrap
+    let mut wrap_at = None;
+    let mut previous = None;
+    for (section_index, section) in sections.iter().enumerate() {
+        let font = fonts[section.font_id.0].as_scaled(section.scale);
+        let spacing = letter_spacings.get(section_index).copied().unwrap_or(0.) * section.scale.x;
+        for (byte_index, c) in section.text.char_indices() {
+            if c == '\n' {
+                lines.push(Vec::new());
+                x = 0.;
+                wrap_at = None;
+                previous = None;
+                continue;
+            } else if c.is_control() {
+                continue;
+            }
+            let id = font.glyph_id(c);
+            if let Some((font_id, previous_id)) = previous {
+                if font_id == section.font_id {
+                    x += font.kern(previous_id, id);
+                }
+            }
+            previous = Some((section.font_id, id));
+
+            let last = lines.len() - 1;
+            if wrap
+                && !c.is_whitespace()
+                && !lines[last].is_empty()
+                && x + font.h_advance(id) > geometry.bounds.0
+            {
+                // The word being laid out moves to the next line along with the glyph
+                let word = match wrap_at {
+                    Some(at) => lines[last].split_off(at),
+                    None => Vec::new(),
+                };
+                let shift = word.first().map_or(x, |glyph| glyph.glyph.position.x);
+                x -= shift;
+                lines.push(
+                    word.into_iter()
+                        .map(|mut glyph| {
+                            glyph.glyph.position.x -= shift;
+                            glyph
+                        })
+                        .collect(),
+                );
+                wrap_at = None;
+            }
+
+            let line = lines.last_mut().expect("There's always a line");
+            line.push(SectionGlyph {
+                section_index,
+                byte_index,
+                glyph: id.with_scale_and_position(section.scale, point(x, 0.)),
+                font_id: section.font_id,
+            });
+            x += font.h_advance(id) + spacing;
+            if c.is_whitespace() {
+                wrap_at = Some(line.len());
+            }
+        }
+    }
+
+    let (left, top) = geometry.screen_position;
+    let mut glyphs = Vec::new();
+    for (index, line) in lines.into_iter().enumerate() {
+        // Trailing whitespace and the spacing after the last glyph are left out of the line's
+        // width so that aligned lines line up with their unspaced neighbors
+        let width = line
+            .iter()
+            .filter(|glyph| {
+                !sections[glyph.section_index].text[glyph.byte_index..]
+                    .starts_with(char::is_whitespace)
+            })
+            .map(|glyph| {
+                let font = fonts[glyph.font_id.0].as_scaled(glyph.glyph.scale);
+                glyph.glyph.position.x + font.h_advance(glyph.glyph.id)
+            })
+            .fold(0f32, f32::max);
+        let start = match h_align {
+            HorizontalAlign::Left => left,
+            HorizontalAlign::Center => left - width / 2.,
+            HorizontalAlign::Right => left - width,
+        };
+        let baseline = top + ascent + index as f32 * line_height;
+        glyphs.extend(line.into_iter().map(|mut glyph| {
+            glyph.glyph.position.x += start;
+            glyph.glyph.position.y = baseline;
+            glyph
+        }));
+    }
+    glyphs
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct TextBox {
     pub indent: f32,
+    /// How many lists the box is nested in
+    pub list_depth: usize,
     pub texts: Vec<Text>,
     pub is_code_block: bool,
     pub is_quote_block: Option<usize>,
-    pub is_checkbox: Option<bool>,
+    /// Set for the lines of a callout. Colors its quote block
+    pub admonition: Option<AdmonitionKind>,
+    /// Set for task list items. Interior mutability lets clicks toggle it
+    pub is_checkbox: Option<Cell<Checkbox>>,
     pub is_anchor: Option<String>,
+    /// Anchors of the elements with an `id` within the box like footnotes and their references
+    pub ids: Vec<String>,
+    /// Level from 1 to 6 of the heading the box holds
+    pub heading_level: Option<u8>,
     pub align: Align,
     pub hidpi_scale: f32,
     pub padding_height: f32,
     pub background_color: Option<[f32; 4]>,
+    /// Size of the text the box is meant to hold. Boxes without any glyphs still take up a line
+    /// of this size
+    pub font_size: f32,
+    /// Space between the text and the sides of its background
+    pub block_padding: f32,
+    /// Caps the width text wraps at. Used for boxes inside of flexible rows
+    pub max_width: Option<f32>,
+    /// Width left beside floated images when the box starts next to one. Set by the positioner
+    pub float_width: Option<f32>,
+    /// Set for the lines that `diff` code blocks add or remove. Tints the line's background
+    pub diff: Option<Change>,
+    /// Set for lines of code blocks
+    pub line_number: Option<LineNumber>,
+    /// Set for the first box of each list item
+    pub list_marker: Option<ListMarker>,
+    /// Set for the terms and definitions of definition lists (`<dl>`)
+    pub definition_part: Option<DefinitionPart>,
+    /// Lays the text out on a fixed grid instead of by each glyph's advance. See
+    /// [`TextLayout::Grid`]
+    pub monospace_grid: bool,
+    /// Keeps the text on a single line that gets cut off with an ellipsis when it runs out of
+    /// room. Set for the cells of tables with a capped column width
+    pub truncate: bool,
+    /// Line of the markdown source that the text starts on
+    pub source_line: Option<usize>,
+    selection_cache: RefCell<Option<CachedSelection>>,
+}
+
+// The selection geometry from the last frame. Recomputing it means iterating over every glyph, so
+// it's reused for as long as the selection and layout stay the same
+#[derive(Clone, Debug)]
+struct CachedSelection {
+    key: SelectionKey,
+    rects: Vec<Rect>,
+    text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SelectionKey {
+    screen_position: Point,
+    bounds: Size,
+    zoom: f32,
+    selection: TextSelection,
 }
 
 impl TextBox {
@@ -23,6 +423,8 @@ impl TextBox {
         TextBox {
             texts,
             hidpi_scale,
+            font_size: DEFAULT_TEXT_SIZE,
+            block_padding: DEFAULT_BLOCK_PADDING,
             ..Default::default()
         }
     }
@@ -31,12 +433,46 @@ impl TextBox {
         self.is_code_block = is_code_block;
     }
 
+    pub fn set_monospace_grid(&mut self, monospace_grid: bool) {
+        self.monospace_grid = monospace_grid;
+    }
+
     pub fn set_quote_block(&mut self, nest: Option<usize>) {
         self.is_quote_block = nest;
     }
 
-    pub fn set_checkbox(&mut self, is_checked: Option<bool>) {
-        self.is_checkbox = is_checked;
+    pub fn set_hidpi_scale(&mut self, hidpi_scale: f32) {
+        self.hidpi_scale = hidpi_scale;
+        for text in &mut self.texts {
+            text.hidpi_scale = hidpi_scale;
+            if let Some(inline_image) = &mut text.inline_image {
+                inline_image.image.hidpi_scale = hidpi_scale;
+            }
+        }
+    }
+
+    pub fn set_admonition(&mut self, admonition: Option<AdmonitionKind>) {
+        self.admonition = admonition;
+    }
+
+    pub fn set_checkbox(&mut self, checkbox: Option<Checkbox>) {
+        self.is_checkbox = checkbox.map(Cell::new);
+    }
+
+    /// Where the checkbox of a task list item at `bounds` gets drawn. Scales with the document's
+    /// `zoom` like the rest of the decorations rather than with the text scale
+    pub fn checkbox_bounds(&self, bounds: &Rect, zoom: f32) -> Option<Rect> {
+        self.is_checkbox.as_ref()?;
+        let font_size = self
+            .texts
+            .first()
+            .map_or(DEFAULT_TEXT_SIZE, |text| text.size);
+        let box_size = font_size * self.hidpi_scale * zoom * 0.75;
+        let min = (
+            bounds.pos.0 - box_size - 10.,
+            bounds.pos.1 + bounds.size.1 / 2. - box_size / 2.,
+        );
+        Some(Rect::new(min, (box_size, box_size)))
     }
 
     pub fn set_anchor(&mut self, anchor: Option<String>) {
@@ -52,26 +488,98 @@ impl TextBox {
         self
     }
 
+    pub fn with_font_size(mut self, font_size: f32) -> Self {
+        self.font_size = font_size;
+        self
+    }
+
     pub fn set_align(&mut self, align: Align) {
         self.align = align;
     }
 
-    pub fn find_hoverable<'a, T: GlyphCruncher>(
-        &'a self,
-        glyph_brush: &'a mut T,
+    pub fn set_line_number(&mut self, line_number: Option<LineNumber>) {
+        self.line_number = line_number;
+    }
+
+    pub fn set_block_padding(&mut self, padding: f32) {
+        self.block_padding = padding;
+    }
+
+    /// Whether a background gets drawn behind the box. Code and quote blocks always have one while
+    /// other boxes only do when they're given a color
+    pub fn has_background(&self) -> bool {
+        self.is_code_block || self.is_quote_block.is_some() || self.background_color.is_some()
+    }
+
+    /// Horizontal padding around the text of boxes with a background in physical pixels. Scales
+    /// with the document's `zoom`, but not with the text scale
+    pub fn block_padding(&self, zoom: f32) -> f32 {
+        if self.has_background() {
+            self.block_padding * self.hidpi_scale * zoom
+        } else {
+            0.
+        }
+    }
+
+    /// The width text should wrap at given the width that's available. Leaves room for the
+    /// padding of boxes with a background (see [`TextBox::block_padding()`]) so text stays within
+    /// it
+    pub fn wrap_width(&self, available: f32, zoom: f32) -> f32 {
+        let available = self
+            .float_width
+            .map_or(available, |width| width.min(available));
+        let available = (available - self.block_padding(zoom)).max(0.);
+        self.max_width
+            .map_or(available, |max_width| max_width.min(available))
+    }
+
+    /// The text that the character at `char_offset` (see [`TextBox::char_offset_at`]) belongs to
+    pub fn text_at(&self, char_offset: usize) -> Option<&Text> {
+        let mut start = 0;
+        self.texts.iter().find(|text| {
+            start += text.text.chars().count();
+            char_offset < start
+        })
+    }
+
+    /// Index of the character at `loc` counted in characters across all of the box's texts
+    pub fn char_offset_at<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
         loc: Point,
         screen_position: Point,
         bounds: Size,
         zoom: f32,
-    ) -> Option<&'a Text> {
-        let fonts: Vec<FontArc> = glyph_brush.fonts().to_vec();
-        glyph_brush
-            .glyphs(&self.glyph_section(screen_position, bounds, zoom))
-            .find(|glyph| {
-                let bounds = Rect::from((fonts[glyph.font_id.0]).glyph_bounds(&glyph.glyph));
-                bounds.contains(loc)
-            })
-            .map(|glyph| &self.texts[glyph.section_index])
+    ) -> Option<usize> {
+        let (_, glyph) = self
+            .glyph_bounds(glyph_brush, screen_position, bounds, zoom)
+            .into_iter()
+            .find(|(glyph_bounds, _)| glyph_bounds.contains(loc))?;
+        let preceding: usize = self.texts[..glyph.section_index]
+            .iter()
+            .map(|text| text.text.chars().count())
+            .sum();
+        let text = &self.texts[glyph.section_index].text;
+        Some(preceding + text[..glyph.byte_index].chars().count())
+    }
+
+    /// Finds where the placeholder glyphs of inline images ended up so that the images can be
+    /// drawn over them
+    pub fn position_inline_images<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+    ) {
+        if self.texts.iter().all(|text| text.inline_image.is_none()) {
+            return;
+        }
+        for (glyph_bounds, glyph) in self.glyph_bounds(glyph_brush, screen_position, bounds, zoom) {
+            if let Some(inline_image) = &self.texts[glyph.section_index].inline_image {
+                *inline_image.bounds.borrow_mut() = Some(glyph_bounds);
+            }
+        }
     }
 
     pub fn glyph_bounds<T: GlyphCruncher>(
@@ -83,7 +591,8 @@ impl TextBox {
     ) -> Vec<(Rect, SectionGlyph)> {
         let mut glyph_bounds = Vec::new();
         let fonts: Vec<FontArc> = glyph_brush.fonts().to_vec();
-        for glyph in glyph_brush.glyphs(&self.glyph_section(screen_position, bounds, zoom)) {
+        let section = self.glyph_section(screen_position, bounds, zoom);
+        for glyph in glyph_brush.glyphs_custom_layout(&section, &self.text_layout()) {
             let bounds = Rect::from((fonts[glyph.font_id.0]).glyph_bounds(&glyph.glyph));
             glyph_bounds.push((bounds, glyph.clone()));
         }
@@ -97,36 +606,237 @@ impl TextBox {
         bounds: Size,
         zoom: f32,
     ) -> Size {
+        let padding = self.padding_height * self.hidpi_scale * zoom;
         if self.texts.is_empty() {
-            return (0., self.padding_height * self.hidpi_scale * zoom);
+            return (0., self.empty_line_height(zoom) + padding);
+        }
+
+        let section = self.glyph_section(screen_position, bounds, zoom);
+        if let Some(text_bounds) =
+            glyph_brush.glyph_bounds_custom_layout(&section, &self.text_layout())
+        {
+            // Truncated text takes up all the width it's given when there's more of it than fits
+            let width = if self.truncate {
+                self.natural_width(glyph_brush, zoom).min(bounds.0)
+            } else {
+                text_bounds.width()
+            };
+            (width, text_bounds.height() + padding)
+        } else {
+            (0., self.empty_line_height(zoom) + padding)
+        }
+    }
+
+    // Width of the text laid out on a single line with nothing to stop it
+    fn natural_width<T: GlyphCruncher>(&self, glyph_brush: &mut T, zoom: f32) -> f32 {
+        let section = Section {
+            text: self.texts.iter().map(|t| t.wgpu_text(zoom)).collect(),
+            ..Section::default().with_layout(Layout::default_single_line())
+        };
+        // Keeps any letter spacing
+        let layout = TextBoxLayout {
+            layout: TextLayout::Flow(Layout::default_single_line()),
+            ..self.text_layout()
+        };
+        glyph_brush
+            .glyph_bounds_custom_layout(section, &layout)
+            .map_or(0., |bounds| bounds.width())
+    }
+
+    /// Whether a truncated box has more text than fits within `width`
+    pub fn overflows<T: GlyphCruncher>(&self, glyph_brush: &mut T, width: f32, zoom: f32) -> bool {
+        self.truncate && self.natural_width(glyph_brush, zoom) > width
+    }
+
+    /// Makes room for the ellipsis of a truncated box that overflows `bounds`. Returns the
+    /// narrower bounds to lay the text out in along with where the ellipsis goes, or `None` when
+    /// the text fits
+    pub fn ellipsis<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+    ) -> Option<(Size, Point)> {
+        if !self.overflows(glyph_brush, bounds.0, zoom) {
+            return None;
+        }
+        let ellipsis_width = glyph_brush
+            .glyph_bounds(self.ellipsis_section((0., 0.), zoom))
+            .map_or(0., |bounds| bounds.width());
+        let clipped = ((bounds.0 - ellipsis_width).max(0.), bounds.1);
+        let end = self
+            .glyph_bounds(glyph_brush, screen_position, clipped, zoom)
+            .iter()
+            .fold(screen_position.0, |end, (rect, _)| end.max(rect.max().0));
+        Some((clipped, (end, screen_position.1)))
+    }
+
+    /// An ellipsis styled like the start of the box's text
+    pub fn ellipsis_section(&self, screen_position: Point, zoom: f32) -> Section {
+        let text = match self.texts.first() {
+            Some(first) => wgpu_glyph::Text {
+                text: ELLIPSIS,
+                ..first.wgpu_text(zoom)
+            },
+            None => wgpu_glyph::Text::new(ELLIPSIS),
+        };
+        Section {
+            screen_position,
+            text: vec![text],
+            ..Default::default()
+        }
+    }
+
+    // The height reserved by a box with nothing to lay out (e.g. a blank table cell) so that it
+    // doesn't collapse
+    fn empty_line_height(&self, zoom: f32) -> f32 {
+        self.line_text_size() * self.hidpi_scale * zoom
+    }
+
+    fn line_text_size(&self) -> f32 {
+        self.texts.first().map_or(self.font_size, |text| text.size)
+    }
+
+    fn line_number_text<'a>(&self, number: &'a str, zoom: f32) -> wgpu_glyph::Text<'a> {
+        wgpu_glyph::Text::new(number)
+            .with_scale(self.line_text_size() * self.hidpi_scale * zoom)
+            .with_font_id(fonts::MONOSPACE_REGULAR)
+    }
+
+    /// Width of the gutter that the line number sits in to the left of the code. Measured with
+    /// the widest number of the block so that all of its lines line up
+    pub fn gutter_width<T: GlyphCruncher>(&self, glyph_brush: &mut T, zoom: f32) -> f32 {
+        let line_number = match self.line_number {
+            Some(line_number) => line_number,
+            None => return 0.,
+        };
+        let widest = "0".repeat(line_number.digits);
+        let section = Section {
+            text: vec![self.line_number_text(&widest, zoom)],
+            ..Default::default()
+        };
+        let width = glyph_brush
+            .glyph_bounds(section)
+            .map_or(0., |bounds| bounds.width());
+        width + GUTTER_GAP * self.hidpi_scale * zoom
+    }
+
+    /// The line number right aligned in the gutter to the left of `screen_position`
+    pub fn line_number_section<'a>(
+        &self,
+        number: &'a str,
+        screen_position: Point,
+        zoom: f32,
+        color: [f32; 4],
+    ) -> Section<'a> {
+        let right = screen_position.0 - GUTTER_GAP * self.hidpi_scale * zoom;
+        Section {
+            screen_position: (right, screen_position.1),
+            text: vec![self.line_number_text(number, zoom).with_color(color)],
+            ..wgpu_glyph::Section::default()
+                .with_layout(Layout::default().h_align(HorizontalAlign::Right))
+        }
+    }
+
+    /// The number of an ordered list item right aligned in the indent to the left of
+    /// `screen_position`. It's as big as the item's first line so that their baselines line up
+    pub fn list_number_section<'a>(
+        &self,
+        number: &'a str,
+        screen_position: Point,
+        zoom: f32,
+        color: [f32; 4],
+    ) -> Section<'a> {
+        let right = screen_position.0 - GUTTER_GAP * self.hidpi_scale * zoom;
+        let text = wgpu_glyph::Text::new(number)
+            .with_scale(self.line_text_size() * self.hidpi_scale * zoom)
+            .with_font_id(fonts::TEXT_BOLD)
+            .with_color(color);
+        Section {
+            screen_position: (right, screen_position.1),
+            text: vec![text],
+            ..wgpu_glyph::Section::default()
+                .with_layout(Layout::default().h_align(HorizontalAlign::Right))
         }
+    }
 
-        if let Some(bounds) =
-            glyph_brush.glyph_bounds(&self.glyph_section(screen_position, bounds, zoom))
+    /// Center and radius of an unordered list item's bullet. It sits in the indent to the left of
+    /// `screen_position` at about the middle of the first line's lowercase letters
+    pub fn bullet_position<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+    ) -> (Point, f32) {
+        let font_size = self.line_text_size() * self.hidpi_scale * zoom;
+        let baseline = self
+            .glyph_bounds(glyph_brush, screen_position, bounds, zoom)
+            .first()
+            .map_or(screen_position.1 + font_size * 0.8, |(_, glyph)| {
+                glyph.glyph.position.y
+            });
+        let radius = font_size * BULLET_RADIUS;
+        let center = (
+            screen_position.0 - GUTTER_GAP * self.hidpi_scale * zoom - radius,
+            baseline - font_size * 0.25,
+        );
+        (center, radius)
+    }
+
+    /// What the glyphs of [`TextBox::glyph_section()`] get laid out with. The section's own
+    /// layout only matches this for text that isn't on a grid
+    pub fn text_layout(&self) -> TextBoxLayout {
+        let layout = if self.monospace_grid {
+            TextLayout::Grid
+        } else if self.truncate {
+            TextLayout::Flow(Layout::default_single_line().h_align(self.horizontal_align()))
+        } else {
+            TextLayout::Flow(Layout::default().h_align(self.horizontal_align()))
+        };
+        let vertical_aligns = if self
+            .texts
+            .iter()
+            .all(|text| text.vertical_align == VerticalAlign::Baseline)
         {
-            (
-                bounds.width(),
-                bounds.height() + self.padding_height * self.hidpi_scale * zoom,
-            )
+            Vec::new()
+        } else {
+            self.texts.iter().map(|text| text.vertical_align).collect()
+        };
+        let letter_spacings = if self.texts.iter().all(|text| text.letter_spacing == 0.) {
+            Vec::new()
         } else {
-            (0., self.padding_height * self.hidpi_scale * zoom)
+            self.texts.iter().map(|text| text.letter_spacing).collect()
+        };
+        TextBoxLayout {
+            layout,
+            vertical_aligns,
+            letter_spacings,
+        }
+    }
+
+    fn horizontal_align(&self) -> HorizontalAlign {
+        match self.align {
+            Align::Center => HorizontalAlign::Center,
+            Align::Left => HorizontalAlign::Left,
+            Align::Right => HorizontalAlign::Right,
         }
     }
 
     pub fn glyph_section(&self, mut screen_position: Point, bounds: Size, zoom: f32) -> Section {
         let texts = self.texts.iter().map(|t| t.wgpu_text(zoom)).collect();
 
-        let horizontal_align = match self.align {
-            Align::Center => {
+        let horizontal_align = self.horizontal_align();
+        match horizontal_align {
+            HorizontalAlign::Center => {
                 screen_position = (screen_position.0 + bounds.0 / 2., screen_position.1);
-                HorizontalAlign::Center
             }
-            Align::Left => HorizontalAlign::Left,
-            Align::Right => {
+            HorizontalAlign::Left => {}
+            HorizontalAlign::Right => {
                 screen_position = (bounds.0 + screen_position.0, screen_position.1);
-                HorizontalAlign::Right
             }
-        };
+        }
         Section {
             screen_position,
             bounds,
@@ -136,13 +846,15 @@ impl TextBox {
         }
     }
 
+    /// Underlines and strike-throughs along with the color of their text. Their thickness and the
+    /// underline's distance below the baseline follow the glyphs' size
     pub fn render_lines<T: GlyphCruncher>(
         &self,
         glyph_brush: &mut T,
         screen_position: Point,
         bounds: Size,
         zoom: f32,
-    ) -> Vec<Line> {
+    ) -> Vec<(Rect, [f32; 4])> {
         let mut has_lines = false;
         for text in &self.texts {
             if text.is_striked || text.is_underlined {
@@ -155,21 +867,108 @@ impl TextBox {
         }
         let mut lines = Vec::new();
         for (glyph_bounds, glyph) in self.glyph_bounds(glyph_brush, screen_position, bounds, zoom) {
-            if self.texts[glyph.section_index].is_underlined {
-                lines.push((
-                    (glyph_bounds.pos.0, glyph_bounds.max().1),
-                    (glyph_bounds.max().0, glyph_bounds.max().1),
-                ));
+            let text = &self.texts[glyph.section_index];
+            if text.inline_image.is_some() {
+                continue;
+            }
+            let font_size = glyph.glyph.scale.y;
+            let thickness = (font_size * LINE_THICKNESS).max(1.);
+            let (min_x, max_x) = (glyph_bounds.pos.0, glyph_bounds.max().0);
+            if text.is_underlined {
+                let y = glyph.glyph.position.y + font_size * UNDERLINE_OFFSET;
+                let line = Rect::from_min_max((min_x, y), (max_x, y + thickness));
+                lines.push((line, text.color()));
             }
-            if self.texts[glyph.section_index].is_striked {
-                let mid_height = glyph_bounds.pos.1 + glyph_bounds.size.1 / 2.;
-                lines.push((
-                    (glyph_bounds.pos.0, mid_height),
-                    (glyph_bounds.max().0, mid_height),
-                ));
+            if text.is_striked {
+                let y = glyph_bounds.pos.1 + glyph_bounds.size.1 / 2. - thickness / 2.;
+                let line = Rect::from_min_max((min_x, y), (max_x, y + thickness));
+                lines.push((line, text.color()));
+            }
+        }
+
+        lines
+    }
+
+    /// Backgrounds behind styled, inserted and deleted text. Neighboring glyphs of the same color
+    /// on the same line share a single rect
+    pub fn text_highlights<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+        change_color: impl Fn(Change) -> [f32; 4],
+    ) -> Vec<(Rect, [f32; 4])> {
+        if self
+            .texts
+            .iter()
+            .all(|text| text.change.is_none() && text.background.is_none())
+        {
+            return Vec::new();
+        }
+        let mut highlights: Vec<(Rect, [f32; 4])> = Vec::new();
+        let mut in_run = false;
+        for (glyph_bounds, glyph) in self.glyph_bounds(glyph_brush, screen_position, bounds, zoom) {
+            let text = &self.texts[glyph.section_index];
+            let color = match text.background.or_else(|| text.change.map(&change_color)) {
+                Some(color) => color,
+                None => {
+                    in_run = false;
+                    continue;
+                }
+            };
+            match highlights.last_mut() {
+                Some((rect, last_color))
+                    if in_run
+                        && *last_color == color
+                        && glyph_bounds.pos.1 < rect.max().1
+                        && rect.pos.1 < glyph_bounds.max().1 =>
+                {
+                    *rect = rect.union(&glyph_bounds);
+                }
+                _ => highlights.push((glyph_bounds, color)),
             }
+            in_run = true;
         }
+        highlights
+    }
 
+    /// Bounds of each line of the link under `loc`. Glyphs of the link that are next to each other
+    /// on a line share a single rect
+    pub fn link_run_at<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        loc: Point,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+    ) -> Vec<Rect> {
+        if self.texts.iter().all(|text| text.link.is_none()) {
+            return Vec::new();
+        }
+        let glyphs = self.glyph_bounds(glyph_brush, screen_position, bounds, zoom);
+        let link_of = |index: usize| self.texts[glyphs[index].1.section_index].link.as_ref();
+        let hovered = glyphs
+            .iter()
+            .position(|(glyph_bounds, _)| glyph_bounds.contains(loc));
+        let (hovered, link) = match hovered.and_then(|index| Some((index, link_of(index)?))) {
+            Some(hovered) => hovered,
+            None => return Vec::new(),
+        };
+        // The run spreads out from the hovered glyph for as long as the link stays the same
+        let mut start = hovered;
+        while start > 0 && link_of(start - 1) == Some(link) {
+            start -= 1;
+        }
+        let mut end = hovered + 1;
+        while end < glyphs.len() && link_of(end) == Some(link) {
+            end += 1;
+        }
+
+        let mut lines = Vec::new();
+        for (glyph_bounds, _) in &glyphs[start..end] {
+            join_lines(&mut lines, glyph_bounds);
+        }
         lines
     }
 
@@ -179,73 +978,401 @@ impl TextBox {
         screen_position: Point,
         bounds: Size,
         zoom: f32,
-        mut selection: Selection,
-    ) -> (Vec<Rect>, String) {
-        let mut selection_rects = Vec::new();
-        let mut selection_text = String::new();
-        if selection.0 == selection.1 {
-            return (selection_rects, selection_text);
+        selection: TextSelection,
+        selection_text: &mut String,
+    ) -> Vec<Rect> {
+        if self.texts.is_empty() {
+            return Vec::new();
         }
-        if selection.0 .1 > selection.1 .1 {
-            std::mem::swap(&mut selection.0, &mut selection.1);
+
+        let key = SelectionKey {
+            screen_position,
+            bounds,
+            zoom,
+            selection,
+        };
+        if let Some(cached) = self.selection_cache.borrow().as_ref() {
+            if cached.key == key {
+                selection_text.push_str(&cached.text);
+                return cached.rects.clone();
+            }
         }
-        let rect = Rect::new(screen_position, bounds);
-        if rect.contains(selection.0) {
-            for (glyph_bounds, glyph) in
-                self.glyph_bounds(glyph_brush, screen_position, bounds, zoom)
-            {
-                if (glyph_bounds.pos.1 >= selection.0 .1 && glyph_bounds.max().1 <= selection.1 .1)
-                    || (glyph_bounds.max().1 <= selection.1 .1
-                        && glyph_bounds.max().1 >= selection.0 .1
-                        && glyph_bounds.max().0 >= selection.0 .0)
-                    || (glyph_bounds.max().1 >= selection.1 .1
-                        && glyph_bounds.pos.1 <= selection.0 .1
-                        && glyph_bounds.pos.0 <= selection.0 .0.max(selection.1 .0)
-                        && glyph_bounds.max().0 >= selection.0 .0.min(selection.1 .0))
-                {
-                    selection_rects.push(glyph_bounds);
-                    if let Some(char) = self.texts[glyph.section_index]
-                        .text
-                        .chars()
-                        .nth(glyph.byte_index)
-                    {
-                        selection_text.push(char);
-                    }
+
+        let mut text = String::new();
+        let rects = self.compute_selection(
+            glyph_brush,
+            screen_position,
+            bounds,
+            zoom,
+            selection,
+            &mut text,
+        );
+        selection_text.push_str(&text);
+        *self.selection_cache.borrow_mut() = Some(CachedSelection {
+            key,
+            rects: rects.clone(),
+            text,
+        });
+        rects
+    }
+
+    fn compute_selection<T: GlyphCruncher>(
+        &self,
+        glyph_brush: &mut T,
+        screen_position: Point,
+        bounds: Size,
+        zoom: f32,
+        selection: TextSelection,
+        selection_text: &mut String,
+    ) -> Vec<Rect> {
+        let mut selection_rects = Vec::new();
+        let mut text = String::new();
+        for (glyph_bounds, glyph) in self.glyph_bounds(glyph_brush, screen_position, bounds, zoom) {
+            if selection.contains(&glyph_bounds) {
+                selection_rects.push(glyph_bounds);
+                // Inline images get highlighted, but their placeholder isn't text to copy
+                let selected = &self.texts[glyph.section_index];
+                if selected.inline_image.is_some() {
+                    continue;
+                }
+                if let Some(char) = selected.text.chars().nth(glyph.byte_index) {
+                    text.push(char);
                 }
             }
+        }
+        // Whitespace doesn't always get a glyph, so fully selected boxes take their text as is.
+        // The same goes for truncated boxes which would otherwise lose the text that got cut off
+        if selection == TextSelection::Whole || (self.truncate && !selection_rects.is_empty()) {
+            text = self
+                .texts
+                .iter()
+                .filter(|text| text.inline_image.is_none())
+                .map(|text| text.text.as_str())
+                .collect();
+        }
+        if !selection_rects.is_empty() {
+            selection_text.push_str(&text);
             selection_text.push('\n');
         }
-        if rect.pos.1 >= selection.0 .1.min(selection.1 .1)
-            && rect.max().1 <= selection.0 .1.max(selection.1 .1)
-        {
-            selection_rects.push(rect.clone());
-            for text in &self.texts {
-                selection_text.push_str(&text.text);
+        selection_rects
+    }
+}
+
+/// The part of a single text box covered by a selection. Selections run in document order, so
+/// only the boxes holding the ends of a selection are partially selected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextSelection {
+    /// Both ends of the selection are within the box
+    Between(Point, Point),
+    /// The selection starts at the point and continues past the end of the box
+    From(Point),
+    /// The selection starts before the box and ends at the point
+    To(Point),
+    /// The box is between the ends of the selection
+    Whole,
+}
+
+impl TextSelection {
+    pub fn contains(&self, glyph: &Rect) -> bool {
+        match *self {
+            Self::Between(start, end) => precedes(glyph, start) != precedes(glyph, end),
+            Self::From(start) => !precedes(glyph, start),
+            Self::To(end) => precedes(glyph, end),
+            Self::Whole => true,
+        }
+    }
+}
+
+// Whether the glyph comes before the point in reading order. Glyphs on lines above the point come
+// first along with glyphs on the point's line that are mostly to its left
+fn precedes(glyph: &Rect, point: Point) -> bool {
+    glyph.max().1 <= point.1
+        || (glyph.pos.1 <= point.1 && glyph.pos.0 + glyph.size.0 / 2. < point.0)
+}
+
+/// The type of a GitHub-style callout like `> [!NOTE]`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdmonitionKind {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl AdmonitionKind {
+    /// Parses the `[!KIND]` marker that starts a callout. Matching is case-insensitive like on
+    /// GitHub
+    pub fn from_marker(marker: &str) -> Option<Self> {
+        let kind = marker.strip_prefix("[!")?.strip_suffix(']')?;
+        match kind.to_ascii_lowercase().as_str() {
+            "note" => Some(Self::Note),
+            "tip" => Some(Self::Tip),
+            "important" => Some(Self::Important),
+            "warning" => Some(Self::Warning),
+            "caution" => Some(Self::Caution),
+            _ => None,
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Note => "Note",
+            Self::Tip => "Tip",
+            Self::Important => "Important",
+            Self::Warning => "Warning",
+            Self::Caution => "Caution",
+        }
+    }
+
+    pub fn icon(self) -> char {
+        match self {
+            Self::Note => '\u{2139}',
+            Self::Tip => '\u{2713}',
+            Self::Important => '\u{2731}',
+            Self::Warning => '\u{26a0}',
+            Self::Caution => '\u{2298}',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Checkbox {
+    #[default]
+    Unchecked,
+    Checked,
+    /// Some but not all of a task's subtasks are checked
+    Indeterminate,
+}
+
+impl From<bool> for Checkbox {
+    fn from(is_checked: bool) -> Self {
+        if is_checked {
+            Self::Checked
+        } else {
+            Self::Unchecked
+        }
+    }
+}
+
+impl Checkbox {
+    fn from_subtasks(subtasks: &[Checkbox]) -> Self {
+        if subtasks.iter().all(|&subtask| subtask == Self::Checked) {
+            Self::Checked
+        } else if subtasks.iter().all(|&subtask| subtask == Self::Unchecked) {
+            Self::Unchecked
+        } else {
+            Self::Indeterminate
+        }
+    }
+}
+
+/// Derives the checkbox of every task that has subtasks from those subtasks. A task's subtasks
+/// are the tasks indented under it up until the next text that's indented as far as it or less
+pub fn resolve_checkboxes(elements: &[Positioned<Element>]) {
+    // Tasks that could still get subtasks along with the states of their subtasks so far
+    let mut open_tasks = Vec::new();
+    for element in elements {
+        match &element.inner {
+            Element::TextBox(text_box) => {
+                close_tasks(&mut open_tasks, text_box.indent);
+                if let Some(checkbox) = &text_box.is_checkbox {
+                    open_tasks.push((text_box.indent, checkbox, Vec::new()));
+                }
             }
-            selection_text.push('\n');
+            Element::Row(row) => resolve_checkboxes(&row.elements),
+            Element::Section(section) => resolve_checkboxes(&section.elements),
+            _ => {}
         }
-        if rect.contains(selection.1) {
-            for (glyph_bounds, glyph) in
-                self.glyph_bounds(glyph_brush, screen_position, bounds, zoom)
-            {
-                if (glyph_bounds.pos.1 >= selection.0 .1 && glyph_bounds.max().1 <= selection.1 .1)
-                    || (glyph_bounds.pos.1 <= selection.1 .1
-                        && glyph_bounds.pos.1 >= selection.0 .1
-                        && glyph_bounds.pos.0 <= selection.1 .0)
-                {
-                    selection_rects.push(glyph_bounds);
-                    if let Some(char) = self.texts[glyph.section_index]
-                        .text
-                        .chars()
-                        .nth(glyph.byte_index)
-                    {
-                        selection_text.push(char);
+    }
+    close_tasks(&mut open_tasks, f32::NEG_INFINITY);
+}
+
+/// Toggles `task` along with all of its subtasks, so that resolving the checkboxes again keeps
+/// the new state instead of deriving the old one back from the subtasks. Returns every task whose
+/// checkbox changed, including the tasks above it
+pub fn toggle_task<'a>(elements: &'a [Positioned<Element>], task: &TextBox) -> Vec<&'a TextBox> {
+    let mut tasks = Vec::new();
+    collect_tasks(elements, &mut tasks);
+    let before: Vec<_> = tasks.iter().map(|&(_, checkbox)| checkbox.get()).collect();
+
+    let checked = task.is_checkbox.as_ref().map(Cell::get) != Some(Checkbox::Checked);
+    set_subtree(elements, task, checked.into());
+    resolve_checkboxes(elements);
+
+    tasks
+        .into_iter()
+        .zip(before)
+        .filter(|((_, checkbox), before)| checkbox.get() != *before)
+        .map(|((text_box, _), _)| text_box)
+        .collect()
+}
+
+// Every task in the document along with its checkbox
+fn collect_tasks<'a>(
+    elements: &'a [Positioned<Element>],
+    tasks: &mut Vec<(&'a TextBox, &'a Cell<Checkbox>)>,
+) {
+    for element in elements {
+        match &element.inner {
+            Element::TextBox(text_box) => {
+                if let Some(checkbox) = &text_box.is_checkbox {
+                    tasks.push((text_box, checkbox));
+                }
+            }
+            Element::Row(row) => collect_tasks(&row.elements, tasks),
+            Element::Section(section) => collect_tasks(&section.elements, tasks),
+            _ => {}
+        }
+    }
+}
+
+// Sets the checkbox of `task` and of every subtask under it. Returns whether `task` was found
+fn set_subtree(elements: &[Positioned<Element>], task: &TextBox, state: Checkbox) -> bool {
+    let text_boxes: Vec<&TextBox> = elements
+        .iter()
+        .filter_map(|element| match &element.inner {
+            Element::TextBox(text_box) => Some(text_box),
+            _ => None,
+        })
+        .collect();
+    if let Some(index) = text_boxes
+        .iter()
+        .position(|&text_box| std::ptr::eq(text_box, task))
+    {
+        let subtasks = text_boxes[index + 1..]
+            .iter()
+            .take_while(|text_box| text_box.indent > task.indent);
+        for text_box in iter::once(&task).chain(subtasks) {
+            if let Some(checkbox) = &text_box.is_checkbox {
+                checkbox.set(state);
+            }
+        }
+        return true;
+    }
+    elements.iter().any(|element| match &element.inner {
+        Element::Row(row) => set_subtree(&row.elements, task, state),
+        Element::Section(section) => set_subtree(&section.elements, task, state),
+        _ => false,
+    })
+}
+
+// Finishes every open task indented at least as far as `indent`
+fn close_tasks(open_tasks: &mut Vec<(f32, &Cell<Checkbox>, Vec<Checkbox>)>, indent: f32) {
+    while open_tasks
+        .last()
+        .map_or(false, |&(task_indent, ..)| task_indent >= indent)
+    {
+        let (_, checkbox, subtasks) = open_tasks.pop().unwrap();
+        if !subtasks.is_empty() {
+            checkbox.set(Checkbox::from_subtasks(&subtasks));
+        }
+        if let Some((.., parent_subtasks)) = open_tasks.last_mut() {
+            parent_subtasks.push(checkbox.get());
+        }
+    }
+}
+
+/// Adds a glyph to the bounds of the lines that a run of glyphs spans. It grows the last line when
+/// it's on the same line and starts a new one otherwise
+pub fn join_lines(lines: &mut Vec<Rect>, glyph_bounds: &Rect) {
+    match lines.last_mut() {
+        Some(rect) if glyph_bounds.pos.1 < rect.max().1 && rect.pos.1 < glyph_bounds.max().1 => {
+            *rect = rect.union(glyph_bounds);
+        }
+        _ => lines.push(glyph_bounds.clone()),
+    }
+}
+
+/// The text of the first top level heading. Documents go by it in the window's title
+pub fn document_title(elements: &[Positioned<Element>]) -> Option<String> {
+    elements.iter().find_map(|element| match &element.inner {
+        Element::TextBox(text_box) if text_box.heading_level == Some(1) => {
+            let title: String = text_box
+                .texts
+                .iter()
+                .map(|text| text.text.as_str())
+                .collect();
+            let title = title.trim();
+            (!title.is_empty()).then(|| title.to_owned())
+        }
+        _ => None,
+    })
+}
+
+/// What a link in the document at `file_path` gets remembered as once it's visited. Relative
+/// paths and anchors only mean something within their document, so they're resolved against it
+pub fn visited_link(link: &str, file_path: &Path) -> String {
+    if link.starts_with('#') {
+        format!("{}{}", file_path.display(), link)
+    } else if link.contains("://") || link.starts_with("mailto:") {
+        link.to_owned()
+    } else {
+        let base = file_path.parent().unwrap_or_else(|| Path::new(""));
+        let mut resolved = PathBuf::new();
+        // Resolved without touching the filesystem since the file may not exist
+        for component in base.join(link).components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !resolved.pop() {
+                        resolved.push(component);
                     }
                 }
+                _ => resolved.push(component),
             }
-            selection_text.push('\n');
         }
-        (selection_rects, selection_text)
+        resolved.display().to_string()
+    }
+}
+
+/// Recolors the links in `elements` that lead to one of the `visited` links of the document at
+/// `file_path`
+pub fn mark_visited(
+    elements: &[Positioned<Element>],
+    visited: &BTreeSet<String>,
+    file_path: &Path,
+) {
+    for element in elements {
+        match &element.inner {
+            Element::TextBox(text_box) => {
+                for text in &text_box.texts {
+                    if let Some(link) = &text.link {
+                        text.is_visited
+                            .set(visited.contains(&visited_link(link, file_path)));
+                    }
+                }
+            }
+            Element::Table(table) => {
+                for cell in table.cells() {
+                    mark_visited(std::slice::from_ref(cell), visited, file_path);
+                }
+            }
+            Element::Row(row) => mark_visited(&row.elements, visited, file_path),
+            Element::Section(section) => {
+                if let Some(summary) = &*section.summary {
+                    mark_visited(std::slice::from_ref(summary), visited, file_path);
+                }
+                mark_visited(&section.elements, visited, file_path);
+            }
+            Element::Image(_) | Element::Spacer(_) => {}
+        }
+    }
+}
+
+/// Colors that links are drawn in depending on whether they've been followed before
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkColors {
+    pub link: [f32; 4],
+    pub visited: [f32; 4],
+}
+
+impl From<&Theme> for LinkColors {
+    fn from(theme: &Theme) -> Self {
+        Self {
+            link: theme.link_color,
+            visited: theme.visited_link_color,
+        }
     }
 }
 
@@ -262,13 +1389,84 @@ pub struct Text {
     pub font: usize,
     pub hidpi_scale: f32,
     pub default_color: [f32; 4],
+    pub inline_image: Option<InlineImage>,
+    /// Used over the text's own color for links
+    pub link_colors: Option<LinkColors>,
+    /// Whether the link has been followed before. See [`mark_visited`]
+    pub is_visited: Cell<bool>,
+    /// Set for text within `<ins>`, `<del>` or `<mark>`
+    pub change: Option<Change>,
+    /// Fill behind the text picked by the `style` of a `<span>`
+    pub background: Option<[f32; 4]>,
+    /// Raises or lowers the text (at a smaller size) for superscripts and subscripts
+    pub vertical_align: VerticalAlign,
+    /// Weight from 100 to 900 that overrides the regular weight. See [`Text::weight`]
+    pub weight: Option<u16>,
+    /// Extra space after every glyph in ems. Spaced out text gets laid out by [`TextBoxLayout`]
+    pub letter_spacing: f32,
+}
+
+/// Weights that body text and headings get drawn at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FontWeights {
+    pub body: u16,
+    pub heading: u16,
+}
+
+impl Default for FontWeights {
+    fn default() -> Self {
+        Self {
+            body: REGULAR_WEIGHT,
+            heading: BOLD_WEIGHT,
+        }
+    }
+}
+
+/// Where text sits relative to the baseline of the text around it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum VerticalAlign {
+    #[default]
+    Baseline,
+    Super,
+    Sub,
+}
+
+/// Whether text was marked as added, removed or highlighted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Inserted,
+    Deleted,
+    Marked,
+}
+
+/// An image that flows within a line of text at the height of the text around it. It's laid out
+/// as a single glyph, so it gets selected as a whole
+#[derive(Debug, Clone)]
+pub struct InlineImage {
+    pub image: Image,
+    // Where the placeholder glyph was last laid out in document coordinates
+    bounds: RefCell<Option<Rect>>,
+}
+
+impl InlineImage {
+    pub fn bounds(&self) -> Option<Rect> {
+        self.bounds.borrow().clone()
+    }
+
+    // Images are treated as square until they finish decoding
+    fn aspect_ratio(&self) -> f32 {
+        match self.image.buffer_dimensions() {
+            (width, height) if width > 0 && height > 0 => width as f32 / height as f32,
+            _ => 1.,
+        }
+    }
 }
 
 impl Text {
     pub fn new(text: String, hidpi_scale: f32, default_text_color: [f32; 4]) -> Self {
         Self {
             text,
-            size: 16.,
+            size: DEFAULT_TEXT_SIZE,
             hidpi_scale,
             default_color: default_text_color,
             ..Default::default()
@@ -280,6 +1478,11 @@ impl Text {
         self
     }
 
+    pub fn with_vertical_align(mut self, vertical_align: VerticalAlign) -> Self {
+        self.vertical_align = vertical_align;
+        self
+    }
+
     pub fn with_color(mut self, color: [f32; 4]) -> Self {
         self.color = Some(color);
         self
@@ -290,6 +1493,24 @@ impl Text {
         self
     }
 
+    /// Links are drawn in their own colors over any color given so far. Colors given after
+    /// still win
+    pub fn with_link_colors(mut self, link_colors: LinkColors) -> Self {
+        self.link_colors = Some(link_colors);
+        self.color = None;
+        self
+    }
+
+    pub fn with_letter_spacing(mut self, letter_spacing: f32) -> Self {
+        self.letter_spacing = letter_spacing;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: u16) -> Self {
+        self.weight = Some(weight);
+        self
+    }
+
     pub fn make_bold(mut self, bold: bool) -> Self {
         self.is_bold = bold;
         self
@@ -310,39 +1531,123 @@ impl Text {
         self
     }
 
+    pub fn with_change(mut self, change: Option<Change>) -> Self {
+        self.change = change;
+        self
+    }
+
+    pub fn with_background(mut self, background: Option<[f32; 4]>) -> Self {
+        self.background = background;
+        self
+    }
+
     pub fn with_font(mut self, font_index: usize) -> Self {
         self.font = font_index;
         self
     }
 
-    fn font_id(&self) -> FontId {
-        let base = self.font * 4;
-        let font = if self.is_bold {
-            if self.is_italic {
-                base + 3
-            } else {
-                base + 2
+    /// Replaces the text with `image` which gets drawn in its place
+    pub fn with_inline_image(mut self, image: Image) -> Self {
+        self.text = INLINE_IMAGE_PLACEHOLDER.to_owned();
+        self.inline_image = Some(InlineImage {
+            image,
+            bounds: RefCell::default(),
+        });
+        self
+    }
+
+    /// Splits the text into runs so that emoji get drawn with the emoji fallback font
+    pub fn split_emoji(self) -> Vec<Text> {
+        if !self.text.chars().any(is_emoji) {
+            return vec![self];
+        }
+
+        let mut runs: Vec<Text> = Vec::new();
+        let mut run = String::new();
+        let mut run_is_emoji = false;
+        for c in self.text.chars() {
+            let c_is_emoji = is_emoji(c);
+            if c_is_emoji != run_is_emoji && !run.is_empty() {
+                runs.push(self.with_run(std::mem::take(&mut run), run_is_emoji));
             }
-        } else if self.is_italic {
-            base + 1
-        } else {
-            base
+            run_is_emoji = c_is_emoji;
+            run.push(c);
+        }
+        if !run.is_empty() {
+            runs.push(self.with_run(run, run_is_emoji));
+        }
+        runs
+    }
+
+    fn with_run(&self, text: String, is_emoji: bool) -> Text {
+        let run = Text {
+            text,
+            ..self.clone()
         };
-        FontId(font)
+        if is_emoji {
+            run.with_font(fonts::EMOJI_FONT)
+        } else {
+            run
+        }
+    }
+
+    /// The requested weight where bold text is at least [`BOLD_WEIGHT`]
+    pub fn weight(&self) -> u16 {
+        let weight = self.weight.unwrap_or(REGULAR_WEIGHT);
+        if self.is_bold {
+            weight.max(BOLD_WEIGHT)
+        } else {
+            weight
+        }
     }
 
-    fn color(&self) -> [f32; 4] {
-        if let Some(color) = self.color {
+    // Only the regular and bold faces of a family get loaded, so the weight picks whichever one is
+    // nearest to it. Ties go to the regular face. Variable fonts get loaded as these same faces
+    // without setting their weight axis (the pinned `ab_glyph` can't set variation coordinates),
+    // so they snap to the nearest face as well
+    fn font_id(&self) -> FontId {
+        let weight = self.weight();
+        let is_bold = weight.abs_diff(BOLD_WEIGHT) < weight.abs_diff(REGULAR_WEIGHT);
+        fonts::font_id(self.font, is_bold, self.is_italic)
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        if self.inline_image.is_some() {
+            // The image gets drawn over the placeholder instead
+            [0.; 4]
+        } else if let Some(color) = self.color {
             color
+        } else if let (Some(_), Some(link_colors)) = (&self.link, self.link_colors) {
+            // Picked as the text gets drawn so that following a link recolors it right away
+            if self.is_visited.get() {
+                link_colors.visited
+            } else {
+                link_colors.link
+            }
         } else {
             self.default_color
         }
     }
 
+    fn scale(&self, zoom: f32) -> PxScale {
+        let mut size = self.size * self.hidpi_scale * zoom;
+        if self.vertical_align != VerticalAlign::Baseline {
+            size *= SCRIPT_SCALE;
+        }
+        match &self.inline_image {
+            // Stretches the placeholder to the width of the image
+            Some(inline_image) => PxScale {
+                x: size * inline_image.aspect_ratio(),
+                y: size,
+            },
+            None => PxScale::from(size),
+        }
+    }
+
     pub fn wgpu_text(&self, zoom: f32) -> wgpu_glyph::Text {
         wgpu_glyph::Text {
             text: &self.text,
-            scale: PxScale::from(self.size * self.hidpi_scale * zoom),
+            scale: self.scale(zoom),
             font_id: self.font_id(),
             extra: Extra {
                 color: self.color(),
@@ -351,3 +1656,448 @@ impl Text {
         }
     }
 }
+
+// Rough check covering the common emoji blocks. Zero width joiners, variation selectors and
+// keycaps are included so that they stay attached to the emoji they modify
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x200D | 0xFE0F | 0x20E3
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        borrow::Cow,
+        collections::BTreeSet,
+        hash::Hash,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use super::{
+        mark_visited, resolve_checkboxes, toggle_task, visited_link, Checkbox, LineNumber,
+        LinkColors, Text, TextBox, TextSelection, BOLD_WEIGHT, DEFAULT_TEXT_SIZE, GUTTER_GAP,
+    };
+    use crate::fonts::{self, EMOJI_FONT};
+    use crate::image::Image;
+    use crate::opts::FontOptions;
+    use crate::positioner::{Positioned, Row};
+    use crate::utils::Rect;
+    use glyph_brush::{GlyphBrush, GlyphBrushBuilder};
+    use wgpu_glyph::{
+        ab_glyph::{self, FontArc},
+        GlyphCruncher, GlyphPositioner, Section, SectionGlyphIter,
+    };
+
+    /// Lays out every section as if it had no glyphs so that sizing can be tested without fonts
+    pub(crate) struct NoGlyphs;
+
+    impl GlyphCruncher for NoGlyphs {
+        fn glyphs_custom_layout<'a, 'b, S, L>(
+            &'b mut self,
+            _section: S,
+            _custom_layout: &L,
+        ) -> SectionGlyphIter<'b>
+        where
+            L: GlyphPositioner + Hash,
+            S: Into<Cow<'a, Section<'a>>>,
+        {
+            [].iter()
+        }
+
+        fn glyph_bounds_custom_layout<'a, S, L>(
+            &mut self,
+            _section: S,
+            _custom_layout: &L,
+        ) -> Option<ab_glyph::Rect>
+        where
+            L: GlyphPositioner + Hash,
+            S: Into<Cow<'a, Section<'a>>>,
+        {
+            None
+        }
+
+        fn fonts(&self) -> &[FontArc] {
+            &[]
+        }
+    }
+
+    #[test]
+    fn empty_reserves_line() {
+        let bounds = (100., f32::INFINITY);
+        let empty = TextBox::new(Vec::new(), 2.);
+        assert_eq!(
+            empty.size(&mut NoGlyphs, (0., 0.), bounds, 1.5),
+            (0., DEFAULT_TEXT_SIZE * 2. * 1.5)
+        );
+
+        let blank_code_line = TextBox::new(Vec::new(), 1.).with_font_size(18.);
+        assert_eq!(
+            blank_code_line.size(&mut NoGlyphs, (0., 0.), bounds, 1.),
+            (0., 18.)
+        );
+    }
+
+    #[test]
+    fn char_offsets_lead_to_their_text() {
+        let text = |text: &str| Text::new(text.to_owned(), 1., [1.; 4]);
+        let text_box = TextBox::new(vec![text("ab"), text("é"), text("cd")], 1.);
+        let text_at = |offset| text_box.text_at(offset).map(|text| text.text.as_str());
+        assert_eq!(text_at(1), Some("ab"));
+        assert_eq!(text_at(2), Some("é"));
+        assert_eq!(text_at(3), Some("cd"));
+        assert_eq!(text_at(5), None);
+    }
+
+    #[test]
+    fn inline_images_stay_out_of_copied_text() {
+        let fonts =
+            fonts::get_fonts(&FontOptions::default()).expect("Laying out text needs a system font");
+        let mut glyph_brush = GlyphBrushBuilder::using_fonts(fonts).build::<()>();
+        let text = |text: &str| Text::new(text.to_owned(), 1., [1.; 4]);
+        let image = Image::from_image_data(Arc::new(Mutex::new(None)), 1.);
+        let text_box = TextBox::new(
+            vec![text("see"), text("").with_inline_image(image), text("here")],
+            1.,
+        );
+        let selected_text = |selection| {
+            let mut selected = String::new();
+            text_box.render_selection(
+                &mut glyph_brush,
+                (0., 0.),
+                (1_000., f32::INFINITY),
+                1.,
+                selection,
+                &mut selected,
+            );
+            selected
+        };
+        assert_eq!(selected_text(TextSelection::Whole), "seehere\n");
+        assert_eq!(selected_text(TextSelection::From((0., 0.))), "seehere\n");
+    }
+
+    #[test]
+    fn gutter_fits_the_widest_line_number() {
+        // Measuring the line numbers needs a system font
+        let fonts = match fonts::get_fonts(&FontOptions::default()) {
+            Ok(fonts) => fonts,
+            Err(_) => return,
+        };
+        let mut glyph_brush = GlyphBrushBuilder::using_fonts(fonts).build::<()>();
+        let code = |line_number| {
+            let mut text_box = TextBox::new(vec![Text::new("code".to_owned(), 1., [1.; 4])], 1.);
+            text_box.set_line_number(line_number);
+            text_box
+        };
+        assert_eq!(code(None).gutter_width(&mut glyph_brush, 1.), 0.);
+
+        let mut digits = |digits, zoom| {
+            let line_number = LineNumber { number: 1, digits };
+            let gutter = code(Some(line_number)).gutter_width(&mut glyph_brush, zoom);
+            gutter - GUTTER_GAP * zoom
+        };
+        let one = digits(1, 1.);
+        assert!(one > 0.);
+        // Digits of the monospace font are all as wide, so every line of a block lines up
+        assert!((digits(3, 1.) - 3. * one).abs() < 0.5, "{one}");
+        assert!((digits(1, 2.) - 2. * one).abs() < 0.5, "{one}");
+    }
+
+    #[test]
+    fn empty_ignores_selection() {
+        let empty = TextBox::new(Vec::new(), 1.);
+        let bounds = (100., f32::INFINITY);
+        let mut selected = String::new();
+        let rects = empty.render_selection(
+            &mut NoGlyphs,
+            (0., 0.),
+            bounds,
+            1.,
+            TextSelection::Whole,
+            &mut selected,
+        );
+        assert!(rects.is_empty());
+        assert!(selected.is_empty());
+        assert!(empty
+            .char_offset_at(&mut NoGlyphs, (1., 1.), (0., 0.), bounds, 1.)
+            .is_none());
+    }
+
+    fn glyph_positions(
+        glyph_brush: &mut GlyphBrush<()>,
+        letter_spacing: f32,
+        width: f32,
+    ) -> Vec<(f32, f32)> {
+        let text =
+            Text::new("spaced out".to_owned(), 1., [1.; 4]).with_letter_spacing(letter_spacing);
+        TextBox::new(vec![text], 1.)
+            .glyph_bounds(glyph_brush, (0., 0.), (width, f32::INFINITY), 1.)
+            .into_iter()
+            .map(|(_, glyph)| (glyph.glyph.position.x, glyph.glyph.position.y))
+            .collect()
+    }
+
+    #[test]
+    fn letter_spacing_spreads_glyphs_apart() {
+        let fonts =
+            fonts::get_fonts(&FontOptions::default()).expect("Laying out text needs a system font");
+        let mut glyph_brush = GlyphBrushBuilder::using_fonts(fonts).build();
+
+        let plain = glyph_positions(&mut glyph_brush, 0., 1_000.);
+        let spaced = glyph_positions(&mut glyph_brush, 0.5, 1_000.);
+        // Every glyph of the first word is half an em further along than the one before it
+        for (index, (plain, spaced)) in plain.iter().zip(&spaced).take("spaced".len()).enumerate() {
+            let expected = plain.0 + index as f32 * DEFAULT_TEXT_SIZE / 2.;
+            assert!((spaced.0 - expected).abs() < 0.01, "{spaced:?} {expected}");
+            assert_eq!(spaced.1, plain.1);
+        }
+
+        // Room for the plain line isn't enough for the spaced one, so its last word wraps
+        let width = plain.last().unwrap().0 + DEFAULT_TEXT_SIZE;
+        assert!(glyph_positions(&mut glyph_brush, 0., width)
+            .iter()
+            .all(|glyph| glyph.1 == plain[0].1));
+        let wrapped = glyph_positions(&mut glyph_brush, 0.5, width);
+        assert_eq!(wrapped[0].0, spaced[0].0);
+        assert!(wrapped.last().unwrap().1 > wrapped[0].1);
+    }
+
+    #[test]
+    fn weights_pick_the_nearest_face() {
+        let text = |weight| Text::new("text".to_owned(), 1., [1.; 4]).with_weight(weight);
+        assert_eq!(text(350).font_id().0, 0);
+        assert_eq!(text(550).font_id().0, 0);
+        assert_eq!(text(600).font_id().0, 2);
+        assert_eq!(text(900).make_italic(true).font_id().0, 3);
+        // Bold text doesn't get any lighter than the bold face
+        let bold = text(300).make_bold(true);
+        assert_eq!(bold.weight(), BOLD_WEIGHT);
+        assert_eq!(bold.with_font(fonts::MONOSPACE_FONT).font_id().0, 6);
+        assert_eq!(fonts::MONOSPACE_REGULAR.0, 4);
+        assert_eq!(fonts::TEXT_BOLD.0, 2);
+    }
+
+    #[test]
+    fn split_emoji() {
+        let text = Text::new("Ship it 🚀 now 👍🏽!".to_owned(), 1., [1.; 4]).make_bold(true);
+        let runs: Vec<_> = text
+            .split_emoji()
+            .into_iter()
+            .map(|run| (run.text, run.font, run.is_bold))
+            .collect();
+        assert_eq!(
+            runs,
+            vec![
+                ("Ship it ".to_owned(), 0, true),
+                ("🚀".to_owned(), EMOJI_FONT, true),
+                (" now ".to_owned(), 0, true),
+                ("👍🏽".to_owned(), EMOJI_FONT, true),
+                ("!".to_owned(), 0, true),
+            ]
+        );
+
+        let plain = Text::new("No emoji here".to_owned(), 1., [1.; 4]);
+        assert_eq!(plain.split_emoji().len(), 1);
+    }
+
+    #[test]
+    fn selection_in_reading_order() {
+        // Two lines of three 10x10 glyphs
+        let glyphs: Vec<_> = (0..2)
+            .flat_map(|line| {
+                (0..3).map(move |col| Rect::new((col as f32 * 10., line as f32 * 10.), (10., 10.)))
+            })
+            .collect();
+        let selected = |selection: TextSelection| -> Vec<usize> {
+            (0..glyphs.len())
+                .filter(|&i| selection.contains(&glyphs[i]))
+                .collect()
+        };
+
+        assert_eq!(selected(TextSelection::From((17., 5.))), [2, 3, 4, 5]);
+        assert_eq!(selected(TextSelection::To((12., 15.))), [0, 1, 2, 3]);
+        assert_eq!(
+            selected(TextSelection::Between((17., 5.), (12., 15.))),
+            [2, 3]
+        );
+        // Dragging backwards selects the same glyphs
+        assert_eq!(
+            selected(TextSelection::Between((12., 15.), (17., 5.))),
+            [2, 3]
+        );
+        assert_eq!(selected(TextSelection::Whole).len(), 6);
+    }
+
+    #[test]
+    fn checkboxes_from_subtasks() {
+        let task = |indent: f32, is_checked: bool| {
+            let mut text_box = TextBox::new(vec![Text::new("task".to_owned(), 1., [1.; 4])], 1.);
+            text_box.indent = indent;
+            text_box.set_checkbox(Some(is_checked.into()));
+            Positioned::new(text_box.into())
+        };
+        let elements = vec![
+            // Partially done
+            task(0., false),
+            task(10., true),
+            task(10., false),
+            // Every subtask is done
+            task(0., false),
+            task(10., true),
+            task(20., true),
+            // No subtasks so it keeps its own state
+            task(0., true),
+        ];
+        resolve_checkboxes(&elements);
+
+        let states: Vec<_> = elements
+            .iter()
+            .map(|element| match &element.inner {
+                crate::Element::TextBox(text_box) => text_box.is_checkbox.as_ref().unwrap().get(),
+                _ => unreachable!(),
+            })
+            .collect();
+        use Checkbox::*;
+        assert_eq!(
+            states,
+            [
+                Indeterminate,
+                Checked,
+                Unchecked,
+                Checked,
+                Checked,
+                Checked,
+                Checked
+            ]
+        );
+    }
+
+    #[test]
+    fn toggling_a_task_toggles_its_subtasks() {
+        let task = |indent: f32, is_checked: bool| {
+            let mut text_box = TextBox::new(vec![Text::new("task".to_owned(), 1., [1.; 4])], 1.);
+            text_box.indent = indent;
+            text_box.set_checkbox(Some(is_checked.into()));
+            Positioned::new(text_box.into())
+        };
+        let elements = vec![
+            task(0., false),
+            task(10., true),
+            task(10., false),
+            task(20., false),
+            task(0., false),
+        ];
+        resolve_checkboxes(&elements);
+        let text_box = |index: usize| match &elements[index].inner {
+            crate::Element::TextBox(text_box) => text_box,
+            _ => unreachable!(),
+        };
+        let states = || -> Vec<_> {
+            (0..elements.len())
+                .map(|index| text_box(index).is_checkbox.as_ref().unwrap().get())
+                .collect()
+        };
+        let changed = |tasks: Vec<&TextBox>| -> Vec<_> {
+            tasks
+                .into_iter()
+                .map(|task| (0..elements.len()).find(|&i| std::ptr::eq(text_box(i), task)))
+                .collect()
+        };
+        use Checkbox::*;
+        assert_eq!(
+            states(),
+            [Indeterminate, Checked, Unchecked, Unchecked, Unchecked]
+        );
+
+        // Checking the partially done task checks everything under it instead of getting
+        // reverted by its subtasks
+        let toggled = toggle_task(&elements, text_box(0));
+        assert_eq!(states(), [Checked, Checked, Checked, Checked, Unchecked]);
+        assert_eq!(changed(toggled), [Some(0), Some(2), Some(3)]);
+
+        // Unchecking a subtask makes the task above it partially done again
+        let toggled = toggle_task(&elements, text_box(3));
+        assert_eq!(
+            states(),
+            [Indeterminate, Checked, Unchecked, Unchecked, Unchecked]
+        );
+        assert_eq!(changed(toggled), [Some(0), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn tasks_in_rows_get_toggled() {
+        let task = |indent: f32, is_checked: bool| {
+            let mut text_box = TextBox::new(vec![Text::new("task".to_owned(), 1., [1.; 4])], 1.);
+            text_box.indent = indent;
+            text_box.set_checkbox(Some(is_checked.into()));
+            Positioned::new(text_box.into())
+        };
+        let row = Row::new(vec![task(0., false), task(10., true)], 1.);
+        let elements = vec![Positioned::new(row.into())];
+        resolve_checkboxes(&elements);
+        let tasks: Vec<_> = match &elements[0].inner {
+            crate::Element::Row(row) => row
+                .elements
+                .iter()
+                .map(|element| match &element.inner {
+                    crate::Element::TextBox(text_box) => text_box,
+                    _ => unreachable!(),
+                })
+                .collect(),
+            _ => unreachable!(),
+        };
+        let states = || -> Vec<_> {
+            tasks
+                .iter()
+                .map(|task| task.is_checkbox.as_ref().unwrap().get())
+                .collect()
+        };
+        use Checkbox::*;
+        assert_eq!(states(), [Checked, Checked]);
+
+        let toggled = toggle_task(&elements, tasks[1]);
+        assert_eq!(states(), [Unchecked, Unchecked]);
+        assert_eq!(toggled.len(), 2);
+    }
+
+    #[test]
+    fn visited_links_resolve_against_their_document() {
+        let doc = Path::new("/notes/todo.md");
+        // Compared as paths since separators differ between platforms
+        let resolved = |link: &str| PathBuf::from(visited_link(link, doc));
+        assert_eq!(resolved("./a.md"), Path::new("/notes/a.md"));
+        assert_eq!(resolved("../b/c.md"), Path::new("/b/c.md"));
+        assert_eq!(resolved("#top"), Path::new("/notes/todo.md#top"));
+        assert_eq!(
+            visited_link("https://example.com/a/../b", doc),
+            "https://example.com/a/../b"
+        );
+
+        // The same relative link from another document leads somewhere else
+        let visited = BTreeSet::from([visited_link("a.md", doc)]);
+        let link = || {
+            Text::new("a".to_owned(), 1., [0.; 4])
+                .with_link("a.md".to_owned())
+                .with_link_colors(LinkColors {
+                    link: [0., 0., 1., 1.],
+                    visited: [1., 0., 1., 1.],
+                })
+        };
+        let elements = [Positioned::new(crate::Element::from(TextBox::new(
+            vec![link(), link().with_color([1., 0., 0., 1.])],
+            1.,
+        )))];
+        let text_box = match &elements[0].inner {
+            crate::Element::TextBox(text_box) => text_box,
+            _ => unreachable!(),
+        };
+        mark_visited(&elements, &visited, Path::new("/elsewhere/index.md"));
+        assert_eq!(text_box.texts[0].color(), [0., 0., 1., 1.]);
+        mark_visited(&elements, &visited, doc);
+        assert_eq!(text_box.texts[0].color(), [1., 0., 1., 1.]);
+        // Links given their own color keep it
+        assert_eq!(text_box.texts[1].color(), [1., 0., 0., 1.]);
+    }
+}
@@ -1,21 +1,29 @@
 use std::{
     collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    process::{Command, Stdio},
+    str,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+use anyhow::Context;
+
+use serde::Deserialize;
 use wgpu_glyph::ab_glyph;
 use winit::window::CursorIcon;
 
 use crate::image::ImageData;
 
-pub type Line = ((f32, f32), (f32, f32));
 pub type Selection = ((f32, f32), (f32, f32));
 pub type Point = (f32, f32);
 pub type Size = (f32, f32);
 pub type MaybeImageData = Arc<Mutex<Option<ImageData>>>;
 pub type ImageCache = Arc<Mutex<HashMap<String, MaybeImageData>>>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Rect {
     pub pos: Point,
     pub size: Point,
@@ -40,6 +48,40 @@ impl Rect {
     pub fn contains(&self, loc: Point) -> bool {
         self.pos.0 <= loc.0 && loc.0 <= self.max().0 && self.pos.1 <= loc.1 && loc.1 <= self.max().1
     }
+
+    pub fn center(&self) -> Point {
+        (self.pos.0 + self.size.0 / 2., self.pos.1 + self.size.1 / 2.)
+    }
+
+    /// Rounds the edges to whole pixels. Sides that had any length keep at least a pixel of it so
+    /// that thin lines don't disappear
+    pub fn snap_to_pixels(&self) -> Rect {
+        let min = (self.pos.0.round(), self.pos.1.round());
+        let max = self.max();
+        let snap = |min: f32, max: f32, size: f32| {
+            if size > 0. {
+                max.round().max(min + 1.)
+            } else {
+                min
+            }
+        };
+        Rect::from_min_max(
+            min,
+            (
+                snap(min.0, max.0, self.size.0),
+                snap(min.1, max.1, self.size.1),
+            ),
+        )
+    }
+
+    /// The smallest rect containing both rects
+    pub fn union(&self, other: &Rect) -> Rect {
+        let (max, other_max) = (self.max(), other.max());
+        Rect::from_min_max(
+            (self.pos.0.min(other.pos.0), self.pos.1.min(other.pos.1)),
+            (max.0.max(other_max.0), max.1.max(other_max.1)),
+        )
+    }
 }
 
 impl From<ab_glyph::Rect> for Rect {
@@ -74,3 +116,384 @@ impl From<CursorIcon> for HoverInfo {
         }
     }
 }
+
+/// Passing `-` as the file reads the markdown from stdin instead
+pub fn is_stdin(file_path: &Path) -> bool {
+    file_path == Path::new("-")
+}
+
+/// Reads all of the markdown at `file_path`, or from stdin for `-`
+pub fn read_markdown(file_path: &Path) -> anyhow::Result<String> {
+    let mut md_string = String::new();
+    if is_stdin(file_path) {
+        io::stdin()
+            .read_to_string(&mut md_string)
+            .context("Could not read stdin")?;
+    } else {
+        File::open(file_path)
+            .and_then(|mut file| file.read_to_string(&mut md_string))
+            .with_context(|| format!("Could not read file at {:?}", file_path))?;
+    }
+    Ok(md_string)
+}
+
+/// Reads markdown from `reader` as it comes in, handing everything read so far to `on_update`.
+/// Long piped input can be rendered while it's still arriving this way. Each update gets the whole
+/// document parsed again, so after the first one they wait until `interval` has passed. A
+/// character that's split between chunks is held back until the rest of it arrives
+pub fn stream_markdown(
+    mut reader: impl Read,
+    interval: Duration,
+    mut on_update: impl FnMut(String),
+) -> io::Result<()> {
+    let mut read = Vec::new();
+    let mut chunk = vec![0; 8 * 1024];
+    // When the last update was handed out and how many bytes of the input it had
+    let mut last_update: Option<Instant> = None;
+    let mut sent = 0;
+    loop {
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        read.extend_from_slice(&chunk[..len]);
+        if last_update.map_or(false, |at| at.elapsed() < interval) {
+            continue;
+        }
+        let complete = match str::from_utf8(&read) {
+            Ok(_) => read.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            // Invalid rather than incomplete, so it's never getting any better
+            Err(_) => read.len(),
+        };
+        if complete > sent {
+            on_update(String::from_utf8_lossy(&read[..complete]).into_owned());
+            last_update = Some(Instant::now());
+            sent = complete;
+        }
+    }
+    // Always ends with the full document even if the input was empty or cut off mid-character,
+    // unless the last update already had all of it
+    if last_update.is_none() || sent < read.len() {
+        on_update(String::from_utf8_lossy(&read).into_owned());
+    }
+    Ok(())
+}
+
+/// Whether the OS's accessibility settings ask for animations to be reduced
+///
+/// This is only detectable on some platforms and defaults to `false` everywhere else
+pub fn os_prefers_reduced_motion() -> bool {
+    let read_setting = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    };
+
+    if cfg!(target_os = "macos") {
+        read_setting(
+            "defaults",
+            &["read", "com.apple.universalaccess", "reduceMotion"],
+        )
+        .map_or(false, |value| value == "1")
+    } else if cfg!(unix) {
+        read_setting(
+            "gsettings",
+            &["get", "org.gnome.desktop.interface", "enable-animations"],
+        )
+        .map_or(false, |value| value == "false")
+    } else {
+        false
+    }
+}
+
+/// Whether the OS is set to dark mode
+///
+/// This is only detectable on some platforms and defaults to `false` everywhere else
+pub fn os_prefers_dark() -> bool {
+    let read_setting = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    };
+
+    if cfg!(target_os = "macos") {
+        read_setting("defaults", &["read", "-g", "AppleInterfaceStyle"])
+            .map_or(false, |value| value == "Dark")
+    } else if cfg!(windows) {
+        read_setting(
+            "reg",
+            &[
+                "query",
+                r"HKCU\Software\Microsoft\Windows\CurrentVersion\Themes\Personalize",
+                "/v",
+                "AppsUseLightTheme",
+            ],
+        )
+        .map_or(false, |value| value.ends_with("0x0"))
+    } else if cfg!(unix) {
+        read_setting(
+            "gsettings",
+            &["get", "org.gnome.desktop.interface", "color-scheme"],
+        )
+        .map_or(false, |value| gnome_prefers_dark(&value))
+    } else {
+        false
+    }
+}
+
+/// Calls `on_change` with whether the OS is set to dark mode each time GNOME reports that the
+/// setting changed until `on_change` returns `false`. Returns `false` when the changes couldn't be
+/// followed, so they have to be checked for some other way
+pub fn monitor_os_theme(mut on_change: impl FnMut(bool) -> bool) -> bool {
+    let child = Command::new("gsettings")
+        .args(["monitor", "org.gnome.desktop.interface", "color-scheme"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    let stdout = match child.stdout.take() {
+        Some(stdout) => stdout,
+        None => return false,
+    };
+    for line in BufReader::new(stdout).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        // Changes come in as `color-scheme: 'prefer-dark'`
+        let value = line.split_once(':').map_or(&*line, |(_, value)| value);
+        if !on_change(gnome_prefers_dark(value.trim())) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return true;
+        }
+    }
+    // `gsettings` quit on its own like when GNOME's settings aren't there
+    let _ = child.wait();
+    false
+}
+
+fn gnome_prefers_dark(color_scheme: &str) -> bool {
+    color_scheme == "'prefer-dark'"
+}
+
+/// How links that lead outside of the document get opened
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct LinkOpener {
+    /// Program and arguments to open links with where `{url}` gets replaced with the link. The
+    /// link goes last when there's no `{url}`. Uses the system's default handler when unset
+    pub command: Option<String>,
+    /// When set only links with one of these schemes get opened
+    pub allowed_schemes: Option<Vec<String>>,
+    /// Links with any of these schemes never get opened
+    pub blocked_schemes: Vec<String>,
+}
+
+impl Default for LinkOpener {
+    fn default() -> Self {
+        Self {
+            command: None,
+            allowed_schemes: None,
+            blocked_schemes: vec!["file".to_owned(), "javascript".to_owned()],
+        }
+    }
+}
+
+impl LinkOpener {
+    /// Whether links with `scheme` can be opened
+    pub fn allows(&self, scheme: &str) -> bool {
+        let listed = |schemes: &[String]| schemes.iter().any(|s| s.eq_ignore_ascii_case(scheme));
+        self.allowed_schemes.as_deref().map_or(true, listed) && !listed(&self.blocked_schemes)
+    }
+
+    /// Opens `link` after checking that its scheme is allowed. Links without a scheme are paths
+    /// to local files, so they go by the `file` scheme
+    pub fn open(&self, link: &str) -> anyhow::Result<()> {
+        let scheme = link_scheme(link).unwrap_or("file");
+        if !self.allows(scheme) {
+            anyhow::bail!("Opening `{}:` links isn't allowed", scheme);
+        }
+        match &self.command {
+            Some(command) => {
+                // Arguments get passed as they are without a shell in between, so nothing in the
+                // link can run commands of its own
+                let mut parts = command.split_whitespace();
+                let program = parts.next().context("The link opening command is empty")?;
+                let mut args: Vec<_> = parts.map(|part| part.replace("{url}", link)).collect();
+                if !command.contains("{url}") {
+                    args.push(link.to_owned());
+                }
+                Command::new(program)
+                    .args(args)
+                    .spawn()
+                    .with_context(|| format!("Failed running `{}`", program))?;
+                Ok(())
+            }
+            None => open::that(link).context("Failed opening link"),
+        }
+    }
+}
+
+/// The scheme at the start of `link` like `https` in `https://example.com`. Single letters are
+/// left out since those are Windows drive letters
+pub fn link_scheme(link: &str) -> Option<&str> {
+    let (scheme, _) = link.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    (valid && scheme.len() > 1).then_some(scheme)
+}
+
+/// Where the character between the brackets of the task list checkbox on `line` (counted from 1)
+/// of `md` is. Block quote markers and the item's list marker can come before the checkbox
+pub fn task_checkbox_index(md: &[u8], line: usize) -> Option<usize> {
+    let start = match line.checked_sub(1)? {
+        0 => 0,
+        above => {
+            md.iter()
+                .enumerate()
+                .filter(|(_, &b)| b == b'\n')
+                .nth(above - 1)?
+                .0
+                + 1
+        }
+    };
+    let line = md[start..].split(|&b| b == b'\n').next()?;
+    let mut i = line
+        .iter()
+        .position(|&b| !matches!(b, b' ' | b'\t' | b'>'))?;
+    let digits = line[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+    match (digits, line.get(i + digits)) {
+        (0, Some(b'-' | b'*' | b'+')) => i += 1,
+        (1..=9, Some(b'.' | b')')) => i += digits + 1,
+        _ => return None,
+    }
+    let spaces = line[i..]
+        .iter()
+        .take_while(|&&b| b == b' ' || b == b'\t')
+        .count();
+    if spaces == 0 {
+        return None;
+    }
+    i += spaces;
+    match line.get(i..i + 3)? {
+        [b'[', b' ' | b'x' | b'X', b']'] => Some(start + i + 1),
+        _ => None,
+    }
+}
+
+/// Ticks or clears the task list checkbox on `line` of the markdown file at `file_path`. Only the
+/// one character between the checkbox's brackets changes
+pub fn write_back_checkbox(file_path: &Path, line: usize, checked: bool) -> anyhow::Result<()> {
+    let mut md =
+        fs::read(file_path).with_context(|| format!("Could not read file at {:?}", file_path))?;
+    let index = task_checkbox_index(&md, line)
+        .with_context(|| format!("No checkbox on line {} of {:?}", line, file_path))?;
+    let mark = if checked { b'x' } else { b' ' };
+    // Leaves the file's modification time alone when there's nothing to change
+    if md[index] == mark || (checked && md[index] == b'X') {
+        return Ok(());
+    }
+    md[index] = mark;
+    fs::write(file_path, md).with_context(|| format!("Could not write file at {:?}", file_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::time::Duration;
+
+    use super::{link_scheme, stream_markdown, task_checkbox_index, LinkOpener, Rect};
+
+    #[test]
+    fn snapping_keeps_thin_lines() {
+        let rule = Rect::new((10.4, 20.6), (100.2, 0.3)).snap_to_pixels();
+        assert_eq!((rule.pos, rule.size), ((10., 21.), (101., 1.)));
+        let empty = Rect::new((1.6, 1.6), (0., 0.)).snap_to_pixels();
+        assert_eq!((empty.pos, empty.size), ((2., 2.), (0., 0.)));
+    }
+
+    // Hands out at most two bytes per read like a slow pipe would
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.0.len().min(buf.len()).min(2);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn streamed_markdown_grows_by_whole_characters() {
+        let stream = |md: &[u8], interval| {
+            let mut updates = Vec::new();
+            stream_markdown(Trickle(md), interval, |update| updates.push(update)).unwrap();
+            updates
+        };
+        // The full document isn't handed out twice
+        assert_eq!(stream("#é\n".as_bytes(), Duration::ZERO), ["#", "#é\n"]);
+        assert_eq!(stream(b"", Duration::ZERO), [""]);
+        // Cut off mid-character
+        assert_eq!(stream(b"ab\xC3", Duration::ZERO), ["ab", "ab\u{FFFD}"]);
+
+        // Updates wait for the interval after the first one, leaving the rest for the end
+        let hour = Duration::from_secs(60 * 60);
+        assert_eq!(stream(b"# Title\n", hour), ["# ", "# Title\n"]);
+    }
+
+    #[test]
+    fn link_schemes() {
+        assert_eq!(link_scheme("https://example.com"), Some("https"));
+        assert_eq!(link_scheme("mailto:someone@example.com"), Some("mailto"));
+        assert_eq!(link_scheme("JavaScript:alert(1)"), Some("JavaScript"));
+        assert_eq!(link_scheme("C:\\docs\\notes.txt"), None);
+        assert_eq!(link_scheme("docs/notes.txt"), None);
+        assert_eq!(link_scheme("#heading"), None);
+    }
+
+    #[test]
+    fn task_checkboxes_are_found_on_their_line() {
+        let md = b"# Tasks\r\n\r\n- [ ] one\r\n  * [x] two\r\n> 3. [X] three\r\n-[ ] not a task\r\n- [] nor this";
+        let index = |line| task_checkbox_index(md, line).map(|index| md[index]);
+        assert_eq!(index(1), None);
+        assert_eq!(index(3), Some(b' '));
+        assert_eq!(index(4), Some(b'x'));
+        assert_eq!(index(5), Some(b'X'));
+        assert_eq!(index(6), None);
+        assert_eq!(index(7), None);
+        assert_eq!(index(8), None);
+        assert_eq!(task_checkbox_index(md, 3), Some(14));
+    }
+
+    #[test]
+    fn link_opener_checks_schemes() {
+        let opener = LinkOpener::default();
+        assert!(opener.allows("https"));
+        assert!(!opener.allows("FILE"));
+        assert!(!opener.allows("javascript"));
+        assert!(opener.open("javascript:alert(1)").is_err());
+        assert!(opener.open("notes.txt").is_err());
+
+        let opener = LinkOpener {
+            allowed_schemes: Some(vec!["https".to_owned()]),
+            ..LinkOpener::default()
+        };
+        assert!(opener.allows("https"));
+        assert!(!opener.allows("http"));
+    }
+}